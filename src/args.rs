@@ -2,6 +2,8 @@ use std::str::FromStr;
 
 use clap::Parser;
 
+use crate::hir::IntegerKind;
+
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 pub struct Args {
@@ -37,6 +39,37 @@ pub struct Args {
     )]
     pub opt_level: char,
 
+    #[clap(
+        long,
+        validator(validate_pointer_size_argument),
+        help = "The size of a pointer in bytes on the target platform: 2, 4, or 8. Defaults to the pointer size of the host"
+    )]
+    pub target_pointer_size: Option<u32>,
+
+    #[clap(
+        long,
+        help = "The type of integer literals whose type is otherwise unconstrained, such as i64 or isz. Defaults to i32"
+    )]
+    pub default_integer_type: Option<IntegerKind>,
+
+    #[clap(
+        long,
+        help = "The maximum number of type variable bindings to follow in a row before assuming a type is infinite. Defaults to 500"
+    )]
+    pub type_recursion_limit: Option<u32>,
+
+    #[clap(
+        long,
+        help = "Store closure environments larger than this many bytes behind a pointer instead of copying them into each closure. Disabled by default"
+    )]
+    pub capture_by_reference_threshold: Option<u32>,
+
+    #[clap(
+        long,
+        help = "Warn when a single definition is monomorphised into more than this many variants. Disabled by default or when 0"
+    )]
+    pub instantiation_warning_threshold: Option<u32>,
+
     #[clap(long, help = "Use plaintext and an indicator line instead of color for pointing out error locations")]
     pub no_color: bool,
 
@@ -46,6 +79,12 @@ pub struct Args {
     #[clap(long, help = "Print out the HIR, Ante's post-monomorphisation IR")]
     pub show_hir: bool,
 
+    #[clap(long, help = "Print the generic definitions contributing the most code after monomorphisation")]
+    pub show_bloat: bool,
+
+    #[clap(long, help = "Print each type every generic definition was instantiated at after monomorphisation")]
+    pub show_instantiations: bool,
+
     #[clap(long, help = "Delete the resulting binary after compiling")]
     pub delete_binary: bool,
 
@@ -54,6 +93,21 @@ pub struct Args {
 
     #[clap(long, help = "Print out the type of each definition")]
     pub show_types: bool,
+
+    #[clap(
+        long,
+        help = "With --show-types, annotate each required trait with where it originates from (Direct, Indirect, GivenDirect, or GivenIndirect)"
+    )]
+    pub show_callsites: bool,
+
+    #[clap(long, help = "Print out the name, location, type, and traits of each definition as JSON")]
+    pub export_types: bool,
+
+    #[clap(long, help = "Print out the traits a caller must satisfy to use each definition")]
+    pub show_obligations: bool,
+
+    #[clap(long, help = "Print out each trait impl along with the type of each of its methods")]
+    pub show_impls: bool,
 }
 
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
@@ -80,3 +134,10 @@ fn validate_opt_argument(arg: &str) -> Result<(), &'static str> {
         _ => Err("Argument to -O must be one of: 0, 1, 2, 3, s, or z"),
     }
 }
+
+fn validate_pointer_size_argument(arg: &str) -> Result<(), &'static str> {
+    match arg {
+        "2" | "4" | "8" => Ok(()),
+        _ => Err("Argument to --target-pointer-size must be one of: 2, 4, or 8"),
+    }
+}
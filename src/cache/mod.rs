@@ -16,13 +16,14 @@
 //! the relevant phase. An example is the `llvm::Generator` in the llvm codegen phase.
 use crate::cache::unsafecache::UnsafeCache;
 use crate::error::location::{Locatable, Location};
+use crate::lexer::token::IntegerKind;
 use crate::nameresolution::NameResolver;
 use crate::parser::ast::{Ast, Definition, TraitDefinition, TraitImpl, TypeAnnotation};
-use crate::types::traits::{ConstraintSignature, RequiredImpl, RequiredTrait, TraitConstraintId};
-use crate::types::{GeneralizedType, Kind, LetBindingLevel, TypeBinding};
+use crate::types::traits::{ConstraintSignature, GivenConstraint, RequiredImpl, RequiredTrait, TraitConstraintId};
+use crate::types::{GeneralizedType, Kind, LetBindingLevel, NormalizedType, TypeBinding};
 use crate::types::{Type, TypeInfo, TypeInfoBody, TypeInfoId, TypeVariableId};
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 
 mod counter;
@@ -92,6 +93,37 @@ pub struct ModuleCache<'a> {
     /// impls that should be in scope and select an instance.
     pub impl_scopes: Vec<Vec<ImplInfoId>>,
 
+    /// Maps ImplResolutionKey -> the impl selected for it, or None if no impl matched.
+    /// Filled out during type inference for trait constraints whose arguments contain
+    /// no type variables since these always resolve to the same impl. This is cleared
+    /// whenever a new impl is pushed since the new impl may overlap with a cached one.
+    pub impl_resolutions: HashMap<ImplResolutionKey, Option<ImplInfoId>>,
+
+    /// Maps ImportInfoId -> ImportInfo
+    /// Filled out during name resolution and used after type
+    /// inference to issue warnings for unused imports.
+    pub import_infos: Vec<ImportInfo<'a>>,
+
+    /// Maps the ModuleId of each module to the modules it refers to a definition, type,
+    /// trait, or impl of. Filled out during name resolution and type inference, and
+    /// used to determine which imports are unused.
+    pub used_modules: HashMap<ModuleId, HashSet<ModuleId>>,
+
+    /// The location, type, and local variables in scope of each typed hole `_` inferred so far.
+    /// Each hole's type is only reported once type inference finishes so that it is as
+    /// specific as possible.
+    pub holes: Vec<(Location<'a>, Type, Vec<String>)>,
+
+    /// The default integer type of each integer literal's type variable, for literals
+    /// within a definition declaring one via `default`. Literals without an entry
+    /// use the global default if their type is still unconstrained after monomorphisation.
+    pub integer_literal_defaults: HashMap<TypeVariableId, IntegerKind>,
+
+    /// The skolems created while unifying `forall` types. These are rigid type variables
+    /// standing in for the quantified variables of a `forall` type: unification never
+    /// binds them to another type. See `typechecker::next_skolem_id`.
+    pub skolems: HashSet<TypeVariableId>,
+
     /// Ante represents each member access (foo.bar) as a trait (.foo)
     /// that is generated for each new field name used globally.
     pub member_access_traits: HashMap<String, TraitInfoId>,
@@ -103,13 +135,18 @@ pub struct ModuleCache<'a> {
     /// integer literals.
     pub int_trait: TraitInfoId,
 
+    /// The prelude's `Shared` marker trait. Types with an impl of
+    /// this trait are reference counted. This is None until the
+    /// prelude is resolved.
+    pub shared_trait: Option<TraitInfoId>,
+
     /// The filepath to ante's stdlib/prelude.an file to be automatically
     /// included when defining a new ante module.
     pub prelude_path: PathBuf,
 }
 
 /// The key for accessing parse trees or `NameResolver`s
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub struct ModuleId(pub usize);
 
 #[derive(Copy, Clone, Eq, PartialEq, PartialOrd, Ord, Hash)]
@@ -121,6 +158,18 @@ impl std::fmt::Debug for DefinitionInfoId {
     }
 }
 
+/// How the arguments to an extern function are passed to it
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ExternAbi {
+    /// Arguments are passed with the same representation Ante uses internally
+    Ante,
+
+    /// Given via `extern "C"`. Arguments are converted to the representation a C function
+    /// expects where it differs from Ante's: strings are passed as a bare `char*` and unions
+    /// without any fields are passed as a C enum, i.e. a 32-bit integer.
+    C,
+}
+
 #[derive(Debug)]
 pub enum DefinitionKind<'a> {
     /// A variable/function definition in the form `a = b`
@@ -129,8 +178,8 @@ pub enum DefinitionKind<'a> {
     /// A trait definition in the form `trait A a with ...`
     TraitDefinition(&'a mut TraitDefinition<'a>),
 
-    /// An extern FFI definition with no body
-    Extern(&'a mut TypeAnnotation<'a>),
+    /// An extern FFI definition with no body, called using the given ABI
+    Extern(&'a mut TypeAnnotation<'a>, ExternAbi),
 
     /// A TypeConstructor function to construct a type.
     /// If the constructed type is a tagged union, tag will
@@ -180,6 +229,10 @@ pub struct DefinitionInfo<'a> {
     /// True if this definition is from a trait impl
     pub trait_impl: Option<ImplInfoId>,
 
+    /// Some(trait_id) if this is the default definition of a member of the given trait.
+    /// Impls that do not define the member use this definition instead.
+    pub trait_default: Option<TraitInfoId>,
+
     /// The type of this definition. Filled out during type inference,
     /// and is guarenteed to be Some afterward.
     pub typ: Option<GeneralizedType>,
@@ -187,6 +240,10 @@ pub struct DefinitionInfo<'a> {
     /// A count of how many times was this variable referenced in the program.
     /// Used primarily for issuing unused warnings.
     pub uses: u32,
+
+    /// The module this definition was declared in. This is None for
+    /// builtin definitions and is used to track which imports are used.
+    pub module: Option<ModuleId>,
 }
 
 impl<'a> Locatable<'a> for DefinitionInfo<'a> {
@@ -245,6 +302,15 @@ pub struct TraitInfo<'a> {
     /// later have to conform to.
     pub definitions: Vec<DefinitionInfoId>,
 
+    /// The default definitions given for some of the declarations above, e.g. `neq`
+    /// in `trait Eq a with eq: a - a -> bool; neq x y = not (eq x y)`.
+    /// Each has the same name as the declaration it provides a default for.
+    pub defaults: Vec<DefinitionInfoId>,
+
+    /// The super-traits of this trait in terms of its typeargs and fundeps,
+    /// e.g. `Eq a` in `trait Ord a given Eq a`. Requiring this trait requires these as well.
+    pub given: Vec<ConstraintSignature>,
+
     /// The Ast node that defines this trait.
     /// A value of None means this trait was builtin to the compiler
     pub trait_node: Option<&'a mut TraitDefinition<'a>>,
@@ -267,6 +333,14 @@ impl<'a> TraitInfo<'a> {
         assert!(self.is_member_access());
         &self.name[1..]
     }
+
+    /// True if this trait gives a default definition for the member of the given name.
+    /// This checks the trait's Ast node since its defaults may not be resolved yet.
+    pub fn has_default(&self, name: &str) -> bool {
+        self.trait_node
+            .as_ref()
+            .is_some_and(|node| node.defaults.iter().any(|default| default.pattern.to_string() == name))
+    }
 }
 
 impl<'a> Locatable<'a> for TraitInfo<'a> {
@@ -284,6 +358,10 @@ impl<'a> Locatable<'a> for TraitInfo<'a> {
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub struct ImplScopeId(pub usize);
 
+/// A trait constraint's trait and normalized type arguments along with the
+/// scope of impls it is resolved in. See `ModuleCache::impl_resolutions`.
+pub type ImplResolutionKey = (TraitInfoId, Vec<NormalizedType>, ImplScopeId);
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub struct ImplInfoId(pub usize);
 
@@ -299,15 +377,39 @@ pub struct ImplInfo<'a> {
     /// These constraints are from the 'given' clause of a trait impl.
     /// They contain a unique TraitConstraintId that is used to map the
     /// constraints inside the impl's definitions.
-    pub given: Vec<ConstraintSignature>,
+    pub given: Vec<GivenConstraint>,
     pub trait_impl: &'a mut TraitImpl<'a>,
 }
 
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct ImportInfoId(pub usize);
+
+/// Corresponds to an `ast::Import` node. These are filled out during name resolution.
+/// An import is used if the importing module refers to anything declared in the
+/// imported module, see `ModuleCache::used_modules`.
+#[derive(Debug)]
+pub struct ImportInfo<'a> {
+    /// The path of the imported module, e.g. `Foo.Bar`
+    pub name: String,
+    pub module_id: ModuleId,
+
+    /// The module containing this import
+    pub importer: ModuleId,
+    pub location: Location<'a>,
+}
+
+impl<'a> Locatable<'a> for ImportInfo<'a> {
+    fn locate(&self) -> Location<'a> {
+        self.location
+    }
+}
+
 impl<'a> ModuleCache<'a> {
     pub fn new(project_directory: &'a Path) -> ModuleCache<'a> {
         let mut cache = ModuleCache {
             relative_roots: vec![project_directory.to_owned(), dirs::config_dir().unwrap().join("ante/stdlib")],
             int_trait: TraitInfoId(0), // Dummy value since we must have the cache to push a trait
+            shared_trait: None,
             prelude_path: dirs::config_dir().unwrap().join("stdlib/prelude"),
             // Really wish you could do ..Default::default() for the remaining fields
             modules: HashMap::default(),
@@ -321,6 +423,12 @@ impl<'a> ModuleCache<'a> {
             trait_infos: Vec::default(),
             impl_infos: Vec::default(),
             impl_scopes: Vec::default(),
+            impl_resolutions: HashMap::default(),
+            import_infos: Vec::default(),
+            used_modules: HashMap::default(),
+            holes: Vec::default(),
+            integer_literal_defaults: HashMap::new(),
+            skolems: HashSet::new(),
             member_access_traits: HashMap::default(),
             current_trait_constraint_id: Default::default(),
         };
@@ -349,10 +457,37 @@ impl<'a> ModuleCache<'a> {
             typ: None,
             uses: 0,
             trait_impl: None,
+            trait_default: None,
+            module: None,
         });
         DefinitionInfoId(id)
     }
 
+    pub fn push_import(
+        &mut self, name: String, module_id: ModuleId, importer: ModuleId, location: Location<'a>,
+    ) -> ImportInfoId {
+        let id = self.import_infos.len();
+        self.import_infos.push(ImportInfo { name, module_id, importer, location });
+        ImportInfoId(id)
+    }
+
+    /// Record that the module `user` refers to something declared in the module `used`,
+    /// marking any import of `used` from within `user` as used.
+    pub fn mark_module_used(&mut self, user: ModuleId, used: ModuleId) {
+        self.used_modules.entry(user).or_default().insert(used);
+    }
+
+    /// Like `mark_module_used`, where `used` is the module of the file containing `declaration`.
+    pub fn mark_module_used_at(&mut self, user: ModuleId, declaration: Location) {
+        if let Some(&used) = self.modules.get(declaration.filename) {
+            self.mark_module_used(user, used);
+        }
+    }
+
+    pub fn is_import_used(&self, import: &ImportInfo) -> bool {
+        self.used_modules.get(&import.importer).is_some_and(|used| used.contains(&import.module_id))
+    }
+
     pub fn push_ast(&mut self, ast: Ast<'a>) -> ModuleId {
         ModuleId(self.parse_trees.push(ast))
     }
@@ -370,11 +505,23 @@ impl<'a> ModuleCache<'a> {
     }
 
     pub fn next_type_variable_id(&mut self, level: LetBindingLevel) -> TypeVariableId {
+        self.next_type_variable_id_with_kind(level, Kind::Normal(0))
+    }
+
+    pub fn next_type_variable_id_with_kind(&mut self, level: LetBindingLevel, kind: Kind) -> TypeVariableId {
         let id = self.type_bindings.len();
-        self.type_bindings.push(TypeBinding::Unbound(level, Kind::Normal(0)));
+        self.type_bindings.push(TypeBinding::Unbound(level, kind));
         TypeVariableId(id)
     }
 
+    /// Returns the kind of the given unbound type variable, or `*` if it is already bound.
+    pub fn kind_of_type_variable(&self, id: TypeVariableId) -> Kind {
+        match &self.type_bindings[id.0] {
+            TypeBinding::Unbound(_, kind) => kind.clone(),
+            TypeBinding::Bound(_) => Kind::Normal(0),
+        }
+    }
+
     pub fn next_type_variable(&mut self, level: LetBindingLevel) -> Type {
         let id = self.next_type_variable_id(level);
         Type::TypeVariable(id)
@@ -390,6 +537,8 @@ impl<'a> ModuleCache<'a> {
             typeargs,
             fundeps,
             definitions: vec![],
+            defaults: vec![],
+            given: vec![],
             trait_node,
             location,
             uses: 0,
@@ -399,7 +548,7 @@ impl<'a> ModuleCache<'a> {
 
     pub fn push_trait_impl(
         &mut self, trait_id: TraitInfoId, typeargs: Vec<Type>, definitions: Vec<DefinitionInfoId>,
-        trait_impl: &'a mut TraitImpl<'a>, given: Vec<ConstraintSignature>, location: Location<'a>,
+        trait_impl: &'a mut TraitImpl<'a>, given: Vec<GivenConstraint>, location: Location<'a>,
     ) -> ImplInfoId {
         let id = self.impl_infos.len();
 
@@ -411,6 +560,7 @@ impl<'a> ModuleCache<'a> {
         }
 
         self.impl_infos.push(ImplInfo { trait_id, typeargs, definitions, location, given, trait_impl });
+        self.impl_resolutions.clear();
         ImplInfoId(id)
     }
 
@@ -460,6 +610,13 @@ impl<'a> ModuleCache<'a> {
             }
         }
 
+        // Otherwise the impl uses the trait's default definition
+        for definition in &self[self[binding].trait_id].defaults {
+            if self[*definition].name == *name {
+                return *definition;
+            }
+        }
+
         unreachable!("No definition for '{}' found in trait impl {}", name, binding.0)
     }
 }
@@ -1,5 +1,5 @@
 use cranelift::frontend::FunctionBuilder;
-use cranelift::prelude::{FloatCC, InstBuilder, IntCC, StackSlotData, StackSlotKind, Value as CraneliftValue};
+use cranelift::prelude::{FloatCC, InstBuilder, IntCC, TrapCode, Type as CraneliftType, Value as CraneliftValue};
 
 use crate::hir::{Ast, Builtin};
 
@@ -36,20 +36,52 @@ pub fn call_builtin<'ast>(builtin: &'ast Builtin, context: &mut Context<'ast>, b
         Builtin::EqChar(a, b) => eq_char(value(a), value(b), builder),
         Builtin::EqBool(a, b) => eq_bool(value(a), value(b), builder),
 
-        Builtin::SignExtend(a, _typ) => sign_extend(value(a), builder),
-        Builtin::ZeroExtend(a, _typ) => zero_extend(value(a), builder),
-
-        Builtin::SignedToFloat(a, _typ) => signed_to_float(value(a), builder),
-        Builtin::UnsignedToFloat(a, _typ) => unsigned_to_float(value(a), builder),
-        Builtin::FloatToSigned(a, _typ) => float_to_signed(value(a), builder),
-        Builtin::FloatToUnsigned(a, _typ) => float_to_unsigned(value(a), builder),
-
-        Builtin::Truncate(a, _typ) => truncate(value(a), builder),
+        Builtin::SignExtend(a, typ) => {
+            let a = value(a);
+            sign_extend(a, single_type(context, typ), builder)
+        },
+        Builtin::ZeroExtend(a, typ) => {
+            let a = value(a);
+            zero_extend(a, single_type(context, typ), builder)
+        },
+
+        Builtin::SignedToFloat(a, typ) => {
+            let a = value(a);
+            signed_to_float(a, single_type(context, typ), builder)
+        },
+        Builtin::UnsignedToFloat(a, typ) => {
+            let a = value(a);
+            unsigned_to_float(a, single_type(context, typ), builder)
+        },
+        Builtin::FloatToSigned(a, typ) => {
+            let a = value(a);
+            float_to_signed(a, single_type(context, typ), builder)
+        },
+        Builtin::FloatToUnsigned(a, typ) => {
+            let a = value(a);
+            float_to_unsigned(a, single_type(context, typ), builder)
+        },
+
+        Builtin::Truncate(a, typ) => {
+            let a = value(a);
+            truncate(a, single_type(context, typ), builder)
+        },
 
         Builtin::Deref(a, typ) => return deref(context, typ, a, builder),
         Builtin::Offset(a, b, elem_size) => offset(value(a), value(b), *elem_size, builder),
         Builtin::Transmute(a, _typ) => transmute(value(a), builder),
         Builtin::StackAlloc(a) => stack_alloc(a, context, builder),
+        Builtin::Unreachable(typ) => return unreachable(typ, context, builder),
+        Builtin::Assume(a) => {
+            // Cranelift has no equivalent to llvm.assume, so the condition is only evaluated
+            value(a);
+            return Value::unit();
+        },
+        Builtin::IncrementRefCount(a) | Builtin::DecrementRefCount(a) => {
+            // There is no runtime support for reference counting yet so these are no-ops
+            value(a);
+            return Value::unit();
+        },
     };
 
     Value::Normal(result)
@@ -154,8 +186,7 @@ fn offset(
 }
 
 // All integers are boxed as an i64, so this is a no-op in this backend
-fn sign_extend(param1: CraneliftValue, builder: &mut FunctionBuilder) -> CraneliftValue {
-    let target_type = builder.func.signature.returns[0].value_type;
+fn sign_extend(param1: CraneliftValue, target_type: CraneliftType, builder: &mut FunctionBuilder) -> CraneliftValue {
     let start_type = builder.func.dfg.value_type(param1);
     assert!(start_type.bytes() <= target_type.bytes());
 
@@ -167,8 +198,7 @@ fn sign_extend(param1: CraneliftValue, builder: &mut FunctionBuilder) -> Craneli
 }
 
 // All integers are boxed as an i64, so this is a no-op in this backend
-fn zero_extend(param1: CraneliftValue, builder: &mut FunctionBuilder) -> CraneliftValue {
-    let target_type = builder.func.signature.returns[0].value_type;
+fn zero_extend(param1: CraneliftValue, target_type: CraneliftType, builder: &mut FunctionBuilder) -> CraneliftValue {
     let start_type = builder.func.dfg.value_type(param1);
     assert!(start_type.bytes() <= target_type.bytes());
 
@@ -179,28 +209,31 @@ fn zero_extend(param1: CraneliftValue, builder: &mut FunctionBuilder) -> Craneli
     }
 }
 
-fn signed_to_float(param1: CraneliftValue, builder: &mut FunctionBuilder) -> CraneliftValue {
-    let target_type = builder.func.signature.returns[0].value_type;
+fn signed_to_float(
+    param1: CraneliftValue, target_type: CraneliftType, builder: &mut FunctionBuilder,
+) -> CraneliftValue {
     builder.ins().fcvt_from_sint(target_type, param1)
 }
 
-fn unsigned_to_float(param1: CraneliftValue, builder: &mut FunctionBuilder) -> CraneliftValue {
-    let target_type = builder.func.signature.returns[0].value_type;
+fn unsigned_to_float(
+    param1: CraneliftValue, target_type: CraneliftType, builder: &mut FunctionBuilder,
+) -> CraneliftValue {
     builder.ins().fcvt_from_uint(target_type, param1)
 }
 
-fn float_to_signed(param1: CraneliftValue, builder: &mut FunctionBuilder) -> CraneliftValue {
-    let target_type = builder.func.signature.returns[0].value_type;
+fn float_to_signed(
+    param1: CraneliftValue, target_type: CraneliftType, builder: &mut FunctionBuilder,
+) -> CraneliftValue {
     builder.ins().fcvt_to_sint(target_type, param1)
 }
 
-fn float_to_unsigned(param1: CraneliftValue, builder: &mut FunctionBuilder) -> CraneliftValue {
-    let target_type = builder.func.signature.returns[0].value_type;
+fn float_to_unsigned(
+    param1: CraneliftValue, target_type: CraneliftType, builder: &mut FunctionBuilder,
+) -> CraneliftValue {
     builder.ins().fcvt_to_uint(target_type, param1)
 }
 
-fn truncate(param1: CraneliftValue, builder: &mut FunctionBuilder) -> CraneliftValue {
-    let target_type = builder.func.signature.returns[0].value_type;
+fn truncate(param1: CraneliftValue, target_type: CraneliftType, builder: &mut FunctionBuilder) -> CraneliftValue {
     let start_type = builder.func.dfg.value_type(param1);
     assert!(start_type.bytes() >= target_type.bytes());
 
@@ -211,24 +244,40 @@ fn truncate(param1: CraneliftValue, builder: &mut FunctionBuilder) -> CraneliftV
     }
 }
 
+/// Returns the cranelift type of the given hir::Type, which must be represented by a single value
+fn single_type(context: &mut Context, typ: &crate::hir::Type) -> CraneliftType {
+    let mut result = None;
+    context.for_each_type_in(typ, |_, typ| result = Some(typ));
+    result.expect("Expected a type represented by a single value")
+}
+
 fn deref<'a>(context: &mut Context<'a>, typ: &crate::hir::Type, addr: &'a Ast, builder: &mut FunctionBuilder) -> Value {
     let addr = addr.eval_single(context, builder);
     context.load_value(typ, addr, &mut 0, builder)
 }
 
 fn stack_alloc<'a>(param1: &'a Ast, context: &mut Context<'a>, builder: &mut FunctionBuilder) -> CraneliftValue {
-    let values = param1.eval_all(context, builder);
-
-    let size = values.iter().map(|value| builder.func.dfg.value_type(*value).bytes()).sum();
-
-    let data = StackSlotData::new(StackSlotKind::ExplicitSlot, size);
-    let slot = builder.create_stack_slot(data);
-
-    let mut offset: u32 = 0;
-    for value in values {
-        builder.ins().stack_store(value, slot, offset as i32);
-        offset += builder.func.dfg.value_type(value).bytes();
-    }
-
+    let value = param1.codegen(context, builder);
+    let slot = context.store_in_new_stack_slot(value, 0, builder);
     builder.ins().stack_addr(pointer_type(), slot, 0)
 }
+
+fn unreachable<'a>(typ: &crate::hir::Type, context: &mut Context<'a>, builder: &mut FunctionBuilder) -> Value {
+    builder.ins().trap(TrapCode::UnreachableCodeReached);
+
+    // Any code following this is dead but still needs a block to be inserted
+    // into and a value of the expected type to operate on.
+    let dead_block = builder.create_block();
+    builder.switch_to_block(dead_block);
+    builder.seal_block(dead_block);
+
+    context.fmap_type(typ, &mut |_, typ| {
+        if typ.is_float() {
+            builder.ins().f64const(0.0)
+        } else if typ.is_bool() {
+            builder.ins().bconst(typ, false)
+        } else {
+            builder.ins().iconst(typ, 0)
+        }
+    })
+}
@@ -3,7 +3,7 @@ use std::path::Path;
 
 use crate::args::Args;
 use crate::hir::{self, Ast, DefinitionId, PrimitiveType, Type};
-use crate::util::fmap;
+use crate::util::{align_up, fmap};
 
 use cranelift::codegen::ir::{types as cranelift_types, FuncRef, Function, StackSlot};
 use cranelift::codegen::verify_function;
@@ -27,7 +27,7 @@ pub struct Context<'ast> {
     data_context: DataContext,
     function_queue: FunctionQueue<'ast>,
 
-    pub current_function_name: Option<DefinitionId>,
+    pub current_function_name: Option<String>,
     next_func_id: u32,
 }
 
@@ -358,8 +358,7 @@ impl<'local> Context<'local> {
     pub fn add_function_to_queue(&mut self, function: &'local hir::Lambda, name: &str) -> Value {
         let signature = self.convert_signature(&function.typ);
 
-        let name = format!("lambda{}", name);
-        let function_id = self.module.declare_function(&name, Linkage::Export, &signature).unwrap();
+        let function_id = self.module.declare_function(name, Linkage::Export, &signature).unwrap();
 
         self.function_queue.push((function, signature.clone(), function_id));
 
@@ -426,73 +425,44 @@ impl<'local> Context<'local> {
     }
 
     pub fn reinterpret_cast(&mut self, value: Value, target_type: &Type, builder: &mut FunctionBuilder) -> Value {
-        let size = size_of(target_type);
+        let slot = self.store_in_new_stack_slot(value, size_of(target_type), builder);
+        load_aligned(target_type, &mut 0, &mut |typ, offset| builder.ins().stack_load(typ, slot, offset))
+    }
+
+    /// Store the given value in a new stack slot which is large enough
+    /// to hold both the value and at least `min_size` bytes.
+    pub fn store_in_new_stack_slot(&mut self, value: Value, min_size: u32, builder: &mut FunctionBuilder) -> StackSlot {
+        let value = self.eval_nested(value, builder);
+        let size = size_of_value(&value, builder).max(min_size);
+
         let data = StackSlotData::new(StackSlotKind::ExplicitSlot, size);
         let slot = builder.create_stack_slot(data);
 
-        self.store_stack_value(value, slot, &mut 0, builder);
-        self.load_stack_value(target_type, slot, &mut 0, builder)
-    }
-
-    fn store_stack_value(&mut self, value: Value, slot: StackSlot, offset: &mut u32, builder: &mut FunctionBuilder) {
-        match value {
-            Value::Tuple(elems) => {
-                for elem in elems {
-                    self.store_stack_value(elem, slot, offset, builder);
-                }
-            },
-            value => {
-                let value = value.eval_single(self, builder);
-                builder.ins().stack_store(value, slot, *offset as i32);
-                *offset += builder.func.dfg.value_type(value).bytes();
-            },
-        }
+        store_aligned(value, &mut 0, builder, &mut |builder, value, offset| {
+            builder.ins().stack_store(value, slot, offset);
+        });
+        slot
     }
 
     pub fn store_value(&mut self, addr: CraneliftValue, value: Value, offset: &mut u32, builder: &mut FunctionBuilder) {
-        match value {
-            Value::Tuple(elems) => {
-                for elem in elems {
-                    self.store_value(addr, elem, offset, builder);
-                }
-            },
-            value => {
-                let value = value.eval_single(self, builder);
-                builder.ins().store(MemFlags::new(), value, addr, *offset as i32);
-                *offset += builder.func.dfg.value_type(value).bytes();
-            },
-        }
-    }
-
-    fn load_stack_value(
-        &mut self, target_type: &Type, slot: StackSlot, offset: &mut u32, builder: &mut FunctionBuilder,
-    ) -> Value {
-        let mut load_single = |typ| {
-            let value = builder.ins().stack_load(typ, slot, *offset as i32);
-            *offset += typ.bytes();
-            Value::Normal(value)
-        };
-
-        match target_type {
-            Type::Tuple(elems) => Value::Tuple(fmap(elems, |elem| self.load_stack_value(elem, slot, offset, builder))),
-            Type::Primitive(p) => load_single(convert_primitive_type(p)),
-            Type::Function(_) => load_single(function_type()),
-        }
+        let value = self.eval_nested(value, builder);
+        store_aligned(value, offset, builder, &mut |builder, value, offset| {
+            builder.ins().store(MemFlags::new(), value, addr, offset);
+        });
     }
 
     pub fn load_value(
-        &mut self, target_type: &Type, addr: CraneliftValue, offset: &mut i32, builder: &mut FunctionBuilder,
+        &mut self, target_type: &Type, addr: CraneliftValue, offset: &mut u32, builder: &mut FunctionBuilder,
     ) -> Value {
-        let mut load_single = |typ| {
-            let value = builder.ins().load(typ, MemFlags::new(), addr, *offset);
-            *offset += typ.bytes() as i32;
-            Value::Normal(value)
-        };
+        load_aligned(target_type, offset, &mut |typ, offset| builder.ins().load(typ, MemFlags::new(), addr, offset))
+    }
 
-        match target_type {
-            Type::Tuple(elems) => Value::Tuple(fmap(elems, |elem| self.load_value(elem, addr, offset, builder))),
-            Type::Primitive(p) => load_single(convert_primitive_type(p)),
-            Type::Function(_) => load_single(function_type()),
+    /// Evaluate each element of the given value while keeping its tuple structure
+    /// so that the alignment of each nested tuple is known before it is stored.
+    fn eval_nested(&mut self, value: Value, builder: &mut FunctionBuilder) -> Value {
+        match value {
+            Value::Tuple(elems) => Value::Tuple(fmap(elems, |elem| self.eval_nested(elem, builder))),
+            other => Value::Normal(other.eval_single(self, builder)),
         }
     }
 
@@ -539,12 +509,94 @@ pub fn int_pointer_type() -> cranelift_types::Type {
     }
 }
 
-/// Returns the size of the given type in bytes
+/// Returns the size of the given type in bytes. Each field of a tuple is placed at the
+/// next offset that is a multiple of its alignment, and the tuple as a whole is padded
+/// to a multiple of its own alignment.
 pub fn size_of(typ: &Type) -> u32 {
     match typ {
         Type::Primitive(p) => convert_primitive_type(p).bytes(),
         Type::Function(_) => function_type().bytes(),
-        Type::Tuple(types) => types.iter().map(size_of).sum(),
+        Type::Tuple(types) => {
+            let size = types.iter().fold(0, |offset, field| align_up(offset, align_of(field)) + size_of(field));
+            align_up(size, align_of(typ))
+        },
+    }
+}
+
+/// Returns the alignment of the given type in bytes
+pub fn align_of(typ: &Type) -> u32 {
+    match typ {
+        Type::Tuple(types) => types.iter().map(align_of).max().unwrap_or(1),
+        other => size_of(other),
+    }
+}
+
+/// Returns the size in bytes of a value already evaluated by eval_nested
+fn size_of_value(value: &Value, builder: &FunctionBuilder) -> u32 {
+    match value {
+        Value::Normal(value) => builder.func.dfg.value_type(*value).bytes(),
+        Value::Tuple(elems) => {
+            let size = elems
+                .iter()
+                .fold(0, |offset, elem| align_up(offset, align_of_value(elem, builder)) + size_of_value(elem, builder));
+            align_up(size, align_of_value(value, builder))
+        },
+        other => unreachable!("Expected an evaluated value, found {:?}", other),
+    }
+}
+
+/// Returns the alignment in bytes of a value already evaluated by eval_nested
+fn align_of_value(value: &Value, builder: &FunctionBuilder) -> u32 {
+    match value {
+        Value::Tuple(elems) => elems.iter().map(|elem| align_of_value(elem, builder)).max().unwrap_or(1),
+        other => size_of_value(other, builder),
+    }
+}
+
+/// Store each element of a value already evaluated by eval_nested at its aligned offset,
+/// mirroring the layout used by size_of. The offset is left just past the value.
+fn store_aligned(
+    value: Value, offset: &mut u32, builder: &mut FunctionBuilder,
+    store: &mut impl FnMut(&mut FunctionBuilder, CraneliftValue, i32),
+) {
+    let alignment = align_of_value(&value, builder);
+    *offset = align_up(*offset, alignment);
+
+    match value {
+        Value::Tuple(elems) => {
+            for elem in elems {
+                store_aligned(elem, offset, builder, store);
+            }
+            *offset = align_up(*offset, alignment);
+        },
+        Value::Normal(value) => {
+            store(builder, value, *offset as i32);
+            *offset += builder.func.dfg.value_type(value).bytes();
+        },
+        other => unreachable!("Expected an evaluated value, found {:?}", other),
+    }
+}
+
+/// Load a value of the given type starting from the given offset, using the same layout as store_aligned
+fn load_aligned(
+    typ: &Type, offset: &mut u32, load: &mut impl FnMut(cranelift_types::Type, i32) -> CraneliftValue,
+) -> Value {
+    *offset = align_up(*offset, align_of(typ));
+
+    let mut load_single = |typ: cranelift_types::Type| {
+        let value = load(typ, *offset as i32);
+        *offset += typ.bytes();
+        Value::Normal(value)
+    };
+
+    match typ {
+        Type::Tuple(elems) => {
+            let value = Value::Tuple(fmap(elems, |elem| load_aligned(elem, offset, load)));
+            *offset = align_up(*offset, align_of(typ));
+            value
+        },
+        Type::Primitive(p) => load_single(convert_primitive_type(p)),
+        Type::Function(_) => load_single(function_type()),
     }
 }
 
@@ -84,8 +84,8 @@ impl CodeGen for hir::Variable {
 impl CodeGen for hir::Lambda {
     fn codegen<'a>(&'a self, context: &mut Context<'a>, _builder: &mut FunctionBuilder) -> Value {
         let name = match context.current_function_name.take() {
-            Some(id) => format!("{}", id),
-            None => format!("_anon{}", context.next_unique_id()),
+            Some(name) => name,
+            None => format!("lambda_anon{}", context.next_unique_id()),
         };
 
         context.add_function_to_queue(self, &name)
@@ -120,7 +120,8 @@ impl CodeGen for hir::Definition {
         #[allow(clippy::map_entry)]
         if !context.definitions.contains_key(&self.variable) {
             if matches!(self.expr.as_ref(), hir::Ast::Lambda(_)) {
-                context.current_function_name = Some(self.variable);
+                let name = self.name.clone().unwrap_or_else(|| format!("lambda{}", self.variable));
+                context.current_function_name = Some(name);
             }
 
             let value = self.expr.codegen(context, builder);
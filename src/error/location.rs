@@ -24,15 +24,16 @@ impl Position {
         Position { index: 0, line: 1, column: 1 }
     }
 
-    /// Increment the position 1 character forward
-    pub fn advance(&mut self, passed_newline: bool) {
-        if passed_newline {
+    /// Increment the position 1 character forward, past the given character.
+    /// The index is a byte offset so that it may be used to slice the file's contents.
+    pub fn advance(&mut self, passed: char) {
+        if passed == '\n' {
             self.line += 1;
             self.column = 1;
         } else {
             self.column += 1;
         }
-        self.index += 1;
+        self.index += passed.len_utf8();
     }
 }
 
@@ -108,6 +109,11 @@ impl<'c> Location<'c> {
 
         Location { filename: self.filename, start, end }
     }
+
+    /// True if the other Location is entirely within this one
+    pub fn contains(&self, other: Location<'c>) -> bool {
+        self.filename == other.filename && self.start.index <= other.start.index && other.end.index <= self.end.index
+    }
 }
 
 /// A trait representing anything that has a Location
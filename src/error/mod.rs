@@ -1,7 +1,9 @@
 //! error/mod.rs - Defines the error, warning, and note macros
 //! used to issue compiler errors. There is also an ErrorMessage type
 //! for storing messages that may be issued later. Note that all issuing
-//! an error does is print it to stderr and update the global ERROR_COUNT.
+//! an error does is buffer it to be printed to stderr later and update the
+//! global ERROR_COUNT. Buffering lets identical messages be reported only once,
+//! which happens when e.g. the same definition is typechecked more than once.
 //!
 //! Compiler passes are expected to continue even after issuing errors so
 //! that as many can be issued as possible. A possible future improvement
@@ -12,17 +14,23 @@ use crate::error::location::Location;
 use colored::ColoredString;
 use colored::*;
 use std::cmp::{max, min};
+use std::collections::BTreeMap;
 use std::fmt::{Display, Formatter};
 use std::fs::File;
 use std::io::{BufReader, Read};
 use std::path::Path;
 use std::sync::atomic::Ordering::SeqCst;
 use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::sync::Mutex;
 
 static COLORED_OUTPUT: AtomicBool = AtomicBool::new(true);
 
 static ERROR_COUNT: AtomicUsize = AtomicUsize::new(0);
 
+/// Diagnostics are buffered globally rather than per thread so that those issued by
+/// passes running on other threads are still flushed along with the rest.
+static DIAGNOSTICS: Mutex<Diagnostics> = Mutex::new(Diagnostics::new());
+
 /// Return an error which may be issued later
 macro_rules! make_error {
     ( $location:expr , $fmt_string:expr $( , $($msg:tt)* )? ) => ({
@@ -31,10 +39,10 @@ macro_rules! make_error {
     });
 }
 
-/// Issue an error message to stderr and increment the error count
+/// Issue an error message and increment the error count
 macro_rules! error {
     ( $location:expr , $fmt_string:expr $( , $($msg:tt)* )? ) => ({
-        eprintln!("{}", make_error!($location, $fmt_string $( , $($msg)* )?));
+        $crate::error::issue(make_error!($location, $fmt_string $( , $($msg)* )?));
     });
 }
 
@@ -46,10 +54,10 @@ macro_rules! make_warning {
     });
 }
 
-/// Issues a warning to stderr
+/// Issues a warning
 macro_rules! warning {
     ( $location:expr , $fmt_string:expr $( , $($msg:tt)* )? ) => ({
-        eprintln!("{}", make_warning!($location, $fmt_string $( , $($msg)* )?));
+        $crate::error::issue(make_warning!($location, $fmt_string $( , $($msg)* )?));
     });
 }
 
@@ -61,10 +69,10 @@ macro_rules! make_note {
     });
 }
 
-/// Issues a note to stderr
+/// Issues a note
 macro_rules! note {
     ( $location:expr , $fmt_string:expr $( , $($msg:tt)* )? ) => ({
-        eprintln!("{}", make_note!($location, $fmt_string $( , $($msg)* )?));
+        $crate::error::issue(make_note!($location, $fmt_string $( , $($msg)* )?));
     });
 }
 
@@ -111,6 +119,13 @@ impl<'a> ErrorMessage<'a> {
         ErrorMessage { msg: msg.into(), location, error_type: ErrorType::Note }
     }
 
+    /// Downgrade this message to a warning. This is used for errors
+    /// that shouldn't halt compilation, like the type mismatches
+    /// found at gradual `dyn` boundaries.
+    pub fn into_warning(self) -> ErrorMessage<'a> {
+        ErrorMessage { error_type: ErrorType::Warning, ..self }
+    }
+
     fn marker(&self) -> ColoredString {
         match self.error_type {
             ErrorType::Error => self.color("error:"),
@@ -130,6 +145,89 @@ impl<'a> ErrorMessage<'a> {
     }
 }
 
+/// The diagnostics issued since they were last flushed, in the order they were issued.
+struct Diagnostics {
+    /// Each rendered message and the notes following it, along
+    /// with the number of times that group of messages was issued.
+    messages: Vec<(String, usize)>,
+
+    /// Maps each rendered group of messages to its index in `messages`
+    indices: BTreeMap<String, usize>,
+
+    /// The most recently issued message along with any notes issued after it so far.
+    /// Notes are deduplicated together with the message they follow so that a
+    /// repeated note is never separated from a new error it belongs to.
+    pending: Option<String>,
+}
+
+impl Diagnostics {
+    const fn new() -> Diagnostics {
+        Diagnostics { messages: Vec::new(), indices: BTreeMap::new(), pending: None }
+    }
+
+    fn issue(&mut self, text: String, error_type: ErrorType) {
+        match (&mut self.pending, error_type) {
+            (Some(pending), ErrorType::Note) => pending.push_str(&text),
+            _ => {
+                self.finish_pending();
+                self.pending = Some(text);
+            },
+        }
+    }
+
+    fn finish_pending(&mut self) {
+        if let Some(text) = self.pending.take() {
+            if let Some(index) = self.indices.get(&text) {
+                self.messages[*index].1 += 1;
+            } else {
+                self.indices.insert(text.clone(), self.messages.len());
+                self.messages.push((text, 1));
+            }
+        }
+    }
+}
+
+/// Issue the given message, buffering it until the next call to `flush_diagnostics`.
+/// Messages identical to one already issued, with the same text and location, are
+/// counted rather than reported again. Repeated errors still increment the error count
+/// so that passes checking for new errors via `get_error_count` see them.
+pub fn issue(message: ErrorMessage) {
+    if message.error_type == ErrorType::Error {
+        ERROR_COUNT.fetch_add(1, SeqCst);
+    }
+
+    let text = format!("{}\n", message);
+    DIAGNOSTICS.lock().unwrap().issue(text, message.error_type);
+}
+
+/// Print each buffered diagnostic to stderr. Messages that were issued
+/// more than once are marked with the number of times they were repeated.
+pub fn flush_diagnostics() {
+    for text in take_diagnostics() {
+        eprint!("{}", text);
+    }
+}
+
+/// Remove each buffered diagnostic, returning them rendered in the order they were issued
+/// rather than printing them. Like `flush_diagnostics`, repeated messages are marked with
+/// the number of times they were repeated.
+pub fn take_diagnostics() -> Vec<String> {
+    let mut diagnostics = std::mem::replace(&mut *DIAGNOSTICS.lock().unwrap(), Diagnostics::new());
+    diagnostics.finish_pending();
+
+    diagnostics
+        .messages
+        .into_iter()
+        .map(|(mut text, count)| {
+            if count > 1 {
+                let end_of_message = text.find('\n').unwrap_or(text.len());
+                text.insert_str(end_of_message, &format!(" (repeated {} times)", count));
+            }
+            text
+        })
+        .collect()
+}
+
 /// Reads the given file, returning all of its contents
 fn read_file_or_panic(path: &Path) -> String {
     let file = File::open(path).unwrap();
@@ -144,6 +242,10 @@ pub fn color_output(should_color: bool) {
     COLORED_OUTPUT.store(should_color, SeqCst);
 }
 
+pub fn colored_output() -> bool {
+    COLORED_OUTPUT.load(SeqCst)
+}
+
 pub fn get_error_count() -> usize {
     ERROR_COUNT.load(SeqCst)
 }
@@ -191,12 +293,6 @@ impl<'a> Display for ErrorMessage<'a> {
     fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
         let start = self.location.start;
 
-        // An error isn't considered an error until it is actually printed out.
-        // That's why ERROR_COUNT is incremented here and not when ErrorMessage is constructed.
-        if self.error_type == ErrorType::Error {
-            ERROR_COUNT.fetch_add(1, SeqCst);
-        }
-
         writeln!(f, "{}\t{} {}", self.location, self.marker(), self.msg)?;
 
         let file_contents = read_file_or_panic(self.location.filename);
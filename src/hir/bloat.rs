@@ -0,0 +1,249 @@
+//! Each instantiation of a generic definition is compiled separately, so a large
+//! generic function used at many types can account for much of a program's code size.
+//! This module reports which generic definitions contribute the most code after
+//! monomorphisation so that users can tell which ones are worth restructuring,
+//! e.g. by moving the parts that do not depend on the generic types into a separate function.
+//!
+//! The size of each instance is estimated by the number of HIR nodes in its body.
+//! Instances merged by function_merging are only compiled once and are counted once.
+use std::collections::{BTreeMap, HashSet};
+
+use super::monomorphisation::{self, Context};
+use super::*;
+use crate::cache::{DefinitionInfoId, DefinitionKind};
+use crate::types::{self, GeneralizedType};
+use crate::util::fmap;
+
+impl<'c> Context<'c> {
+    /// Returns a report of each generic definition that was monomorphised,
+    /// ranked by the estimated size of all of its instances combined.
+    pub fn bloat_report(&self) -> String {
+        let mut report = String::from("Generic definitions by estimated code size:\n");
+        for (name, instances, size) in self.rank_generic_definitions() {
+            let plural = if instances == 1 { "" } else { "s" };
+            report += &format!("  {}: {} instance{}, {} nodes\n", name, instances, plural, size);
+        }
+        report
+    }
+
+    /// Returns a report listing each type every generic definition was instantiated at.
+    /// Useful when debugging why a definition was monomorphised more often than expected.
+    pub fn instantiations_report(&self) -> String {
+        let mut report = String::from("Instantiations of generic definitions:\n");
+        for id in self.instances().into_keys() {
+            report += &format!("  {}:\n", self.definition_name(id));
+            for (typ, definition_id) in self.instantiations_of(id) {
+                report += &format!("    {} : {}\n", definition_id, typ.display(&self.cache));
+            }
+        }
+        report
+    }
+
+    /// Returns each type the given definition was instantiated at during monomorphisation
+    /// along with the id of the resulting definition, in the order they were instantiated.
+    /// Macro definitions are substituted at each use and have no id so they are not included.
+    pub fn instantiations_of(&self, id: DefinitionInfoId) -> Vec<(types::Type, DefinitionId)> {
+        let definitions = self.definitions.iter().filter(|(key, _)| key.id == id);
+        definitions
+            .filter_map(|(key, definition)| match definition {
+                monomorphisation::Definition::Normal(info) | monomorphisation::Definition::Mutable(info) => {
+                    Some((key.typ.clone(), info.definition_id))
+                },
+                monomorphisation::Definition::Macro(_) => None,
+            })
+            .collect()
+    }
+
+    /// Returns the (name, instance count, total node count) of each generic definition,
+    /// largest first.
+    fn rank_generic_definitions(&self) -> Vec<(String, usize, usize)> {
+        let mut ranking = fmap(self.instances(), |(id, instances)| {
+            let size: usize = instances.iter().map(|instance| instance.node_count()).sum();
+            (self.definition_name(id), instances.len(), size)
+        });
+
+        ranking.sort_by(|(name1, _, size1), (name2, _, size2)| size2.cmp(size1).then_with(|| name1.cmp(name2)));
+        ranking
+    }
+
+    /// Groups the bodies of each distinct monomorphised instance by the generic definition they came from
+    fn instances(&self) -> BTreeMap<DefinitionInfoId, Vec<&Ast>> {
+        let mut instances: BTreeMap<_, Vec<_>> = BTreeMap::new();
+        let mut seen = HashSet::new();
+
+        for (key, definition) in self.definitions.iter() {
+            if let monomorphisation::Definition::Normal(DefinitionInfo { definition: Some(ast), definition_id }) =
+                definition
+            {
+                if self.is_generic_definition(key.id) && seen.insert(*definition_id) {
+                    instances.entry(key.id).or_default().push(ast.as_ref());
+                }
+            }
+        }
+
+        instances
+    }
+
+    fn is_generic_definition(&self, id: DefinitionInfoId) -> bool {
+        let info = &self.cache[id];
+        let is_polymorphic = matches!(&info.typ, Some(GeneralizedType::PolyType(typevars, _)) if !typevars.is_empty());
+        is_polymorphic && matches!(info.definition, Some(DefinitionKind::Definition(_)))
+    }
+
+    fn definition_name(&self, id: DefinitionInfoId) -> String {
+        let info = &self.cache[id];
+        let module = info.location.filename.file_stem().unwrap_or_default().to_string_lossy();
+        format!("{}.{}", module, info.name)
+    }
+}
+
+trait NodeCount {
+    fn node_count(&self) -> usize;
+}
+
+impl NodeCount for Ast {
+    fn node_count(&self) -> usize {
+        dispatch_on_hir!(self, NodeCount::node_count)
+    }
+}
+
+impl NodeCount for Literal {
+    fn node_count(&self) -> usize {
+        1
+    }
+}
+
+impl NodeCount for Variable {
+    fn node_count(&self) -> usize {
+        1
+    }
+}
+
+impl NodeCount for Lambda {
+    fn node_count(&self) -> usize {
+        1 + self.body.node_count()
+    }
+}
+
+impl NodeCount for FunctionCall {
+    fn node_count(&self) -> usize {
+        1 + self.function.node_count() + self.args.iter().map(NodeCount::node_count).sum::<usize>()
+    }
+}
+
+impl NodeCount for Definition {
+    fn node_count(&self) -> usize {
+        1 + self.expr.node_count()
+    }
+}
+
+impl NodeCount for If {
+    fn node_count(&self) -> usize {
+        let otherwise = self.otherwise.as_ref().map_or(0, |otherwise| otherwise.node_count());
+        1 + self.condition.node_count() + self.then.node_count() + otherwise
+    }
+}
+
+impl NodeCount for Match {
+    fn node_count(&self) -> usize {
+        1 + self.decision_tree.node_count() + self.branches.iter().map(NodeCount::node_count).sum::<usize>()
+    }
+}
+
+impl NodeCount for DecisionTree {
+    fn node_count(&self) -> usize {
+        match self {
+            DecisionTree::Leaf(_) => 1,
+            DecisionTree::Definition(definition, rest) => definition.node_count() + rest.node_count(),
+            DecisionTree::Switch { int_to_switch_on, cases, else_case } => {
+                let cases = cases.iter().map(|(_, case)| case.node_count()).sum::<usize>();
+                let else_case = else_case.as_ref().map_or(0, |else_case| else_case.node_count());
+                1 + int_to_switch_on.node_count() + cases + else_case
+            },
+        }
+    }
+}
+
+impl NodeCount for Return {
+    fn node_count(&self) -> usize {
+        1 + self.expression.node_count()
+    }
+}
+
+impl NodeCount for Sequence {
+    fn node_count(&self) -> usize {
+        1 + self.statements.iter().map(NodeCount::node_count).sum::<usize>()
+    }
+}
+
+impl NodeCount for Extern {
+    fn node_count(&self) -> usize {
+        1
+    }
+}
+
+impl NodeCount for Assignment {
+    fn node_count(&self) -> usize {
+        1 + self.lhs.node_count() + self.rhs.node_count()
+    }
+}
+
+impl NodeCount for MemberAccess {
+    fn node_count(&self) -> usize {
+        1 + self.lhs.node_count()
+    }
+}
+
+impl NodeCount for Tuple {
+    fn node_count(&self) -> usize {
+        1 + self.fields.iter().map(NodeCount::node_count).sum::<usize>()
+    }
+}
+
+impl NodeCount for ReinterpretCast {
+    fn node_count(&self) -> usize {
+        1 + self.lhs.node_count()
+    }
+}
+
+impl NodeCount for Builtin {
+    fn node_count(&self) -> usize {
+        use Builtin::*;
+        match self {
+            AddInt(a, b)
+            | AddFloat(a, b)
+            | SubInt(a, b)
+            | SubFloat(a, b)
+            | MulInt(a, b)
+            | MulFloat(a, b)
+            | DivSigned(a, b)
+            | DivUnsigned(a, b)
+            | DivFloat(a, b)
+            | ModSigned(a, b)
+            | ModUnsigned(a, b)
+            | ModFloat(a, b)
+            | LessSigned(a, b)
+            | LessUnsigned(a, b)
+            | LessFloat(a, b)
+            | EqInt(a, b)
+            | EqFloat(a, b)
+            | EqChar(a, b)
+            | EqBool(a, b)
+            | Offset(a, b, _) => 1 + a.node_count() + b.node_count(),
+            SignExtend(a, _)
+            | ZeroExtend(a, _)
+            | SignedToFloat(a, _)
+            | UnsignedToFloat(a, _)
+            | FloatToSigned(a, _)
+            | FloatToUnsigned(a, _)
+            | Truncate(a, _)
+            | Deref(a, _)
+            | Transmute(a, _)
+            | StackAlloc(a)
+            | Assume(a)
+            | IncrementRefCount(a)
+            | DecrementRefCount(a) => 1 + a.node_count(),
+            Unreachable(_) => 1,
+        }
+    }
+}
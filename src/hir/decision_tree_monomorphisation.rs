@@ -3,18 +3,21 @@ use std::convert::TryInto;
 use crate::{
     cache::{DefinitionInfoId, DefinitionKind},
     parser::ast,
-    types::pattern::{Case, DecisionTree, VariantTag},
+    types::{
+        self,
+        pattern::{Case, DecisionTree, VariantTag},
+    },
     util::fmap,
 };
 
-use super::monomorphisation::{Context, Definition};
+use super::monomorphisation::{tag_value, Context, Definition};
 use crate::hir;
 
 impl<'c> Context<'c> {
     pub fn monomorphise_match(&mut self, match_: &ast::Match<'c>) -> hir::Ast {
         let match_prelude = self.store_initial_value(match_);
-        let decision_tree = self.monomorphise_tree(match_.decision_tree.as_ref().unwrap());
-        let branches = fmap(&match_.branches, |branch| self.monomorphise(&branch.1));
+        let decision_tree = self.monomorphise_tree(match_.decision_tree.as_ref().unwrap(), match_);
+        let branches = fmap(&match_.branches, |branch| self.monomorphise(&branch.2));
         let result_type = self.convert_type(match_.typ.as_ref().unwrap());
 
         hir::Ast::Sequence(hir::Sequence {
@@ -27,27 +30,68 @@ impl<'c> Context<'c> {
     fn store_initial_value(&mut self, match_: &ast::Match<'c>) -> hir::Ast {
         let value = self.monomorphise(match_.expression.as_ref());
 
-        if let Some(DecisionTree::Switch(id, _)) = &match_.decision_tree {
-            let (def, new_id) = self.fresh_definition(value);
-            let typ = self.follow_all_bindings(self.cache[*id].typ.as_ref().unwrap().as_monotype());
-            self.definitions.insert((*id, typ), new_id.into());
-            def
-        } else {
-            value
+        // The first Switch may come after any number of Guards at the root of the tree.
+        // Branches reached before it can only use the value through a pattern which is a
+        // single variable, so these are bound to the value as well.
+        let mut ids = vec![];
+        let mut tree = match_.decision_tree.as_ref();
+        while let Some(DecisionTree::Guard(_, rest)) = tree {
+            tree = Some(rest.as_ref());
+        }
+        if let Some(DecisionTree::Switch(id, _)) = tree {
+            ids.push(*id);
+        }
+        for (pattern, _, _) in &match_.branches {
+            if let (true, ast::Ast::Variable(variable)) = (pattern.is_matchable_variable(), pattern) {
+                ids.push(variable.definition.unwrap());
+            }
+        }
+
+        if ids.is_empty() {
+            return value;
         }
+
+        let (def, new_id) = self.fresh_definition(value);
+        for id in ids {
+            let key = self.definition_key(id, self.cache[id].typ.as_ref().unwrap().as_monotype());
+            self.definitions.insert(key, new_id.into());
+        }
+        def
     }
 
-    fn monomorphise_tree(&mut self, tree: &DecisionTree) -> hir::DecisionTree {
+    fn monomorphise_tree(&mut self, tree: &DecisionTree, match_: &ast::Match<'c>) -> hir::DecisionTree {
         match tree {
             DecisionTree::Leaf(index) => hir::DecisionTree::Leaf(*index),
-            DecisionTree::Switch(id_to_match_on, cases) => self.monomorphise_switch(*id_to_match_on, cases),
+            DecisionTree::Switch(id_to_match_on, cases) => self.monomorphise_switch(*id_to_match_on, cases, match_),
+            DecisionTree::Guard(index, rest) => self.monomorphise_match_guard(*index, rest, match_),
             DecisionTree::Fail => {
                 unreachable!("Patterns should be verified to be complete before monomorphisation")
             },
         }
     }
 
-    fn monomorphise_switch(&mut self, id_to_match_on: DefinitionInfoId, cases: &[Case]) -> hir::DecisionTree {
+    /// Guards are compiled to a Switch on whether the guard is true so that
+    /// backends only ever need to switch on integer tags.
+    fn monomorphise_match_guard(
+        &mut self, index: usize, rest: &DecisionTree, match_: &ast::Match<'c>,
+    ) -> hir::DecisionTree {
+        let guard = self.monomorphise(match_.branches[index].1.as_ref().unwrap());
+
+        let guard_tag = hir::Ast::If(hir::If {
+            condition: Box::new(guard),
+            then: Box::new(tag_value(1)),
+            otherwise: Some(Box::new(tag_value(0))),
+            result_type: Self::tag_type(),
+        });
+
+        let cases = vec![(1, hir::DecisionTree::Leaf(index))];
+        let else_case = Some(Box::new(self.monomorphise_tree(rest, match_)));
+        hir::DecisionTree::Switch { int_to_switch_on: Box::new(guard_tag), cases, else_case }
+    }
+
+    fn monomorphise_switch(
+        &mut self, id_to_match_on: DefinitionInfoId, cases: &[Case], match_: &ast::Match<'c>,
+    ) -> hir::DecisionTree {
         let typ = self.cache[id_to_match_on].typ.as_ref().unwrap().as_monotype();
 
         let value = match self.lookup_definition(id_to_match_on, typ) {
@@ -55,41 +99,114 @@ impl<'c> Context<'c> {
             _ => unreachable!(),
         };
 
-        if cases.len() == 1 {
+        if cases.iter().any(|case| matches!(case.tag, Some(VariantTag::Range(..)))) {
+            let typ = typ.clone();
+            self.monomorphise_range_switch(value, cases, &typ, match_)
+        } else if cases.len() == 1 {
             // If there's only 1 case we must be destructuring a struct, no need to check a tag
-            self.monomorphise_case_no_tag_value(&cases[0], value.definition_id)
+            let typ = typ.clone();
+            self.monomorphise_case_no_tag_value(&cases[0], value.definition_id, &typ, match_)
         } else {
             let (cases, match_all_case) = self.split_cases(cases);
 
             let typ = typ.clone();
-            let monomorphised_type = self.convert_type(&typ);
-
-            let cases = fmap(cases, |case| self.monomorphise_case(case, value.clone()));
-            let else_case =
-                match_all_case.map(|case| Box::new(self.monomorphise_case_no_tag_value(case, value.definition_id)));
+            let cases = fmap(cases, |case| self.monomorphise_case(case, value.clone(), &typ, match_));
+            let else_case = match_all_case
+                .map(|case| Box::new(self.monomorphise_case_no_tag_value(case, value.definition_id, &typ, match_)));
 
-            let tag = self.extract_tag(value, &monomorphised_type);
+            let tag = self.extract_tag(value, &typ);
             hir::DecisionTree::Switch { int_to_switch_on: Box::new(tag), cases, else_case }
         }
     }
 
-    fn monomorphise_case(&mut self, case: &Case, match_value: hir::DefinitionInfo) -> (u32, hir::DecisionTree) {
+    /// Ranges can't be matched by comparing a tag for equality, so each range case is compiled
+    /// to its own Switch on whether the value is within the range, falling through to the next
+    /// range case otherwise. The last Switch falls through to the match-all case.
+    fn monomorphise_range_switch(
+        &mut self, value: hir::DefinitionInfo, cases: &[Case], typ: &types::Type, match_: &ast::Match<'c>,
+    ) -> hir::DecisionTree {
+        let (cases, match_all_case) = self.split_cases(cases);
+        let match_all_case = match_all_case.expect("Range patterns should always require a match-all case");
+
+        let cases = fmap(cases, |case| match case.tag {
+            Some(VariantTag::Range(start, end)) => {
+                let contains = self.range_contains(value.clone(), start, end, typ);
+                (contains, self.monomorphise_tree(&case.branch, match_))
+            },
+            _ => unreachable!("Ranges should not be mixed with other tags in a Switch"),
+        });
+
+        let mut tree = self.monomorphise_case_no_tag_value(match_all_case, value.definition_id, typ, match_);
+
+        for (contains, branch) in cases.into_iter().rev() {
+            let cases = vec![(1, branch)];
+            let else_case = Some(Box::new(tree));
+            tree = hir::DecisionTree::Switch { int_to_switch_on: Box::new(contains), cases, else_case };
+        }
+
+        tree
+    }
+
+    /// Returns a tag which is 1 if `start <= value <= end` and 0 otherwise.
+    fn range_contains(&mut self, value: hir::DefinitionInfo, start: i64, end: i64, typ: &types::Type) -> hir::Ast {
+        use hir::types::{IntegerKind::*, PrimitiveType, Type};
+        let typ = self.convert_type(typ);
+
+        let less_than = |lhs: hir::Ast, rhs: hir::Ast| {
+            let (lhs, rhs) = (Box::new(lhs), Box::new(rhs));
+            match &typ {
+                Type::Primitive(PrimitiveType::Integer(I8 | I16 | I32 | I64 | Isz)) => {
+                    hir::Ast::Builtin(hir::Builtin::LessSigned(lhs, rhs))
+                },
+                _ => hir::Ast::Builtin(hir::Builtin::LessUnsigned(lhs, rhs)),
+            }
+        };
+
+        let bound = |bound: i64| match &typ {
+            Type::Primitive(PrimitiveType::Integer(kind)) => {
+                hir::Ast::Literal(hir::Literal::Integer(bound as u64, *kind))
+            },
+            Type::Primitive(PrimitiveType::Char) => {
+                let c = char::from_u32(bound as u32).expect("Range pattern bound is not a valid char");
+                hir::Ast::Literal(hir::Literal::Char(c))
+            },
+            _ => unreachable!("Range patterns should only match integers or chars"),
+        };
+
+        let if_then_else = |condition, then, otherwise| {
+            hir::Ast::If(hir::If {
+                condition: Box::new(condition),
+                then: Box::new(then),
+                otherwise: Some(Box::new(otherwise)),
+                result_type: Self::tag_type(),
+            })
+        };
+
+        let below_start = less_than(value.clone().into(), bound(start));
+        let above_end = less_than(bound(end), value.into());
+        let within_end = if_then_else(above_end, tag_value(0), tag_value(1));
+        if_then_else(below_start, tag_value(0), within_end)
+    }
+
+    fn monomorphise_case(
+        &mut self, case: &Case, match_value: hir::DefinitionInfo, typ: &types::Type, match_: &ast::Match<'c>,
+    ) -> (u32, hir::DecisionTree) {
         let tree = if case.fields.is_empty() {
-            self.monomorphise_tree(&case.branch)
+            self.monomorphise_tree(&case.branch, match_)
         } else {
             // variable = value = reinterpret match_value as variant_type
-            let value = self.cast_to_variant_type(match_value, case);
+            let value = self.cast_to_variant_type(match_value, case, typ);
             let variable = self.next_unique_id();
-            let field_bindings = self.bind_patterns(variable, case);
+            let field_bindings = self.bind_patterns(variable, case, typ);
 
-            let mut tree = self.monomorphise_tree(&case.branch);
+            let mut tree = self.monomorphise_tree(&case.branch, match_);
 
             for definition in field_bindings.into_iter().rev() {
                 tree = hir::DecisionTree::Definition(definition, Box::new(tree));
             }
 
             let expr = Box::new(value);
-            let cast_definition = hir::Definition { variable, expr };
+            let cast_definition = hir::Definition { variable, name: None, expr };
 
             hir::DecisionTree::Definition(cast_definition, Box::new(tree))
         };
@@ -98,10 +215,12 @@ impl<'c> Context<'c> {
         (expected_tag_value as u32, tree)
     }
 
-    fn monomorphise_case_no_tag_value(&mut self, case: &Case, match_value: hir::DefinitionId) -> hir::DecisionTree {
-        let field_bindings = self.bind_patterns(match_value, case);
+    fn monomorphise_case_no_tag_value(
+        &mut self, case: &Case, match_value: hir::DefinitionId, typ: &types::Type, match_: &ast::Match<'c>,
+    ) -> hir::DecisionTree {
+        let field_bindings = self.bind_patterns(match_value, case, typ);
 
-        let mut tree = self.monomorphise_tree(&case.branch);
+        let mut tree = self.monomorphise_tree(&case.branch, match_);
 
         for definition in field_bindings.into_iter().rev() {
             tree = hir::DecisionTree::Definition(definition, Box::new(tree));
@@ -110,10 +229,14 @@ impl<'c> Context<'c> {
         tree
     }
 
-    fn extract_tag(&mut self, value: hir::DefinitionInfo, typ: &hir::Type) -> hir::Ast {
+    pub fn extract_tag(&mut self, value: hir::DefinitionInfo, typ: &types::Type) -> hir::Ast {
         use hir::types::*;
-        match typ {
-            Type::Primitive(PrimitiveType::Integer(_)) => value.into(),
+        if let Some(niche) = self.niche_layout_of_type(typ) {
+            return self.niche_tag(value.into(), &niche);
+        }
+
+        match self.convert_type(typ) {
+            Type::Primitive(PrimitiveType::Integer(_) | PrimitiveType::Char) => value.into(),
             Type::Tuple(_) => self.extract(value.into(), 0),
             _ => unreachable!(),
         }
@@ -145,6 +268,7 @@ impl<'c> Context<'c> {
                 ast::LiteralKind::Bool(_) => unreachable!(),
                 ast::LiteralKind::Unit => unreachable!(),
             },
+            VariantTag::Range(..) => unreachable!("Range cases are compiled by monomorphise_range_switch"),
             VariantTag::UserDefined(id) => {
                 match &self.cache[*id].definition {
                     Some(DefinitionKind::TypeConstructor { tag: Some(tag), .. }) => *tag,
@@ -154,14 +278,20 @@ impl<'c> Context<'c> {
         }
     }
 
-    fn bind_patterns(&mut self, variant: hir::DefinitionId, case: &Case) -> Vec<hir::Definition> {
+    /// Binds each field of the given case to the corresponding field of `variant`, a value of type `typ`
+    /// or, for unions, the value cast to the type of the case's variant.
+    fn bind_patterns(&mut self, variant: hir::DefinitionId, case: &Case, typ: &types::Type) -> Vec<hir::Definition> {
         match &case.tag {
             Some(VariantTag::UserDefined(id)) => {
-                let info_type = self.cache.definition_infos[id.0].typ.as_ref().unwrap();
-                // Skip the tag value for unions when extracting fields
-                let start_index = if info_type.is_union_constructor(&self.cache) { 1 } else { 0 };
+                let info_type = self.cache.definition_infos[id.0].typ.clone().unwrap();
+                // Skip the tag value for unions when extracting fields. Unions with a NicheLayout have no tag.
+                let is_union = info_type.is_union_constructor(&self.cache);
+                let start_index = if is_union && self.niche_layout_of_type(typ).is_none() { 1 } else { 0 };
 
-                let info_type = info_type.clone();
+                // Erased struct fields are not stored, so the remaining fields may be at earlier indices
+                let field_indices = self.struct_field_indices(typ);
+                let variant_index = if is_union { self.get_tag_value(case) as usize } else { 0 };
+                let boxed_fields = self.recursive_fields(typ, variant_index);
 
                 // Note: should not use function_type for any bindings, it is from a generalized
                 // info_type that makes it only useful for checking if it is a function or not.
@@ -169,20 +299,32 @@ impl<'c> Context<'c> {
 
                 if function_type.is_some() {
                     fmap(case.fields.iter().enumerate(), |(i, field_aliases)| {
-                        let field_index = start_index + i as u32;
+                        let field_index = match &field_indices {
+                            Some(indices) => indices[i],
+                            None => Some(start_index + i as u32),
+                        };
                         let variant_variable: hir::Variable = variant.into();
                         let field_variable = self.next_unique_id();
 
                         for field_alias in field_aliases {
                             let alias_type = self.cache[*field_alias].typ.as_ref().unwrap().as_monotype();
-                            let field_type = self.follow_all_bindings(alias_type);
-                            self.definitions.insert((*field_alias, field_type), field_variable.into());
+                            let key = self.definition_key(*field_alias, alias_type);
+                            self.definitions.insert(key, field_variable.into());
                         }
 
-                        hir::Definition {
-                            variable: field_variable,
-                            expr: Box::new(self.extract(variant_variable.into(), field_index)),
+                        let mut field = match field_index {
+                            Some(index) => self.extract(variant_variable.into(), index),
+                            None => hir::Ast::Literal(hir::Literal::Unit),
+                        };
+
+                        // Recursive fields are stored behind a pointer. Fields without any aliases
+                        // are never used so there is no need to load them.
+                        if let (true, Some(alias)) = (boxed_fields[i], field_aliases.first()) {
+                            let alias_type = self.cache[*alias].typ.clone().unwrap().into_monotype();
+                            field = self.unbox_value(field, &alias_type);
                         }
+
+                        hir::Definition { variable: field_variable, name: None, expr: Box::new(field) }
                     })
                 } else {
                     vec![]
@@ -193,8 +335,8 @@ impl<'c> Context<'c> {
                 for field_aliases in &case.fields {
                     for field_alias in field_aliases {
                         let alias_type = self.cache[*field_alias].typ.as_ref().unwrap().as_monotype();
-                        let field_type = self.follow_all_bindings(alias_type);
-                        self.definitions.insert((*field_alias, field_type), variant.into());
+                        let key = self.definition_key(*field_alias, alias_type);
+                        self.definitions.insert(key, variant.into());
                     }
                 }
                 // We've aliased everything this pattern was bound to and did not
@@ -202,24 +344,41 @@ impl<'c> Context<'c> {
                 // no need to return any new definitions to insert.
                 vec![]
             },
-            Some(VariantTag::True | VariantTag::False | VariantTag::Unit | VariantTag::Literal(_)) => vec![], // No fields to bind
+            Some(
+                VariantTag::True
+                | VariantTag::False
+                | VariantTag::Unit
+                | VariantTag::Literal(_)
+                | VariantTag::Range(..),
+            ) => {
+                vec![] // No fields to bind
+            },
         }
     }
 
-    fn cast_to_variant_type(&mut self, value: hir::DefinitionInfo, case: &Case) -> hir::Ast {
+    /// Unions with a NicheLayout have no tag before the fields of each variant, see `niche_layout`
+    fn cast_to_variant_type(&mut self, value: hir::DefinitionInfo, case: &Case, typ: &types::Type) -> hir::Ast {
         let value = value.into();
         match &case.tag {
             Some(VariantTag::UserDefined(id)) => {
                 let mut elems = Vec::with_capacity(case.fields.len() + 1);
 
                 let constructor = self.follow_all_bindings(self.cache[*id].typ.as_ref().unwrap().remove_forall());
-                if constructor.is_union_constructor(&self.cache) {
+                let is_union = constructor.is_union_constructor(&self.cache);
+                if is_union && self.niche_layout_of_type(typ).is_none() {
                     elems.push(Self::tag_type());
                 }
 
-                for field_aliases in &case.fields {
-                    let typ = self.cache[field_aliases[0]].typ.as_ref().unwrap().clone().into_monotype();
-                    elems.push(self.convert_type(&typ));
+                let variant_index = if is_union { self.get_tag_value(case) as usize } else { 0 };
+                let boxed_fields = self.recursive_fields(typ, variant_index);
+
+                for (field_aliases, boxed) in case.fields.iter().zip(boxed_fields) {
+                    if boxed {
+                        elems.push(hir::Type::Primitive(hir::types::PrimitiveType::Pointer));
+                    } else {
+                        let typ = self.cache[field_aliases[0]].typ.as_ref().unwrap().clone().into_monotype();
+                        elems.push(self.convert_type(&typ));
+                    }
                 }
 
                 // TODO: Add padding to cast to smaller type in case some backends need it
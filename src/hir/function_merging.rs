@@ -0,0 +1,218 @@
+//! Monomorphisation often produces several copies of a function which differ only in
+//! the types of values they never inspect. For example, a function taking a `Ptr a`
+//! compiles to the same code for each `a` since pointers are opaque in the HIR.
+//!
+//! To avoid compiling each of these copies, monomorphisation compares the canonical
+//! form of each new function against those it has already compiled, reusing the
+//! existing function if one matches. The canonical form of a function is the function
+//! with each id bound within it renumbered in the order it is defined. This way
+//! two functions compare equal if they differ only in the names of their locals.
+use std::collections::HashMap;
+
+use super::*;
+
+/// Returns the canonical form of the given function, used to find other functions identical to it
+pub fn canonicalize(lambda: &Lambda) -> Lambda {
+    let mut lambda = lambda.clone();
+    lambda.canonicalize(&mut Renamer::default());
+    lambda
+}
+
+#[derive(Default)]
+struct Renamer {
+    ids: HashMap<DefinitionId, DefinitionId>,
+}
+
+impl Renamer {
+    /// Ids bound within the function are renumbered from the top of the id space
+    /// so that they can never collide with the ids of any globals it refers to.
+    fn bind(&mut self, id: &mut DefinitionId) {
+        let new_id = DefinitionId(usize::MAX - self.ids.len());
+        self.ids.insert(*id, new_id);
+        *id = new_id;
+    }
+
+    /// Ids not bound within the function refer to globals and are left unchanged
+    fn rename(&self, id: &mut DefinitionId) {
+        if let Some(new_id) = self.ids.get(id) {
+            *id = *new_id;
+        }
+    }
+}
+
+trait Canonicalize {
+    fn canonicalize(&mut self, renamer: &mut Renamer);
+}
+
+impl Canonicalize for Ast {
+    fn canonicalize(&mut self, renamer: &mut Renamer) {
+        dispatch_on_hir!(self, Canonicalize::canonicalize, renamer)
+    }
+}
+
+impl Canonicalize for Literal {
+    fn canonicalize(&mut self, _: &mut Renamer) {}
+}
+
+impl Canonicalize for Variable {
+    fn canonicalize(&mut self, renamer: &mut Renamer) {
+        renamer.rename(&mut self.definition_id);
+    }
+}
+
+impl Canonicalize for Lambda {
+    fn canonicalize(&mut self, renamer: &mut Renamer) {
+        for (arg, _) in &mut self.args {
+            renamer.bind(&mut arg.definition_id);
+        }
+        self.body.canonicalize(renamer);
+    }
+}
+
+impl Canonicalize for FunctionCall {
+    fn canonicalize(&mut self, renamer: &mut Renamer) {
+        self.function.canonicalize(renamer);
+        for arg in &mut self.args {
+            arg.canonicalize(renamer);
+        }
+    }
+}
+
+impl Canonicalize for Definition {
+    fn canonicalize(&mut self, renamer: &mut Renamer) {
+        // Bind the variable first so that recursive references within expr are renamed as well
+        renamer.bind(&mut self.variable);
+        self.expr.canonicalize(renamer);
+    }
+}
+
+impl Canonicalize for If {
+    fn canonicalize(&mut self, renamer: &mut Renamer) {
+        self.condition.canonicalize(renamer);
+        self.then.canonicalize(renamer);
+        if let Some(otherwise) = &mut self.otherwise {
+            otherwise.canonicalize(renamer);
+        }
+    }
+}
+
+impl Canonicalize for Match {
+    fn canonicalize(&mut self, renamer: &mut Renamer) {
+        // The decision tree defines the variables used within each branch
+        self.decision_tree.canonicalize(renamer);
+        for branch in &mut self.branches {
+            branch.canonicalize(renamer);
+        }
+    }
+}
+
+impl Canonicalize for DecisionTree {
+    fn canonicalize(&mut self, renamer: &mut Renamer) {
+        match self {
+            DecisionTree::Leaf(_) => (),
+            DecisionTree::Definition(definition, rest) => {
+                definition.canonicalize(renamer);
+                rest.canonicalize(renamer);
+            },
+            DecisionTree::Switch { int_to_switch_on, cases, else_case } => {
+                int_to_switch_on.canonicalize(renamer);
+                for (_, case) in cases {
+                    case.canonicalize(renamer);
+                }
+                if let Some(else_case) = else_case {
+                    else_case.canonicalize(renamer);
+                }
+            },
+        }
+    }
+}
+
+impl Canonicalize for Return {
+    fn canonicalize(&mut self, renamer: &mut Renamer) {
+        self.expression.canonicalize(renamer);
+    }
+}
+
+impl Canonicalize for Sequence {
+    fn canonicalize(&mut self, renamer: &mut Renamer) {
+        for statement in &mut self.statements {
+            statement.canonicalize(renamer);
+        }
+    }
+}
+
+impl Canonicalize for Extern {
+    fn canonicalize(&mut self, _: &mut Renamer) {}
+}
+
+impl Canonicalize for Assignment {
+    fn canonicalize(&mut self, renamer: &mut Renamer) {
+        self.lhs.canonicalize(renamer);
+        self.rhs.canonicalize(renamer);
+    }
+}
+
+impl Canonicalize for MemberAccess {
+    fn canonicalize(&mut self, renamer: &mut Renamer) {
+        self.lhs.canonicalize(renamer);
+    }
+}
+
+impl Canonicalize for Tuple {
+    fn canonicalize(&mut self, renamer: &mut Renamer) {
+        for field in &mut self.fields {
+            field.canonicalize(renamer);
+        }
+    }
+}
+
+impl Canonicalize for ReinterpretCast {
+    fn canonicalize(&mut self, renamer: &mut Renamer) {
+        self.lhs.canonicalize(renamer);
+    }
+}
+
+impl Canonicalize for Builtin {
+    fn canonicalize(&mut self, renamer: &mut Renamer) {
+        use Builtin::*;
+        match self {
+            AddInt(a, b)
+            | AddFloat(a, b)
+            | SubInt(a, b)
+            | SubFloat(a, b)
+            | MulInt(a, b)
+            | MulFloat(a, b)
+            | DivSigned(a, b)
+            | DivUnsigned(a, b)
+            | DivFloat(a, b)
+            | ModSigned(a, b)
+            | ModUnsigned(a, b)
+            | ModFloat(a, b)
+            | LessSigned(a, b)
+            | LessUnsigned(a, b)
+            | LessFloat(a, b)
+            | EqInt(a, b)
+            | EqFloat(a, b)
+            | EqChar(a, b)
+            | EqBool(a, b)
+            | Offset(a, b, _) => {
+                a.canonicalize(renamer);
+                b.canonicalize(renamer);
+            },
+            SignExtend(a, _)
+            | ZeroExtend(a, _)
+            | SignedToFloat(a, _)
+            | UnsignedToFloat(a, _)
+            | FloatToSigned(a, _)
+            | FloatToUnsigned(a, _)
+            | Truncate(a, _)
+            | Deref(a, _)
+            | Transmute(a, _)
+            | StackAlloc(a)
+            | Assume(a)
+            | IncrementRefCount(a)
+            | DecrementRefCount(a) => a.canonicalize(renamer),
+            Unreachable(_) => (),
+        }
+    }
+}
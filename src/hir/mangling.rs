@@ -0,0 +1,20 @@
+//! Monomorphised definitions are identified by opaque `hir::DefinitionId`s which say
+//! nothing about where a compiled function came from. To make linking and debugging
+//! easier, each monomorphised global definition is also given a symbol name built from
+//! the module and name of the original definition along with the type it was
+//! instantiated with. For example, the `i32` instantiation of the prelude's `print`
+//! is named `prelude.print<(i32 -> unit)>`.
+use crate::cache::{DefinitionInfoId, ModuleCache};
+use crate::types::{self, typeprinter::TypePrinter, GeneralizedType};
+
+/// Returns the symbol name of the given definition instantiated with the given type.
+/// The same definition and type always produce the same name while any two different
+/// instantiations of a definition produce different names.
+///
+/// PRE-REQUISITE: `typ` must equal `follow_all_bindings(typ)`
+pub fn mangle(id: DefinitionInfoId, typ: &types::Type, cache: &ModuleCache) -> String {
+    let info = &cache[id];
+    let module = info.location.filename.file_stem().unwrap_or_default().to_string_lossy();
+    let typ = TypePrinter::plain(GeneralizedType::MonoType(typ.clone()), cache);
+    format!("{}.{}<{}>", module, info.name, typ)
+}
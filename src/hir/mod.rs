@@ -7,7 +7,10 @@
 //! - All trait function calls are replaced with references to the exact
 //!   function to call statically (monomorphisation) or are passed in as
 //!   arguments to calling functions (boxing).
+mod bloat;
 mod decision_tree_monomorphisation;
+mod function_merging;
+mod mangling;
 mod monomorphisation;
 mod printer;
 mod types;
@@ -21,7 +24,7 @@ use std::rc::Rc;
 #[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct DefinitionId(usize);
 
-#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Literal {
     Integer(u64, IntegerKind),
     Float(u64),
@@ -48,6 +51,22 @@ pub struct DefinitionInfo {
 
 pub type Variable = DefinitionInfo;
 
+/// Variables are compared by their id alone. The definition they refer
+/// to is already uniquely identified by its id.
+impl PartialEq for DefinitionInfo {
+    fn eq(&self, other: &Self) -> bool {
+        self.definition_id == other.definition_id
+    }
+}
+
+impl Eq for DefinitionInfo {}
+
+impl std::hash::Hash for DefinitionInfo {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.definition_id.hash(state);
+    }
+}
+
 impl From<Variable> for Ast {
     fn from(v: Variable) -> Ast {
         Ast::Variable(v)
@@ -68,7 +87,7 @@ impl DefinitionId {
 
 /// \a b. expr
 /// Function definitions are also desugared to a ast::Definition with a ast::Lambda as its body
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub struct Lambda {
     pub args: Vec<(Variable, /*mutable?*/ bool)>,
     pub body: Box<Ast>,
@@ -76,7 +95,7 @@ pub struct Lambda {
 }
 
 /// foo a b c
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub struct FunctionCall {
     pub function: Box<Ast>,
     pub args: Vec<Ast>,
@@ -86,9 +105,13 @@ pub struct FunctionCall {
 /// Unlike ast::Definition, hir::Definition
 /// is desugared of any patterns, its lhs must
 /// be a single variable to simplify backends.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub struct Definition {
     pub variable: DefinitionId,
+
+    /// The symbol name of a monomorphised global definition, used by backends when
+    /// naming the compiled function. See `mangling::mangle`. This is None for locals.
+    pub name: Option<String>,
     pub expr: Box<Ast>,
 }
 
@@ -99,7 +122,7 @@ impl From<Definition> for DefinitionInfo {
 }
 
 /// if condition then expression else expression
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub struct If {
     pub condition: Box<Ast>,
     pub then: Box<Ast>,
@@ -107,7 +130,7 @@ pub struct If {
     pub result_type: Type,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub struct Match {
     // Unlike ast::Match this only contains the parts of the
     // branch after the ->.
@@ -136,7 +159,7 @@ pub struct Match {
 // }
 // ```
 // Where two different paths need to share the same leaf branch.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub enum DecisionTree {
     Leaf(usize),
     Definition(Definition, Box<DecisionTree>),
@@ -144,7 +167,7 @@ pub enum DecisionTree {
 }
 
 /// return expression
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub struct Return {
     pub expression: Box<Ast>,
 }
@@ -153,7 +176,7 @@ pub struct Return {
 /// statement2
 /// ...
 /// statementN
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub struct Sequence {
     pub statements: Vec<Ast>,
 }
@@ -165,26 +188,26 @@ pub struct Sequence {
 ///     declaration2
 ///     ...
 ///     declarationN
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub struct Extern {
     pub name: String,
     pub typ: Type,
 }
 
 /// lhs := rhs
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub struct Assignment {
     pub lhs: Box<Ast>,
     pub rhs: Box<Ast>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub struct MemberAccess {
     pub lhs: Box<Ast>,
     pub member_index: u32,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub struct Tuple {
     pub fields: Vec<Ast>,
 }
@@ -194,13 +217,13 @@ pub struct Tuple {
 /// then lowered to this. lhs's type should be the same
 /// size as the target type, though there may be
 /// padding differences currently.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub struct ReinterpretCast {
     pub lhs: Box<Ast>,
     pub target_type: Type,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub enum Builtin {
     AddInt(Box<Ast>, Box<Ast>),
     AddFloat(Box<Ast>, Box<Ast>),
@@ -243,9 +266,22 @@ pub enum Builtin {
 
     /// Allocate space for the given value on the stack, and store it there. Return the stack address
     StackAlloc(Box<Ast>),
+
+    /// Marks the current code path as unreachable. The Type is that of the value this
+    /// expression would otherwise produce so that it may be used in any context.
+    Unreachable(Type),
+
+    /// Tells the backend it may assume the given boolean condition is true
+    Assume(Box<Ast>),
+
+    /// Increment the reference count of the given reference-counted value
+    IncrementRefCount(Box<Ast>),
+
+    /// Decrement the reference count of the given reference-counted value
+    DecrementRefCount(Box<Ast>),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub enum Ast {
     Literal(Literal),
     Variable(Variable),
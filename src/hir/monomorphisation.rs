@@ -1,15 +1,21 @@
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::{Hash, Hasher};
 use std::rc::Rc;
 
-use crate::cache::{DefinitionInfoId, DefinitionKind, ImplInfoId, ModuleCache, VariableId};
+use crate::args::Args;
+use crate::cache::{DefinitionInfoId, DefinitionKind, ExternAbi, ImplInfoId, ModuleCache, VariableId};
+use crate::error;
+use crate::error::location::{Locatable, Location};
 use crate::hir;
 use crate::nameresolution::builtin::BUILTIN_ID;
 use crate::parser::ast;
+use crate::types::traitchecker;
 use crate::types::traits::{Callsite, RequiredImpl, TraitConstraintId};
 use crate::types::typechecker::{self, TypeBindings};
 use crate::types::typed::Typed;
-use crate::types::{self, TypeInfoId, TypeVariableId};
-use crate::util::{fmap, trustme};
+use crate::types::{self, NormalizedType, TypeInfoId, TypeVariableId};
+use crate::util::{align_up, fmap, trustme};
 
 use super::types::{IntegerKind, Type};
 
@@ -18,14 +24,36 @@ const DEFAULT_INTEGER_KIND: IntegerKind = IntegerKind::I32;
 /// The type to bind most typevars to if they are still unbound when we codegen them.
 const UNBOUND_TYPE: types::Type = types::Type::Primitive(types::PrimitiveType::UnitType);
 
-/// Arbitrary recursion limit for following type variable mappings
+/// Arbitrary recursion limit for following type variable mappings.
+/// This is the default, it may be raised with `--type-recursion-limit` for large programs.
 const RECURSION_LIMIT: u32 = 500;
 
 /// Monomorphise this ast, simplifying it by removing all generics, traits,
-/// and unneeded ast constructs.
-pub fn monomorphise<'c>(ast: &ast::Ast<'c>, cache: ModuleCache<'c>) -> hir::Ast {
-    let mut context = Context::new(cache);
-    context.monomorphise(ast)
+/// and unneeded ast constructs. If `--show-bloat` is set, this also prints
+/// which generic definitions contribute the most code, and `--show-instantiations`
+/// prints the types each was instantiated at. See hir::bloat.
+pub fn monomorphise<'c>(ast: &ast::Ast<'c>, cache: ModuleCache<'c>, args: &Args) -> hir::Ast {
+    let target_pointer_size = args.target_pointer_size.unwrap_or(std::mem::size_of::<*const i8>() as u32);
+    let default_integer_kind = args.default_integer_type.unwrap_or(DEFAULT_INTEGER_KIND);
+    let recursion_limit = args.type_recursion_limit.unwrap_or(RECURSION_LIMIT);
+    let mut context = Context::new(
+        cache,
+        target_pointer_size,
+        default_integer_kind,
+        recursion_limit,
+        args.capture_by_reference_threshold,
+        args.instantiation_warning_threshold,
+        ast.locate(),
+    );
+    let hir = context.monomorphise(ast);
+    context.warn_about_excessive_instantiations();
+    if args.show_bloat {
+        print!("{}", context.bloat_report());
+    }
+    if args.show_instantiations {
+        print!("{}", context.instantiations_report());
+    }
+    hir
 }
 
 pub struct Context<'c> {
@@ -34,10 +62,24 @@ pub struct Context<'c> {
 
     /// Monomorphisation can result in what was 1 DefinitionInfoId being split into
     /// many different monomorphised variants, each represented by a unique hir::DefinitionId.
-    pub definitions: HashMap<(DefinitionInfoId, types::Type), Definition>,
+    pub definitions: DefinitionMap,
 
     types: HashMap<(types::TypeInfoId, Vec<types::Type>), Type>,
 
+    /// Caches the sizes of user-defined types, keyed by the type with all bindings followed.
+    /// Only types without any unbound type variables or inferred integer kinds are cached
+    /// since the size of those may change depending on the current monomorphisation bindings.
+    sizes: HashMap<types::Type, usize>,
+
+    /// Caches the result of `follow_all_bindings` for each type converted. As with `sizes`,
+    /// only fully bound results are cached, and the cache is cleared whenever the current
+    /// monomorphisation bindings change since they may bind the type variables within.
+    followed_types: RefCell<HashMap<types::Type, types::Type>>,
+
+    /// The number of definitions given each symbol name so far. Definitions of the same
+    /// name and type from different scopes are given a numeric suffix to keep them unique.
+    symbol_names: HashMap<String, usize>,
+
     /// Compile-time mapping of variable -> definition for impls that were resolved
     /// after type inference. This is needed for definitions that are polymorphic in
     /// the impls they may use within.
@@ -46,6 +88,58 @@ pub struct Context<'c> {
     direct_given_impl_mappings: Vec<DirectGivenImpls>,
     indirect_given_impl_mappings: Vec<IndirectGivenImpls>,
 
+    /// The extern `abort` function called when the program reaches a typed hole.
+    /// Declared lazily the first time it is needed.
+    abort_function: Option<hir::DefinitionInfo>,
+
+    /// The extern `malloc` function used to allocate recursive fields, see `is_recursive_field`.
+    /// Declared lazily the first time it is needed.
+    malloc_function: Option<hir::DefinitionInfo>,
+
+    /// The reference counted variables bound in each enclosing function or sequence.
+    /// Each is decremented once the scope it was bound in ends.
+    rc_scopes: Vec<Vec<hir::Variable>>,
+
+    /// Every local variable of a reference counted type. There is no ownership analysis
+    /// yet, so each use of one of these variables is conservatively treated as a clone
+    /// and increments its reference count.
+    rc_variables: HashSet<hir::DefinitionId>,
+
+    /// Maps the canonical form of each monomorphised global function to its definition.
+    /// A later function with the same canonical form reuses this definition rather than
+    /// being compiled separately. See hir::function_merging for details.
+    functions: HashMap<hir::Lambda, hir::DefinitionInfo>,
+
+    /// Global definitions which are still being monomorphised, along with whether they
+    /// have been referenced while monomorphising. Recursive definitions cannot be merged
+    /// since those references would be left referring to a definition that is never compiled.
+    unfinished_definitions: HashMap<hir::DefinitionId, /*referenced?*/ bool>,
+
+    /// The size of a pointer in bytes on the platform being compiled for.
+    /// Defaults to the pointer size of the host.
+    target_pointer_size: u32,
+
+    /// The kind of integer literals whose kind is never constrained by the program.
+    /// Defaults to DEFAULT_INTEGER_KIND.
+    default_integer_kind: IntegerKind,
+
+    /// The maximum number of type variable bindings followed in a row before giving up.
+    /// Defaults to RECURSION_LIMIT.
+    recursion_limit: u32,
+
+    /// Closure environments larger than this many bytes are stored behind a pointer rather
+    /// than copied into the closure itself, see `environment_is_captured_by_reference`.
+    /// Disabled unless given on the command line.
+    capture_by_reference_threshold: Option<u32>,
+
+    /// Definitions monomorphised into more variants than this are warned about
+    /// once monomorphisation finishes. Disabled when None or 0.
+    instantiation_warning_threshold: Option<u32>,
+
+    /// The location of the variable currently being monomorphised. Types carry no location
+    /// of their own so this is used to point out errors found while converting them.
+    location: Location<'c>,
+
     next_id: usize,
 }
 
@@ -81,6 +175,16 @@ impl Definition {
             },
         }
     }
+
+    fn is_function(&self) -> bool {
+        match self {
+            Definition::Normal(info) => match info.definition.as_deref() {
+                Some(hir::Ast::Definition(definition)) => matches!(definition.expr.as_ref(), hir::Ast::Lambda(_)),
+                _ => false,
+            },
+            _ => false,
+        }
+    }
 }
 
 impl From<hir::DefinitionId> for Definition {
@@ -89,16 +193,94 @@ impl From<hir::DefinitionId> for Definition {
     }
 }
 
+/// The key each monomorphised variant of a definition is cached under. Keys are compared and
+/// hashed by their normalized type so that variants differing only in the names of their unbound
+/// type variables are shared. `typ` keeps the followed type the key was made from for display.
+#[derive(Clone)]
+pub struct DefinitionKey {
+    pub id: DefinitionInfoId,
+    pub typ: types::Type,
+    normalized: NormalizedType,
+}
+
+impl PartialEq for DefinitionKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id && self.normalized == other.normalized
+    }
+}
+
+impl Eq for DefinitionKey {}
+
+impl Hash for DefinitionKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+        self.normalized.hash(state);
+    }
+}
+
+/// Maps each monomorphised variant of a definition to its `Definition` while also
+/// remembering the order keys were first inserted in. Iterating a HashMap directly
+/// would make any output derived from it differ between runs of the compiler.
+#[derive(Default)]
+pub struct DefinitionMap {
+    definitions: HashMap<DefinitionKey, Definition>,
+    order: Vec<DefinitionKey>,
+}
+
+impl DefinitionMap {
+    pub fn get(&self, key: &DefinitionKey) -> Option<&Definition> {
+        self.definitions.get(key)
+    }
+
+    /// Inserts or replaces the definition for the given key. Replacing a definition
+    /// keeps the key's original position in the iteration order.
+    pub fn insert(&mut self, key: DefinitionKey, definition: Definition) {
+        if self.definitions.insert(key.clone(), definition).is_none() {
+            self.order.push(key);
+        }
+    }
+
+    pub fn remove(&mut self, key: &DefinitionKey) {
+        if self.definitions.remove(key).is_some() {
+            self.order.retain(|existing| existing != key);
+        }
+    }
+
+    /// Iterates over each definition in the order its key was first inserted
+    pub fn iter(&self) -> impl Iterator<Item = (&DefinitionKey, &Definition)> {
+        self.order.iter().map(move |key| (key, &self.definitions[key]))
+    }
+}
+
 impl<'c> Context<'c> {
-    fn new(cache: ModuleCache) -> Context {
+    fn new(
+        cache: ModuleCache<'c>, target_pointer_size: u32, default_integer_kind: IntegerKind, recursion_limit: u32,
+        capture_by_reference_threshold: Option<u32>, instantiation_warning_threshold: Option<u32>,
+        location: Location<'c>,
+    ) -> Context<'c> {
         Context {
+            target_pointer_size,
+            default_integer_kind,
+            recursion_limit,
+            capture_by_reference_threshold,
+            instantiation_warning_threshold,
+            location,
             monomorphisation_bindings: vec![],
-            definitions: HashMap::new(),
+            definitions: DefinitionMap::default(),
             types: HashMap::new(),
+            sizes: HashMap::new(),
+            followed_types: RefCell::new(HashMap::new()),
+            symbol_names: HashMap::new(),
             direct_impl_mappings: vec![HashMap::new()],
             indirect_impl_mappings: vec![HashMap::new()],
             direct_given_impl_mappings: vec![HashMap::new()],
             indirect_given_impl_mappings: vec![HashMap::new()],
+            abort_function: None,
+            malloc_function: None,
+            rc_scopes: vec![],
+            rc_variables: HashSet::new(),
+            functions: HashMap::new(),
+            unfinished_definitions: HashMap::new(),
             next_id: 0,
             cache,
         }
@@ -115,13 +297,14 @@ impl<'c> Context<'c> {
         match ast {
             Literal(literal) => self.monomorphise_literal(literal),
             Variable(variable) => self.monomorphise_variable(variable),
-            Lambda(lambda) => self.monomorphise_lambda(lambda),
+            Lambda(lambda) => self.monomorphise_lambda(lambda, true),
             FunctionCall(call) => self.monomorphise_call(call),
             Definition(definition) => self.monomorphise_definition(definition),
             If(if_) => self.monomorphise_if(if_),
+            Guard(guard) => self.monomorphise_guard(guard),
             Match(match_) => self.monomorphise_match(match_),
             TypeDefinition(_) => unit_literal(),
-            TypeAnnotation(annotation) => self.monomorphise(&annotation.lhs),
+            TypeAnnotation(annotation) => self.monomorphise_type_annotation(annotation),
             Import(_) => unit_literal(),
             TraitDefinition(_) => unit_literal(),
             TraitImpl(_) => unit_literal(),
@@ -130,35 +313,52 @@ impl<'c> Context<'c> {
             Extern(_) => unit_literal(),
             MemberAccess(member_access) => self.monomorphise_member_access(member_access),
             Assignment(assignment) => self.monomorphise_assignment(assignment),
+            RecordSpread(spread) => self.monomorphise_record_spread(spread),
+            TypeTest(test) => self.monomorphise_type_test(test),
+            Where(where_) => self.monomorphise_where(where_),
+            Range(_) => unreachable!("Range patterns should only be compiled as part of a match's decision tree"),
+            Hole(hole) => self.monomorphise_hole(hole),
         }
     }
 
     /// Follow the bindings as far as possible.
     /// Returns a non-type variable on success.
     /// Returns the last type variable found on failure.
-    fn find_binding(&self, id: TypeVariableId, fuel: u32) -> Result<&types::Type, TypeVariableId> {
+    fn find_binding(&self, id: TypeVariableId) -> Result<&types::Type, TypeVariableId> {
         use types::Type::*;
         use types::TypeBinding::*;
 
-        if fuel == 0 {
-            panic!("Recursion limit reached in find_binding");
-        }
+        let mut current = id;
+        for _ in 0..self.recursion_limit {
+            let binding = match &self.cache.type_bindings[current.0] {
+                Bound(binding) => Some(binding),
+                Unbound(..) => self.monomorphisation_bindings.iter().rev().find_map(|bindings| bindings.get(&current)),
+            };
 
-        let fuel = fuel - 1;
-        match &self.cache.type_bindings[id.0] {
-            Bound(TypeVariable(id2) | Ref(id2)) => self.find_binding(*id2, fuel),
-            Bound(binding) => Ok(binding),
-            Unbound(..) => {
-                for bindings in self.monomorphisation_bindings.iter().rev() {
-                    match bindings.get(&id) {
-                        Some(TypeVariable(id2) | Ref(id2)) => return self.find_binding(*id2, fuel),
-                        Some(binding) => return Ok(binding),
-                        None => (),
-                    }
-                }
-                Err(id)
-            },
+            match binding {
+                Some(TypeVariable(next) | Ref(next) | Record(_, next)) => current = *next,
+                Some(binding) => return Ok(binding),
+                None => return Err(current),
+            }
         }
+
+        self.recursion_limit_reached(id);
+        Err(current)
+    }
+
+    /// Issue an error for the given type variable whose bindings could not be followed
+    /// within the recursion limit. Callers then treat it as unbound so that compilation may continue.
+    fn recursion_limit_reached(&self, id: TypeVariableId) {
+        error!(
+            self.location,
+            "Recursion limit of {} reached while following the bindings of type variable {}",
+            self.recursion_limit,
+            id.0
+        );
+        note!(
+            self.location,
+            "This may indicate an infinite type. For large programs the limit can be raised with --type-recursion-limit"
+        );
     }
 
     /// If this type is a type variable, follow what it is bound to
@@ -167,7 +367,7 @@ impl<'c> Context<'c> {
         use types::Type::*;
 
         match typ {
-            TypeVariable(id) => self.find_binding(*id, RECURSION_LIMIT),
+            TypeVariable(id) | Record(_, id) => self.find_binding(*id),
             _ => Ok(typ),
         }
     }
@@ -175,65 +375,104 @@ impl<'c> Context<'c> {
     /// Recursively follow all type variables in this type such that all Bound
     /// type variables are replaced with whatever they are bound to.
     pub fn follow_all_bindings<'a>(&'a self, typ: &'a types::Type) -> types::Type {
-        self.follow_all_bindings_inner(typ, RECURSION_LIMIT)
+        if let Some(followed) = self.followed_types.borrow().get(typ) {
+            return followed.clone();
+        }
+
+        let followed = self.follow_all_bindings_inner(typ, self.recursion_limit, None);
+        if is_fully_bound(&followed) {
+            self.followed_types.borrow_mut().insert(typ.clone(), followed.clone());
+        }
+        followed
     }
 
-    fn follow_all_bindings_inner<'a>(&'a self, typ: &'a types::Type, fuel: u32) -> types::Type {
+    /// `origin` is the outermost type variable whose binding `typ` is within, if any.
+    /// It is reported if the recursion limit is reached, e.g. by an infinite type.
+    fn follow_all_bindings_inner<'a>(
+        &'a self, typ: &'a types::Type, fuel: u32, origin: Option<TypeVariableId>,
+    ) -> types::Type {
         use types::Type::*;
 
-        if fuel == 0 {
-            panic!("Recursion limit reached in convert_type");
-        }
-
-        let fuel = fuel - 1;
+        // Only following a type variable can recur indefinitely, so this is the only case checked
+        let fuel = fuel.saturating_sub(1);
         match typ {
-            TypeVariable(id) => match self.find_binding(*id, fuel) {
-                Ok(binding) => self.follow_all_bindings_inner(binding, fuel),
+            TypeVariable(id) if fuel == 0 => {
+                self.recursion_limit_reached(origin.unwrap_or(*id));
+                typ.clone()
+            },
+            TypeVariable(id) => match self.find_binding(*id) {
+                Ok(binding) => self.follow_all_bindings_inner(binding, fuel, origin.or(Some(*id))),
                 Err(id) => TypeVariable(id),
             },
             Primitive(_) => typ.clone(),
             Function(f) => {
                 let f = types::FunctionType {
-                    parameters: fmap(&f.parameters, |param| self.follow_all_bindings_inner(param, fuel)),
-                    return_type: Box::new(self.follow_all_bindings_inner(&f.return_type, fuel)),
-                    environment: Box::new(self.follow_all_bindings_inner(&f.environment, fuel)),
+                    parameters: fmap(&f.parameters, |param| self.follow_all_bindings_inner(param, fuel, origin)),
+                    return_type: Box::new(self.follow_all_bindings_inner(&f.return_type, fuel, origin)),
+                    environment: Box::new(self.follow_all_bindings_inner(&f.environment, fuel, origin)),
                     is_varargs: f.is_varargs,
+                    varargs_type: f
+                        .varargs_type
+                        .as_ref()
+                        .map(|typ| Box::new(self.follow_all_bindings_inner(typ, fuel, origin))),
                 };
                 Function(f)
             },
             UserDefined(_) => typ.clone(),
             TypeApplication(con, args) => {
-                let con = self.follow_all_bindings_inner(con, fuel);
-                let args = fmap(args, |arg| self.follow_all_bindings_inner(arg, fuel));
+                let con = self.follow_all_bindings_inner(con, fuel, origin);
+                let args = fmap(args, |arg| self.follow_all_bindings_inner(arg, fuel, origin));
                 TypeApplication(Box::new(con), args)
             },
             Ref(_) => typ.clone(),
+            Forall(variables, typ) => {
+                Forall(variables.clone(), Box::new(self.follow_all_bindings_inner(typ, fuel, origin)))
+            },
+            // A record is replaced with the struct its row variable is bound to
+            Record(_, row) => match self.find_binding(*row) {
+                Ok(binding) => self.follow_all_bindings_inner(binding, fuel, origin.or(Some(*row))),
+                Err(id) => TypeVariable(id),
+            },
         }
     }
 
-    fn size_of_struct_type(&mut self, info: &types::TypeInfo, fields: &[types::Field], args: &[types::Type]) -> usize {
-        let bindings = typechecker::type_application_bindings(info, args);
+    fn size_of_struct_type(&mut self, fields: Vec<types::Type>) -> usize {
+        let field_types: Vec<_> = fields.into_iter().filter(|field| !self.is_erased_field(field)).collect();
+        self.size_of_fields(&field_types)
+    }
 
-        fields
-            .iter()
-            .map(|field| {
-                let field_type = typechecker::bind_typevars(&field.field_type, &bindings, &self.cache);
-                self.size_of_type(&field_type)
-            })
-            .sum()
+    /// Returns the size of a struct with the given field types. Each field is placed at the
+    /// next offset that is a multiple of its alignment, and the struct as a whole is padded
+    /// to a multiple of the largest alignment of any of its fields.
+    fn size_of_fields(&mut self, fields: &[types::Type]) -> usize {
+        let mut size = 0;
+        let mut alignment = 1;
+
+        for field in fields {
+            let field_alignment = self.align_of_type(field);
+            size = align_up(size, field_alignment) + self.size_of_type(field);
+            alignment = alignment.max(field_alignment);
+        }
+
+        align_up(size, alignment)
     }
 
-    fn size_of_union_type(
-        &mut self, info: &types::TypeInfo, variants: &[types::TypeConstructor<'c>], args: &[types::Type],
-    ) -> usize {
-        let bindings = typechecker::type_application_bindings(info, args);
+    /// Returns the size of a union variant with the given field types, including its u8 tag
+    fn size_of_union_variant(&mut self, variant: &[types::Type]) -> usize {
+        let tag = types::Type::Primitive(types::PrimitiveType::IntegerType(crate::lexer::token::IntegerKind::U8));
+        let fields: Vec<_> = std::iter::once(tag).chain(variant.iter().cloned()).collect();
+        self.size_of_fields(&fields)
+    }
+
+    fn size_of_union_type(&mut self, variants: &[Vec<types::Type>]) -> usize {
+        if let Some(niche) = self.niche_layout(variants) {
+            return self.size_of_fields(&variants[niche.data_tag as usize]);
+        }
 
-        match self.find_largest_union_variant(variants, &bindings) {
+        match self.find_largest_union_variant(variants) {
             None => 0, // Void type
-            Some(variant) => {
-                // The size of a union is the size of its largest field, plus 1 byte for the tag
-                variant.iter().map(|field| self.size_of_type(field)).sum::<usize>() + 1
-            },
+            // The size of a union is the size of its largest variant laid out after the tag
+            Some(variant) => self.size_of_union_variant(&variant),
         }
     }
 
@@ -241,26 +480,17 @@ impl<'c> Context<'c> {
         let info = &self.cache[id];
         assert!(info.args.len() == args.len(), "Kind error during llvm code generation");
 
-        use types::TypeInfoBody::*;
-        match &info.body {
-            // TODO: Need to split out self.types and self.cache parameters to be able to remove this
-            Union(variants) => trustme::make_mut_ref(self).size_of_union_type(info, variants, args),
-            Struct(fields) => trustme::make_mut_ref(self).size_of_struct_type(info, fields, args),
-
-            // Aliases should be desugared prior to codegen
-            Alias(_) => unreachable!(),
-            Unknown => unreachable!(),
+        let mut variants = self.variant_layouts(id, args);
+        if info.union_variants().is_some() {
+            self.size_of_union_type(&variants)
+        } else {
+            self.size_of_struct_type(variants.remove(0))
         }
     }
 
-    /// TODO: Adjust based on target architecture
-    fn ptr_size() -> usize {
-        std::mem::size_of::<*const i8>()
-    }
-
     /// Returns the size in bits of this integer.
     ///
-    /// Will bind the integer to an i32 if this integer is an IntegerKind::Inferred
+    /// Will use the default integer kind if this integer is an IntegerKind::Inferred
     /// that has not already been bound to a concrete type.
     fn integer_bit_count(&mut self, kind: crate::lexer::token::IntegerKind) -> u32 {
         use IntegerKind::*;
@@ -269,11 +499,32 @@ impl<'c> Context<'c> {
             I16 | U16 => 16,
             I32 | U32 => 32,
             I64 | U64 => 64,
-            Isz | Usz => Self::ptr_size() as u32 * 8,
+            Isz | Usz => self.target_pointer_size * 8,
         }
     }
 
     fn size_of_type(&mut self, typ: &types::Type) -> usize {
+        use types::Type::*;
+        match typ {
+            UserDefined(_) | TypeApplication(..) => {
+                let typ = self.follow_all_bindings(typ);
+                if let Some(size) = self.sizes.get(&typ) {
+                    return *size;
+                }
+
+                // The size is only cached once it is fully computed. This never observes a partially
+                // computed size since recursive types must recur through a pointer, whose size is known.
+                let size = self.size_of_type_uncached(&typ);
+                if is_fully_bound(&typ) {
+                    self.sizes.insert(typ, size);
+                }
+                size
+            },
+            _ => self.size_of_type_uncached(typ),
+        }
+    }
+
+    fn size_of_type_uncached(&mut self, typ: &types::Type) -> usize {
         use types::PrimitiveType::*;
         use types::Type::*;
         match typ {
@@ -282,12 +533,12 @@ impl<'c> Context<'c> {
             Primitive(CharType) => 1,
             Primitive(BooleanType) => 1,
             Primitive(UnitType) => 1,
-            Primitive(Ptr) => Self::ptr_size(),
+            Primitive(Ptr) => self.target_pointer_size as usize,
 
-            Function(..) => Self::ptr_size(),
+            Function(..) | Forall(..) => self.target_pointer_size as usize,
 
-            TypeVariable(id) => {
-                let binding = self.find_binding(*id, RECURSION_LIMIT).unwrap_or(&UNBOUND_TYPE).clone();
+            TypeVariable(id) | Record(_, id) => {
+                let binding = self.find_binding(*id).unwrap_or(&UNBOUND_TYPE).clone();
                 self.size_of_type(&binding)
             },
 
@@ -295,10 +546,66 @@ impl<'c> Context<'c> {
 
             TypeApplication(typ, args) => match typ.as_ref() {
                 UserDefined(id) => self.size_of_user_defined_type(*id, args),
+                Primitive(Ptr) | Ref(_) => self.target_pointer_size as usize,
                 _ => unreachable!("Kind error inside size_of_type"),
             },
 
-            Ref(_) => Self::ptr_size(),
+            Ref(_) => self.target_pointer_size as usize,
+        }
+    }
+
+    /// Returns the alignment of the given type in bytes. This is
+    /// the largest alignment of any of the type's fields.
+    fn align_of_type(&mut self, typ: &types::Type) -> usize {
+        use types::Type::*;
+        match typ {
+            Primitive(_) => self.size_of_type(typ),
+            Function(..) | Forall(..) | Ref(_) => self.target_pointer_size as usize,
+
+            TypeVariable(id) | Record(_, id) => {
+                let binding = self.find_binding(*id).unwrap_or(&UNBOUND_TYPE).clone();
+                self.align_of_type(&binding)
+            },
+
+            UserDefined(id) => self.align_of_user_defined_type(*id, &[]),
+
+            TypeApplication(typ, args) => match typ.as_ref() {
+                UserDefined(id) => self.align_of_user_defined_type(*id, args),
+                Primitive(types::PrimitiveType::Ptr) | Ref(_) => self.target_pointer_size as usize,
+                _ => unreachable!("Kind error inside align_of_type"),
+            },
+        }
+    }
+
+    fn align_of_user_defined_type(&mut self, id: TypeInfoId, args: &[types::Type]) -> usize {
+        let mut variants = self.variant_layouts(id, args);
+
+        let field_types = if self.cache[id].union_variants().is_some() {
+            // Only the largest variant of a union is stored, and its u8 tag
+            // never raises its alignment above that of the variant's fields
+            self.find_largest_union_variant(&variants).unwrap_or_default()
+        } else {
+            variants.remove(0)
+        };
+
+        field_types.iter().map(|typ| self.align_of_type(typ)).max().unwrap_or(1)
+    }
+
+    /// Returns the name of the prelude's `TypeKind` variant for the given type along
+    /// with its number of fields if it is a struct, or variants if it is a union.
+    fn kind_of_type(&self, typ: &types::Type) -> (&'static str, usize) {
+        use types::Type::*;
+        match self.follow_bindings_shallow(typ) {
+            Ok(Primitive(types::PrimitiveType::Ptr)) | Ok(Ref(_)) => ("PointerKind", 0),
+            Ok(Primitive(_)) => ("PrimitiveKind", 0),
+            Ok(Function(..)) | Ok(Forall(..)) => ("FunctionKind", 0),
+            Ok(UserDefined(id)) => match &self.cache[*id].body {
+                types::TypeInfoBody::Struct(fields) => ("StructKind", fields.len()),
+                types::TypeInfoBody::Union(variants) => ("UnionKind", variants.len()),
+                _ => unreachable!("Aliases should be desugared prior to monomorphisation"),
+            },
+            Ok(TypeApplication(typ, _)) => self.kind_of_type(typ),
+            Ok(TypeVariable(_) | Record(..)) | Err(_) => self.kind_of_type(&UNBOUND_TYPE),
         }
     }
 
@@ -317,34 +624,146 @@ impl<'c> Context<'c> {
         })
     }
 
-    fn convert_struct_type(
-        &mut self, id: TypeInfoId, info: &types::TypeInfo, fields: &[types::Field<'c>], args: Vec<types::Type>,
-    ) -> Type {
-        let bindings = typechecker::type_application_bindings(info, &args);
-
-        let t = Type::Tuple(vec![]);
-        self.types.insert((id, args.clone()), t);
+    fn convert_struct_type(&mut self, id: TypeInfoId, args: Vec<types::Type>) -> Type {
+        let fields = self.variant_layouts(id, &args).remove(0);
+        let fields: Vec<_> = fields.into_iter().filter(|field_type| !self.is_erased_field(field_type)).collect();
 
-        let fields = fmap(fields, |field| {
-            let field_type = typechecker::bind_typevars(&field.field_type, &bindings, &self.cache);
-            self.convert_type(&field_type)
-        });
-
-        let t = Type::Tuple(fields);
+        let t = Type::Tuple(fmap(fields, |field_type| self.convert_type(&field_type)));
         self.types.insert((id, args), t.clone());
         t
     }
 
-    /// Given a list of TypeConstructors representing each variant of a sum type,
-    /// find the largest variant in memory (with the given type bindings for any type variables)
-    /// and return its field types.
-    fn find_largest_union_variant(
-        &mut self, variants: &[types::TypeConstructor<'c>], bindings: &TypeBindings,
-    ) -> Option<Vec<types::Type>> {
-        let variants: Vec<Vec<types::Type>> =
-            fmap(variants, |variant| fmap(&variant.args, |arg| typechecker::bind_typevars(arg, bindings, &self.cache)));
+    /// Struct fields which carry no information, such as those of type unit, are
+    /// left out of the struct entirely once it is monomorphised.
+    fn is_erased_field(&mut self, typ: &types::Type) -> bool {
+        self.follow_all_bindings(typ).is_unit(&self.cache) || self.size_of_type(typ) == 0
+    }
+
+    /// Returns the index of each field of the given struct type within its monomorphised tuple,
+    /// or None for fields which are erased. Returns None if the given type is not a struct.
+    pub fn struct_field_indices(&mut self, typ: &types::Type) -> Option<Vec<Option<u32>>> {
+        let (id, args) = self.user_defined_type_parts(typ)?;
+        if self.cache[id].union_variants().is_some() {
+            return None;
+        }
+
+        let fields = self.variant_layouts(id, &args).remove(0);
+        Some(self.field_indices(&fields))
+    }
+
+    /// Returns the index of each of the given struct fields within the struct's
+    /// monomorphised tuple, or None for fields which are erased.
+    fn field_indices(&mut self, fields: &[types::Type]) -> Vec<Option<u32>> {
+        let mut next_index = 0;
+        fmap(fields, |field| {
+            if self.is_erased_field(field) {
+                None
+            } else {
+                next_index += 1;
+                Some(next_index - 1)
+            }
+        })
+    }
+
+    /// Given the field types of each variant of a sum type, find the
+    /// largest variant in memory and return its field types.
+    fn find_largest_union_variant(&mut self, variants: &[Vec<types::Type>]) -> Option<Vec<types::Type>> {
+        variants.iter().max_by_key(|variant| self.size_of_union_variant(variant)).cloned()
+    }
+
+    /// Returns the TypeInfoId and type arguments of the given type if it is a user-defined type
+    fn user_defined_type_parts(&self, typ: &types::Type) -> Option<(TypeInfoId, Vec<types::Type>)> {
+        match self.follow_all_bindings(typ) {
+            types::Type::UserDefined(id) => Some((id, vec![])),
+            types::Type::TypeApplication(constructor, args) => match constructor.as_ref() {
+                types::Type::UserDefined(id) => Some((*id, args)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Returns the field types of each variant of the given user-defined type with its
+    /// type variables bound to the given arguments. A struct has a single variant.
+    fn variants_of_user_defined_type(&self, id: TypeInfoId, args: &[types::Type]) -> Vec<Vec<types::Type>> {
+        let info = &self.cache[id];
+        let bindings = typechecker::type_application_bindings(info, args);
+        let bind = |typ| typechecker::bind_typevars(typ, &bindings, &self.cache);
+
+        use types::TypeInfoBody::*;
+        match &info.body {
+            Struct(fields) => vec![fmap(fields, |field| bind(&field.field_type))],
+            Union(variants) => fmap(variants, |variant| fmap(&variant.args, &bind)),
+
+            // Aliases should be desugared prior to codegen
+            Alias(_) => unreachable!(),
+            Unknown => unreachable!(),
+        }
+    }
+
+    /// Returns the field types each variant of the given user-defined type is stored as.
+    /// These are the field types of each variant, except that recursive fields are boxed.
+    fn variant_layouts(&self, id: TypeInfoId, args: &[types::Type]) -> Vec<Vec<types::Type>> {
+        let variants = self.variants_of_user_defined_type(id, args);
+        fmap(variants, |fields| {
+            fmap(fields, |field| {
+                if self.is_recursive_field(&field, id, args) {
+                    types::Type::TypeApplication(
+                        Box::new(types::Type::Primitive(types::PrimitiveType::Ptr)),
+                        vec![field],
+                    )
+                } else {
+                    field
+                }
+            })
+        })
+    }
+
+    /// A field is recursive if it stores the type it is a field of, either directly or within
+    /// the fields of another type. Storing such a field inline would give its type an infinite
+    /// size, so recursive fields are instead stored behind a pointer to a heap allocation.
+    fn is_recursive_field(&self, field: &types::Type, id: TypeInfoId, args: &[types::Type]) -> bool {
+        let target = (id, fmap(args, |arg| self.follow_all_bindings(arg)));
+        self.stores_type(field, &target, &mut HashSet::new())
+    }
+
+    /// True if a value of the given type stores a value of the target type inline.
+    /// `visited` holds the types that have already been searched.
+    ///
+    /// A type like `type Nested a = | Flat a | Nest (Nested (a, a))` stores a different type
+    /// at each level so this search would never end. Types are conservatively assumed to store
+    /// the target type once RECURSION_LIMIT types have been searched, boxing the field instead.
+    fn stores_type(
+        &self, typ: &types::Type, target: &(TypeInfoId, Vec<types::Type>),
+        visited: &mut HashSet<(TypeInfoId, Vec<types::Type>)>,
+    ) -> bool {
+        let key = match self.user_defined_type_parts(typ) {
+            Some(key) => key,
+            None => return false,
+        };
+
+        if key == *target || visited.len() >= RECURSION_LIMIT as usize {
+            return true;
+        }
+
+        if !visited.insert(key.clone()) {
+            return false;
+        }
+
+        let variants = self.variants_of_user_defined_type(key.0, &key.1);
+        variants.iter().flatten().any(|field| self.stores_type(field, target, visited))
+    }
 
-        variants.into_iter().max_by_key(|variant| variant.iter().map(|arg| self.size_of_type(arg)).sum::<usize>())
+    /// Returns whether each field of the given variant of a user-defined type is boxed,
+    /// see `is_recursive_field`. Structs only have a single variant, numbered 0.
+    pub fn recursive_fields(&self, typ: &types::Type, variant: usize) -> Vec<bool> {
+        match self.user_defined_type_parts(typ) {
+            Some((id, args)) => {
+                let variants = self.variants_of_user_defined_type(id, &args);
+                fmap(&variants[variant], |field| self.is_recursive_field(field, id, &args))
+            },
+            None => vec![],
+        }
     }
 
     /// Returns the type of a tag in an unoptimized tagged union
@@ -352,22 +771,104 @@ impl<'c> Context<'c> {
         Type::Primitive(hir::types::PrimitiveType::Integer(IntegerKind::U8))
     }
 
-    fn convert_union_type(
-        &mut self, id: TypeInfoId, info: &types::TypeInfo, variants: &[types::TypeConstructor<'c>],
-        args: Vec<types::Type>,
-    ) -> Type {
-        let bindings = typechecker::type_application_bindings(info, &args);
+    /// Returns the NicheLayout of the given union, if it has one.
+    ///
+    /// A union has a NicheLayout if it has exactly two variants, only one of which carries
+    /// data, and that data contains a reference. References are never null, so the variant
+    /// without data can be represented by a null reference instead of needing a separate tag.
+    pub fn niche_layout(&mut self, variants: &[Vec<types::Type>]) -> Option<NicheLayout> {
+        if variants.len() != 2 {
+            return None;
+        }
 
-        let mut t = Type::Tuple(vec![]);
+        let (data_tag, empty_tag) = match (variants[0].is_empty(), variants[1].is_empty()) {
+            (false, true) => (0, 1),
+            (true, false) => (1, 0),
+            _ => return None,
+        };
 
-        if let Some(variant) = self.find_largest_union_variant(variants, &bindings) {
-            self.types.insert((id, args.clone()), t);
+        let reference_path = self.find_reference(&variants[data_tag as usize])?;
+        Some(NicheLayout { data_tag, empty_tag, reference_path })
+    }
 
-            let mut fields = vec![Self::tag_type()];
-            for typ in variant {
-                fields.push(self.convert_type(&typ));
+    /// Returns the NicheLayout of the given type if it is a union with one, see `niche_layout`
+    pub fn niche_layout_of_type(&mut self, typ: &types::Type) -> Option<NicheLayout> {
+        let (id, args) = self.user_defined_type_parts(typ)?;
+        self.cache[id].union_variants()?;
+
+        let variants = self.variant_layouts(id, &args);
+        self.niche_layout(&variants)
+    }
+
+    /// Returns the field indices leading to the first reference within the given fields,
+    /// searching within the fields of any structs as well.
+    fn find_reference(&mut self, fields: &[types::Type]) -> Option<Vec<u32>> {
+        for (i, field) in fields.iter().enumerate() {
+            let path = match self.follow_all_bindings(field) {
+                types::Type::UserDefined(id) => self.find_reference_in_struct(id, &[]),
+                types::Type::TypeApplication(constructor, args) => match constructor.as_ref() {
+                    types::Type::Ref(_) => Some(vec![]),
+                    types::Type::UserDefined(id) => self.find_reference_in_struct(*id, &args),
+                    _ => None,
+                },
+                _ => None,
+            };
+
+            if let Some(mut path) = path {
+                path.insert(0, i as u32);
+                return Some(path);
             }
+        }
+        None
+    }
+
+    fn find_reference_in_struct(&mut self, id: TypeInfoId, args: &[types::Type]) -> Option<Vec<u32>> {
+        if self.cache[id].union_variants().is_some() {
+            return None;
+        }
+
+        let fields = self.variant_layouts(id, args).remove(0);
+        let mut path = self.find_reference(&fields)?;
+
+        // The path indexes the struct's full field list but erased fields are left out of its tuple
+        path[0] = self.field_indices(&fields)[path[0] as usize].expect("References are never erased");
+        Some(path)
+    }
+
+    /// Returns the tag of a union value with the given NicheLayout: the tag of
+    /// the variant without data if its reference is null, or the other tag otherwise.
+    pub fn niche_tag(&self, value: hir::Ast, niche: &NicheLayout) -> hir::Ast {
+        let reference = niche.reference_path.iter().fold(value, |value, index| self.extract(value, *index));
+        let address = hir::Ast::Builtin(hir::Builtin::Transmute(
+            Box::new(reference),
+            Type::Primitive(hir::types::PrimitiveType::Integer(IntegerKind::Usz)),
+        ));
+        let is_null = hir::Builtin::EqInt(Box::new(address), Box::new(int_literal(0, IntegerKind::Usz)));
+
+        hir::Ast::If(hir::If {
+            condition: Box::new(hir::Ast::Builtin(is_null)),
+            then: Box::new(tag_value(niche.empty_tag)),
+            otherwise: Some(Box::new(tag_value(niche.data_tag))),
+            result_type: Self::tag_type(),
+        })
+    }
+
+    fn convert_union_type(&mut self, id: TypeInfoId, args: Vec<types::Type>) -> Type {
+        let variants = self.variant_layouts(id, &args);
+
+        let mut t = Type::Tuple(vec![]);
 
+        if let Some(niche) = self.niche_layout(&variants) {
+            let fields = &variants[niche.data_tag as usize];
+            t = Type::Tuple(fmap(fields, |field| self.convert_type(field)));
+        } else if let Some(variant) = self.find_largest_union_variant(&variants) {
+            // The fields of a smaller variant may be placed within the padding of the largest one,
+            // so the bytes after the tag are stored as integers covering the whole union instead.
+            let size = self.size_of_union_variant(&variant) as u32;
+            let alignment = self.align_of_user_defined_type(id, &args) as u32;
+
+            let mut fields = vec![Self::tag_type()];
+            fields.extend(union_padding(1, size, alignment));
             t = Type::Tuple(fields);
         }
 
@@ -386,8 +887,8 @@ impl<'c> Context<'c> {
         use types::TypeInfoBody::*;
         let typ = match &info.body {
             // TODO: Need to split out self.types and self.cache parameters to be able to remove this
-            Union(variants) => trustme::make_mut_ref(self).convert_union_type(id, info, variants, args),
-            Struct(fields) => trustme::make_mut_ref(self).convert_struct_type(id, info, fields, args),
+            Union(_) => trustme::make_mut_ref(self).convert_union_type(id, args),
+            Struct(_) => trustme::make_mut_ref(self).convert_struct_type(id, args),
 
             // Aliases should be desugared prior to codegen
             Alias(_) => unreachable!(),
@@ -401,21 +902,61 @@ impl<'c> Context<'c> {
         self.follow_bindings_shallow(environment).map_or(false, |env| env.is_unit(&self.cache))
     }
 
+    /// True if a closure environment of the given type is stored behind a pointer
+    /// rather than by value. This depends only on the type so that each closure
+    /// and each call to it agree on the layout of the environment.
+    fn environment_is_captured_by_reference(&self, environment: &Type) -> bool {
+        self.capture_by_reference_threshold
+            .is_some_and(|threshold| self.size_of_monomorphised_type(environment) > threshold)
+    }
+
+    /// Issue a warning for each definition that was monomorphised into more variants than
+    /// `instantiation_warning_threshold`. This is called once after monomorphisation
+    /// finishes so each definition is warned about at most once.
+    fn warn_about_excessive_instantiations(&self) {
+        let threshold = match self.instantiation_warning_threshold {
+            Some(threshold) if threshold != 0 => threshold as usize,
+            _ => return,
+        };
+
+        let mut counts: BTreeMap<DefinitionInfoId, usize> = BTreeMap::new();
+        for (key, _) in self.definitions.iter() {
+            // Parameters and pattern variables are instantiated along with their function
+            if matches!(self.cache[key.id].definition, Some(DefinitionKind::Definition(_))) {
+                *counts.entry(key.id).or_default() += 1;
+            }
+        }
+
+        for (id, count) in counts {
+            if count > threshold {
+                let info = &self.cache[id];
+                warning!(
+                    info.location,
+                    "{} was monomorphised into {} variants, more than the threshold of {}",
+                    info.name,
+                    count,
+                    threshold
+                );
+            }
+        }
+    }
+
     /// Monomorphise a types::Type into a hir::Type with no generics.
     pub fn convert_type(&mut self, typ: &types::Type) -> Type {
-        self.convert_type_inner(typ, RECURSION_LIMIT)
+        self.convert_type_inner(typ, self.recursion_limit)
     }
 
     pub fn convert_type_inner(&mut self, typ: &types::Type, fuel: u32) -> Type {
         use types::PrimitiveType::Ptr;
         use types::Type::*;
 
-        if fuel == 0 {
-            panic!("Recursion limit reached in convert_type");
-        }
-
-        let fuel = fuel - 1;
+        let fuel = fuel.saturating_sub(1);
         match typ {
+            TypeVariable(id) if fuel == 0 => {
+                self.recursion_limit_reached(*id);
+                self.convert_type_inner(&UNBOUND_TYPE, fuel)
+            },
+
             Primitive(primitive) => self.convert_primitive_type(primitive),
 
             Function(function) => {
@@ -424,7 +965,10 @@ impl<'c> Context<'c> {
                 let return_type = Box::new(self.convert_type_inner(&function.return_type, fuel));
 
                 let environment = (!self.empty_closure_environment(&function.environment)).then(|| {
-                    let environment_parameter = self.convert_type_inner(&function.environment, fuel);
+                    let mut environment_parameter = self.convert_type_inner(&function.environment, fuel);
+                    if self.environment_is_captured_by_reference(&environment_parameter) {
+                        environment_parameter = Type::Primitive(hir::PrimitiveType::Pointer);
+                    }
                     parameters.push(environment_parameter.clone());
                     environment_parameter
                 });
@@ -441,7 +985,7 @@ impl<'c> Context<'c> {
                 }
             },
 
-            TypeVariable(id) => match self.find_binding(*id, fuel) {
+            TypeVariable(id) | Record(_, id) => match self.find_binding(*id) {
                 Ok(binding) => {
                     let binding = binding.clone();
                     self.convert_type_inner(&binding, fuel)
@@ -479,20 +1023,28 @@ impl<'c> Context<'c> {
                     "Kind error during monomorphisation. Attempted to translate a `ref` without a type argument"
                 )
             },
+
+            // The error for this is issued by `check_no_forall_types` at the use of the function
+            // with this type, so compilation stops before codegen ever sees the converted type.
+            Forall(_, typ) => self.convert_type_inner(typ, fuel),
         }
     }
 
     fn convert_integer_kind(&self, kind: crate::lexer::token::IntegerKind) -> IntegerKind {
         use crate::lexer::token::IntegerKind;
         match kind {
-            IntegerKind::Unknown => DEFAULT_INTEGER_KIND,
+            IntegerKind::Unknown => self.default_integer_kind,
             IntegerKind::Inferred(id) => {
                 use types::PrimitiveType;
                 use types::Type::*;
 
-                match self.find_binding(id, RECURSION_LIMIT) {
+                match self.find_binding(id) {
                     Ok(Primitive(PrimitiveType::IntegerType(kind))) => self.convert_integer_kind(*kind),
-                    Err(_) => DEFAULT_INTEGER_KIND,
+                    // Prefer the default of the definition this literal is in
+                    Err(_) => match self.cache.integer_literal_defaults.get(&id) {
+                        Some(default) => self.convert_integer_kind(*default),
+                        None => self.default_integer_kind,
+                    },
                     Ok(other) => {
                         unreachable!("convert_integer_kind called with non-integer type {}", other.display(&self.cache))
                     },
@@ -511,13 +1063,33 @@ impl<'c> Context<'c> {
         }
     }
 
+    /// Integer literals used where a float is expected are compiled as float literals
+    fn is_float_literal(&self, kind: crate::lexer::token::IntegerKind) -> bool {
+        use crate::lexer::token::IntegerKind::Inferred;
+        use types::{PrimitiveType::FloatType, Type::Primitive};
+        match kind {
+            Inferred(id) => matches!(self.find_binding(id), Ok(Primitive(FloatType))),
+            _ => false,
+        }
+    }
+
     fn monomorphise_literal(&mut self, literal: &ast::Literal) -> hir::Ast {
         use hir::Ast::*;
         use hir::Literal::*;
 
         match &literal.kind {
             ast::LiteralKind::Integer(n, kind) => {
+                if self.is_float_literal(*kind) {
+                    let value = *n as i64 as f64;
+                    return Literal(Float(value.to_bits()));
+                }
+
+                // Literals with a fixed-size suffix were already checked during type checking
+                let needs_check = kind.can_represent(*n).is_none();
                 let kind = self.convert_integer_kind(*kind);
+                if needs_check && !kind.can_represent(*n, self.target_pointer_size * 8) {
+                    error!(literal.location, "This integer literal does not fit in type {}", kind);
+                }
                 Literal(Integer(*n, kind))
             },
             ast::LiteralKind::Float(f) => Literal(Float(*f)),
@@ -550,7 +1122,7 @@ impl<'c> Context<'c> {
         for required_impl in required_impls {
             match required_impl.callsite {
                 Callsite::Direct(callsite) => {
-                    let binding = self.cache.find_method_in_impl(callsite, required_impl.binding);
+                    let binding = find_method_in_impl(&self.cache, callsite, required_impl.binding, new_indirect);
                     new_direct.insert(callsite, binding);
                 },
                 Callsite::Indirect(callsite, id) => {
@@ -596,14 +1168,85 @@ impl<'c> Context<'c> {
         let definition_id = self.get_definition_id(variable);
 
         let typ = variable.typ.as_ref().unwrap();
+        if !self.check_no_forall_types(typ, variable.location) {
+            return unit_literal();
+        }
+
+        let outer_location = std::mem::replace(&mut self.location, variable.location);
         let definition = self.monomorphise_definition_id(definition_id, id, typ, &variable.instantiation_mapping);
+        let reference = definition.reference(self, typ);
+        self.location = outer_location;
+
+        match reference {
+            hir::Ast::Variable(variable) if self.rc_variables.contains(&variable.definition_id) => {
+                let increment = hir::Builtin::IncrementRefCount(Box::new(variable.clone().into()));
+                let statements = vec![hir::Ast::Builtin(increment), variable.into()];
+                hir::Ast::Sequence(hir::Sequence { statements })
+            },
+            value => value,
+        }
+    }
+
+    /// Functions with polymorphic (`forall`) parameters cannot be compiled yet since each use of
+    /// such a parameter may be at a different type. Issues an error and returns false if the
+    /// given type contains a `forall` type.
+    fn check_no_forall_types(&self, typ: &types::Type, location: Location<'c>) -> bool {
+        if contains_forall(&self.follow_all_bindings(typ)) {
+            error!(location, "Compiling functions with polymorphic (forall) parameters is not yet supported");
+            false
+        } else {
+            true
+        }
+    }
 
-        definition.reference(self, typ)
+    fn is_reference_counted(&mut self, typ: &types::Type, variable: &ast::Variable<'c>) -> bool {
+        let (callsite, scope) = (variable.id.unwrap(), variable.impl_scope.unwrap());
+        traitchecker::is_reference_counted(typ, callsite, scope, &mut self.cache)
+    }
+
+    /// Wraps the given body of a scope such that each reference counted variable bound in the
+    /// scope is decremented after the body is evaluated. The value of the body is preserved.
+    fn decrement_at_scope_end(&mut self, body: hir::Ast, variables: Vec<hir::Variable>) -> hir::Ast {
+        if variables.is_empty() {
+            return body;
+        }
+
+        let (result, result_id) = self.fresh_definition(body);
+        let mut statements = vec![result];
+
+        for variable in variables.into_iter().rev() {
+            let decrement = hir::Builtin::DecrementRefCount(Box::new(variable.into()));
+            statements.push(hir::Ast::Builtin(decrement));
+        }
+
+        statements.push(hir::Ast::Variable(result_id.into()));
+        hir::Ast::Sequence(hir::Sequence { statements })
     }
 
     pub fn lookup_definition(&self, id: DefinitionInfoId, typ: &types::Type) -> Option<Definition> {
+        self.definitions.get(&self.definition_key(id, typ)).cloned()
+    }
+
+    /// Returns the key `(id, typ)` is cached under in `self.definitions`. The type is normalized
+    /// so that monomorphisations differing only in the names of their unbound type variables
+    /// (e.g. `a -> b` and `c -> d`) share a single definition.
+    pub fn definition_key(&self, id: DefinitionInfoId, typ: &types::Type) -> DefinitionKey {
         let typ = self.follow_all_bindings(typ);
-        self.definitions.get(&(id, typ)).cloned()
+        let normalized = typ.normalize(&self.cache);
+        DefinitionKey { id, typ, normalized }
+    }
+
+    /// Returns a unique symbol name for the given definition instantiated with the given type.
+    fn symbol_name(&mut self, id: DefinitionInfoId, typ: &types::Type) -> String {
+        let name = hir::mangling::mangle(id, typ, &self.cache);
+        let count = self.symbol_names.entry(name.clone()).or_insert(0);
+        *count += 1;
+
+        if *count == 1 {
+            name
+        } else {
+            format!("{}.{}", name, *count - 1)
+        }
     }
 
     fn push_monomorphisation_bindings(
@@ -612,15 +1255,23 @@ impl<'c> Context<'c> {
     ) {
         if !instantiation_mapping.is_empty() {
             self.monomorphisation_bindings.push(instantiation_mapping.clone());
+            self.followed_types.get_mut().clear();
         }
 
-        if definition.trait_impl.is_some() {
+        if definition.trait_impl.is_some() || definition.trait_default.is_some() {
             let definition_type = definition.typ.as_ref().unwrap().remove_forall();
-            let bindings = typechecker::try_unify(typ, definition_type, definition.location, &mut self.cache)
-                .map_err(|error| eprintln!("{}", error))
-                .expect("Unification error during monomorphisation");
+            // Bindings are still pushed on error so that they are popped in pop_monomorphisation_bindings.
+            // Compilation stops once monomorphisation finishes since an error was issued.
+            let bindings = match typechecker::try_unify(typ, definition_type, definition.location, &mut self.cache) {
+                Ok(bindings) => bindings.bindings,
+                Err(message) => {
+                    error::issue(message);
+                    TypeBindings::new()
+                },
+            };
 
-            self.monomorphisation_bindings.push(Rc::new(bindings.bindings));
+            self.monomorphisation_bindings.push(Rc::new(bindings));
+            self.followed_types.get_mut().clear();
         }
     }
 
@@ -629,10 +1280,12 @@ impl<'c> Context<'c> {
     ) {
         if !instantiation_mapping.is_empty() {
             self.monomorphisation_bindings.pop();
+            self.followed_types.get_mut().clear();
         }
 
-        if definition.trait_impl.is_some() {
+        if definition.trait_impl.is_some() || definition.trait_default.is_some() {
             self.monomorphisation_bindings.pop();
+            self.followed_types.get_mut().clear();
         }
     }
 
@@ -659,7 +1312,7 @@ impl<'c> Context<'c> {
 
             match required_trait.callsite {
                 Callsite::Direct(callsite) => {
-                    let binding = self.cache.find_method_in_impl(callsite, binding);
+                    let binding = find_method_in_impl(&self.cache, callsite, binding, &mut new_indirect);
                     new_direct.insert(callsite, binding);
                 },
                 Callsite::Indirect(callsite, id) => {
@@ -699,6 +1352,11 @@ impl<'c> Context<'c> {
         instantiation_mapping: &Rc<TypeBindings>,
     ) -> Definition {
         if let Some(value) = self.lookup_definition(id, typ) {
+            if let Definition::Normal(info) = &value {
+                if let Some(referenced) = self.unfinished_definitions.get_mut(&info.definition_id) {
+                    *referenced = true;
+                }
+            }
             return value;
         }
 
@@ -715,17 +1373,38 @@ impl<'c> Context<'c> {
                 // Any recursive calls to this variable will refer to this binding
                 let definition_id = self.next_unique_id();
                 let info = hir::DefinitionInfo { definition: None, definition_id };
-                self.definitions.insert((id, typ.clone()), Definition::Normal(info));
+                let key = self.definition_key(id, &typ);
+                self.definitions.insert(key.clone(), Definition::Normal(info));
+                self.unfinished_definitions.insert(definition_id, false);
+
+                let name = self.symbol_name(id, &typ);
+                let mut def = self.monomorphise_nonlocal_definition(definition, definition_id, name);
 
-                let def = self.monomorphise_nonlocal_definition(definition, definition_id);
-                self.definitions.insert((id, typ), def.clone());
+                let recursive = self.unfinished_definitions.remove(&definition_id).unwrap();
+                if !recursive && !definition.mutable {
+                    def = self.merge_function(def);
+                }
+
+                // Only functions are compiled globally. Other values compiled here, such as trait
+                // constants like `default = 7`, would otherwise be shared by every function using them
+                // despite being defined within the first, so they are recompiled at each use instead.
+                if def.is_function() || definition.mutable {
+                    self.definitions.insert(key, def.clone());
+                } else {
+                    self.definitions.remove(&key);
+                }
                 def
             },
-            Some(DefinitionKind::Extern(_)) => self.make_extern(id, &typ),
+            Some(DefinitionKind::Extern(_, abi)) => {
+                let abi = *abi;
+                self.make_extern(id, &typ, abi)
+            },
             Some(DefinitionKind::TypeConstructor { tag, name: _ }) => {
                 let definition = self.monomorphise_type_constructor(tag, &typ);
                 self.define_type_constructor(definition, id, typ)
             },
+            // The typechecker resolves an impl for every trait method use which isn't propagated to
+            // an enclosing definition, and reports an error otherwise, so this is never reached.
             Some(DefinitionKind::TraitDefinition(_)) => {
                 unreachable!(
                     "Cannot monomorphise from a TraitDefinition.\nNo cached impl for {} {}: {}",
@@ -765,26 +1444,113 @@ impl<'c> Context<'c> {
 
     /// This function is 'make_extern' rathern than 'monomorphise_extern' since extern declarations
     /// shouldn't be monomorphised across multiple types.
-    fn make_extern(&mut self, id: DefinitionInfoId, typ: &types::Type) -> Definition {
+    fn make_extern(&mut self, id: DefinitionInfoId, typ: &types::Type, abi: ExternAbi) -> Definition {
         // extern definitions should only be declared once - never duplicated & monomorphised.
         // For this reason their value is always stored with the Unit type in the definitions map.
         if let Some(value) = self.lookup_definition(id, &UNBOUND_TYPE) {
-            self.definitions.insert((id, typ.clone()), value.clone());
+            self.definitions.insert(self.definition_key(id, typ), value.clone());
             return value;
         }
 
         let name = self.cache[id].name.clone();
-        let extern_ = hir::Ast::Extern(hir::Extern { name, typ: self.convert_type(typ) });
+        let extern_ = match abi {
+            ExternAbi::Ante => hir::Ast::Extern(hir::Extern { name, typ: self.convert_type(typ) }),
+            ExternAbi::C => self.make_c_extern(id, name, typ),
+        };
 
         let definition = self.make_definition(extern_);
 
         // Insert the global for both the current type and the unit type
         let definition = Definition::Normal(definition);
-        self.definitions.insert((id, typ.clone()), definition.clone());
-        self.definitions.insert((id, UNBOUND_TYPE.clone()), definition.clone());
+        self.definitions.insert(self.definition_key(id, typ), definition.clone());
+        self.definitions.insert(self.definition_key(id, &UNBOUND_TYPE), definition.clone());
         definition
     }
 
+    /// Externs using the C ABI are wrapped in a function converting each argument whose
+    /// declared type is represented differently in C, see `ExternAbi::C`. Only the declared
+    /// type is checked since the extern is shared between each type it is used at.
+    /// Externs without any arguments to convert are left unwrapped.
+    fn make_c_extern(&mut self, id: DefinitionInfoId, name: String, typ: &types::Type) -> hir::Ast {
+        let function_type = match self.convert_type(typ) {
+            Type::Function(function_type) => function_type,
+            typ => return hir::Ast::Extern(hir::Extern { name, typ }),
+        };
+
+        let (parameters, return_conversion) = match self.cache[id].typ.as_ref().map(|typ| typ.remove_forall()) {
+            Some(types::Type::Function(function)) => {
+                let parameters = function.parameters.clone();
+                (fmap(&parameters, |parameter| self.c_conversion(parameter)), self.c_conversion(&function.return_type))
+            },
+            _ => (vec![], None),
+        };
+
+        // C functions cannot return strings by value, so a declared string return type is left as is
+        let return_conversion = return_conversion.filter(|conversion| *conversion != CConversion::StringPointer);
+
+        if parameters.iter().all(Option::is_none) && return_conversion.is_none() {
+            return hir::Ast::Extern(hir::Extern { name, typ: Type::Function(function_type) });
+        }
+
+        if function_type.is_varargs {
+            let location = self.cache[id].location;
+            error!(location, "The arguments of the varargs function {} cannot be converted to the C ABI", name);
+            return hir::Ast::Extern(hir::Extern { name, typ: Type::Function(function_type) });
+        }
+
+        let c_parameters = function_type.parameters.iter().zip(&parameters);
+        let c_function_type = hir::types::FunctionType {
+            parameters: fmap(c_parameters, |(parameter, conversion)| match conversion {
+                Some(conversion) => conversion.c_type(),
+                None => parameter.clone(),
+            }),
+            return_type: Box::new(match return_conversion {
+                Some(conversion) => conversion.c_type(),
+                None => function_type.return_type.as_ref().clone(),
+            }),
+            is_varargs: false,
+        };
+
+        let extern_ = hir::Ast::Extern(hir::Extern { name, typ: Type::Function(c_function_type.clone()) });
+        let extern_ = self.make_definition(extern_);
+
+        let args = fmap(&function_type.parameters, |_| (self.fresh_variable(), false));
+        let call_args = fmap(args.iter().zip(&parameters), |((arg, _), conversion)| match conversion {
+            Some(conversion) => conversion.to_c(arg.clone().into()),
+            None => arg.clone().into(),
+        });
+
+        let call = hir::Ast::FunctionCall(hir::FunctionCall {
+            function: Box::new(extern_.into()),
+            args: call_args,
+            function_type: c_function_type,
+        });
+
+        let body = match return_conversion {
+            Some(conversion) => conversion.to_ante(call),
+            None => call,
+        };
+
+        hir::Ast::Lambda(hir::Lambda { args, body: Box::new(body), typ: function_type })
+    }
+
+    /// Returns how a value of the given declared type is converted when passed to a C function,
+    /// or None if Ante already represents it the same way C does.
+    fn c_conversion(&self, typ: &types::Type) -> Option<CConversion> {
+        match self.follow_bindings_shallow(typ).ok()? {
+            types::Type::UserDefined(types::STRING_TYPE) => Some(CConversion::StringPointer),
+            types::Type::UserDefined(id) => match &self.cache[*id].body {
+                types::TypeInfoBody::Union(variants)
+                    if !variants.is_empty() && variants.iter().all(|variant| variant.args.is_empty()) =>
+                {
+                    Some(CConversion::EnumTag)
+                },
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
     /// Wrap the given Ast in a new DefinitionInfo and store it
     fn define_type_constructor(
         &mut self, definition_rhs: hir::Ast, original_id: DefinitionInfoId, typ: types::Type,
@@ -793,13 +1559,14 @@ impl<'c> Context<'c> {
             let variable = self.next_unique_id();
             let expr = Box::new(definition_rhs);
 
-            let definition = hir::Definition { variable, expr };
+            let name = Some(self.symbol_name(original_id, &typ));
+            let definition = hir::Definition { variable, name, expr };
             Definition::Normal(hir::DefinitionInfo::from(definition))
         } else {
             Definition::Macro(definition_rhs)
         };
 
-        self.definitions.insert((original_id, typ), def.clone());
+        self.definitions.insert(self.definition_key(original_id, &typ), def.clone());
         def
     }
 
@@ -810,7 +1577,7 @@ impl<'c> Context<'c> {
     pub fn fresh_definition(&mut self, definition_rhs: hir::Ast) -> (hir::Ast, hir::DefinitionId) {
         let variable = self.next_unique_id();
         let expr = Box::new(definition_rhs);
-        let definition = hir::Ast::Definition(hir::Definition { variable, expr });
+        let definition = hir::Ast::Definition(hir::Definition { variable, name: None, expr });
         (definition, variable)
     }
 
@@ -823,16 +1590,21 @@ impl<'c> Context<'c> {
     ///
     /// TODO: This may be a clone of monomorphise_definition now
     fn monomorphise_nonlocal_definition(
-        &mut self, definition: &ast::Definition<'c>, definition_id: hir::DefinitionId,
+        &mut self, definition: &ast::Definition<'c>, definition_id: hir::DefinitionId, name: String,
     ) -> Definition {
+        // Global definitions live for the entire program so they are never reference counted.
+        // Hide the enclosing scopes so they don't capture any globals defined here.
+        let rc_scopes = std::mem::take(&mut self.rc_scopes);
         let value = self.monomorphise(&*definition.expr);
 
-        let new_definition = hir::Ast::Definition(hir::Definition { variable: definition_id, expr: Box::new(value) });
+        let new_definition =
+            hir::Ast::Definition(hir::Definition { variable: definition_id, name: Some(name), expr: Box::new(value) });
 
         let mut nested_definitions = vec![new_definition];
         let typ = self.follow_all_bindings(definition.pattern.get_type().unwrap());
 
         self.desugar_pattern(&definition.pattern, definition_id, typ, definition.mutable, &mut nested_definitions);
+        self.rc_scopes = rc_scopes;
 
         let definition = if nested_definitions.len() == 1 {
             nested_definitions.remove(0)
@@ -844,6 +1616,21 @@ impl<'c> Context<'c> {
         Definition::Normal(var)
     }
 
+    /// If the given definition is a function identical to one already monomorphised,
+    /// return the existing function instead so that only one copy of it is compiled.
+    fn merge_function(&mut self, definition: Definition) -> Definition {
+        if let Definition::Normal(info) = &definition {
+            if let Some(hir::Ast::Definition(hir::Definition { expr, .. })) = info.definition.as_deref() {
+                if let hir::Ast::Lambda(lambda) = expr.as_ref() {
+                    let canonical = hir::function_merging::canonicalize(lambda);
+                    let existing = self.functions.entry(canonical).or_insert_with(|| info.clone());
+                    return Definition::Normal(existing.clone());
+                }
+            }
+        }
+        definition
+    }
+
     /// Simplifies a pattern and expression like `(a, b) = foo ()`
     /// into multiple successive bindings:
     /// ```
@@ -870,9 +1657,14 @@ impl<'c> Context<'c> {
                 let id = variable_pattern.definition.unwrap();
 
                 let variable = hir::Variable { definition_id, definition: None };
-                let definition = if mutable { Definition::Mutable(variable) } else { Definition::Normal(variable) };
 
-                self.definitions.insert((id, typ), definition);
+                if !mutable && !self.rc_scopes.is_empty() && self.is_reference_counted(&typ, variable_pattern) {
+                    self.rc_scopes.last_mut().unwrap().push(variable.clone());
+                    self.rc_variables.insert(definition_id);
+                }
+
+                let definition = if mutable { Definition::Mutable(variable) } else { Definition::Normal(variable) };
+                self.definitions.insert(self.definition_key(id, &typ), definition);
             },
             TypeAnnotation(annotation) => {
                 let mutable = mutable || annotation.mutable;
@@ -881,17 +1673,37 @@ impl<'c> Context<'c> {
             // Match a struct pattern
             FunctionCall(call) if call.is_pair_constructor() => {
                 let variable = hir::Variable { definition_id, definition: None };
+                let field_indices = self.struct_field_indices(&typ).unwrap();
+                let boxed_fields = self.recursive_fields(&typ, 0);
+                let pointer_size = self.target_pointer_size as usize;
                 let mut offset = 0;
 
-                for (i, arg_pattern) in call.args.iter().enumerate() {
+                for ((arg_pattern, field_index), boxed) in call.args.iter().zip(field_indices).zip(boxed_fields) {
                     let arg_type = self.follow_all_bindings(arg_pattern.get_type().unwrap());
 
-                    let extract = if mutable {
-                        let new_ptr = offset_ptr(variable.clone().into(), offset as u64);
-                        offset += self.size_of_type(&arg_type);
-                        new_ptr
-                    } else {
-                        self.extract(variable.clone().into(), i as u32)
+                    let extract = match field_index {
+                        // A recursive field stores the address of its value, which is the new pointer
+                        Some(_) if mutable && boxed => {
+                            offset = align_up(offset, pointer_size);
+                            let field_ptr = offset_ptr(variable.clone().into(), offset as u64);
+                            offset += pointer_size;
+                            let pointer_type = Type::Primitive(hir::types::PrimitiveType::Pointer);
+                            hir::Ast::Builtin(hir::Builtin::Deref(Box::new(field_ptr), pointer_type))
+                        },
+                        Some(_) if mutable => {
+                            offset = align_up(offset, self.align_of_type(&arg_type));
+                            let new_ptr = offset_ptr(variable.clone().into(), offset as u64);
+                            offset += self.size_of_type(&arg_type);
+                            new_ptr
+                        },
+                        Some(index) if boxed => {
+                            let field = self.extract(variable.clone().into(), index);
+                            self.unbox_value(field, &arg_type)
+                        },
+                        Some(index) => self.extract(variable.clone().into(), index),
+                        // Erased fields take up no space, so any address is valid for them
+                        None if mutable => offset_ptr(variable.clone().into(), offset as u64),
+                        None => unit_literal(),
                     };
 
                     let (definition, id) = self.fresh_definition(extract);
@@ -908,21 +1720,55 @@ impl<'c> Context<'c> {
 
     fn monomorphise_type_constructor(&mut self, tag: &Option<u8>, typ: &types::Type) -> hir::Ast {
         use hir::types::Type::*;
+        let (niche, erased_fields, boxed_fields) = match (tag, self.follow_all_bindings(typ)) {
+            (None, types::Type::Function(function)) => {
+                let erased_fields = fmap(&function.parameters, |parameter| self.is_erased_field(parameter));
+                (None, erased_fields, self.recursive_fields(&function.return_type, 0))
+            },
+            (None, _) => (None, vec![], vec![]),
+            (Some(tag), types::Type::Function(function)) => {
+                let boxed_fields = self.recursive_fields(&function.return_type, *tag as usize);
+                (self.niche_layout_of_type(&function.return_type), vec![], boxed_fields)
+            },
+            (Some(_), union_type) => (self.niche_layout_of_type(&union_type), vec![], vec![]),
+        };
+
         let typ = self.convert_type(typ);
         match typ {
             Function(function_type) => {
                 let args = fmap(&function_type.parameters, |_| (self.fresh_variable(), false));
 
                 let mut tuple_args = Vec::with_capacity(args.len() + 1);
-                let mut tuple_size =
-                    function_type.parameters.iter().map(|parameter| self.size_of_monomorphised_type(parameter)).sum();
+                let mut tuple_types = Vec::with_capacity(args.len() + 1);
 
                 if let Some(tag) = tag {
-                    tuple_args.push(tag_value(*tag));
-                    tuple_size += self.size_of_monomorphised_type(&Self::tag_type());
+                    if niche.is_none() {
+                        tuple_args.push(tag_value(*tag));
+                        tuple_types.push(Self::tag_type());
+                    }
                 }
 
-                tuple_args.extend(args.iter().map(|arg| arg.0.clone().into()));
+                // Erased struct fields are left out of the tuple, see `is_erased_field`,
+                // and recursive fields are stored in a new allocation, see `is_recursive_field`
+                let fields = args.iter().zip(&function_type.parameters).enumerate();
+                for (i, (arg, parameter)) in fields {
+                    if erased_fields.get(i).copied().unwrap_or(false) {
+                        continue;
+                    } else if boxed_fields.get(i).copied().unwrap_or(false) {
+                        tuple_args.push(self.box_value(arg.0.clone().into(), parameter));
+                        tuple_types.push(Type::Primitive(hir::types::PrimitiveType::Pointer));
+                    } else {
+                        tuple_args.push(arg.0.clone().into());
+                        tuple_types.push(parameter.clone());
+                    }
+                }
+
+                // The variant with data in a NicheLayout is stored as just its fields
+                if niche.is_some() {
+                    let body = Box::new(self.tuple(tuple_args));
+                    return hir::Ast::Lambda(hir::Lambda { args, body, typ: function_type });
+                }
+                let tuple_size = self.size_of_monomorphised_type(&Type::Tuple(tuple_types));
 
                 let tuple = hir::Ast::Tuple(hir::Tuple { fields: tuple_args });
 
@@ -941,6 +1787,8 @@ impl<'c> Context<'c> {
             // reinterpret_cast to the appropriate type.
             Tuple(..) => match tag {
                 None => unit_literal(),
+                // The variant without data in a NicheLayout is all zeroes, including its null reference
+                Some(_) if niche.is_some() => self.make_reinterpret_cast(self.tuple(vec![]), 0, typ),
                 Some(tag) => {
                     let value = tag_value(*tag);
                     let size = self.size_of_monomorphised_type(&Self::tag_type());
@@ -953,8 +1801,32 @@ impl<'c> Context<'c> {
         }
     }
 
+    /// Copies the given value of the given type into a new heap allocation and returns a pointer to it
+    fn box_value(&mut self, value: hir::Ast, typ: &Type) -> hir::Ast {
+        let size = self.size_of_monomorphised_type(typ);
+        let malloc = self.get_malloc_function();
+        let allocation = hir::Ast::FunctionCall(hir::FunctionCall {
+            function: Box::new(malloc.into()),
+            args: vec![int_literal(size as u64, IntegerKind::Usz)],
+            function_type: malloc_function_type(),
+        });
+
+        let (definition, id) = self.fresh_definition(allocation);
+        let store = hir::Ast::Assignment(hir::Assignment { lhs: Box::new(id.to_variable()), rhs: Box::new(value) });
+        hir::Ast::Sequence(hir::Sequence { statements: vec![definition, store, id.to_variable()] })
+    }
+
+    /// Loads a value of the given type from a recursive field, which is stored behind a pointer
+    pub fn unbox_value(&mut self, value: hir::Ast, typ: &types::Type) -> hir::Ast {
+        let typ = self.convert_type(typ);
+        hir::Ast::Builtin(hir::Builtin::Deref(Box::new(value), typ))
+    }
+
     /// Create a reinterpret_cast instruction for the given Ast value.
     /// arg_type_size is the size of the value represented by the given ast, in bytes.
+    /// Smaller values are padded with zeroes up to the size of the target type, which is
+    /// only meant for internal casts like constructing union variants. See `transmute` for
+    /// the user-facing equivalent which requires both types to be the same size.
     fn make_reinterpret_cast(&mut self, ast: hir::Ast, mut arg_type_size: u32, target_type: Type) -> hir::Ast {
         let target_size = self.size_of_monomorphised_type(&target_type);
         assert!(arg_type_size <= target_size);
@@ -966,10 +1838,11 @@ impl<'c> Context<'c> {
         let mut padded = vec![ast];
         let type_tower = [(IntegerKind::U64, 8), (IntegerKind::U32, 4), (IntegerKind::U16, 2), (IntegerKind::U8, 1)];
 
+        // Each padding field is aligned to its own size when laid out after the previous field
         for (int_kind, size) in type_tower {
-            while arg_type_size + size <= target_size {
+            while align_up(arg_type_size, size) + size <= target_size {
                 padded.push(int_literal(0, int_kind));
-                arg_type_size += size;
+                arg_type_size = align_up(arg_type_size, size) + size;
             }
         }
 
@@ -987,18 +1860,41 @@ impl<'c> Context<'c> {
                             I16 | U16 => 2,
                             I32 | U32 => 4,
                             I64 | U64 => 8,
-                            Isz | Usz => Self::ptr_size() as u32,
+                            Isz | Usz => self.target_pointer_size,
                         }
                     },
                     hir::types::PrimitiveType::Float => 8,
                     hir::types::PrimitiveType::Char => 1,
                     hir::types::PrimitiveType::Boolean => 1,
                     hir::types::PrimitiveType::Unit => 1, // TODO: this can depend on the backend
-                    hir::types::PrimitiveType::Pointer => Self::ptr_size() as u32,
+                    hir::types::PrimitiveType::Pointer => self.target_pointer_size,
                 }
             },
-            Type::Function(_) => Self::ptr_size() as u32, // Closures would be represented as tuples
-            Type::Tuple(fields) => fields.iter().map(|f| self.size_of_monomorphised_type(f)).sum(),
+            Type::Function(_) => self.target_pointer_size, // Closures would be represented as tuples
+            Type::Tuple(fields) => {
+                let size = self.offset_of_field(fields, fields.len());
+                align_up(size, self.align_of_monomorphised_type(typ))
+            },
+        }
+    }
+
+    fn align_of_monomorphised_type(&self, typ: &Type) -> u32 {
+        match typ {
+            Type::Primitive(_) | Type::Function(_) => self.size_of_monomorphised_type(typ),
+            Type::Tuple(fields) => fields.iter().map(|f| self.align_of_monomorphised_type(f)).max().unwrap_or(1),
+        }
+    }
+
+    /// Returns the offset in bytes of the field at the given index within a tuple of the given fields.
+    /// If the index is the number of fields, this is the end of the last field.
+    fn offset_of_field(&self, fields: &[Type], index: usize) -> u32 {
+        let end_of_previous = fields[..index].iter().fold(0, |offset, field| {
+            align_up(offset, self.align_of_monomorphised_type(field)) + self.size_of_monomorphised_type(field)
+        });
+
+        match fields.get(index) {
+            Some(field) => align_up(end_of_previous, self.align_of_monomorphised_type(field)),
+            None => end_of_previous,
         }
     }
 
@@ -1037,11 +1933,28 @@ impl<'c> Context<'c> {
         }
     }
 
-    fn monomorphise_lambda(&mut self, lambda: &ast::Lambda<'c>) -> hir::Ast {
+    /// Monomorphise a lambda, bundling it with its environment if it is a closure.
+    /// `may_escape` should be false only if the closure cannot outlive the current function,
+    /// in which case an environment captured by reference may be stored on the stack.
+    fn monomorphise_lambda(&mut self, lambda: &ast::Lambda<'c>, may_escape: bool) -> hir::Ast {
         let t = lambda.typ.as_ref().unwrap();
+        if !self.check_no_forall_types(t, lambda.location) {
+            return unit_literal();
+        }
+
         let t = self.follow_all_bindings(t);
         let typ = self.get_function_type(&t, &lambda.args);
+
+        let by_reference = match &t {
+            types::Type::Function(function) if !lambda.closure_environment.is_empty() => {
+                let environment = self.convert_type(&function.environment);
+                self.environment_is_captured_by_reference(&environment)
+            },
+            _ => false,
+        };
+
         let mut body_prelude = vec![];
+        self.rc_scopes.push(vec![]);
 
         // Bind each parameter node to the nth parameter of `function`
         // This will also desugar any patterns in the parameter, prepending extra
@@ -1055,18 +1968,42 @@ impl<'c> Context<'c> {
             (param, mutable)
         });
 
-        args.extend(lambda.closure_environment.values().map(|(_, inner_var, _)| {
-            let param = self.fresh_variable();
-            let info = &self.cache[*inner_var];
-            let typ = info.typ.as_ref().unwrap().as_monotype();
-            let typ = self.follow_all_bindings(typ);
-            self.definitions.insert((*inner_var, typ), Definition::Normal(param.clone()));
+        if by_reference {
+            // The environment is a single pointer to a tuple of each captured value. Each captured
+            // variable is bound to the address of its field and is loaded from it on each use.
+            let environment = self.fresh_variable();
+            let inner_types = fmap(lambda.closure_environment.values(), |(_, inner_var, _)| {
+                let typ = self.cache[*inner_var].typ.as_ref().unwrap().as_monotype();
+                self.follow_all_bindings(typ)
+            });
+            let fields = fmap(&inner_types, |typ| self.convert_type(typ));
+
+            for (i, ((_, inner_var, _), typ)) in lambda.closure_environment.values().zip(inner_types).enumerate() {
+                let offset = self.offset_of_field(&fields, i) as u64;
+                let (field_address, id) = self.fresh_definition(offset_ptr(environment.clone().into(), offset));
+                body_prelude.push(field_address);
+                self.definitions.insert(self.definition_key(*inner_var, &typ), Definition::Mutable(id.into()));
+            }
 
-            (param, info.mutable)
-        }));
+            args.push((environment, false));
+        } else {
+            args.extend(lambda.closure_environment.values().map(|(_, inner_var, _)| {
+                let param = self.fresh_variable();
+                let info = &self.cache[*inner_var];
+                let typ = info.typ.as_ref().unwrap().as_monotype();
+                let typ = self.follow_all_bindings(typ);
+                self.definitions.insert(self.definition_key(*inner_var, &typ), Definition::Normal(param.clone()));
+
+                (param, info.mutable)
+            }));
+        }
 
         let body = self.monomorphise(&lambda.body);
 
+        // Any reference counted parameters are owned by the function and dropped at its end
+        let rc_parameters = self.rc_scopes.pop().unwrap();
+        let body = self.decrement_at_scope_end(body, rc_parameters);
+
         let body = Box::new(if body_prelude.is_empty() {
             body
         } else {
@@ -1082,10 +2019,25 @@ impl<'c> Context<'c> {
             let mut values = Vec::with_capacity(lambda.closure_environment.len() + 1);
             values.push(function);
 
+            let mut fields = Vec::with_capacity(lambda.closure_environment.len());
             for (outer_var, (var_id, _, bindings)) in &lambda.closure_environment {
                 let typ = self.cache[*outer_var].typ.as_ref().unwrap().clone().into_monotype();
                 let definition = self.monomorphise_definition_id(*outer_var, *var_id, &typ, bindings);
                 values.push(definition.reference(self, &typ));
+                fields.push(typ);
+            }
+
+            if by_reference {
+                let environment = self.tuple(values.split_off(1));
+                let fields = fmap(&fields, |typ| self.convert_type(typ));
+
+                // A closure which may escape cannot point into the current stack frame
+                let environment = if may_escape {
+                    self.box_value(environment, &Type::Tuple(fields))
+                } else {
+                    hir::Ast::Builtin(hir::Builtin::StackAlloc(Box::new(environment)))
+                };
+                values.push(environment);
             }
 
             self.tuple(values)
@@ -1106,6 +2058,130 @@ impl<'c> Context<'c> {
         }
     }
 
+    fn align_of_type_arg0(&mut self, type_type: &types::Type) -> u32 {
+        match self.follow_all_bindings(type_type) {
+            types::Type::TypeApplication(_, arg_types) => {
+                assert_eq!(arg_types.len(), 1);
+                self.align_of_type(&arg_types[0]) as u32
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    /// Creates a prelude `TypeInfo` record describing `t` given the type `Type t`.
+    /// The fields are in the same order as the TypeInfo definition in the prelude.
+    fn type_info(&mut self, type_arg: &types::Type, result_type: &types::Type) -> hir::Ast {
+        let typ = match self.follow_all_bindings(type_arg) {
+            types::Type::TypeApplication(_, mut args) if args.len() == 1 => args.remove(0),
+            other => {
+                unreachable!("TypeInfo expects an argument of type `Type t` but found {}", other.display(&self.cache))
+            },
+        };
+
+        let size = self.size_of_type(&typ);
+        let alignment = self.align_of_type(&typ);
+        let (kind, field_count) = self.kind_of_type(&typ);
+
+        let kind_type = match self.follow_bindings_shallow(result_type) {
+            Ok(types::Type::UserDefined(id)) => self.cache[*id].find_field("kind").unwrap().1.field_type.clone(),
+            _ => unreachable!("TypeInfo should return the prelude's TypeInfo type"),
+        };
+
+        let tag = match &kind_type {
+            types::Type::UserDefined(id) => {
+                let variants = self.cache[*id].union_variants().unwrap();
+                variants.iter().position(|variant| variant.name == kind).unwrap()
+            },
+            _ => unreachable!("TypeInfo.kind should be the prelude's TypeKind type"),
+        };
+
+        let kind = self.monomorphise_type_constructor(&Some(tag as u8), &kind_type);
+
+        self.tuple(vec![
+            int_literal(size as u64, IntegerKind::Usz),
+            int_literal(alignment as u64, IntegerKind::Usz),
+            int_literal(field_count as u64, IntegerKind::Usz),
+            kind,
+        ])
+    }
+
+    /// Lowers a three-way comparison of two primitives into the equivalent of
+    /// `if a < b then Less else if a == b then Equal else Greater`, where the result
+    /// is a value of the prelude's `Ordering` type.
+    fn compare(
+        &mut self, args: &[ast::Ast<'c>], less: fn(Box<hir::Ast>, Box<hir::Ast>) -> hir::Builtin,
+        equal: fn(Box<hir::Ast>, Box<hir::Ast>) -> hir::Builtin, result_type: &types::Type,
+    ) -> hir::Ast {
+        let lhs = self.monomorphise(&args[1]);
+        let rhs = self.monomorphise(&args[2]);
+        let (lhs_definition, lhs) = self.fresh_definition(lhs);
+        let (rhs_definition, rhs) = self.fresh_definition(rhs);
+
+        let mut ordering = |name: &str| {
+            let tag = match self.follow_bindings_shallow(result_type) {
+                Ok(types::Type::UserDefined(id)) => {
+                    let variants = self.cache[*id].union_variants().unwrap();
+                    variants.iter().position(|variant| variant.name == name).unwrap()
+                },
+                _ => unreachable!("Compare builtins should return the prelude's Ordering type"),
+            };
+            Box::new(self.monomorphise_type_constructor(&Some(tag as u8), result_type))
+        };
+
+        let (less_value, equal_value, greater_value) = (ordering("Less"), ordering("Equal"), ordering("Greater"));
+        let result_type = self.convert_type(result_type);
+
+        let is_equal = Box::new(hir::Ast::Builtin(equal(
+            Box::new(hir::Variable::from(lhs).into()),
+            Box::new(hir::Variable::from(rhs).into()),
+        )));
+        let otherwise = hir::Ast::If(hir::If {
+            condition: is_equal,
+            then: equal_value,
+            otherwise: Some(greater_value),
+            result_type: result_type.clone(),
+        });
+
+        let is_less = Box::new(hir::Ast::Builtin(less(
+            Box::new(hir::Variable::from(lhs).into()),
+            Box::new(hir::Variable::from(rhs).into()),
+        )));
+        let comparison = hir::Ast::If(hir::If {
+            condition: is_less,
+            then: less_value,
+            otherwise: Some(Box::new(otherwise)),
+            result_type,
+        });
+
+        hir::Ast::Sequence(hir::Sequence { statements: vec![lhs_definition, rhs_definition, comparison] })
+    }
+
+    /// Lowers the "Transmute" builtin to a ReinterpretCast of the given value to the result type.
+    /// Unlike `make_reinterpret_cast`, the value is never padded: the sizes of both types must match.
+    fn transmute(&mut self, value: &ast::Ast<'c>, result_type: &types::Type) -> hir::Ast {
+        let source_type = self.follow_all_bindings(value.get_type().unwrap());
+        let result_type = self.follow_all_bindings(result_type);
+
+        let target_type = self.convert_type(&result_type);
+        let source = self.convert_type(&source_type);
+        let source_size = self.size_of_monomorphised_type(&source);
+        let target_size = self.size_of_monomorphised_type(&target_type);
+
+        if source_size != target_size {
+            error!(
+                self.location,
+                "Cannot transmute {} of size {} to {} of size {}, the sizes must be equal",
+                source_type.display(&self.cache),
+                source_size,
+                result_type.display(&self.cache),
+                target_size
+            );
+        }
+
+        let lhs = Box::new(self.monomorphise(value));
+        hir::Ast::ReinterpretCast(hir::ReinterpretCast { lhs, target_type })
+    }
+
     fn convert_builtin(&mut self, args: &[ast::Ast<'c>], result_type: &types::Type) -> hir::Ast {
         use hir::Builtin::*;
         let arg = match &args[0] {
@@ -1148,6 +2224,11 @@ impl<'c> Context<'c> {
             "EqChar" => binary(self, EqChar),
             "EqBool" => binary(self, EqBool),
 
+            // Three-way comparisons are lowered to a chain of the comparisons above
+            "CompareSigned" => return self.compare(args, LessSigned, EqInt, result_type),
+            "CompareUnsigned" => return self.compare(args, LessUnsigned, EqInt, result_type),
+            "CompareFloat" => return self.compare(args, LessFloat, EqFloat, result_type),
+
             "SignExtend" => cast(self, SignExtend),
             "ZeroExtend" => cast(self, ZeroExtend),
 
@@ -1164,15 +2245,27 @@ impl<'c> Context<'c> {
                 Box::new(self.monomorphise(&args[2])),
                 self.size_of_type_arg0(result_type),
             ),
-            "Transmute" => cast(self, Transmute),
+            "Transmute" => return self.transmute(&args[1], result_type),
 
-            // We know the result of SizeOf now, so replace it with a constant
+            "Unreachable" => Unreachable(self.convert_type(result_type)),
+            "Assume" => Assume(Box::new(self.monomorphise(&args[1]))),
+
+            // We know the results of SizeOf and AlignOf now, so replace them with constants
             "SizeOf" => {
                 // We expect (size_of : Type t -> usz), so get the size of t
                 let size = self.size_of_type_arg0(args[1].get_type().unwrap());
                 return int_literal(size as u64, IntegerKind::Usz);
             },
 
+            "AlignOf" => {
+                // We expect (align_of : Type t -> usz), so get the alignment of t
+                let alignment = self.align_of_type_arg0(args[1].get_type().unwrap());
+                return int_literal(alignment as u64, IntegerKind::Usz);
+            },
+
+            // Like SizeOf, each field of the TypeInfo record is known now so it is replaced with constants
+            "TypeInfo" => return self.type_info(args[1].get_type().unwrap(), result_type),
+
             _ => unreachable!("Unknown builtin '{}'", arg),
         })
     }
@@ -1189,7 +2282,11 @@ impl<'c> Context<'c> {
                 // generalized.
                 // TODO: Review this restriction. `a = Some 2` is no longer generalized due to the
                 // value restriction.
-                let mut args = fmap(&call.args, |arg| self.monomorphise(arg));
+                let mut args = fmap(&call.args, |arg| match arg {
+                    // A closure passed directly to a call is assumed not to outlive it
+                    ast::Ast::Lambda(lambda) => self.monomorphise_lambda(lambda, false),
+                    _ => self.monomorphise(arg),
+                });
                 let function = self.monomorphise(&call.function);
 
                 args = self.fix_arg_mutability(args, &function);
@@ -1233,8 +2330,16 @@ impl<'c> Context<'c> {
         match definition.expr.as_ref() {
             // If the value is a function we can skip it and come back later to only
             // monomorphise it when we know what types it should be instantiated with.
-            // TODO: Do we need a check for Variables as well since they can also be generalized?
             ast::Ast::Lambda(_) => unit_literal(),
+            // Variables are generalized as well. If this one is still polymorphic here, e.g. `g = def`
+            // for some trait method `def`, it may require impls which are only known at its uses, so
+            // it is likewise compiled later once each use decides its type.
+            ast::Ast::Variable(_)
+                if !definition.mutable
+                    && !is_fully_bound(&self.follow_all_bindings(definition.pattern.get_type().unwrap())) =>
+            {
+                unit_literal()
+            },
             _ => {
                 let mut expr = self.monomorphise(&definition.expr);
                 if definition.mutable {
@@ -1272,13 +2377,107 @@ impl<'c> Context<'c> {
         hir::Ast::If(hir::If { condition, then, otherwise, result_type })
     }
 
+    /// Lowers a guard expression into a chain of nested `If`s:
+    /// `| c1 -> e1 | c2 -> e2 | else -> e3` becomes `if c1 then e1 else if c2 then e2 else e3`.
+    /// Without an else branch each branch's value is discarded since the guard returns unit.
+    fn monomorphise_guard(&mut self, guard: &ast::Guard<'c>) -> hir::Ast {
+        let result_type = self.convert_type(guard.typ.as_ref().unwrap());
+        let mut otherwise = guard.otherwise.as_ref().map(|e| Box::new(self.monomorphise(e)));
+
+        for (condition, branch) in guard.branches.iter().rev() {
+            let condition = Box::new(self.monomorphise(condition));
+            let mut then = self.monomorphise(branch);
+
+            if guard.otherwise.is_none() {
+                then = hir::Ast::Sequence(hir::Sequence { statements: vec![then, unit_literal()] });
+            }
+
+            let then = Box::new(then);
+            let result_type = result_type.clone();
+            otherwise = Some(Box::new(hir::Ast::If(hir::If { condition, then, otherwise, result_type })));
+        }
+
+        *otherwise.unwrap()
+    }
+
+    /// Type annotations are erased unless they are a gradual boundary (`lhs : dyn rhs`).
+    /// A mismatch at a gradual boundary is only a warning during type inference since it
+    /// may depend on how a generic function is instantiated. Once monomorphised, both types
+    /// are known so the boundary is checked here rather than at runtime: the value passes
+    /// through unchanged if both types have the same representation, otherwise the cast
+    /// could never succeed and an error is issued.
+    fn monomorphise_type_annotation(&mut self, annotation: &ast::TypeAnnotation<'c>) -> hir::Ast {
+        let lhs = self.monomorphise(&annotation.lhs);
+
+        if annotation.gradual {
+            let lhs_type = self.follow_all_bindings(annotation.lhs.get_type().unwrap());
+            let target_type = self.follow_all_bindings(annotation.typ.as_ref().unwrap());
+
+            if self.convert_type(&lhs_type) != self.convert_type(&target_type) {
+                error!(
+                    annotation.location,
+                    "Cannot cast a value of type {} to {} at this gradual boundary",
+                    lhs_type.display(&self.cache),
+                    target_type.display(&self.cache)
+                );
+            }
+        }
+
+        lhs
+    }
+
+    /// Typed holes are only reported as warnings, so a program may still reach one when it runs.
+    /// There is no value to give in its place so the program is aborted instead.
+    fn monomorphise_hole(&mut self, hole: &ast::Hole<'c>) -> hir::Ast {
+        let typ = self.convert_type(hole.typ.as_ref().unwrap());
+
+        let abort = self.get_abort_function();
+        let abort = hir::Ast::FunctionCall(hir::FunctionCall {
+            function: Box::new(abort.into()),
+            args: vec![unit_literal()],
+            function_type: abort_function_type(),
+        });
+
+        // abort never returns but we still need a value of the hole's type afterward
+        let placeholder = self.make_reinterpret_cast(self.tuple(vec![]), 0, typ);
+        hir::Ast::Sequence(hir::Sequence { statements: vec![abort, placeholder] })
+    }
+
+    fn get_abort_function(&mut self) -> hir::DefinitionInfo {
+        if let Some(abort) = &self.abort_function {
+            return abort.clone();
+        }
+
+        let typ = Type::Function(abort_function_type());
+        let extern_ = hir::Ast::Extern(hir::Extern { name: "abort".into(), typ });
+        let definition = self.make_definition(extern_);
+        self.abort_function = Some(definition.clone());
+        definition
+    }
+
+    fn get_malloc_function(&mut self) -> hir::DefinitionInfo {
+        if let Some(malloc) = &self.malloc_function {
+            return malloc.clone();
+        }
+
+        let typ = Type::Function(malloc_function_type());
+        let extern_ = hir::Ast::Extern(hir::Extern { name: "malloc".into(), typ });
+        let definition = self.make_definition(extern_);
+        self.malloc_function = Some(definition.clone());
+        definition
+    }
+
     fn monomorphise_return(&mut self, return_: &ast::Return<'c>) -> hir::Ast {
         hir::Ast::Return(hir::Return { expression: Box::new(self.monomorphise(&return_.expression)) })
     }
 
     fn monomorphise_sequence(&mut self, sequence: &ast::Sequence<'c>) -> hir::Ast {
+        self.rc_scopes.push(vec![]);
         let statements = fmap(&sequence.statements, |statement| self.monomorphise(statement));
-        hir::Ast::Sequence(hir::Sequence { statements })
+        let sequence = hir::Ast::Sequence(hir::Sequence { statements });
+
+        let rc_variables = self.rc_scopes.pop().unwrap();
+        self.decrement_at_scope_end(sequence, rc_variables)
     }
 
     fn get_field_index(&self, field_name: &str, typ: &types::Type) -> u32 {
@@ -1296,9 +2495,115 @@ impl<'c> Context<'c> {
     }
 
     fn monomorphise_member_access(&mut self, member_access: &ast::MemberAccess<'c>) -> hir::Ast {
-        let index = self.get_field_index(&member_access.field, member_access.lhs.get_type().unwrap());
+        let typ = member_access.lhs.get_type().unwrap();
+        let field_type = member_access.typ.as_ref().unwrap();
         let lhs = self.monomorphise(&member_access.lhs);
-        self.extract(lhs, index)
+
+        match member_access.field.parse() {
+            Ok(index) => self.tuple_element(lhs, typ, index, field_type),
+            Err(_) => {
+                let index = self.get_field_index(&member_access.field, typ);
+                self.struct_field(lhs, typ, index, field_type)
+            },
+        }
+    }
+
+    /// Returns the field at the given index of a struct value of type `typ`
+    fn struct_field(&mut self, lhs: hir::Ast, typ: &types::Type, index: u32, field_type: &types::Type) -> hir::Ast {
+        let field_indices = self.struct_field_indices(typ).unwrap();
+        let boxed = self.recursive_fields(typ, 0)[index as usize];
+
+        match field_indices[index as usize] {
+            Some(index) if boxed => {
+                let field = self.extract(lhs, index);
+                self.unbox_value(field, field_type)
+            },
+            Some(index) => self.extract(lhs, index),
+            // Erased fields are always unit, but the struct is still evaluated
+            None => hir::Ast::Sequence(hir::Sequence { statements: vec![lhs, unit_literal()] }),
+        }
+    }
+
+    /// Returns the element at the given index of a tuple value of type `typ`. Each element but the
+    /// last is the first field of a pair whose second field holds the remaining elements.
+    fn tuple_element(
+        &mut self, mut tuple: hir::Ast, typ: &types::Type, index: usize, element_type: &types::Type,
+    ) -> hir::Ast {
+        let element_type = self.follow_all_bindings(element_type);
+        let mut rest_type = self.follow_all_bindings(typ);
+
+        for _ in 0..index {
+            let second_type = match &rest_type {
+                types::Type::TypeApplication(_, args) => args[1].clone(),
+                other => unreachable!("Expected a tuple type but found {}", other.display(&self.cache)),
+            };
+            tuple = self.struct_field(tuple, &rest_type, 1, &second_type);
+            rest_type = second_type;
+        }
+
+        // The typechecker decides whether this is the last element, which may differ from the length of
+        // the tuple type here if its length was unknown at the time, e.g. `t.1` for a generic tuple `t`.
+        if rest_type == element_type {
+            tuple
+        } else {
+            self.struct_field(tuple, &rest_type, 0, &element_type)
+        }
+    }
+
+    /// Builds a new struct from the fields of the first base, replacing any overridden fields.
+    /// Any other bases have each of their fields overridden and are only evaluated.
+    fn monomorphise_record_spread(&mut self, spread: &ast::RecordSpread<'c>) -> hir::Ast {
+        let typ = spread.typ.as_ref().unwrap();
+        let mut bases = fmap(&spread.bases, |base| self.monomorphise(base));
+        let base = bases.remove(0);
+
+        let field_indices = match self.struct_field_indices(typ) {
+            Some(indices) => indices,
+            // Only a single base with no overrides may have a non-struct type
+            None => return base,
+        };
+
+        let (base_definition, base_id) = self.fresh_definition(base);
+        let field_count = field_indices.iter().flatten().count();
+        let mut fields = fmap(0..field_count, |i| self.extract(base_id.to_variable(), i as u32));
+
+        let mut statements = vec![base_definition];
+        statements.append(&mut bases);
+        let boxed_fields = self.recursive_fields(typ, 0);
+
+        for (field, value) in &spread.overrides {
+            let value_type = self.convert_type(value.get_type().unwrap());
+            let value = self.monomorphise(value);
+            let field_index = self.get_field_index(field, typ) as usize;
+
+            match field_indices[field_index] {
+                Some(index) if boxed_fields[field_index] => fields[index as usize] = self.box_value(value, &value_type),
+                Some(index) => fields[index as usize] = value,
+                // Erased fields are not stored but their new values are still evaluated
+                None => statements.push(value),
+            }
+        }
+
+        statements.push(self.tuple(fields));
+        hir::Ast::Sequence(hir::Sequence { statements })
+    }
+
+    fn monomorphise_where(&mut self, where_: &ast::Where<'c>) -> hir::Ast {
+        let mut statements = fmap(&where_.definitions, |definition| self.monomorphise_definition(definition));
+        statements.push(self.monomorphise(&where_.expression));
+        hir::Ast::Sequence(hir::Sequence { statements })
+    }
+
+    /// Lowers `x is Variant` to a comparison of x's tag against the tag of the variant
+    fn monomorphise_type_test(&mut self, test: &ast::TypeTest<'c>) -> hir::Ast {
+        let expected_tag = typechecker::union_variant_tag(&test.constructor, &self.cache).unwrap();
+        let value = self.monomorphise(&test.expression);
+        let (value_definition, value_id) = self.fresh_definition(value);
+        let tag = self.extract_tag(value_id.into(), test.expression.get_type().unwrap());
+
+        let expected_tag = int_literal(expected_tag as u64, IntegerKind::U8);
+        let comparison = hir::Ast::Builtin(hir::Builtin::EqInt(Box::new(tag), Box::new(expected_tag)));
+        hir::Ast::Sequence(hir::Sequence { statements: vec![value_definition, comparison] })
     }
 
     fn monomorphise_assignment(&mut self, assignment: &ast::Assignment<'c>) -> hir::Ast {
@@ -1365,16 +2670,9 @@ impl<'c> Context<'c> {
                     other => unreachable!("Tried to extract from non-tuple type: {}", other),
                 };
 
+                let offset = self.offset_of_field(&elems, member_index as usize);
                 let field_type = elems.swap_remove(member_index as usize);
 
-                // The element order was changed by swap_remove above, but we only
-                // take the elements that are strictly less than that index
-                let offset: u32 = elems
-                    .into_iter()
-                    .take(member_index as usize)
-                    .map(|typ| self.size_of_monomorphised_type(&typ))
-                    .sum();
-
                 if offset == 0 {
                     Ast::Builtin(Deref(addr, field_type))
                 } else {
@@ -1391,15 +2689,162 @@ impl<'c> Context<'c> {
     }
 }
 
+/// Returns integer types covering each byte from `start` up to `end`. Each integer is no larger
+/// than `alignment` and is placed at an offset which is a multiple of its size.
+fn union_padding(start: u32, end: u32, alignment: u32) -> Vec<Type> {
+    let type_tower = [(IntegerKind::U64, 8), (IntegerKind::U32, 4), (IntegerKind::U16, 2), (IntegerKind::U8, 1)];
+    let mut fields = vec![];
+    let mut offset = start;
+
+    while offset < end {
+        let (kind, size) = type_tower
+            .iter()
+            .copied()
+            .find(|(_, size)| *size <= alignment && offset % size == 0 && offset + size <= end)
+            .unwrap();
+
+        fields.push(Type::Primitive(hir::types::PrimitiveType::Integer(kind)));
+        offset += size;
+    }
+
+    fields
+}
+
+/// A conversion applied to the arguments of externs using the C ABI, see `ExternAbi::C`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum CConversion {
+    /// A string is passed as its `c_string` field, a bare `char*`
+    StringPointer,
+
+    /// A union without any fields is passed as a C enum, i.e. its tag extended to 32 bits
+    EnumTag,
+}
+
+impl CConversion {
+    fn c_type(self) -> Type {
+        match self {
+            CConversion::StringPointer => Type::Primitive(hir::types::PrimitiveType::Pointer),
+            CConversion::EnumTag => Type::Primitive(hir::types::PrimitiveType::Integer(IntegerKind::I32)),
+        }
+    }
+
+    /// Converts the given Ante value to its C representation
+    fn to_c(self, value: hir::Ast) -> hir::Ast {
+        let field = hir::Ast::MemberAccess(hir::MemberAccess { lhs: Box::new(value), member_index: 0 });
+        match self {
+            CConversion::StringPointer => field,
+            CConversion::EnumTag => hir::Ast::Builtin(hir::Builtin::ZeroExtend(Box::new(field), self.c_type())),
+        }
+    }
+
+    /// Converts the given C value back to its Ante representation
+    fn to_ante(self, value: hir::Ast) -> hir::Ast {
+        match self {
+            CConversion::StringPointer => unreachable!("Strings are never converted from C"),
+            CConversion::EnumTag => {
+                let tag = hir::Builtin::Truncate(Box::new(value), Context::tag_type());
+                hir::Ast::Tuple(hir::Tuple { fields: vec![hir::Ast::Builtin(tag)] })
+            },
+        }
+    }
+}
+
+/// The layout of a union with two variants where only one variant carries data,
+/// and that data contains a reference. The union is stored as only the fields of
+/// the variant with data, with a null reference representing the other variant.
+#[derive(Debug, Clone)]
+pub struct NicheLayout {
+    /// The tag of the variant carrying data
+    pub data_tag: u8,
+
+    /// The tag of the variant without data, represented by a null reference
+    pub empty_tag: u8,
+
+    /// The field indices leading to the reference within the variant carrying data
+    pub reference_path: Vec<u32>,
+}
+
+/// Find the definition of the method used at the given callsite within the given impl.
+/// If the impl uses the trait's default definition instead, the default's own uses
+/// of the trait are mapped to the same impl as well.
+fn find_method_in_impl(
+    cache: &ModuleCache, callsite: VariableId, impl_id: ImplInfoId, indirect: &mut IndirectImpls,
+) -> DefinitionInfoId {
+    let method = cache.find_method_in_impl(callsite, impl_id);
+    let trait_id = cache[impl_id].trait_id;
+
+    if cache[method].trait_default == Some(trait_id) {
+        for required in &cache[method].required_traits {
+            if required.signature.trait_id == trait_id {
+                indirect.insert((callsite, required.signature.id), impl_id);
+            }
+        }
+    }
+    method
+}
+
+/// True if the given type, with its bindings already followed, contains no type
+/// variables or inferred integer kinds whose size may vary between instantiations.
+fn is_fully_bound(typ: &types::Type) -> bool {
+    use crate::lexer::token::IntegerKind::{Inferred, Unknown};
+    use types::PrimitiveType::IntegerType;
+    use types::Type::*;
+    match typ {
+        TypeVariable(_) | Record(..) => false,
+        Primitive(IntegerType(Inferred(_) | Unknown)) => false,
+        Primitive(_) => true,
+        Function(function) => {
+            function.parameters.iter().all(is_fully_bound)
+                && is_fully_bound(&function.return_type)
+                && is_fully_bound(&function.environment)
+                && function.varargs_type.as_deref().into_iter().all(is_fully_bound)
+        },
+        UserDefined(_) => true,
+        TypeApplication(constructor, args) => is_fully_bound(constructor) && args.iter().all(is_fully_bound),
+        Ref(_) => true,
+        Forall(..) => false,
+    }
+}
+
+/// True if the given type, whose bindings should already be followed, contains a `forall` type
+fn contains_forall(typ: &types::Type) -> bool {
+    use types::Type::*;
+    match typ {
+        Forall(..) => true,
+        Primitive(_) | UserDefined(_) | TypeVariable(_) | Ref(_) => false,
+        Function(function) => {
+            function.parameters.iter().any(contains_forall)
+                || contains_forall(&function.return_type)
+                || contains_forall(&function.environment)
+                || function.varargs_type.as_deref().into_iter().any(contains_forall)
+        },
+        TypeApplication(constructor, args) => contains_forall(constructor) || args.iter().any(contains_forall),
+        Record(fields, _) => fields.iter().any(|(_, field)| contains_forall(field)),
+    }
+}
+
 fn unit_literal() -> hir::Ast {
     hir::Ast::Literal(hir::Literal::Unit)
 }
 
+/// abort : unit -> unit
+fn abort_function_type() -> hir::FunctionType {
+    let unit = Type::Primitive(hir::PrimitiveType::Unit);
+    hir::FunctionType { parameters: vec![unit.clone()], return_type: Box::new(unit), is_varargs: false }
+}
+
+/// malloc : usz -> Ptr
+fn malloc_function_type() -> hir::FunctionType {
+    let usz = Type::Primitive(hir::PrimitiveType::Integer(IntegerKind::Usz));
+    let pointer = Type::Primitive(hir::PrimitiveType::Pointer);
+    hir::FunctionType { parameters: vec![usz], return_type: Box::new(pointer), is_varargs: false }
+}
+
 fn int_literal(value: u64, kind: IntegerKind) -> hir::Ast {
     hir::Ast::Literal(hir::Literal::Integer(value, kind))
 }
 
-fn tag_value(tag: u8) -> hir::Ast {
+pub fn tag_value(tag: u8) -> hir::Ast {
     int_literal(tag as u64, IntegerKind::U8)
 }
 
@@ -1408,3 +2853,36 @@ pub fn offset_ptr(addr: hir::Ast, offset: u64) -> hir::Ast {
     let offset = int_literal(offset, IntegerKind::Usz);
     hir::Ast::Builtin(hir::Builtin::Offset(addr, Box::new(offset), 1))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nameresolution::NameResolver;
+
+    /// Sizing a type many times gives the same size each time, and sizing a type containing
+    /// `Big` reuses the size cached for `Big` rather than recomputing it from its fields.
+    #[test]
+    fn size_of_type_reuses_cached_sizes() {
+        let contents = "type Big = a: i64, b: i64, c: i64, d: i64\ntype Both a b = first: a, second: b\n";
+        let cache = NameResolver::start_from_source(contents);
+        let location = cache.parse_trees.get_mut(0).unwrap().locate();
+
+        let type_named = |name: &str| {
+            let id = cache.type_infos.iter().position(|info| info.name == name).unwrap();
+            types::Type::UserDefined(TypeInfoId(id))
+        };
+        let big = type_named("Big");
+        let both = types::Type::TypeApplication(Box::new(type_named("Both")), vec![big.clone(), big.clone()]);
+
+        let mut context = Context::new(cache, 8, DEFAULT_INTEGER_KIND, RECURSION_LIMIT, None, None, location);
+        for _ in 0..1000 {
+            assert_eq!(context.size_of_type(&both), 64);
+        }
+        assert_eq!(context.sizes.get(&big), Some(&32));
+
+        // Only `Big` still has a cached size, so `Both Big Big` must be sized from it
+        context.sizes.remove(&both);
+        context.sizes.insert(big, 48);
+        assert_eq!(context.size_of_type(&both), 96);
+    }
+}
@@ -146,6 +146,11 @@ impl FmtAst for Definition {
     fn fmt_ast(&self, printer: &mut AstPrinter, f: &mut Formatter) -> fmt::Result {
         printer.already_printed.insert(self.variable);
 
+        if let Some(name) = &self.name {
+            write!(f, "// {}", name)?;
+            printer.newline(f)?;
+        }
+
         write!(f, "v{} = ", self.variable.0)?;
         printer.block(self.expr.as_ref(), f)
     }
@@ -266,6 +271,10 @@ impl FmtAst for Builtin {
             Builtin::Offset(a, b, size) => printer.fmt_offset(a, b, *size, f),
             Builtin::Transmute(a, b) => printer.fmt_cast("#Transmute", a, b, f),
             Builtin::StackAlloc(value) => printer.fmt_call("#StackAlloc", &[value], f),
+            Builtin::Unreachable(_) => write!(f, "#Unreachable"),
+            Builtin::Assume(condition) => printer.fmt_call("#Assume", &[condition], f),
+            Builtin::IncrementRefCount(value) => printer.fmt_call("#IncrementRefCount", &[value], f),
+            Builtin::DecrementRefCount(value) => printer.fmt_call("#DecrementRefCount", &[value], f),
         }
     }
 }
@@ -1,3 +1,5 @@
+use std::str::FromStr;
+
 use crate::util::fmap;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
@@ -14,6 +16,54 @@ pub enum IntegerKind {
     Usz,
 }
 
+impl IntegerKind {
+    /// Whether an integer literal with the given value fits in this kind on a target with the
+    /// given pointer size in bits. Negative literals are stored as the two's complement of their
+    /// value, so they only fit in signed kinds.
+    pub fn can_represent(self, value: u64, pointer_size_in_bits: u32) -> bool {
+        use IntegerKind::*;
+        let bits = match self {
+            I8 | U8 => 8,
+            I16 | U16 => 16,
+            I32 | U32 => 32,
+            I64 | U64 => 64,
+            Isz | Usz => pointer_size_in_bits,
+        };
+
+        match self {
+            _ if bits >= 64 => true,
+            I8 | I16 | I32 | I64 | Isz => {
+                let max = (1i64 << (bits - 1)) - 1;
+                (-max - 1..=max).contains(&(value as i64))
+            },
+            U8 | U16 | U32 | U64 | Usz => value >> bits == 0,
+        }
+    }
+}
+
+impl FromStr for IntegerKind {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use IntegerKind::*;
+        match s {
+            "i8" => Ok(I8),
+            "i16" => Ok(I16),
+            "i32" => Ok(I32),
+            "i64" => Ok(I64),
+            "isz" => Ok(Isz),
+            "u8" => Ok(U8),
+            "u16" => Ok(U16),
+            "u32" => Ok(U32),
+            "u64" => Ok(U64),
+            "usz" => Ok(Usz),
+            _ => {
+                Err("Unknown integer type. Valid integer types are i8, i16, i32, i64, isz, u8, u16, u32, u64, and usz")
+            },
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub enum PrimitiveType {
     Integer(IntegerKind),
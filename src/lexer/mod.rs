@@ -40,6 +40,7 @@ pub mod token;
 
 use crate::error::location::{EndPosition, Locatable, Location, Position};
 use std::collections::HashMap;
+use std::num::IntErrorKind;
 use std::path::Path;
 use std::str::Chars;
 use token::{IntegerKind, LexerError, Token};
@@ -56,6 +57,10 @@ pub struct Lexer<'cache, 'contents> {
     current_indent_level: usize,
     return_newline: bool, // Hack to always return a newline after an Unindent token
     previous_token_expects_indent: bool,
+    previous_token_was_member_access: bool,
+
+    /// An `extern` may be followed by an ABI string before its indented block of declarations
+    previous_token_was_extern: bool,
     chars: Chars<'contents>,
     keywords: HashMap<&'static str, Token>,
 }
@@ -111,6 +116,7 @@ impl<'cache, 'contents> Lexer<'cache, 'contents> {
             ("unit", Token::UnitType),
             ("ref", Token::Ref),
             ("mut", Token::Mut),
+            ("dyn", Token::Dyn),
             ("true", Token::BooleanLiteral(true)),
             ("false", Token::BooleanLiteral(false)),
             ("and", Token::And),
@@ -118,14 +124,17 @@ impl<'cache, 'contents> Lexer<'cache, 'contents> {
             ("block", Token::Block),
             ("break", Token::Break),
             ("continue", Token::Continue),
+            ("derive", Token::Derive),
             ("do", Token::Do),
             ("else", Token::Else),
             ("extern", Token::Extern),
             ("for", Token::For),
             ("fn", Token::Fn),
+            ("forall", Token::Forall),
             ("given", Token::Given),
             ("if", Token::If),
             ("impl", Token::Impl),
+            ("implicit", Token::Implicit),
             ("import", Token::Import),
             ("in", Token::In),
             ("is", Token::Is),
@@ -138,6 +147,7 @@ impl<'cache, 'contents> Lexer<'cache, 'contents> {
             ("then", Token::Then),
             ("trait", Token::Trait),
             ("type", Token::Type),
+            ("where", Token::Where),
             ("while", Token::While),
             ("with", Token::With),
         ]
@@ -160,6 +170,8 @@ impl<'cache, 'contents> Lexer<'cache, 'contents> {
             current_indent_level: 0,
             return_newline: false,
             previous_token_expects_indent: false,
+            previous_token_was_member_access: false,
+            previous_token_was_extern: false,
             chars,
             keywords: Lexer::get_keywords(),
         }
@@ -175,6 +187,7 @@ impl<'cache, 'contents> Lexer<'cache, 'contents> {
                 | Token::If
                 | Token::Match
                 | Token::Then
+                | Token::Where
                 | Token::While
                 | Token::With
                 | Token::Equal
@@ -190,7 +203,7 @@ impl<'cache, 'contents> Lexer<'cache, 'contents> {
         let ret = self.current;
         self.current = self.next;
         self.next = self.chars.next().unwrap_or('\0');
-        self.current_position.advance(ret == '\n');
+        self.current_position.advance(ret);
         ret
     }
 
@@ -226,10 +239,11 @@ impl<'cache, 'contents> Lexer<'cache, 'contents> {
         self.get_slice_containing_current_token()
     }
 
-    fn lex_integer(&mut self) -> String {
+    /// Lexes the digits of an integer in the given radix, stripping any `_` separators
+    fn lex_integer(&mut self, radix: u32) -> String {
         let start = self.current_position.index;
 
-        while !self.at_end_of_input() && (self.current.is_digit(10) || self.current == '_') {
+        while !self.at_end_of_input() && (self.current.is_digit(radix) || self.current == '_') {
             self.advance();
         }
 
@@ -237,6 +251,13 @@ impl<'cache, 'contents> Lexer<'cache, 'contents> {
         self.file_contents[start..end].replace('_', "")
     }
 
+    /// Lexes the index of a tuple element in a member access like `foo.0.1`.
+    /// This is always an integer so that `0.1` is not lexed as a float.
+    fn lex_tuple_index(&mut self) -> IterElem<'cache> {
+        let index = self.lex_integer(10).parse().unwrap();
+        Some((Token::IntegerLiteral(index, IntegerKind::Unknown), self.locate()))
+    }
+
     fn lex_integer_suffix(&mut self) -> Result<IntegerKind, Token> {
         let start = self.current_position.index;
         while self.current.is_alphanumeric() || self.current == '_' {
@@ -260,22 +281,51 @@ impl<'cache, 'contents> Lexer<'cache, 'contents> {
         }
     }
 
+    fn radix_prefix(&self) -> Option<u32> {
+        match (self.current, self.next) {
+            ('0', 'x') => Some(16),
+            ('0', 'o') => Some(8),
+            ('0', 'b') => Some(2),
+            _ => None,
+        }
+    }
+
     fn lex_number(&mut self) -> IterElem<'cache> {
-        let integer_string = self.lex_integer();
+        if let Some(radix) = self.radix_prefix() {
+            self.advance();
+            self.advance();
+            let integer_string = self.lex_integer(radix);
+            return self.lex_integer_literal(&integer_string, radix);
+        }
+
+        let integer_string = self.lex_integer(10);
 
         if self.current == '.' && self.next.is_digit(10) {
             self.advance();
-            let float_string = integer_string + "." + &self.lex_integer();
+            let float_string = integer_string + "." + &self.lex_integer(10);
 
             let float = float_string.parse().unwrap();
             Some((Token::FloatLiteral(float), self.locate()))
         } else {
-            let integer = integer_string.parse().unwrap();
-            let location = self.locate();
-            match self.lex_integer_suffix() {
-                Ok(suffix) => Some((Token::IntegerLiteral(integer, suffix), location)),
-                Err(lexer_error) => Some((lexer_error, location)),
-            }
+            self.lex_integer_literal(&integer_string, 10)
+        }
+    }
+
+    /// Decodes the given digits into an integer literal token, lexing any integer suffix afterward
+    fn lex_integer_literal(&mut self, digits: &str, radix: u32) -> IterElem<'cache> {
+        let location = self.locate();
+
+        let integer = match u64::from_str_radix(digits, radix) {
+            Ok(integer) => integer,
+            Err(error) if *error.kind() == IntErrorKind::Empty => {
+                return Some((Token::Invalid(LexerError::ExpectedDigits(radix)), location))
+            },
+            Err(_) => return Some((Token::Invalid(LexerError::IntegerTooLarge), location)),
+        };
+
+        match self.lex_integer_suffix() {
+            Ok(suffix) => Some((Token::IntegerLiteral(integer, suffix), location)),
+            Err(lexer_error) => Some((lexer_error, location)),
         }
     }
 
@@ -286,12 +336,12 @@ impl<'cache, 'contents> Lexer<'cache, 'contents> {
             self.lex_number().map(|(token, location)| {
                 (
                     match token {
-                        Token::IntegerLiteral(x, kind) => {
-                            let x = format!("-{}", x).parse::<i64>().unwrap();
-                            Token::IntegerLiteral(x as u64, kind)
+                        Token::IntegerLiteral(x, kind) if x <= i64::MIN.unsigned_abs() => {
+                            Token::IntegerLiteral(x.wrapping_neg(), kind)
                         },
+                        Token::IntegerLiteral(..) => Token::Invalid(LexerError::IntegerTooLarge),
                         Token::FloatLiteral(x) => Token::FloatLiteral(-x),
-                        _ => unreachable!(),
+                        other => other,
                     },
                     location,
                 )
@@ -309,6 +359,7 @@ impl<'cache, 'contents> Lexer<'cache, 'contents> {
         match self.keywords.get(word) {
             Some(keyword) => {
                 self.previous_token_expects_indent = Lexer::should_expect_indent_after_token(keyword);
+                self.previous_token_was_extern = *keyword == Token::Extern;
                 Some((keyword.clone(), location))
             },
             None if is_type => Some((Token::TypeName(word.to_owned()), location)),
@@ -481,9 +532,12 @@ impl<'cache, 'contents> Iterator for Lexer<'cache, 'contents> {
         }
 
         self.previous_token_expects_indent = false;
+        let previous_token_was_member_access = std::mem::take(&mut self.previous_token_was_member_access);
+        let previous_token_was_extern = std::mem::take(&mut self.previous_token_was_extern);
 
         match (self.current, self.next) {
-            (c, _) if c.is_digit(10) => self.lex_number(),
+            (c, _) if c.is_ascii_digit() && previous_token_was_member_access => self.lex_tuple_index(),
+            (c, _) if c.is_ascii_digit() => self.lex_number(),
             (c, _) if c.is_alphanumeric() || c == '_' => self.lex_alphanumeric(),
             ('\0', _) => {
                 if self.current_position.index > self.file_contents.len() {
@@ -492,6 +546,11 @@ impl<'cache, 'contents> Iterator for Lexer<'cache, 'contents> {
                     self.advance_with(Token::EndOfInput)
                 }
             },
+            ('"', _) if previous_token_was_extern => {
+                let abi = self.lex_string();
+                self.previous_token_expects_indent = true;
+                abi
+            },
             ('"', _) => self.lex_string(),
             ('\'', _) => self.lex_char_literal(),
             ('/', '/') => self.lex_singleline_comment(),
@@ -512,6 +571,7 @@ impl<'cache, 'contents> Iterator for Lexer<'cache, 'contents> {
             },
             ('.', _) => {
                 self.previous_token_expects_indent = true;
+                self.previous_token_was_member_access = true;
                 self.advance_with(Token::MemberAccess)
             },
             ('-', _) => self.lex_negative(),
@@ -530,6 +590,8 @@ impl<'cache, 'contents> Iterator for Lexer<'cache, 'contents> {
             ('+', _) => self.advance_with(Token::Add),
             ('[', _) => self.advance_with(Token::BracketLeft),
             (']', _) => self.advance_with(Token::BracketRight),
+            ('{', _) => self.advance_with(Token::BraceLeft),
+            ('}', _) => self.advance_with(Token::BraceRight),
             ('|', _) => self.advance_with(Token::Pipe),
             (':', _) => self.advance_with(Token::Colon),
             (';', _) => self.advance_with(Token::Semicolon),
@@ -21,6 +21,8 @@ pub enum LexerError {
     InvalidCharacterInSignificantWhitespace(char), // Only spaces are allowed in significant whitespace
     InvalidEscapeSequence(char),
     InvalidIntegerSuffx,
+    IntegerTooLarge,
+    ExpectedDigits(u32),  // A radix prefix like `0x` was not followed by any digits in that radix
     IndentChangeTooSmall, // All indentation changes must be >= 2 spaces in size difference relative to the previous level
     UnindentToNewLevel,   // Unindented to a new indent level rather than returning to a previous one
     Expected(char),
@@ -86,6 +88,7 @@ pub enum Token {
     UnitType,
     Ref,
     Mut,
+    Dyn,
 
     // Keywords
     And,
@@ -93,14 +96,17 @@ pub enum Token {
     Block,
     Break,
     Continue,
+    Derive,
     Do,
     Else,
     Extern,
     For,
     Fn,
+    Forall,
     Given,
     If,
     Impl,
+    Implicit,
     Import,
     In,
     Is,
@@ -114,6 +120,7 @@ pub enum Token {
     Trait,
     Type,
     While,
+    Where,
     With,
 
     // Operators
@@ -135,6 +142,8 @@ pub enum Token {
     Add,                // +
     BracketLeft,        // [
     BracketRight,       // ]
+    BraceLeft,          // {
+    BraceRight,         // }
     Pipe,               // |
     Colon,              // :
     Semicolon,          // ;
@@ -197,6 +206,8 @@ impl Display for LexerError {
             },
             InvalidEscapeSequence(c) => write!(f, "Invalid character in escape sequence: '{}' (U+{:x})", c, *c as u32),
             InvalidIntegerSuffx => write!(f, "Invalid suffix after integer literal (expected an integer type like i32 or a non-alphanumeric character)"),
+            IntegerTooLarge => write!(f, "Integer literal is too large to fit in 64 bits"),
+            ExpectedDigits(radix) => write!(f, "Expected base {} digits after the prefix of this integer literal", radix),
             IndentChangeTooSmall => write!(f, "This indent/unindent is too small, it should be at least 2 spaces apart from the previous indentation level"),
             UnindentToNewLevel => write!(f, "This unindent doesn't return to any previous indentation level"),
             Expected(c) => write!(f, "Expected {} (U+{:x}) while lexing", *c, *c as u32),
@@ -205,6 +216,28 @@ impl Display for LexerError {
     }
 }
 
+impl IntegerKind {
+    /// Whether an integer literal with the given value fits in this kind. Negative literals are
+    /// stored as the two's complement of their value, so they only fit in signed kinds.
+    /// This is None for pointer-sized integers, whose size depends on the target, and for
+    /// integers whose kind is not yet known.
+    pub fn can_represent(self, value: u64) -> Option<bool> {
+        use IntegerKind::*;
+        let signed = |min: i64, max: i64| (min..=max).contains(&(value as i64));
+        match self {
+            I8 => Some(signed(i8::MIN as i64, i8::MAX as i64)),
+            I16 => Some(signed(i16::MIN as i64, i16::MAX as i64)),
+            I32 => Some(signed(i32::MIN as i64, i32::MAX as i64)),
+            I64 => Some(true),
+            U8 => Some(value <= u8::MAX as u64),
+            U16 => Some(value <= u16::MAX as u64),
+            U32 => Some(value <= u32::MAX as u64),
+            U64 => Some(true),
+            Isz | Usz | Unknown | Inferred(_) => None,
+        }
+    }
+}
+
 impl Display for IntegerKind {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         use IntegerKind::*;
@@ -256,6 +289,7 @@ impl Display for Token {
             UnitType => write!(f, "'unit'"),
             Ref => write!(f, "'ref'"),
             Mut => write!(f, "'mut'"),
+            Dyn => write!(f, "'dyn'"),
 
             // Keywords
             And => write!(f, "'and'"),
@@ -263,14 +297,17 @@ impl Display for Token {
             Block => write!(f, "'block'"),
             Break => write!(f, "'break'"),
             Continue => write!(f, "'continue'"),
+            Derive => write!(f, "'derive'"),
             Do => write!(f, "'do'"),
             Else => write!(f, "'else'"),
             Extern => write!(f, "'extern'"),
             For => write!(f, "'for'"),
             Fn => write!(f, "'fn'"),
+            Forall => write!(f, "'forall'"),
             Given => write!(f, "'given'"),
             If => write!(f, "'if'"),
             Impl => write!(f, "'impl'"),
+            Implicit => write!(f, "'implicit'"),
             Import => write!(f, "'import'"),
             In => write!(f, "'in'"),
             Is => write!(f, "'is'"),
@@ -284,6 +321,7 @@ impl Display for Token {
             Trait => write!(f, "'trait'"),
             Type => write!(f, "'type'"),
             While => write!(f, "'while'"),
+            Where => write!(f, "'where'"),
             With => write!(f, "'with'"),
 
             // Operators
@@ -305,6 +343,8 @@ impl Display for Token {
             Add => write!(f, "'+'"),
             BracketLeft => write!(f, "'['"),
             BracketRight => write!(f, "']'"),
+            BraceLeft => write!(f, "'{{'"),
+            BraceRight => write!(f, "'}}'"),
             Pipe => write!(f, "'|'"),
             Colon => write!(f, "':'"),
             Semicolon => write!(f, "';'"),
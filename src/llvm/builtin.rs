@@ -16,6 +16,14 @@ use inkwell::values::{BasicValue, BasicValueEnum, IntValue};
 use inkwell::{AddressSpace, FloatPredicate, IntPredicate};
 
 pub fn call_builtin<'g>(builtin: &Builtin, generator: &mut Generator<'g>) -> BasicValueEnum<'g> {
+    // Reference counting operations are inserted into user code rather than being called from
+    // the prelude, so handle them before marking the current function as alwaysinline below.
+    // There is no runtime support for reference counting yet so these are currently no-ops.
+    if let Builtin::IncrementRefCount(a) | Builtin::DecrementRefCount(a) = builtin {
+        a.codegen(generator);
+        return generator.unit_value();
+    }
+
     let current_function = generator.current_function();
     let always_inline = Attribute::get_named_enum_kind_id("alwaysinline");
     assert_ne!(always_inline, 0);
@@ -65,6 +73,9 @@ pub fn call_builtin<'g>(builtin: &Builtin, generator: &mut Generator<'g>) -> Bas
         Builtin::Offset(a, b, size) => offset(a, int(b), *size, generator),
         Builtin::Transmute(a, _typ) => transmute_value(a, generator),
         Builtin::StackAlloc(a) => stack_alloc(a, generator),
+        Builtin::Unreachable(typ) => unreachable(typ, generator),
+        Builtin::Assume(a) => assume(int(a), generator),
+        Builtin::IncrementRefCount(_) | Builtin::DecrementRefCount(_) => unreachable!(),
     }
 }
 
@@ -244,3 +255,22 @@ fn stack_alloc<'g>(x: &Ast, generator: &mut Generator<'g>) -> BasicValueEnum<'g>
 
     generator.builder.build_pointer_cast(alloca, opaque_ptr_type, "bitcast").as_basic_value_enum()
 }
+
+fn unreachable<'g>(typ: &Type, generator: &mut Generator<'g>) -> BasicValueEnum<'g> {
+    generator.builder.build_unreachable();
+
+    // The block is terminated but we still need to return a value of the expected type
+    let typ = generator.convert_type(typ);
+    Generator::undef_value(typ)
+}
+
+fn assume<'g>(condition: IntValue<'g>, generator: &mut Generator<'g>) -> BasicValueEnum<'g> {
+    let assume = generator.module.get_function("llvm.assume").unwrap_or_else(|| {
+        let bool_type = generator.context.bool_type();
+        let function_type = generator.context.void_type().fn_type(&[bool_type.into()], false);
+        generator.module.add_function("llvm.assume", function_type, None)
+    });
+
+    generator.builder.build_call(assume, &[condition.into()], "");
+    generator.unit_value()
+}
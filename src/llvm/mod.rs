@@ -58,6 +58,10 @@ pub struct Generator<'context> {
     auto_derefs: HashSet<DefinitionId>,
 
     current_function_info: Option<DefinitionId>,
+
+    /// The symbol name of the definition we're currently compiling, if it has one.
+    /// The next function created will be given this name.
+    current_function_name: Option<String>,
 }
 
 /// Codegen the given Ast, producing a binary file at the given path.
@@ -78,6 +82,7 @@ pub fn run(path: &Path, ast: hir::Ast, args: &Args) {
         definitions: HashMap::new(),
         auto_derefs: HashSet::new(),
         current_function_info: None,
+        current_function_name: None,
     };
 
     // Codegen main, and all functions reachable from it
@@ -261,7 +266,7 @@ impl<'g> Generator<'g> {
             hir::Type::Function(f) => self.convert_function_type(f).into(),
             hir::Type::Tuple(tuple) => {
                 let fields = fmap(tuple, |typ| self.convert_type(typ));
-                self.context.struct_type(&fields, true).into()
+                self.context.struct_type(&fields, false).into()
             },
         }
     }
@@ -380,7 +385,7 @@ impl<'g> Generator<'g> {
     fn tuple(
         &mut self, elements: Vec<BasicValueEnum<'g>>, element_types: Vec<BasicTypeEnum<'g>>,
     ) -> BasicValueEnum<'g> {
-        let tuple_type = self.context.struct_type(&element_types, true);
+        let tuple_type = self.context.struct_type(&element_types, false);
 
         // LLVM wants the const elements to be included in the struct literal itself.
         // Attempting to do build_insert_value would a const value will return the struct as-is
@@ -491,7 +496,8 @@ impl<'g> CodeGen<'g> for hir::Variable {
 impl<'g> CodeGen<'g> for hir::Lambda {
     fn codegen(&self, generator: &mut Generator<'g>) -> BasicValueEnum<'g> {
         let caller_block = generator.current_block();
-        let (function, function_value) = generator.function("lambda", &self.typ);
+        let name = generator.current_function_name.take().unwrap_or_else(|| "lambda".to_owned());
+        let (function, function_value) = generator.function(&name, &self.typ);
 
         // Bind each parameter node to the nth parameter of `function`
         for (i, (parameter, _)) in self.args.iter().enumerate() {
@@ -536,6 +542,7 @@ impl<'g> CodeGen<'g> for hir::Definition {
             }
 
             generator.current_function_info = Some(self.variable);
+            generator.current_function_name = self.name.clone();
             let value = self.expr.codegen(generator);
             generator.definitions.insert(self.variable, value);
         }
@@ -631,9 +638,11 @@ impl<'g> CodeGen<'g> for hir::Extern {
         if matches!(&self.typ, hir::Type::Function(_)) {
             let function_type = llvm_type.into_pointer_type().get_element_type().into_function_type();
 
-            generator
-                .module
-                .add_function(name, function_type, Some(Linkage::External))
+            // The same extern may be declared more than once, e.g. `malloc` is declared by both
+            // the prelude and monomorphisation when allocating recursive fields.
+            let function = generator.module.get_function(name);
+            function
+                .unwrap_or_else(|| generator.module.add_function(name, function_type, Some(Linkage::External)))
                 .as_global_value()
                 .as_basic_value_enum()
         } else {
@@ -34,7 +34,7 @@ mod types;
 #[cfg(feature = "llvm")]
 mod llvm;
 
-use cache::ModuleCache;
+use cache::{ImplInfoId, ModuleCache};
 use lexer::Lexer;
 use nameresolution::NameResolver;
 
@@ -50,8 +50,9 @@ static ALLOCATOR: mimalloc::MiMalloc = mimalloc::MiMalloc;
 
 /// Called when the "--check --show-types" command-line flags are given.
 /// Iterates through each Definition from the first compiled module (so excluding imports)
-/// and prints the type and required traits for each.
-fn print_definition_types(cache: &ModuleCache) {
+/// and prints the type and required traits for each. With `--show-callsites`, each
+/// required trait is also annotated with the kind of callsite it originates from.
+fn print_definition_types(cache: &ModuleCache, verbose_callsites: bool) {
     let resolver = cache.name_resolvers.get_mut(0).unwrap();
     let mut definitions = resolver.exports.definitions.iter().collect::<Vec<_>>();
 
@@ -63,13 +64,43 @@ fn print_definition_types(cache: &ModuleCache) {
 
         print!("{} : ", name);
         if let Some(typ) = &info.typ {
-            types::typeprinter::show_type_and_traits(typ, &info.required_traits, &info.trait_info, cache);
+            let (traits, trait_info) = (&info.required_traits, &info.trait_info);
+            types::typeprinter::show_type_and_traits(typ, traits, trait_info, verbose_callsites, cache);
         } else {
             println!("(none)");
         }
     }
 }
 
+/// Called when the "--check --show-obligations" command-line flags are given.
+/// Iterates through each Definition exported from the first compiled module and prints
+/// the traits a downstream caller must satisfy to use it.
+fn print_definition_obligations(cache: &ModuleCache) {
+    let resolver = cache.name_resolvers.get_mut(0).unwrap();
+    let mut definitions = resolver.exports.definitions.iter().collect::<Vec<_>>();
+
+    // Make sure the output has a deterministic order for testing
+    definitions.sort();
+
+    for (name, definition_id) in definitions {
+        print!("{} : ", name);
+        match types::traits::Obligations::of(*definition_id, cache) {
+            Some(obligations) => types::typeprinter::show_obligations(&obligations, cache),
+            None => println!("(none)"),
+        }
+    }
+}
+
+/// Called when the "--check --show-impls" command-line flags are given.
+/// Prints each trait impl defined in the first compiled module.
+fn print_impls(filename: &Path, cache: &ModuleCache) {
+    for (i, info) in cache.impl_infos.iter().enumerate() {
+        if info.location.filename == filename {
+            types::typeprinter::show_impl(ImplInfoId(i), cache);
+        }
+    }
+}
+
 /// Convenience macro for unwrapping a Result or printing an error message and returning () on Err.
 macro_rules! expect {( $result:expr , $fmt_string:expr $( , $($msg:tt)* )? ) => ({
     match $result {
@@ -95,7 +126,9 @@ pub fn main() {
     let mut contents = String::new();
     expect!(reader.read_to_string(&mut contents), "Failed to read {} into a string\n", filename.display());
 
-    error::color_output(!args.no_color);
+    // Setting NO_COLOR disables color in the same way as --no-color, see https://no-color.org
+    let no_color_env = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    error::color_output(!args.no_color && !no_color_env);
     util::timing::time_passes(args.show_time);
 
     // Phase 1: Lexing
@@ -109,7 +142,9 @@ pub fn main() {
 
     // Phase 2: Parsing
     util::timing::start_time("Parsing");
-    let root = expect!(parser::parse(&tokens), "");
+    let result = parser::parse(&tokens);
+    error::flush_diagnostics();
+    let root = expect!(result, "");
 
     if args.parse {
         println!("{}", root);
@@ -119,22 +154,42 @@ pub fn main() {
     // Phase 3: Name resolution
     // Timing for name resolution is within the start method to
     // break up the declare and define passes
-    expect!(NameResolver::start(root, &mut cache), "");
+    let result = NameResolver::start(root, &mut cache);
+    error::flush_diagnostics();
+    expect!(result, "");
 
     // Phase 4: Type inference
     util::timing::start_time("Type Inference");
     let ast = cache.parse_trees.get_mut(0).unwrap();
     types::typechecker::infer_ast(ast, &mut cache);
+    error::flush_diagnostics();
 
     if args.show_types {
-        print_definition_types(&cache);
+        print_definition_types(&cache, args.show_callsites);
+    }
+
+    if args.export_types {
+        print!("{}", types::typejson::export_definitions(&cache));
+    }
+
+    if args.show_obligations {
+        print_definition_obligations(&cache);
+    }
+
+    if args.show_impls {
+        print_impls(filename, &cache);
     }
 
     if args.check || error::get_error_count() != 0 {
         return;
     }
 
-    let hir = hir::monomorphise(ast, cache);
+    let hir = hir::monomorphise(ast, cache, &args);
+    error::flush_diagnostics();
+    if error::get_error_count() != 0 {
+        return;
+    }
+
     if args.show_hir {
         println!("{}", hir);
     }
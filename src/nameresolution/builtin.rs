@@ -51,6 +51,7 @@ pub fn define_builtins(cache: &mut ModuleCache) {
         return_type: Box::new(Type::TypeVariable(a)),
         environment: Box::new(Type::Primitive(PrimitiveType::UnitType)),
         is_varargs: true,
+        varargs_type: None,
     });
 
     let builtin_type = GeneralizedType::PolyType(vec![a], builtin_fn_type);
@@ -117,6 +118,7 @@ fn define_string(cache: &mut ModuleCache) -> Type {
         return_type: Box::new(string.clone()),
         environment: Box::new(Type::Primitive(PrimitiveType::UnitType)),
         is_varargs: false,
+        varargs_type: None,
     });
 
     cache.definition_infos[constructor.0].typ = Some(GeneralizedType::MonoType(constructor_type));
@@ -157,6 +159,7 @@ fn define_pair(cache: &mut ModuleCache) {
         return_type: pair_a_b,
         environment: Box::new(Type::Primitive(PrimitiveType::UnitType)),
         is_varargs: false,
+        varargs_type: None,
     });
 
     let constructor_type = GeneralizedType::PolyType(vec![a, b], constructor_type);
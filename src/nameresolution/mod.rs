@@ -38,18 +38,20 @@
 //!   - `impl_id: Option<ImplInfoId>` for `ast::TraitImpl`s
 //!   - `module_id: Option<ModuleId>` for `ast::Import`s,
 use crate::cache::{DefinitionInfoId, ModuleCache, ModuleId};
-use crate::cache::{DefinitionKind, ImplInfoId, TraitInfoId};
+use crate::cache::{DefinitionKind, ExternAbi, ImplInfoId, TraitInfoId};
 use crate::error::{
     self,
     location::{Locatable, Location},
 };
-use crate::lexer::{token::Token, Lexer};
+use crate::lexer::token::{IntegerKind, Token};
+use crate::lexer::Lexer;
 use crate::nameresolution::scope::{FunctionScopes, Scope};
 use crate::parser::{self, ast, ast::Ast};
-use crate::types::traits::ConstraintSignature;
+use crate::types::traits::{ConstraintSignature, GivenConstraint};
+use crate::types::typechecker;
 use crate::types::typed::Typed;
 use crate::types::{
-    Field, FunctionType, GeneralizedType, LetBindingLevel, PrimitiveType, Type, TypeConstructor, TypeInfoBody,
+    Field, FunctionType, GeneralizedType, Kind, LetBindingLevel, PrimitiveType, Type, TypeConstructor, TypeInfoBody,
     TypeInfoId, TypeVariableId, INITIAL_LEVEL, STRING_TYPE,
 };
 use crate::util::{fmap, timing, trustme};
@@ -57,6 +59,7 @@ use crate::util::{fmap, timing, trustme};
 use colored::Colorize;
 
 use std::borrow::Cow;
+use std::collections::HashSet;
 use std::fs::File;
 use std::io::{BufReader, Read};
 use std::path::{Path, PathBuf};
@@ -135,10 +138,6 @@ pub struct NameResolver {
     /// E.g. (a, b) = mut ...
     in_mutable_context: bool,
 
-    /// True if we're recursing within the lhs of an assignment.
-    /// In this case, we need to assert all variables we find are mutable.
-    in_assignment_context: bool,
-
     /// The trait we're currently declaring. While this is Some(id) all
     /// declarations will be declared as part of the trait.
     current_trait: Option<TraitInfoId>,
@@ -155,6 +154,13 @@ pub struct NameResolver {
     /// Keeps track of all the definitions collected within a pattern so they
     /// can all be tagged with the expression they were defined as later
     definitions_collected: Vec<DefinitionInfoId>,
+
+    /// Every implicit parameter and `implicit` definition in this module. Only these
+    /// may be used to fill in the implicit parameters of a function call.
+    implicit_definitions: HashSet<DefinitionInfoId>,
+
+    /// The default integer type of the innermost definition we're in that declared one, if any
+    default_integer_kind: Option<IntegerKind>,
 }
 
 impl PartialEq for NameResolver {
@@ -167,20 +173,14 @@ macro_rules! lookup_fn {
     ( $name:ident , $stack_field:ident , $cache_field:ident, $return_type:ty ) => {
         fn $name<'c>(&self, name: &str, cache: &mut ModuleCache<'c>) -> Option<$return_type> {
             let function_scope = self.scopes.last().unwrap();
-            for stack in function_scope.iter().rev() {
-                if let Some(id) = stack.$stack_field.get(name) {
-                    cache.$cache_field[id.0].uses += 1;
-                    return Some(*id);
-                }
-            }
+            let local = function_scope.iter().rev().find_map(|stack| stack.$stack_field.get(name));
 
             // Check globals/imports in global scope
-            if let Some(id) = self.global_scope().$stack_field.get(name) {
-                cache.$cache_field[id.0].uses += 1;
-                return Some(*id);
-            }
-
-            None
+            let id = *local.or_else(|| self.global_scope().$stack_field.get(name))?;
+            cache.$cache_field[id.0].uses += 1;
+            let location = cache.$cache_field[id.0].location;
+            cache.mark_module_used_at(self.module_id, location);
+            Some(id)
         }
     };
 }
@@ -201,6 +201,10 @@ impl NameResolver {
         for stack in current_function_scope.iter().rev() {
             if let Some(&id) = stack.definitions.get(name) {
                 cache.definition_infos[id.0].uses += 1;
+                // The current function scope is the global scope when referencing top-level imports
+                if let Some(module_id) = cache.definition_infos[id.0].module {
+                    cache.mark_module_used(self.module_id, module_id);
+                }
                 return Some(id);
             }
         }
@@ -220,9 +224,12 @@ impl NameResolver {
         }
 
         // Otherwise, check globals/imports
-        if let Some(id) = self.global_scope().definitions.get(name) {
+        if let Some(&id) = self.global_scope().definitions.get(name) {
             cache.definition_infos[id.0].uses += 1;
-            return Some(*id);
+            if let Some(module_id) = cache.definition_infos[id.0].module {
+                cache.mark_module_used(self.module_id, module_id);
+            }
+            return Some(id);
         }
 
         None
@@ -259,6 +266,9 @@ impl NameResolver {
 
             let to = self.add_closure_parameter_definition(environment_name, mutable, next_fn, location, cache);
             self.scopes[next_fn].add_closure_environment_variable_mapping(environment, to, location, cache);
+            if self.implicit_definitions.contains(&environment) {
+                self.implicit_definitions.insert(to);
+            }
             environment = to;
             ret = Some(to);
         }
@@ -266,6 +276,63 @@ impl NameResolver {
         ret.unwrap()
     }
 
+    /// Mark the variable bound by an implicit parameter or `implicit` definition
+    /// so that it may be used to supply the implicit parameters of function calls.
+    fn mark_implicit(&mut self, pattern: &Ast) {
+        match pattern {
+            Ast::Variable(ast::Variable { definition: Some(id), .. }) => {
+                self.implicit_definitions.insert(*id);
+            },
+            Ast::TypeAnnotation(annotation) => self.mark_implicit(&annotation.lhs),
+            _ => error!(pattern.locate(), "Implicit definitions must define a single variable"),
+        }
+    }
+
+    /// Prepend an argument for each implicit parameter of the function being called.
+    /// Each argument refers to the nearest implicit definition of the same name, so
+    /// functions with implicit parameters of their own pass them along automatically.
+    ///
+    /// Only direct calls to a function are supplied their implicit arguments.
+    fn supply_implicit_arguments<'c>(&mut self, call: &mut ast::FunctionCall<'c>, cache: &mut ModuleCache<'c>) {
+        let implicit_parameters = match call.function.as_ref() {
+            Ast::Variable(ast::Variable { definition: Some(id), .. }) => {
+                match &cache.definition_infos[id.0].definition {
+                    Some(DefinitionKind::Definition(definition)) => match definition.expr.as_ref() {
+                        Ast::Lambda(lambda) => lambda.implicit_parameters.clone(),
+                        _ => return,
+                    },
+                    _ => return,
+                }
+            },
+            _ => return,
+        };
+
+        let location = call.function.locate();
+        let mut implicit_args = Vec::with_capacity(implicit_parameters.len());
+
+        for name in implicit_parameters {
+            match self.reference_definition(&name, location, cache) {
+                Some(id) if self.implicit_definitions.contains(&id) => {
+                    let mut arg = Ast::variable(name.clone(), location);
+                    if let Ast::Variable(variable) = &mut arg {
+                        variable.definition = Some(id);
+                        variable.id = Some(cache.push_variable(name, location));
+                        variable.impl_scope = Some(self.current_scope().impl_scope);
+                    }
+                    implicit_args.push(arg);
+                },
+                Some(id) => {
+                    error!(location, "No implicit {} is in scope for this call to {}", name, call.function);
+                    let definition_location = cache.definition_infos[id.0].location;
+                    note!(definition_location, "{} is defined here but is not implicit", name);
+                },
+                None => error!(location, "No implicit {} is in scope for this call to {}", name, call.function),
+            }
+        }
+
+        call.args.splice(0..0, implicit_args);
+    }
+
     fn add_closure_parameter_definition<'c>(
         &mut self, parameter: &str, mutable: bool, function_scope_index: usize, location: Location<'c>,
         cache: &mut ModuleCache<'c>,
@@ -324,6 +391,15 @@ impl NameResolver {
         self.type_variable_scopes[top].push_existing_type_variable(key, id)
     }
 
+    fn push_kinded_type_variable(&mut self, key: String, id: TypeVariableId) -> TypeVariableId {
+        let top = self.type_variable_scopes.len() - 1;
+        self.type_variable_scopes[top].push_kinded_type_variable(key, id)
+    }
+
+    fn has_known_kind(&self, id: TypeVariableId) -> bool {
+        self.type_variable_scopes.iter().any(|scope| scope.has_known_kind(id))
+    }
+
     fn push_new_type_variable<'c>(&mut self, key: String, cache: &mut ModuleCache<'c>) -> TypeVariableId {
         let id = cache.next_type_variable_id(self.let_binding_level);
         self.push_existing_type_variable(key, id)
@@ -364,8 +440,29 @@ impl NameResolver {
         self.scopes[0].first()
     }
 
+    /// True if we are in the outermost scope of the module. Scopes nested
+    /// within the module, e.g. those of where clauses, are not global.
     fn in_global_scope(&self) -> bool {
-        self.scopes.len() == 1
+        self.scopes.len() == 1 && self.scopes[0].scopes.len() == 1
+    }
+
+    /// The names of each variable defined in a non-global scope that is currently visible, sorted by name.
+    /// Names generated by the compiler, e.g. for explicitly curried arguments, are excluded.
+    fn local_definition_names(&self) -> Vec<String> {
+        // The first scope of the first function is the global scope
+        let mut names: Vec<String> = self
+            .scopes
+            .iter()
+            .enumerate()
+            .flat_map(|(i, function)| function.iter().skip(if i == 0 { 1 } else { 0 }))
+            .flat_map(|scope| scope.definitions.keys())
+            .filter(|name| !name.starts_with('$'))
+            .cloned()
+            .collect();
+
+        names.sort();
+        names.dedup();
+        names
     }
 
     fn push_let_binding_level(&mut self) {
@@ -402,16 +499,79 @@ impl NameResolver {
         info.trait_info = Some((trait_id, args));
     }
 
+    /// True if we're currently declaring a trait method and the given
+    /// definition is a method of a different trait.
+    fn is_method_of_other_trait(&self, definition: DefinitionInfoId, cache: &ModuleCache) -> bool {
+        match (self.current_trait, &cache.definition_infos[definition.0].trait_info) {
+            (Some(current_trait), Some((trait_id, _))) => current_trait != *trait_id,
+            _ => false,
+        }
+    }
+
+    /// Trait methods that share a name with a method of another trait in scope
+    /// must be qualified with their trait name when used, e.g. `Show.show`.
+    fn check_for_ambiguous_trait_method(
+        &self, name: &str, definition: DefinitionInfoId, location: Location, cache: &ModuleCache,
+    ) {
+        let methods = match self.global_scope().ambiguous_trait_methods.get(name) {
+            Some(methods) if methods.contains(&definition) => methods,
+            _ => return,
+        };
+
+        let mut traits = fmap(methods, |method| {
+            let (trait_id, _) = cache.definition_infos[method.0].trait_info.as_ref().unwrap();
+            cache.trait_infos[trait_id.0].name.clone()
+        });
+        traits.sort();
+
+        error!(location, "{} is ambiguous, it is a method of each of these traits: {}", name, traits.join(", "));
+        note!(location, "Qualify the method with its trait to disambiguate, e.g. {}.{}", traits[0], name);
+    }
+
+    /// Lookup the method of the given trait for a qualified trait method, e.g. `Show.show`
+    fn lookup_trait_method<'c>(
+        &self, trait_name: &str, method: &str, location: Location<'c>, cache: &mut ModuleCache<'c>,
+    ) -> Option<DefinitionInfoId> {
+        let trait_id = match self.lookup_trait(trait_name, cache) {
+            Some(id) => id,
+            None => {
+                error!(location, "Trait {} was not found in scope", trait_name);
+                return None;
+            },
+        };
+
+        let definitions = &cache.trait_infos[trait_id.0].definitions;
+        match definitions.iter().find(|id| cache.definition_infos[id.0].name == method) {
+            Some(&id) => {
+                cache.definition_infos[id.0].uses += 1;
+                Some(id)
+            },
+            None => {
+                error!(location, "Trait {} has no method named {}", trait_name, method);
+                None
+            },
+        }
+    }
+
     /// Push a new Definition onto the current scope.
     fn push_definition<'c>(
         &mut self, name: &str, mutable: bool, cache: &mut ModuleCache<'c>, location: Location<'c>,
     ) -> DefinitionInfoId {
         let id = cache.push_definition(name, mutable, location);
+        cache.definition_infos[id.0].module = Some(self.module_id);
 
-        if let Some(existing_definition) = self.current_scope().definitions.get(name) {
-            error!(location, "{} is already in scope", name);
-            let previous_location = cache.definition_infos[existing_definition.0].location;
-            note!(previous_location, "{} previously defined here", name);
+        if let Some(&existing_definition) = self.current_scope().definitions.get(name) {
+            if self.is_method_of_other_trait(existing_definition, cache) {
+                // Methods of different traits may share a name, though they must be qualified when used
+                self.current_scope().add_ambiguous_trait_methods(name, &[existing_definition, id]);
+                if self.in_global_scope() {
+                    self.exports.add_ambiguous_trait_methods(name, &[existing_definition, id]);
+                }
+            } else {
+                error!(location, "{} is already in scope", name);
+                let previous_location = cache.definition_infos[existing_definition.0].location;
+                note!(previous_location, "{} previously defined here", name);
+            }
         }
 
         if self.required_definitions.is_some() {
@@ -475,7 +635,7 @@ impl NameResolver {
     #[allow(clippy::too_many_arguments)]
     fn push_trait_impl<'c>(
         &mut self, trait_id: TraitInfoId, args: Vec<Type>, definitions: Vec<DefinitionInfoId>,
-        trait_impl: &'c mut ast::TraitImpl<'c>, given: Vec<ConstraintSignature>, cache: &mut ModuleCache<'c>,
+        trait_impl: &'c mut ast::TraitImpl<'c>, given: Vec<GivenConstraint>, cache: &mut ModuleCache<'c>,
         location: Location<'c>,
     ) -> ImplInfoId {
         // Any overlapping impls are only reported when they're used during typechecking
@@ -510,6 +670,18 @@ impl<'c> NameResolver {
         }
     }
 
+    /// Lexes and parses the given source then resolves it as the main module of a new
+    /// cache, panicking on any error. The resolved ast is `cache.parse_trees[0]`.
+    /// This lets unit tests of later passes start from a source string.
+    #[cfg(test)]
+    pub fn start_from_source(contents: &'c str) -> ModuleCache<'c> {
+        let mut cache = ModuleCache::new(Path::new("."));
+        let tokens = Lexer::new(Path::new("test.an"), contents).collect::<Vec<_>>();
+        let root = parser::parse(&tokens).unwrap();
+        NameResolver::start(root, &mut cache).unwrap();
+        cache
+    }
+
     /// Creates a NameResolver and performs the declare pass on
     /// the given ast, collecting all of its publically exported symbols
     /// into the `exports` field.
@@ -530,11 +702,12 @@ impl<'c> NameResolver {
             state: NameResolutionState::DeclareInProgress,
             auto_declare: false,
             in_mutable_context: false,
-            in_assignment_context: false,
             current_trait: None,
             required_definitions: None,
             current_function: None,
             definitions_collected: vec![],
+            implicit_definitions: HashSet::new(),
+            default_integer_kind: None,
             let_binding_level: LetBindingLevel(INITIAL_LEVEL),
             module_id,
         };
@@ -579,12 +752,13 @@ impl<'c> NameResolver {
             ast::Type::Pointer(_) => Type::Primitive(PrimitiveType::Ptr),
             ast::Type::Boolean(_) => Type::Primitive(PrimitiveType::BooleanType),
             ast::Type::Unit(_) => Type::Primitive(PrimitiveType::UnitType),
-            ast::Type::Function(args, ret, is_varargs, _) => {
-                let parameters = fmap(args, |arg| self.convert_type(cache, arg));
-                let return_type = Box::new(self.convert_type(cache, ret));
+            ast::Type::Function(args, ret, is_varargs, varargs_type, _) => {
+                let parameters = fmap(args, |arg| self.convert_value_type(cache, arg));
+                let return_type = Box::new(self.convert_value_type(cache, ret));
                 let environment = Box::new(Type::Primitive(PrimitiveType::UnitType));
                 let is_varargs = *is_varargs;
-                Type::Function(FunctionType { parameters, return_type, environment, is_varargs })
+                let varargs_type = varargs_type.as_ref().map(|typ| Box::new(self.convert_value_type(cache, typ)));
+                Type::Function(FunctionType { parameters, return_type, environment, is_varargs, varargs_type })
             },
             ast::Type::TypeVariable(name, location) => match self.lookup_type_variable(name) {
                 Some(id) => Type::TypeVariable(id),
@@ -605,13 +779,14 @@ impl<'c> NameResolver {
                     Type::Primitive(PrimitiveType::UnitType)
                 },
             },
-            ast::Type::TypeApplication(constructor, args, _) => {
-                let constructor = Box::new(self.convert_type(cache, constructor));
-                let args = fmap(args, |arg| self.convert_type(cache, arg));
-                Type::TypeApplication(constructor, args)
+            ast::Type::TypeApplication(ast_constructor, ast_args, _) => {
+                let constructor = self.convert_type(cache, ast_constructor);
+                let args = fmap(ast_args, |arg| self.convert_type(cache, arg));
+                self.check_type_application_kinds(&constructor, &args, ast_constructor, ast_args, cache);
+                Type::TypeApplication(Box::new(constructor), args)
             },
             ast::Type::Pair(first, rest, location) => {
-                let args = vec![self.convert_type(cache, first), self.convert_type(cache, rest)];
+                let args = vec![self.convert_value_type(cache, first), self.convert_value_type(cache, rest)];
 
                 let pair = match self.lookup_type(&Token::Comma.to_string(), cache) {
                     Some(id) => Type::UserDefined(id),
@@ -623,15 +798,105 @@ impl<'c> NameResolver {
 
                 Type::TypeApplication(Box::new(pair), args)
             },
-            ast::Type::Reference(_) => {
+            ast::Type::Reference(None, _) => {
                 // When translating ref types, all have a hidden lifetime variable that is unified
                 // under the hood by the compiler to determine the reference's stack lifetime.
-                // This is never able to be manually specified by the programmer, so we use
-                // next_type_variable_id on the cache rather than the NameResolver's version which
-                // would add a name into scope.
+                // An unnamed region can never be referred to again, so we use next_type_variable_id
+                // on the cache rather than the NameResolver's version which would add a name into scope.
                 let lifetime_variable = cache.next_type_variable_id(self.let_binding_level);
                 Type::Ref(lifetime_variable)
             },
+            ast::Type::Reference(Some(region), location) => {
+                // Named regions are scoped like type variables so that each `ref{r}` within the same
+                // signature shares one lifetime variable. They are keyed as `{r}` since this can never
+                // clash with the name of a type variable.
+                let key = format!("{{{}}}", region);
+                match self.lookup_type_variable(&key) {
+                    Some(id) => Type::Ref(id),
+                    None if self.auto_declare => Type::Ref(self.push_new_type_variable(key, cache)),
+                    None => {
+                        error!(*location, "Region {} was not found in scope", region);
+                        Type::Ref(cache.next_type_variable_id(self.let_binding_level))
+                    },
+                }
+            },
+            ast::Type::Forall(names, typ, _) => {
+                self.push_type_variable_scope();
+                let variables = fmap(names, |name| self.push_new_type_variable(name.clone(), cache));
+                let typ = self.convert_type(cache, typ);
+                self.pop_type_variable_scope();
+                Type::Forall(variables, Box::new(typ))
+            },
+            ast::Type::Record(fields, location) => {
+                let mut names = HashSet::new();
+                let fields = fmap(fields, |(name, typ)| {
+                    if !names.insert(name) {
+                        error!(*location, "Field {} is repeated in this record type", name);
+                    }
+                    (name.clone(), self.convert_value_type(cache, typ))
+                });
+                // Like the lifetime variables of refs, the struct a record stands in for is never named
+                let row = cache.next_type_variable_id(self.let_binding_level);
+                Type::Record(fields, row)
+            },
+        }
+    }
+
+    /// Converts an ast::Type which must be the type of a value, such as the type of a parameter
+    /// or field. Type constructors not applied to each of their arguments are reported here so
+    /// that monomorphisation never encounters a type application of the wrong arity.
+    pub fn convert_value_type(&mut self, cache: &mut ModuleCache<'c>, ast_type: &ast::Type<'c>) -> Type {
+        let typ = self.convert_type(cache, ast_type);
+        if let Some(kind) = self.kind_of(&typ, cache) {
+            if !kind.parameters().is_empty() {
+                error!(ast_type.locate(), "Expected a type of kind * but {} has kind {}", ast_type, kind);
+            }
+        }
+        typ
+    }
+
+    /// Returns the kind of the given type, or None if it is
+    /// or is an application of a type variable with an unknown kind.
+    fn kind_of(&self, typ: &Type, cache: &ModuleCache) -> Option<Kind> {
+        match typ {
+            Type::Primitive(PrimitiveType::Ptr) | Type::Ref(_) => Some(Kind::Normal(1)),
+            Type::Primitive(_) | Type::Function(_) | Type::Forall(..) | Type::Record(..) => Some(Kind::Normal(0)),
+            Type::UserDefined(id) => {
+                let args = &cache.type_infos[id.0].args;
+                Some(Kind::from_parameters(fmap(args, |arg| cache.kind_of_type_variable(*arg))))
+            },
+            Type::TypeVariable(id) if self.has_known_kind(*id) => Some(cache.kind_of_type_variable(*id)),
+            Type::TypeVariable(_) => None,
+            Type::TypeApplication(constructor, args) => self.kind_of(constructor, cache)?.apply(args.len()),
+        }
+    }
+
+    /// Kind check a type application so that misuses of higher-kinded types are reported
+    /// here rather than as more confusing unification errors during type inference.
+    fn check_type_application_kinds(
+        &self, constructor: &Type, args: &[Type], ast_constructor: &ast::Type, ast_args: &[ast::Type],
+        cache: &ModuleCache,
+    ) {
+        let constructor_kind = match self.kind_of(constructor, cache) {
+            Some(kind) => kind,
+            None => return,
+        };
+
+        let parameters = constructor_kind.parameters();
+        if args.len() > parameters.len() {
+            let location = ast_constructor.locate();
+            error!(location, "Too many type arguments for {}, which has kind {}", ast_constructor, constructor_kind);
+            return;
+        }
+
+        for ((parameter, arg), ast_arg) in parameters.iter().zip(args).zip(ast_args) {
+            match self.kind_of(arg, cache) {
+                Some(arg_kind) if arg_kind != *parameter => {
+                    let location = ast_arg.locate();
+                    error!(location, "Expected a type of kind {} but {} has kind {}", parameter, ast_arg, arg_kind);
+                },
+                _ => (),
+            }
         }
     }
 
@@ -659,7 +924,9 @@ impl<'c> NameResolver {
         self.resolve_all_definitions(vec![ast].into_iter(), cache, definition);
     }
 
-    fn resolve_extern_definitions(&mut self, declaration: &mut ast::TypeAnnotation<'c>, cache: &mut ModuleCache<'c>) {
+    fn resolve_extern_definitions(
+        &mut self, declaration: &mut ast::TypeAnnotation<'c>, abi: ExternAbi, cache: &mut ModuleCache<'c>,
+    ) {
         self.definitions_collected.clear();
         self.auto_declare = true;
         self.push_type_variable_scope();
@@ -670,7 +937,7 @@ impl<'c> NameResolver {
         self.auto_declare = false;
         for id in self.definitions_collected.iter() {
             let declaration = trustme::extend_lifetime(declaration);
-            cache.definition_infos[id.0].definition = Some(DefinitionKind::Extern(declaration));
+            cache.definition_infos[id.0].definition = Some(DefinitionKind::Extern(declaration, abi));
         }
     }
 
@@ -681,13 +948,16 @@ impl<'c> NameResolver {
         It: Iterator<Item = &'a mut T>,
         T: Resolvable<'c>,
     {
-        self.definitions_collected.clear();
-        self.auto_declare = true;
+        // Each Definition::declare clears definitions_collected, so they must be gathered per-definition
+        let mut definitions = vec![];
         for pattern in patterns {
+            self.definitions_collected.clear();
+            self.auto_declare = true;
             pattern.declare(self, cache);
+            self.auto_declare = false;
+            definitions.append(&mut self.definitions_collected);
         }
-        self.auto_declare = false;
-        self.definitions_collected.clone()
+        definitions
     }
 
     fn resolve_all_definitions<'a, T: 'a, It, F>(
@@ -708,22 +978,29 @@ impl<'c> NameResolver {
         }
     }
 
-    fn resolve_required_traits(
-        &mut self, given: &[ast::Trait<'c>], cache: &mut ModuleCache<'c>,
-    ) -> Vec<ConstraintSignature> {
-        let mut required_traits = Vec::with_capacity(given.len());
-        for trait_ in given {
-            if let Some(trait_id) = self.lookup_trait(&trait_.name, cache) {
-                required_traits.push(ConstraintSignature {
-                    trait_id,
-                    args: fmap(&trait_.args, |arg| self.convert_type(cache, arg)),
-                    id: cache.next_trait_constraint_id(),
-                });
-            } else {
-                error!(trait_.location, "Could not find trait {} in scope", trait_.name.blue());
+    fn resolve_given_clause(&mut self, given: &[ast::Given<'c>], cache: &mut ModuleCache<'c>) -> Vec<GivenConstraint> {
+        let mut constraints = Vec::with_capacity(given.len());
+        for constraint in given {
+            match constraint {
+                ast::Given::Trait(trait_) => {
+                    if let Some(trait_id) = self.lookup_trait(&trait_.name, cache) {
+                        constraints.push(GivenConstraint::Trait(ConstraintSignature {
+                            trait_id,
+                            args: fmap(&trait_.args, |arg| self.convert_type(cache, arg)),
+                            id: cache.next_trait_constraint_id(),
+                        }));
+                    } else {
+                        error!(trait_.location, "Could not find trait {} in scope", trait_.name.blue());
+                    }
+                },
+                ast::Given::Equality(lhs, rhs) => {
+                    let lhs = self.convert_type(cache, lhs);
+                    let rhs = self.convert_type(cache, rhs);
+                    constraints.push(GivenConstraint::Equality(lhs, rhs));
+                },
             }
         }
-        required_traits
+        constraints
     }
 
     fn try_set_current_function(&mut self, definition: &ast::Definition<'c>) {
@@ -778,6 +1055,7 @@ impl<'c> Resolvable<'c> for ast::Variable<'c> {
                 },
                 Identifier(name) => (Cow::Borrowed(name), true),
                 TypeConstructor(name) => (Cow::Borrowed(name), false),
+                TraitMethod(..) => (Cow::Owned(self.to_string()), false),
             };
 
             if should_declare {
@@ -803,18 +1081,30 @@ impl<'c> Resolvable<'c> for ast::Variable<'c> {
                     Operator(token) => Cow::Owned(token.to_string()),
                     Identifier(name) => Cow::Borrowed(name),
                     TypeConstructor(name) => Cow::Borrowed(name),
+                    TraitMethod(_, method) => Cow::Borrowed(method),
                 };
 
                 self.impl_scope = Some(resolver.current_scope().impl_scope);
-                self.definition = resolver.reference_definition(&name, self.location, cache);
+                self.definition = match &self.kind {
+                    TraitMethod(trait_name, method) => {
+                        resolver.lookup_trait_method(trait_name, method, self.location, cache)
+                    },
+                    _ => resolver.reference_definition(&name, self.location, cache),
+                };
+
+                if let (Identifier(_), Some(definition)) = (&self.kind, self.definition) {
+                    resolver.check_for_ambiguous_trait_method(&name, definition, self.location, cache);
+                }
+
                 self.id = Some(cache.push_variable(name.into_owned(), self.location));
             }
 
-            // If it is still not declared, print an error
+            // If it is still not declared, print an error. Qualified trait methods
+            // that couldn't be found will have already issued an error.
             if self.definition.is_none() {
-                error!(self.location, "No declaration for {} was found in scope", self);
-            } else if resolver.in_assignment_context && !cache.definition_infos[self.definition.unwrap().0].mutable {
-                error!(self.location, "Variable {} must be mutable to be assigned to", self);
+                if !matches!(self.kind, ast::VariableKind::TraitMethod(..)) {
+                    error!(self.location, "No declaration for {} was found in scope", self);
+                }
             }
         }
     }
@@ -828,10 +1118,14 @@ impl<'c> Resolvable<'c> for ast::Lambda<'c> {
         resolver.try_add_current_function_to_scope();
         resolver.resolve_all_definitions(self.args.iter_mut(), cache, || DefinitionKind::Parameter);
 
+        for arg in self.args.iter().take(self.implicit_parameters.len()) {
+            resolver.mark_implicit(arg);
+        }
+
         if let Some(typ) = &self.return_type {
             // Auto-declare any new type variables within the return type
             resolver.auto_declare = true;
-            self.body.set_type(resolver.convert_type(cache, typ));
+            self.body.set_type(resolver.convert_value_type(cache, typ));
             resolver.auto_declare = false;
         }
 
@@ -844,16 +1138,13 @@ impl<'c> Resolvable<'c> for ast::FunctionCall<'c> {
     fn declare(&mut self, _resolver: &mut NameResolver, _cache: &mut ModuleCache<'c>) {}
 
     fn define(&mut self, resolver: &mut NameResolver, cache: &mut ModuleCache<'c>) {
-        let old_context = resolver.in_assignment_context;
-        resolver.in_assignment_context = false;
-
         self.function.define(resolver, cache);
 
         for arg in self.args.iter_mut() {
             arg.define(resolver, cache)
         }
 
-        resolver.in_assignment_context = old_context;
+        resolver.supply_implicit_arguments(self, cache);
     }
 }
 
@@ -868,6 +1159,10 @@ impl<'c> Resolvable<'c> for ast::Definition<'c> {
 
         resolver.resolve_declarations(self.pattern.as_mut(), cache, definition);
 
+        if self.implicit {
+            resolver.mark_implicit(&self.pattern);
+        }
+
         resolver.in_mutable_context = false;
         self.level = Some(resolver.let_binding_level);
         resolver.pop_type_variable_scope();
@@ -889,8 +1184,20 @@ impl<'c> Resolvable<'c> for ast::Definition<'c> {
         resolver.in_mutable_context = false;
         self.level = Some(resolver.let_binding_level);
 
+        // The most local default applies, so nested definitions only inherit
+        // the default of their enclosing definition if they lack their own.
+        let outer_default = resolver.default_integer_kind;
+        self.default_integer_kind = self.default_integer_kind.or(outer_default);
+        resolver.default_integer_kind = self.default_integer_kind;
+
         resolver.try_set_current_function(self);
         self.expr.define(resolver, cache);
+        resolver.default_integer_kind = outer_default;
+
+        // Local definitions are only marked here, after expr, so they cannot supply themselves
+        if self.implicit {
+            resolver.mark_implicit(&self.pattern);
+        }
 
         resolver.pop_type_variable_scope();
         resolver.pop_let_binding_level();
@@ -915,17 +1222,41 @@ impl<'c> Resolvable<'c> for ast::If<'c> {
     }
 }
 
+impl<'c> Resolvable<'c> for ast::Guard<'c> {
+    fn declare(&mut self, _resolver: &mut NameResolver, _cache: &mut ModuleCache<'c>) {}
+
+    fn define(&mut self, resolver: &mut NameResolver, cache: &mut ModuleCache<'c>) {
+        for (condition, branch) in self.branches.iter_mut() {
+            condition.define(resolver, cache);
+
+            resolver.push_scope(cache);
+            branch.define(resolver, cache);
+            resolver.pop_scope(cache, true, None);
+        }
+
+        if let Some(otherwise) = &mut self.otherwise {
+            resolver.push_scope(cache);
+            otherwise.define(resolver, cache);
+            resolver.pop_scope(cache, true, None);
+        }
+    }
+}
+
 impl<'c> Resolvable<'c> for ast::Match<'c> {
     fn declare(&mut self, _resolver: &mut NameResolver, _cache: &mut ModuleCache<'c>) {}
 
     fn define(&mut self, resolver: &mut NameResolver, cache: &mut ModuleCache<'c>) {
         self.expression.define(resolver, cache);
 
-        for (pattern, rhs) in self.branches.iter_mut() {
+        for (pattern, guard, rhs) in self.branches.iter_mut() {
             resolver.push_scope(cache);
 
             resolver.resolve_definitions(pattern, cache, || DefinitionKind::MatchPattern);
 
+            if let Some(guard) = guard {
+                guard.define(resolver, cache);
+            }
+
             rhs.define(resolver, cache);
             resolver.pop_scope(cache, true, None);
         }
@@ -953,6 +1284,7 @@ fn create_variant_constructor_type(
             return_type: Box::new(result),
             environment: Box::new(Type::Primitive(PrimitiveType::UnitType)),
             is_varargs: false,
+            varargs_type: None,
         });
     }
 
@@ -974,7 +1306,7 @@ fn create_variants<'c>(
 ) -> Vec<TypeConstructor<'c>> {
     let mut index = 0;
     fmap(vec, |(name, types, location)| {
-        let args = fmap(types, |t| resolver.convert_type(cache, t));
+        let args = fmap(types, |t| resolver.convert_value_type(cache, t));
 
         let id = resolver.push_definition(name, false, cache, *location);
         cache.definition_infos[id.0].typ = Some(create_variant_constructor_type(parent_type_id, args.clone(), cache));
@@ -989,7 +1321,7 @@ type Fields<'c> = Vec<(String, ast::Type<'c>, Location<'c>)>;
 
 fn create_fields<'c>(vec: &Fields<'c>, resolver: &mut NameResolver, cache: &mut ModuleCache<'c>) -> Vec<Field<'c>> {
     fmap(vec, |(name, field_type, location)| {
-        let field_type = resolver.convert_type(cache, field_type);
+        let field_type = resolver.convert_value_type(cache, field_type);
 
         Field { name: name.clone(), field_type, location: *location }
     })
@@ -997,7 +1329,10 @@ fn create_fields<'c>(vec: &Fields<'c>, resolver: &mut NameResolver, cache: &mut
 
 impl<'c> Resolvable<'c> for ast::TypeDefinition<'c> {
     fn declare(&mut self, resolver: &mut NameResolver, cache: &mut ModuleCache<'c>) {
-        let args = fmap(&self.args, |_| cache.next_type_variable_id(resolver.let_binding_level));
+        let args = fmap(&self.args, |(name, kind)| {
+            let kind = kind.clone().unwrap_or_else(|| infer_type_parameter_kind(name, &self.definition));
+            cache.next_type_variable_id_with_kind(resolver.let_binding_level, kind)
+        });
         let id = resolver.push_type_info(self.name.clone(), args, cache, self.location);
         self.type_info = Some(id);
     }
@@ -1015,8 +1350,8 @@ impl<'c> Resolvable<'c> for ast::TypeDefinition<'c> {
             let ids = &mut cache.type_infos[id.0].args.iter();
             // re-insert the typevars into scope.
             // These names are guarenteed to not collide since we just pushed a new scope.
-            for (key, id) in keys.zip(ids) {
-                resolver.push_existing_type_variable(key.clone(), *id);
+            for ((key, _), id) in keys.zip(ids) {
+                resolver.push_kinded_type_variable(key.clone(), *id);
             }
         }
 
@@ -1061,11 +1396,46 @@ impl<'c> Resolvable<'c> for ast::TypeAnnotation<'c> {
         self.lhs.define(resolver, cache);
         resolver.in_mutable_context = old_context;
 
-        let rhs = resolver.convert_type(cache, &self.rhs);
+        let rhs = resolver.convert_value_type(cache, &self.rhs);
         self.typ = Some(rhs);
     }
 }
 
+/// Infer the kind of a type parameter without a declared kind from how it is applied
+/// within its type definition. Parameters that are never applied have kind `*`.
+fn infer_type_parameter_kind(name: &str, body: &ast::TypeDefinitionBody) -> Kind {
+    let types: Vec<&ast::Type> = match body {
+        ast::TypeDefinitionBody::Union(variants) => variants.iter().flat_map(|(_, args, _)| args).collect(),
+        ast::TypeDefinitionBody::Struct(fields) => fields.iter().map(|(_, typ, _)| typ).collect(),
+        ast::TypeDefinitionBody::Alias(typ) => vec![typ],
+    };
+
+    let arity = types.into_iter().find_map(|typ| find_type_variable_arity(name, typ));
+    Kind::Normal(arity.unwrap_or(0))
+}
+
+/// Find the number of type arguments the given type variable is applied to within the given type, if any.
+fn find_type_variable_arity(name: &str, typ: &ast::Type) -> Option<usize> {
+    match typ {
+        ast::Type::TypeApplication(constructor, args, _) => match constructor.as_ref() {
+            ast::Type::TypeVariable(constructor_name, _) if constructor_name == name => Some(args.len()),
+            _ => find_type_variable_arity(name, constructor)
+                .or_else(|| args.iter().find_map(|arg| find_type_variable_arity(name, arg))),
+        },
+        ast::Type::Function(parameters, return_type, _, varargs_type, _) => parameters
+            .iter()
+            .chain(varargs_type.as_deref())
+            .find_map(|parameter| find_type_variable_arity(name, parameter))
+            .or_else(|| find_type_variable_arity(name, return_type)),
+        ast::Type::Pair(first, rest, _) => {
+            find_type_variable_arity(name, first).or_else(|| find_type_variable_arity(name, rest))
+        },
+        ast::Type::Forall(_, typ, _) => find_type_variable_arity(name, typ),
+        ast::Type::Record(fields, _) => fields.iter().find_map(|(_, typ)| find_type_variable_arity(name, typ)),
+        _ => None,
+    }
+}
+
 fn find_file(relative_path: &Path, cache: &mut ModuleCache) -> Option<(File, PathBuf)> {
     let relative_path = PathBuf::from(relative_path);
 
@@ -1150,6 +1520,8 @@ impl<'c> Resolvable<'c> for ast::Import<'c> {
 
     fn define(&mut self, resolver: &mut NameResolver, cache: &mut ModuleCache<'c>) {
         if let Some(module_id) = self.module_id {
+            cache.push_import(self.path.join("."), module_id, resolver.module_id, self.location);
+
             if let Some(exports) = define_module(module_id, cache, self.location) {
                 resolver.current_scope().import(exports, cache, self.location);
             }
@@ -1172,10 +1544,26 @@ impl<'c> Resolvable<'c> for ast::TraitDefinition<'c> {
 
         let fundeps = fmap(&self.fundeps, |arg| resolver.push_new_type_variable(arg.clone(), cache));
 
+        // Super-traits are resolved before the trait itself is in scope so a trait cannot be its own super-trait
+        let given = resolver.resolve_given_clause(&self.given, cache);
+        let super_traits = given.into_iter().filter_map(|given| match given {
+            GivenConstraint::Trait(signature) => Some(signature),
+            GivenConstraint::Equality(..) => {
+                error!(self.location, "Only traits may be given in the definition of trait {}", self.name.blue());
+                None
+            },
+        });
+        let super_traits = super_traits.collect();
+
         assert!(resolver.current_trait.is_none());
 
         let trait_id =
             resolver.push_trait(self.name.clone(), args, fundeps, trustme::extend_lifetime(self), cache, self.location);
+        cache[trait_id].given = super_traits;
+
+        if self.name == "Shared" && resolver.filepath == builtin::prelude_path() {
+            cache.shared_trait = Some(trait_id);
+        }
 
         resolver.current_trait = Some(trait_id);
 
@@ -1185,7 +1573,7 @@ impl<'c> Resolvable<'c> for ast::TraitDefinition<'c> {
             resolver.resolve_declarations(declaration.lhs.as_mut(), cache, definition);
 
             resolver.auto_declare = true;
-            let rhs = resolver.convert_type(cache, &declaration.rhs);
+            let rhs = resolver.convert_value_type(cache, &declaration.rhs);
             resolver.auto_declare = false;
             declaration.typ = Some(rhs);
         }
@@ -1200,6 +1588,30 @@ impl<'c> Resolvable<'c> for ast::TraitDefinition<'c> {
         if self.trait_info.is_none() {
             self.declare(resolver, cache);
         }
+
+        let trait_id = self.trait_info.unwrap();
+        for default in self.defaults.iter_mut() {
+            // Each default is given its own scope so that it does not conflict with the
+            // trait member of the same name. It is only ever referred to through impls.
+            resolver.push_scope(cache);
+            default.declare(resolver, cache);
+            default.define(resolver, cache);
+            resolver.pop_scope(cache, false, None);
+
+            let is_declared = |id: &DefinitionInfoId| cache[*id].name == default.pattern.to_string();
+            match default.pattern.as_ref() {
+                ast::Ast::Variable(variable) if cache[trait_id].definitions.iter().any(is_declared) => {
+                    let id = variable.definition.unwrap();
+                    cache[id].trait_default = Some(trait_id);
+                    cache[trait_id].defaults.push(id);
+                },
+                _ => error!(
+                    default.location,
+                    "Default definitions in trait {} must define one of its declarations",
+                    self.name.blue()
+                ),
+            }
+        }
     }
 }
 
@@ -1248,7 +1660,11 @@ impl<'c> Resolvable<'c> for ast::TraitImpl<'c> {
         // TODO cleanup: is required_definitions still required since we can
         // resolve_all_definitions now? The checks in push_definition can probably
         // be moved here instead
-        for required_definition in resolver.required_definitions.as_ref().unwrap() {
+        let required_definitions = resolver.required_definitions.as_ref().unwrap();
+        let trait_info = &cache.trait_infos[trait_id.0];
+        let missing = required_definitions.iter().filter(|id| !trait_info.has_default(&cache[**id].name));
+
+        for required_definition in missing {
             error!(
                 self.location,
                 "impl is missing a definition for {}", cache.definition_infos[required_definition.0].name
@@ -1264,7 +1680,12 @@ impl<'c> Resolvable<'c> for ast::TraitImpl<'c> {
             definition.level = Some(resolver.let_binding_level);
         }
 
-        let given = resolver.resolve_required_traits(&self.given, cache);
+        // An impl given a trait is also given each of that trait's super-traits
+        let mut given = resolver.resolve_given_clause(&self.given, cache);
+        let given_traits = given.iter().filter_map(GivenConstraint::as_trait).cloned().collect::<Vec<_>>();
+        for signature in given_traits {
+            given.extend(typechecker::super_traits(&signature, cache).into_iter().map(GivenConstraint::Trait));
+        }
 
         resolver.pop_let_binding_level();
         resolver.pop_scope(cache, false, None);
@@ -1314,8 +1735,17 @@ impl<'c> Resolvable<'c> for ast::Extern<'c> {
         self.level = Some(resolver.let_binding_level);
         resolver.push_let_binding_level();
 
+        let abi = match self.abi.as_deref() {
+            None => ExternAbi::Ante,
+            Some("C") => ExternAbi::C,
+            Some(abi) => {
+                error!(self.location, "Unknown ABI \"{}\", the only ABI supported by extern is \"C\"", abi);
+                ExternAbi::Ante
+            },
+        };
+
         for declaration in self.declarations.iter_mut() {
-            resolver.resolve_extern_definitions(declaration, cache);
+            resolver.resolve_extern_definitions(declaration, abi, cache);
         }
 
         resolver.pop_let_binding_level();
@@ -1337,13 +1767,70 @@ impl<'c> Resolvable<'c> for ast::MemberAccess<'c> {
     }
 }
 
+impl<'c> Resolvable<'c> for ast::Where<'c> {
+    fn declare(&mut self, _resolver: &mut NameResolver, _cache: &mut ModuleCache<'c>) {}
+
+    /// The definitions of a where clause are all declared before any are defined
+    /// so that they may refer to each other regardless of the order they are given in.
+    fn define(&mut self, resolver: &mut NameResolver, cache: &mut ModuleCache<'c>) {
+        resolver.push_scope(cache);
+
+        for definition in self.definitions.iter_mut() {
+            definition.declare(resolver, cache);
+        }
+        for definition in self.definitions.iter_mut() {
+            definition.define(resolver, cache);
+        }
+
+        self.expression.define(resolver, cache);
+        resolver.pop_scope(cache, true, None);
+    }
+}
+
+impl<'c> Resolvable<'c> for ast::TypeTest<'c> {
+    fn declare(&mut self, _resolver: &mut NameResolver, _cache: &mut ModuleCache<'c>) {}
+
+    fn define(&mut self, resolver: &mut NameResolver, cache: &mut ModuleCache<'c>) {
+        self.expression.define(resolver, cache);
+        self.constructor.define(resolver, cache);
+    }
+}
+
+impl<'c> Resolvable<'c> for ast::Hole<'c> {
+    fn declare(&mut self, _resolver: &mut NameResolver, _cache: &mut ModuleCache<'c>) {}
+
+    fn define(&mut self, resolver: &mut NameResolver, _cache: &mut ModuleCache<'c>) {
+        self.bindings_in_scope = resolver.local_definition_names();
+    }
+}
+
+impl<'c> Resolvable<'c> for ast::Range<'c> {
+    fn declare(&mut self, _resolver: &mut NameResolver, _cache: &mut ModuleCache<'c>) {}
+
+    fn define(&mut self, resolver: &mut NameResolver, cache: &mut ModuleCache<'c>) {
+        self.start.define(resolver, cache);
+        self.end.define(resolver, cache);
+    }
+}
+
+impl<'c> Resolvable<'c> for ast::RecordSpread<'c> {
+    fn declare(&mut self, _resolver: &mut NameResolver, _cache: &mut ModuleCache<'c>) {}
+
+    fn define(&mut self, resolver: &mut NameResolver, cache: &mut ModuleCache<'c>) {
+        for base in self.bases.iter_mut() {
+            base.define(resolver, cache);
+        }
+        for (_, value) in self.overrides.iter_mut() {
+            value.define(resolver, cache);
+        }
+    }
+}
+
 impl<'c> Resolvable<'c> for ast::Assignment<'c> {
     fn declare(&mut self, _resolver: &mut NameResolver, _cache: &mut ModuleCache<'c>) {}
 
     fn define(&mut self, resolver: &mut NameResolver, cache: &mut ModuleCache<'c>) {
-        resolver.in_assignment_context = true;
         self.lhs.define(resolver, cache);
-        resolver.in_assignment_context = false;
         self.rhs.define(resolver, cache);
     }
 }
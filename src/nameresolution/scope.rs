@@ -11,10 +11,11 @@
 //! than the general Scope for other symbols. See the TypeVariableScope
 //! struct for more details on this.
 use crate::cache::{DefinitionInfoId, ImplInfoId, ImplScopeId, ModuleCache, TraitInfoId};
+use crate::error;
 use crate::error::location::{Locatable, Location};
 use crate::parser::ast;
 use crate::types::{TypeInfoId, TypeVariableId};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
 
 /// A scope represents all symbols defined in a given scope.
@@ -35,6 +36,11 @@ pub struct Scope {
     pub traits: HashMap<String, TraitInfoId>,
     pub impls: HashMap<TraitInfoId, Vec<ImplInfoId>>,
     pub impl_scope: ImplScopeId,
+
+    /// Methods of different traits may share the same name. Each such name maps
+    /// to every trait method using it so that unqualified uses can be reported as
+    /// ambiguous. The methods can still be referred to with `TraitName.method`.
+    pub ambiguous_trait_methods: HashMap<String, Vec<DefinitionInfoId>>,
 }
 
 impl Scope {
@@ -45,6 +51,17 @@ impl Scope {
             types: HashMap::new(),
             traits: HashMap::new(),
             impls: HashMap::new(),
+            ambiguous_trait_methods: HashMap::new(),
+        }
+    }
+
+    /// Record that the given trait methods share a name and thus cannot be used unqualified.
+    pub fn add_ambiguous_trait_methods(&mut self, name: &str, methods: &[DefinitionInfoId]) {
+        let entry = self.ambiguous_trait_methods.entry(name.to_owned()).or_default();
+        for method in methods {
+            if !entry.contains(method) {
+                entry.push(*method);
+            }
         }
     }
 
@@ -72,9 +89,13 @@ impl Scope {
     /// Helper for `import` which imports all non-impl symbols.
     fn import_definitions_types_and_traits(&mut self, other: &Scope, cache: &mut ModuleCache, location: Location) {
         macro_rules! merge_table {
-            ( $field:tt , $cache_field:tt , $errors:tt ) => {{
+            ( $field:tt , $cache_field:tt , $errors:tt , $can_share_name:expr ) => {{
                 for (k, v) in other.$field.iter() {
                     if let Some(existing) = self.$field.get(k) {
+                        if $can_share_name(*existing, *v) {
+                            continue;
+                        }
+
                         let prev_loc = cache.$cache_field[existing.0].locate();
                         let error = make_error!(location, "import shadows previous definition of {}", k);
                         let note = make_note!(prev_loc, "{} was previously defined here", k);
@@ -86,15 +107,33 @@ impl Scope {
             }};
         }
 
+        for (name, methods) in other.ambiguous_trait_methods.iter() {
+            self.add_ambiguous_trait_methods(name, methods);
+        }
+
+        // Methods from different traits are allowed to share names
+        for (name, id) in other.definitions.iter() {
+            if let Some(existing) = self.definitions.get(name) {
+                if are_methods_of_different_traits(*existing, *id, cache) {
+                    let existing = *existing;
+                    self.add_ambiguous_trait_methods(name, &[existing, *id]);
+                }
+            }
+        }
+
         let mut errors = vec![];
-        merge_table!(definitions, definition_infos, errors);
-        merge_table!(types, type_infos, errors);
-        merge_table!(traits, trait_infos, errors);
+        let trait_methods = |existing, new| are_methods_of_different_traits(existing, new, cache);
+        merge_table!(definitions, definition_infos, errors, trait_methods);
+        merge_table!(types, type_infos, errors, |_, _| false);
+        merge_table!(traits, trait_infos, errors, |_, _| false);
 
         if !errors.is_empty() {
             // Using sort_by instead of sort_by_key here avoids cloning the ErrorMessage
             errors.sort_by(|x, y| x.0.cmp(&y.0));
-            errors.into_iter().for_each(|(error, note)| eprintln!("{}\n{}", error, note));
+            for (message, note) in errors {
+                error::issue(message);
+                error::issue(note);
+            }
         }
     }
 
@@ -131,11 +170,22 @@ impl Scope {
 
         if !warnings.is_empty() {
             warnings.sort();
-            warnings.into_iter().for_each(|warning| eprintln!("{}", warning));
+            warnings.into_iter().for_each(error::issue);
         }
     }
 }
 
+/// True if both definitions are methods declared by two distinct traits.
+pub fn are_methods_of_different_traits(first: DefinitionInfoId, second: DefinitionInfoId, cache: &ModuleCache) -> bool {
+    let first = &cache.definition_infos[first.0].trait_info;
+    let second = &cache.definition_infos[second.0].trait_info;
+
+    match (first, second) {
+        (Some((first, _)), Some((second, _))) => first != second,
+        _ => false,
+    }
+}
+
 /// A TypeVariableScope is an alternative to "normal" scopes that other symbols
 /// live in. This is needed in general because type variables do not follow normal
 /// scoping rules. Consider the following trait definition:
@@ -150,6 +200,11 @@ impl Scope {
 #[derive(Debug, Default)]
 pub struct TypeVariableScope {
     type_variables: HashMap<String, TypeVariableId>,
+
+    /// Type parameters of type definitions have a known kind, either declared
+    /// explicitly or inferred from the type's definition. Other type variables,
+    /// like those in function signatures, have unknown kinds and aren't kind checked.
+    kinded_type_variables: HashSet<TypeVariableId>,
 }
 
 impl TypeVariableScope {
@@ -159,9 +214,18 @@ impl TypeVariableScope {
         id
     }
 
+    pub fn push_kinded_type_variable(&mut self, key: String, id: TypeVariableId) -> TypeVariableId {
+        self.kinded_type_variables.insert(id);
+        self.push_existing_type_variable(key, id)
+    }
+
     pub fn get(&self, key: &str) -> Option<&TypeVariableId> {
         self.type_variables.get(key)
     }
+
+    pub fn has_known_kind(&self, id: TypeVariableId) -> bool {
+        self.kinded_type_variables.contains(&id)
+    }
 }
 
 #[derive(Debug)]
@@ -31,6 +31,7 @@ use crate::types::pattern::DecisionTree;
 use crate::types::traits::RequiredTrait;
 use crate::types::typechecker::TypeBindings;
 use crate::types::{self, LetBindingLevel, TypeInfoId};
+use crate::util::fmap;
 use std::collections::{BTreeMap, HashMap};
 use std::rc::Rc;
 
@@ -56,6 +57,9 @@ pub enum VariableKind {
     Identifier(String),
     Operator(Token),
     TypeConstructor(String),
+
+    /// A trait method qualified by its trait name, e.g. `Show.show`
+    TraitMethod(String, String),
 }
 
 /// a, b, (+), Some, etc.
@@ -106,6 +110,11 @@ pub type ClosureEnvironment = BTreeMap<
 #[derive(Debug)]
 pub struct Lambda<'a> {
     pub args: Vec<Ast<'a>>,
+
+    /// The names of any `(implicit name)` parameters. These are always the first
+    /// parameters in `args` and are filled in by name resolution at each direct call
+    /// to this function from the nearest implicit binding of the same name.
+    pub implicit_parameters: Vec<String>,
     pub body: Box<Ast<'a>>,
     pub return_type: Option<Type<'a>>,
 
@@ -122,6 +131,9 @@ pub struct Lambda<'a> {
 pub struct FunctionCall<'a> {
     pub function: Box<Ast<'a>>,
     pub args: Vec<Ast<'a>>,
+
+    /// True if the last argument was written as a trailing closure: `foo a { x -> ... }`
+    pub trailing_closure: bool,
     pub location: Location<'a>,
     pub typ: Option<types::Type>,
 }
@@ -143,6 +155,15 @@ pub struct Definition<'a> {
     pub pattern: Box<Ast<'a>>,
     pub expr: Box<Ast<'a>>,
     pub mutable: bool,
+
+    /// True for `implicit name = expr` definitions, which may supply
+    /// the implicit parameters of functions called within their scope.
+    pub implicit: bool,
+
+    /// The integer type given by a preceding `default i64` line, used for integer literals
+    /// in this definition whose type is otherwise unconstrained. Definitions without their
+    /// own default inherit the default of the definition they are nested in during name resolution.
+    pub default_integer_kind: Option<IntegerKind>,
     pub location: Location<'a>,
     pub level: Option<LetBindingLevel>,
     pub info: Option<DefinitionInfoId>,
@@ -159,15 +180,33 @@ pub struct If<'a> {
     pub typ: Option<types::Type>,
 }
 
+/// | condition1 -> branch1
+/// | condition2 -> branch2
+/// ...
+/// | else -> otherwise
+///
+/// Each condition is tested in order and the branch of the
+/// first true condition is evaluated. Without an `else` branch
+/// a guard expression evaluates to unit, similar to `If`.
+#[derive(Debug)]
+pub struct Guard<'a> {
+    pub branches: Vec<(Ast<'a>, Ast<'a>)>,
+    pub otherwise: Option<Box<Ast<'a>>>,
+    pub location: Location<'a>,
+    pub typ: Option<types::Type>,
+}
+
 /// match expression with
 /// | pattern1 -> branch1
-/// | pattern2 -> branch2
+/// | pattern2 if guard2 -> branch2
 /// ...
 /// | patternN -> branchN
 #[derive(Debug)]
 pub struct Match<'a> {
     pub expression: Box<Ast<'a>>,
-    pub branches: Vec<(Ast<'a>, Ast<'a>)>,
+
+    /// Each branch is a pattern, an optional guard condition, and the code to run if both match
+    pub branches: Vec<(Ast<'a>, Option<Ast<'a>>, Ast<'a>)>,
 
     /// The decision tree is outputted from the completeness checking
     /// step and is used during codegen to efficiently compile each pattern branch.
@@ -189,12 +228,27 @@ pub enum Type<'a> {
     Pointer(Location<'a>),
     Boolean(Location<'a>),
     Unit(Location<'a>),
-    Reference(Location<'a>),
-    Function(Vec<Type<'a>>, Box<Type<'a>>, /*varargs:*/ bool, Location<'a>),
+    /// `ref` or `ref{r}`, with the name of the region the reference points into if one is given
+    Reference(Option<String>, Location<'a>),
+    Function(
+        Vec<Type<'a>>,
+        Box<Type<'a>>,
+        /*varargs:*/ bool,
+        /*varargs type:*/ Option<Box<Type<'a>>>,
+        Location<'a>,
+    ),
     TypeVariable(String, Location<'a>),
     UserDefined(String, Location<'a>),
     TypeApplication(Box<Type<'a>>, Vec<Type<'a>>, Location<'a>),
     Pair(Box<Type<'a>>, Box<Type<'a>>, Location<'a>),
+
+    /// `forall a b. typ`. A polymorphic type used as a function parameter's
+    /// type to accept polymorphic functions as arguments.
+    Forall(Vec<String>, Box<Type<'a>>, Location<'a>),
+
+    /// `{ field1: type1, ..., fieldN: typeN }`. An anonymous record type accepting
+    /// any struct with at least the given fields.
+    Record(Vec<(String, Type<'a>)>, Location<'a>),
 }
 
 /// The AST representation of a trait usage.
@@ -207,6 +261,14 @@ pub struct Trait<'a> {
     pub location: Location<'a>,
 }
 
+/// A single member of a `given` clause. This is either a trait
+/// that must be implemented or a pair of types that must be equal.
+#[derive(Debug)]
+pub enum Given<'a> {
+    Trait(Trait<'a>),
+    Equality(Type<'a>, Type<'a>),
+}
+
 #[derive(Debug)]
 pub enum TypeDefinitionBody<'a> {
     Union(Vec<(String, Vec<Type<'a>>, Location<'a>)>),
@@ -215,10 +277,12 @@ pub enum TypeDefinitionBody<'a> {
 }
 
 /// type Name arg1 arg2 ... argN = definition
+///
+/// Each argument may optionally declare its kind, e.g. `type Proxy (f: * -> *) = ...`
 #[derive(Debug)]
 pub struct TypeDefinition<'a> {
     pub name: String,
-    pub args: Vec<String>,
+    pub args: Vec<(String, Option<types::Kind>)>,
     pub definition: TypeDefinitionBody<'a>,
     pub location: Location<'a>,
     pub type_info: Option<TypeInfoId>,
@@ -226,11 +290,18 @@ pub struct TypeDefinition<'a> {
 }
 
 /// lhs : rhs
+/// lhs : dyn rhs
 #[derive(Debug)]
 pub struct TypeAnnotation<'a> {
     pub lhs: Box<Ast<'a>>,
     pub rhs: Type<'a>,
     pub mutable: bool,
+
+    /// True if this annotation is a gradual boundary, `lhs : dyn rhs`.
+    /// Failing to unify lhs with rhs here only issues a warning and the
+    /// check is instead deferred until runtime.
+    pub gradual: bool,
+
     pub location: Location<'a>,
     pub typ: Option<types::Type>,
 }
@@ -244,7 +315,7 @@ pub struct Import<'a> {
     pub module_id: Option<ModuleId>,
 }
 
-/// trait Name arg1 arg2 ... argN -> fundep1 fundep2 ... fundepN
+/// trait Name arg1 arg2 ... argN -> fundep1 fundep2 ... fundepN given super_trait1, ..., super_traitN
 ///     declaration1
 ///     declaration2
 ///     ...
@@ -255,11 +326,20 @@ pub struct TraitDefinition<'a> {
     pub args: Vec<String>,
     pub fundeps: Vec<String>,
 
+    /// The super-traits of this trait, e.g. `Eq a` in `trait Ord a given Eq a`.
+    /// These are required whenever this trait is.
+    pub given: Vec<Given<'a>>,
+
     // Storing function declarations as TypeAnnotations here
     // throws away any names given to parameters. In practice
     // this shouldn't matter until refinement types are implemented
     // that can depend upon these names.
     pub declarations: Vec<TypeAnnotation<'a>>,
+
+    /// Default definitions for some of the declarations above. These are
+    /// used by any impl of the trait that doesn't define them itself.
+    pub defaults: Vec<Definition<'a>>,
+
     pub level: Option<LetBindingLevel>,
     pub location: Location<'a>,
     pub trait_info: Option<TraitInfoId>,
@@ -275,7 +355,7 @@ pub struct TraitDefinition<'a> {
 pub struct TraitImpl<'a> {
     pub trait_name: String,
     pub trait_args: Vec<Type<'a>>,
-    pub given: Vec<Trait<'a>>,
+    pub given: Vec<Given<'a>>,
 
     pub definitions: Vec<Definition<'a>>,
     pub location: Location<'a>,
@@ -311,9 +391,15 @@ pub struct Sequence<'a> {
 ///     declaration2
 ///     ...
 ///     declarationN
+///
+/// An ABI may be given after `extern`, e.g. `extern "C" puts: string -> i32`
 #[derive(Debug)]
 pub struct Extern<'a> {
     pub declarations: Vec<TypeAnnotation<'a>>,
+
+    /// The ABI string given after `extern`, if any. Validated during name resolution.
+    pub abi: Option<String>,
+
     pub level: Option<LetBindingLevel>,
     pub location: Location<'a>,
     pub typ: Option<types::Type>,
@@ -328,6 +414,83 @@ pub struct MemberAccess<'a> {
     pub typ: Option<types::Type>,
 }
 
+/// expression where
+///     definition1
+///     ...
+///     definitionN
+///
+/// The definitions are only visible within the expression.
+#[derive(Debug)]
+pub struct Where<'a> {
+    pub expression: Box<Ast<'a>>,
+    pub definitions: Vec<Definition<'a>>,
+    pub location: Location<'a>,
+    pub typ: Option<types::Type>,
+}
+
+/// expression is Variant
+///
+/// Tests whether a union value was constructed with the given variant.
+#[derive(Debug)]
+pub struct TypeTest<'a> {
+    pub expression: Box<Ast<'a>>,
+    pub constructor: Box<Ast<'a>>,
+    pub location: Location<'a>,
+    pub typ: Option<types::Type>,
+}
+
+/// { ..base1, ..., ..baseN, field1 = value1, ..., fieldN = valueN }
+///
+/// Copies each field of the bases that is not overridden by a `field = value` entry.
+#[derive(Debug)]
+pub struct RecordSpread<'a> {
+    pub bases: Vec<Ast<'a>>,
+    pub overrides: Vec<(String, Ast<'a>)>,
+    pub location: Location<'a>,
+    pub typ: Option<types::Type>,
+}
+
+/// start .. end
+///
+/// A pattern matching each integer or char from start to end, inclusive.
+/// Range patterns are only valid within the patterns of a match expression.
+#[derive(Debug)]
+pub struct Range<'a> {
+    pub start: Box<Ast<'a>>,
+    pub end: Box<Ast<'a>>,
+    pub location: Location<'a>,
+    pub typ: Option<types::Type>,
+}
+
+impl<'a> Range<'a> {
+    /// Returns the start and end of this range, with chars given as their codepoints.
+    /// Integers are given as signed values since negative literals are stored as the
+    /// two's complement of their value. Returns None unless the bounds are either
+    /// both integers or both chars.
+    pub fn bounds(&self) -> Option<(i64, i64)> {
+        match (self.start.as_ref(), self.end.as_ref()) {
+            (Ast::Literal(start), Ast::Literal(end)) => match (&start.kind, &end.kind) {
+                (LiteralKind::Integer(start, _), LiteralKind::Integer(end, _)) => Some((*start as i64, *end as i64)),
+                (LiteralKind::Char(start), LiteralKind::Char(end)) => Some((*start as i64, *end as i64)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+/// _
+///
+/// A typed hole standing in for an expression that has not been written yet.
+/// The type inferred for it is reported to the user after type inference.
+#[derive(Debug)]
+pub struct Hole<'a> {
+    /// The names of the local variables in scope of this hole, filled out during name resolution.
+    pub bindings_in_scope: Vec<String>,
+    pub location: Location<'a>,
+    pub typ: Option<types::Type>,
+}
+
 /// lhs := rhs
 #[derive(Debug)]
 pub struct Assignment<'a> {
@@ -345,6 +508,7 @@ pub enum Ast<'a> {
     FunctionCall(FunctionCall<'a>),
     Definition(Definition<'a>),
     If(If<'a>),
+    Guard(Guard<'a>),
     Match(Match<'a>),
     TypeDefinition(TypeDefinition<'a>),
     TypeAnnotation(TypeAnnotation<'a>),
@@ -356,6 +520,11 @@ pub enum Ast<'a> {
     Extern(Extern<'a>),
     MemberAccess(MemberAccess<'a>),
     Assignment(Assignment<'a>),
+    RecordSpread(RecordSpread<'a>),
+    TypeTest(TypeTest<'a>),
+    Where(Where<'a>),
+    Range(Range<'a>),
+    Hole(Hole<'a>),
 }
 
 impl PartialEq for LiteralKind {
@@ -402,7 +571,7 @@ impl<'a> Ast<'a> {
 
     /// True if this variable can be matched on, ie. it
     /// is both a Variable node and is not a VariableKind::TypeConstructor
-    fn is_matchable_variable(&self) -> bool {
+    pub fn is_matchable_variable(&self) -> bool {
         match self {
             Ast::Variable(variable) => !matches!(variable.kind, VariableKind::TypeConstructor(..)),
             _ => false,
@@ -457,6 +626,18 @@ impl<'a> Ast<'a> {
         })
     }
 
+    pub fn trait_method(trait_name: String, method: String, location: Location<'a>) -> Ast<'a> {
+        Ast::Variable(Variable {
+            kind: VariableKind::TraitMethod(trait_name, method),
+            location,
+            definition: None,
+            id: None,
+            impl_scope: None,
+            instantiation_mapping: Rc::new(HashMap::new()),
+            typ: None,
+        })
+    }
+
     pub fn type_constructor(name: String, location: Location<'a>) -> Ast<'a> {
         Ast::Variable(Variable {
             kind: VariableKind::TypeConstructor(name),
@@ -470,9 +651,21 @@ impl<'a> Ast<'a> {
     }
 
     pub fn lambda(args: Vec<Ast<'a>>, return_type: Option<Type<'a>>, body: Ast<'a>, location: Location<'a>) -> Ast<'a> {
+        Ast::lambda_with_implicits(vec![], args, return_type, body, location)
+    }
+
+    /// Create a lambda whose first parameters are the given implicit parameters
+    pub fn lambda_with_implicits(
+        implicit_parameters: Vec<(String, Ast<'a>)>, args: Vec<Ast<'a>>, return_type: Option<Type<'a>>, body: Ast<'a>,
+        location: Location<'a>,
+    ) -> Ast<'a> {
         assert!(!args.is_empty());
+        let (implicit_parameters, mut implicit_args): (Vec<_>, Vec<_>) = implicit_parameters.into_iter().unzip();
+        implicit_args.extend(args);
+
         Ast::Lambda(Lambda {
-            args,
+            args: implicit_args,
+            implicit_parameters,
             body: Box::new(body),
             closure_environment: BTreeMap::new(),
             return_type,
@@ -483,8 +676,24 @@ impl<'a> Ast<'a> {
     }
 
     pub fn function_call(function: Ast<'a>, args: Vec<Ast<'a>>, location: Location<'a>) -> Ast<'a> {
+        Ast::function_call_with_trailing_closure(function, args, None, location)
+    }
+
+    /// Create a function call whose last argument is the given trailing closure, if any
+    pub fn function_call_with_trailing_closure(
+        function: Ast<'a>, mut args: Vec<Ast<'a>>, trailing_closure: Option<Ast<'a>>, location: Location<'a>,
+    ) -> Ast<'a> {
+        let has_trailing_closure = trailing_closure.is_some();
+        args.extend(trailing_closure);
         assert!(!args.is_empty());
-        Ast::FunctionCall(FunctionCall { function: Box::new(function), args, location, typ: None })
+
+        Ast::FunctionCall(FunctionCall {
+            function: Box::new(function),
+            args,
+            trailing_closure: has_trailing_closure,
+            location,
+            typ: None,
+        })
     }
 
     pub fn if_expr(condition: Ast<'a>, then: Ast<'a>, otherwise: Option<Ast<'a>>, location: Location<'a>) -> Ast<'a> {
@@ -497,26 +706,34 @@ impl<'a> Ast<'a> {
         })
     }
 
+    pub fn guard(branches: Vec<(Ast<'a>, Ast<'a>)>, otherwise: Option<Ast<'a>>, location: Location<'a>) -> Ast<'a> {
+        Ast::Guard(Guard { branches, otherwise: otherwise.map(Box::new), location, typ: None })
+    }
+
     pub fn definition(pattern: Ast<'a>, expr: Ast<'a>, location: Location<'a>) -> Ast<'a> {
         Ast::Definition(Definition {
             pattern: Box::new(pattern),
             expr: Box::new(expr),
             location,
             mutable: false,
+            implicit: false,
+            default_integer_kind: None,
             level: None,
             info: None,
             typ: None,
         })
     }
 
-    pub fn match_expr(expression: Ast<'a>, mut branches: Vec<(Ast<'a>, Ast<'a>)>, location: Location<'a>) -> Ast<'a> {
+    pub fn match_expr(
+        expression: Ast<'a>, mut branches: Vec<(Ast<'a>, Option<Ast<'a>>, Ast<'a>)>, location: Location<'a>,
+    ) -> Ast<'a> {
         // (Issue #80) When compiling a match statement with a single variable branch e.g:
         // `match ... | x -> ... ` a single Leaf node will be emitted as the decision tree
         // after type checking which causes us to fail since `x` will not be bound to anything
         // without a `Case` node being present. This is a hack to avoid this situation by compiling
         // this class of expressions into let bindings instead.
-        if branches.len() == 1 && branches[0].0.is_matchable_variable() {
-            let (pattern, rest) = branches.pop().unwrap();
+        if branches.len() == 1 && branches[0].0.is_matchable_variable() && branches[0].1.is_none() {
+            let (pattern, _, rest) = branches.pop().unwrap();
             let definition = Ast::definition(pattern, expression, location);
             // TODO: turning this into a sequence can leak names in the match branch to surrounding
             // code. Soundness-wise this isn't an issue since in this case we know it will always
@@ -528,13 +745,16 @@ impl<'a> Ast<'a> {
     }
 
     pub fn type_definition(
-        name: String, args: Vec<String>, definition: TypeDefinitionBody<'a>, location: Location<'a>,
+        name: String, args: Vec<(String, Option<types::Kind>)>, definition: TypeDefinitionBody<'a>,
+        location: Location<'a>,
     ) -> Ast<'a> {
         Ast::TypeDefinition(TypeDefinition { name, args, definition, location, type_info: None, typ: None })
     }
 
-    pub fn type_annotation(lhs: Ast<'a>, rhs: Type<'a>, mutable: bool, location: Location<'a>) -> Ast<'a> {
-        Ast::TypeAnnotation(TypeAnnotation { lhs: Box::new(lhs), rhs, mutable, location, typ: None })
+    pub fn type_annotation(
+        lhs: Ast<'a>, rhs: Type<'a>, mutable: bool, gradual: bool, location: Location<'a>,
+    ) -> Ast<'a> {
+        Ast::TypeAnnotation(TypeAnnotation { lhs: Box::new(lhs), rhs, mutable, gradual, location, typ: None })
     }
 
     pub fn import(path: Vec<String>, location: Location<'a>) -> Ast<'a> {
@@ -543,15 +763,17 @@ impl<'a> Ast<'a> {
     }
 
     pub fn trait_definition(
-        name: String, args: Vec<String>, fundeps: Vec<String>, declarations: Vec<TypeAnnotation<'a>>,
-        location: Location<'a>,
+        name: String, args: Vec<String>, fundeps: Vec<String>, given: Vec<Given<'a>>,
+        declarations: Vec<TypeAnnotation<'a>>, defaults: Vec<Definition<'a>>, location: Location<'a>,
     ) -> Ast<'a> {
         assert!(!args.is_empty());
         Ast::TraitDefinition(TraitDefinition {
             name,
             args,
             fundeps,
+            given,
             declarations,
+            defaults,
             location,
             level: None,
             trait_info: None,
@@ -560,7 +782,7 @@ impl<'a> Ast<'a> {
     }
 
     pub fn trait_impl(
-        trait_name: String, trait_args: Vec<Type<'a>>, given: Vec<Trait<'a>>, definitions: Vec<Definition<'a>>,
+        trait_name: String, trait_args: Vec<Type<'a>>, given: Vec<Given<'a>>, definitions: Vec<Definition<'a>>,
         location: Location<'a>,
     ) -> Ast<'a> {
         assert!(!trait_args.is_empty());
@@ -586,8 +808,8 @@ impl<'a> Ast<'a> {
         Ast::Sequence(Sequence { statements, location, typ: None })
     }
 
-    pub fn extern_expr(declarations: Vec<TypeAnnotation<'a>>, location: Location<'a>) -> Ast<'a> {
-        Ast::Extern(Extern { declarations, location, level: None, typ: None })
+    pub fn extern_expr(declarations: Vec<TypeAnnotation<'a>>, abi: Option<String>, location: Location<'a>) -> Ast<'a> {
+        Ast::Extern(Extern { declarations, abi, location, level: None, typ: None })
     }
 
     pub fn member_access(lhs: Ast<'a>, field: String, location: Location<'a>) -> Ast<'a> {
@@ -597,6 +819,32 @@ impl<'a> Ast<'a> {
     pub fn assignment(lhs: Ast<'a>, rhs: Ast<'a>, location: Location<'a>) -> Ast<'a> {
         Ast::Assignment(Assignment { lhs: Box::new(lhs), rhs: Box::new(rhs), location, typ: None })
     }
+
+    pub fn where_expr(expression: Ast<'a>, definitions: Vec<Definition<'a>>, location: Location<'a>) -> Ast<'a> {
+        Ast::Where(Where { expression: Box::new(expression), definitions, location, typ: None })
+    }
+
+    pub fn type_test(expression: Ast<'a>, constructor: Ast<'a>, location: Location<'a>) -> Ast<'a> {
+        let expression = Box::new(expression);
+        let constructor = Box::new(constructor);
+        Ast::TypeTest(TypeTest { expression, constructor, location, typ: None })
+    }
+
+    pub fn range(start: Ast<'a>, end: Ast<'a>, location: Location<'a>) -> Ast<'a> {
+        Ast::Range(Range { start: Box::new(start), end: Box::new(end), location, typ: None })
+    }
+
+    pub fn hole(location: Location<'a>) -> Ast<'a> {
+        Ast::Hole(Hole { bindings_in_scope: vec![], location, typ: None })
+    }
+
+    /// Each field is either a base, `(None, base)`, or an override, `(Some(field), value)`
+    pub fn record_spread(fields: Vec<(Option<String>, Ast<'a>)>, location: Location<'a>) -> Ast<'a> {
+        let (bases, overrides): (Vec<_>, Vec<_>) = fields.into_iter().partition(|(field, _)| field.is_none());
+        let bases = fmap(bases, |(_, base)| base);
+        let overrides = fmap(overrides, |(field, value)| (field.unwrap(), value));
+        Ast::RecordSpread(RecordSpread { bases, overrides, location, typ: None })
+    }
 }
 
 /// A macro for calling a method on every variant of an Ast node.
@@ -611,6 +859,7 @@ macro_rules! dispatch_on_expr {
             $crate::parser::ast::Ast::FunctionCall(inner) =>    $function(inner $(, $($args),* )? ),
             $crate::parser::ast::Ast::Definition(inner) =>      $function(inner $(, $($args),* )? ),
             $crate::parser::ast::Ast::If(inner) =>              $function(inner $(, $($args),* )? ),
+            $crate::parser::ast::Ast::Guard(inner) =>           $function(inner $(, $($args),* )? ),
             $crate::parser::ast::Ast::Match(inner) =>           $function(inner $(, $($args),* )? ),
             $crate::parser::ast::Ast::TypeDefinition(inner) =>  $function(inner $(, $($args),* )? ),
             $crate::parser::ast::Ast::TypeAnnotation(inner) =>  $function(inner $(, $($args),* )? ),
@@ -622,6 +871,11 @@ macro_rules! dispatch_on_expr {
             $crate::parser::ast::Ast::Extern(inner) =>          $function(inner $(, $($args),* )? ),
             $crate::parser::ast::Ast::MemberAccess(inner) =>    $function(inner $(, $($args),* )? ),
             $crate::parser::ast::Ast::Assignment(inner) =>      $function(inner $(, $($args),* )? ),
+            $crate::parser::ast::Ast::RecordSpread(inner) =>    $function(inner $(, $($args),* )? ),
+            $crate::parser::ast::Ast::TypeTest(inner) =>        $function(inner $(, $($args),* )? ),
+            $crate::parser::ast::Ast::Where(inner) =>           $function(inner $(, $($args),* )? ),
+            $crate::parser::ast::Ast::Range(inner) =>           $function(inner $(, $($args),* )? ),
+            $crate::parser::ast::Ast::Hole(inner) =>            $function(inner $(, $($args),* )? ),
         }
     });
 }
@@ -642,12 +896,35 @@ macro_rules! impl_locatable_for {
     };
 }
 
+impl<'a> Locatable<'a> for Type<'a> {
+    fn locate(&self) -> Location<'a> {
+        match self {
+            Type::Integer(_, location) => *location,
+            Type::Float(location) => *location,
+            Type::Char(location) => *location,
+            Type::String(location) => *location,
+            Type::Pointer(location) => *location,
+            Type::Boolean(location) => *location,
+            Type::Unit(location) => *location,
+            Type::Reference(_, location) => *location,
+            Type::Function(_, _, _, _, location) => *location,
+            Type::TypeVariable(_, location) => *location,
+            Type::UserDefined(_, location) => *location,
+            Type::TypeApplication(_, _, location) => *location,
+            Type::Pair(_, _, location) => *location,
+            Type::Forall(_, _, location) => *location,
+            Type::Record(_, location) => *location,
+        }
+    }
+}
+
 impl_locatable_for!(Literal);
 impl_locatable_for!(Variable);
 impl_locatable_for!(Lambda);
 impl_locatable_for!(FunctionCall);
 impl_locatable_for!(Definition);
 impl_locatable_for!(If);
+impl_locatable_for!(Guard);
 impl_locatable_for!(Match);
 impl_locatable_for!(TypeDefinition);
 impl_locatable_for!(TypeAnnotation);
@@ -659,3 +936,8 @@ impl_locatable_for!(Sequence);
 impl_locatable_for!(Extern);
 impl_locatable_for!(MemberAccess);
 impl_locatable_for!(Assignment);
+impl_locatable_for!(RecordSpread);
+impl_locatable_for!(TypeTest);
+impl_locatable_for!(Where);
+impl_locatable_for!(Range);
+impl_locatable_for!(Hole);
@@ -365,6 +365,16 @@ pub fn identifier<'a, 'b>(input: Input<'a, 'b>) -> ParseResult<'a, 'b, String> {
     }
 }
 
+/// The field name of a member access. This is either an identifier or, for tuples, the index of an element.
+pub fn field_name<'a, 'b>(input: Input<'a, 'b>) -> ParseResult<'a, 'b, String> {
+    match &input[0] {
+        (Token::IntegerLiteral(index, IntegerKind::Unknown), location) => {
+            Ok((&input[1..], index.to_string(), *location))
+        },
+        _ => identifier(input),
+    }
+}
+
 pub fn typename<'a, 'b>(input: Input<'a, 'b>) -> ParseResult<'a, 'b, String> {
     match &input[0] {
         (Token::TypeName(name), location) => Ok((&input[1..], name.clone(), *location)),
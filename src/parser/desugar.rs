@@ -1,5 +1,7 @@
 use crate::error::location::Location;
-use crate::parser::ast::Ast;
+use crate::lexer::token::IntegerKind;
+use crate::parser::ast::{Ast, Given, Trait, Type, TypeDefinitionBody};
+use crate::types::Kind;
 use crate::{error::location::Locatable, lexer::token::Token, parser::ast, util::fmap};
 
 /// Turns `(foo _  _ 2)` into `(fn $1 $2 -> (foo $1 $2 2))`
@@ -38,13 +40,14 @@ where
 }
 
 fn matches_underscore(arg: &Ast) -> bool {
-    matches!(arg, Ast::Variable(ast::Variable{ kind: ast::VariableKind::Identifier(x), ..}) if x == "_")
+    matches!(arg, Ast::Hole(_))
 }
 
 /// Turns:
 /// - `bar |> foo` into `foo bar` (applies to <| as well)
 /// - `a and b` into `if a then b else false`
 /// - `a or b` into `if a then true else b`
+/// - `a is Variant` into a type test of `a`
 ///
 /// Also handles explicitly curried operators. E.g. `_ or false` will
 /// be translated as `fn $1 -> if $1 then true else false`
@@ -58,6 +61,7 @@ pub fn desugar_operators<'a>(operator: Token, lhs: Ast<'a>, rhs: Ast<'a>, locati
             Some(Token::ApplyRight) => prepend_argument_to_function(rhs, lhs, location),
             Some(Token::And) => Ast::if_expr(lhs, rhs, Some(Ast::bool_literal(false, location)), location),
             Some(Token::Or) => Ast::if_expr(lhs, Ast::bool_literal(true, location), Some(rhs), location),
+            Some(Token::Is) => Ast::type_test(lhs, rhs, location),
             Some(operator_token) => {
                 let operator = Ast::operator(operator_token, location);
                 Ast::function_call(operator, vec![lhs, rhs], location)
@@ -79,3 +83,240 @@ fn prepend_argument_to_function<'a>(f: Ast<'a>, arg: Ast<'a>, location: Location
         _ => Ast::function_call(f, vec![arg], location),
     }
 }
+
+/// Turns `derive Eq, Ord, Hash` followed by a type definition into the type definition
+/// followed by an impl of each trait. Each impl compares or hashes the fields of its
+/// arguments in order and requires the same trait of each of the type's parameters:
+///
+/// ```ante
+/// derive Eq
+/// type Pair a = first: a, second: a
+/// ```
+/// becomes
+/// ```ante
+/// type Pair a = first: a, second: a
+///
+/// impl Eq (Pair a) given Eq a with
+///     (==) $l $r = Eq.== $l.first $r.first and Eq.== $l.second $r.second
+/// ```
+pub fn desugar_derive<'a>(traits: Vec<(String, Location<'a>)>, definition: Ast<'a>, location: Location<'a>) -> Ast<'a> {
+    let impls = match &definition {
+        Ast::TypeDefinition(definition) => fmap(traits, |(name, location)| derive_impl(name, definition, location)),
+        _ => unreachable!("derive is only parsed before a type definition"),
+    };
+
+    let mut statements = vec![definition];
+    statements.extend(impls);
+    Ast::sequence(statements, location)
+}
+
+fn derive_impl<'a>(trait_name: String, definition: &ast::TypeDefinition<'a>, location: Location<'a>) -> Ast<'a> {
+    let constructor = Type::UserDefined(definition.name.clone(), location);
+    let typ = if definition.args.is_empty() {
+        constructor
+    } else {
+        let args = fmap(&definition.args, |(arg, _)| Type::TypeVariable(arg.clone(), location));
+        Type::TypeApplication(Box::new(constructor), args, location)
+    };
+
+    // Parameters of a higher kind such as `f` in `type Foo (f: * -> *) = ...` can't implement
+    // the trait themselves. They're left to the impls of any fields that apply them instead.
+    let given = definition.args.iter().filter(|(_, kind)| matches!(kind, None | Some(Kind::Normal(0))));
+    let given = fmap(given, |(arg, _)| {
+        let args = vec![Type::TypeVariable(arg.clone(), location)];
+        Given::Trait(Trait { name: trait_name.clone(), args, location })
+    });
+
+    let method = match trait_name.as_str() {
+        "Eq" => derive_eq(&definition.definition, location),
+        "Ord" => derive_ord(&definition.definition, location),
+        "Hash" => derive_hash(&definition.definition, location),
+        _ => unreachable!("{} is not a derivable trait", trait_name),
+    };
+
+    Ast::trait_impl(trait_name, vec![typ], given, vec![method], location)
+}
+
+/// (==) $l $r = ...
+fn derive_eq<'a>(body: &TypeDefinitionBody<'a>, location: Location<'a>) -> ast::Definition<'a> {
+    let body = match body {
+        TypeDefinitionBody::Struct(fields) => {
+            let pairs =
+                fmap(fields, |(field, _, _)| (field_of("$l", field, location), field_of("$r", field, location)));
+            all_equal(pairs, location)
+        },
+        TypeDefinitionBody::Union(variants) => {
+            let mut branches = fmap(variants, |(variant, fields, _)| {
+                let (pattern, pairs) = variant_pair_pattern(variant, fields.len(), location);
+                (pattern, None, all_equal(pairs, location))
+            });
+
+            if variants.len() > 1 {
+                branches.push((Ast::variable("_".into(), location), None, Ast::bool_literal(false, location)));
+            }
+            Ast::match_expr(pair(variable("$l", location), variable("$r", location), location), branches, location)
+        },
+        TypeDefinitionBody::Alias(_) => unreachable!("Aliases cannot be derived"),
+    };
+
+    method(Ast::operator(Token::EqualEqual, location), &["$l", "$r"], body, location)
+}
+
+/// compare $l $r = ...
+///
+/// Fields are compared lexicographically. Values of different union variants
+/// are ordered by the order their variants were declared in.
+fn derive_ord<'a>(body: &TypeDefinitionBody<'a>, location: Location<'a>) -> ast::Definition<'a> {
+    let body = match body {
+        TypeDefinitionBody::Struct(fields) => {
+            let pairs =
+                fmap(fields, |(field, _, _)| (field_of("$l", field, location), field_of("$r", field, location)));
+            lexicographic(pairs, location)
+        },
+        TypeDefinitionBody::Union(variants) => {
+            let mut branches = fmap(variants, |(variant, fields, _)| {
+                let (pattern, pairs) = variant_pair_pattern(variant, fields.len(), location);
+                (pattern, None, lexicographic(pairs, location))
+            });
+
+            if variants.len() > 1 {
+                let tags = vec![variant_tag(variants, "$l", location), variant_tag(variants, "$r", location)];
+                let compare_tags = call_trait_method("Ord", "compare".into(), tags, location);
+                branches.push((Ast::variable("_".into(), location), None, compare_tags));
+            }
+            Ast::match_expr(pair(variable("$l", location), variable("$r", location), location), branches, location)
+        },
+        TypeDefinitionBody::Alias(_) => unreachable!("Aliases cannot be derived"),
+    };
+
+    method(Ast::variable("compare".into(), location), &["$l", "$r"], body, location)
+}
+
+/// hash $v = ...
+///
+/// The hash of each field is combined with `h * 31 + field_hash`. The hash of
+/// a union value starts from the index of its variant.
+fn derive_hash<'a>(body: &TypeDefinitionBody<'a>, location: Location<'a>) -> ast::Definition<'a> {
+    let body = match body {
+        TypeDefinitionBody::Struct(fields) => {
+            let fields = fmap(fields, |(field, _, _)| field_of("$v", field, location));
+            combine_hashes(Ast::integer(0, IntegerKind::U64, location), fields, location)
+        },
+        TypeDefinitionBody::Union(variants) => {
+            let branches = fmap(variants.iter().enumerate(), |(index, (variant, fields, _))| {
+                let fields = fmap(0..fields.len(), |i| variable(&format!("$x{}", i), location));
+                let pattern = variant_pattern(variant, fields.len(), "$x", location);
+                let tag = Ast::integer(index as u64, IntegerKind::U64, location);
+                (pattern, None, combine_hashes(tag, fields, location))
+            });
+            Ast::match_expr(variable("$v", location), branches, location)
+        },
+        TypeDefinitionBody::Alias(_) => unreachable!("Aliases cannot be derived"),
+    };
+
+    method(Ast::variable("hash".into(), location), &["$v"], body, location)
+}
+
+fn method<'a>(name: Ast<'a>, args: &[&str], body: Ast<'a>, location: Location<'a>) -> ast::Definition<'a> {
+    let args = fmap(args, |arg| variable(arg, location));
+    ast::Definition {
+        pattern: Box::new(name),
+        expr: Box::new(Ast::lambda(args, None, body, location)),
+        mutable: false,
+        implicit: false,
+        default_integer_kind: None,
+        location,
+        level: None,
+        info: None,
+        typ: None,
+    }
+}
+
+fn variable<'a>(name: &str, location: Location<'a>) -> Ast<'a> {
+    Ast::variable(name.to_string(), location)
+}
+
+fn field_of<'a>(name: &str, field: &str, location: Location<'a>) -> Ast<'a> {
+    Ast::member_access(variable(name, location), field.to_string(), location)
+}
+
+fn pair<'a>(first: Ast<'a>, second: Ast<'a>, location: Location<'a>) -> Ast<'a> {
+    Ast::function_call(Ast::operator(Token::Comma, location), vec![first, second], location)
+}
+
+/// `Variant $x0 $x1 ... $xN` using the given prefix for each field name
+fn variant_pattern<'a>(variant: &str, field_count: usize, prefix: &str, location: Location<'a>) -> Ast<'a> {
+    let constructor = Ast::type_constructor(variant.to_string(), location);
+    if field_count == 0 {
+        constructor
+    } else {
+        let fields = fmap(0..field_count, |i| variable(&format!("{}{}", prefix, i), location));
+        Ast::function_call(constructor, fields, location)
+    }
+}
+
+/// Returns the pattern `Variant $x0 ... $xN, Variant $y0 ... $yN` along
+/// with the pairs of fields `($x0, $y0) ... ($xN, $yN)` it binds.
+fn variant_pair_pattern<'a>(
+    variant: &str, field_count: usize, location: Location<'a>,
+) -> (Ast<'a>, Vec<(Ast<'a>, Ast<'a>)>) {
+    let lhs = variant_pattern(variant, field_count, "$x", location);
+    let rhs = variant_pattern(variant, field_count, "$y", location);
+    let pairs =
+        fmap(0..field_count, |i| (variable(&format!("$x{}", i), location), variable(&format!("$y{}", i), location)));
+    (pair(lhs, rhs, location), pairs)
+}
+
+/// `match value | Variant0 .. -> 0u8 | Variant1 .. -> 1u8 | ...`
+fn variant_tag<'a>(variants: &[(String, Vec<Type<'a>>, Location<'a>)], value: &str, location: Location<'a>) -> Ast<'a> {
+    let branches = fmap(variants.iter().enumerate(), |(index, (variant, fields, _))| {
+        let wildcards = fmap(fields, |_| variable("_", location));
+        let constructor = Ast::type_constructor(variant.clone(), location);
+        let pattern =
+            if wildcards.is_empty() { constructor } else { Ast::function_call(constructor, wildcards, location) };
+        (pattern, None, Ast::integer(index as u64, IntegerKind::U8, location))
+    });
+    Ast::match_expr(variable(value, location), branches, location)
+}
+
+/// `Eq.== l0 r0 and Eq.== l1 r1 and ...`, or `true` if there are no pairs
+fn all_equal<'a>(pairs: Vec<(Ast<'a>, Ast<'a>)>, location: Location<'a>) -> Ast<'a> {
+    let mut comparisons =
+        fmap(pairs, |(l, r)| call_trait_method("Eq", Token::EqualEqual.to_string(), vec![l, r], location));
+
+    let mut result = comparisons.pop().unwrap_or_else(|| Ast::bool_literal(true, location));
+    while let Some(comparison) = comparisons.pop() {
+        result = Ast::if_expr(comparison, result, Some(Ast::bool_literal(false, location)), location);
+    }
+    result
+}
+
+/// Compares each pair in turn until one is not `Equal`, or returns `Equal` if there are no pairs
+fn lexicographic<'a>(pairs: Vec<(Ast<'a>, Ast<'a>)>, location: Location<'a>) -> Ast<'a> {
+    let mut comparisons = fmap(pairs, |(l, r)| call_trait_method("Ord", "compare".into(), vec![l, r], location));
+
+    let mut result = comparisons.pop().unwrap_or_else(|| Ast::type_constructor("Equal".into(), location));
+    while let Some(comparison) = comparisons.pop() {
+        let equal = (Ast::type_constructor("Equal".into(), location), None, result);
+        let different = (variable("$o", location), None, variable("$o", location));
+        result = Ast::match_expr(comparison, vec![equal, different], location);
+    }
+    result
+}
+
+/// Folds each value's hash into `initial` with `h * 31 + Hash.hash value`
+fn combine_hashes<'a>(initial: Ast<'a>, values: Vec<Ast<'a>>, location: Location<'a>) -> Ast<'a> {
+    values.into_iter().fold(initial, |hash, value| {
+        let scaled = vec![hash, Ast::integer(31, IntegerKind::U64, location)];
+        let scaled = call_trait_method("Mul", Token::Multiply.to_string(), scaled, location);
+        let value = call_trait_method("Hash", "hash".into(), vec![value], location);
+        call_trait_method("Add", Token::Add.to_string(), vec![scaled, value], location)
+    })
+}
+
+/// Calls the given method qualified by its trait, e.g. `Eq.==`, so that it
+/// cannot be shadowed by other definitions of the same name in scope.
+fn call_trait_method<'a>(trait_name: &str, method: String, args: Vec<Ast<'a>>, location: Location<'a>) -> Ast<'a> {
+    let method = Ast::trait_method(trait_name.to_string(), method, location);
+    Ast::function_call(method, args, location)
+}
@@ -43,27 +43,32 @@ impl<'a> Locatable<'a> for ParseError<'a> {
     }
 }
 
-impl<'a> Display for ParseError<'a> {
-    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+impl<'a> ParseError<'a> {
+    /// Convert this error into an ErrorMessage so that it may be issued like any other diagnostic
+    pub fn to_error_message(&self) -> ErrorMessage<'a> {
         match self {
-            ParseError::Fatal(error) => error.fmt(fmt),
+            ParseError::Fatal(error) => error.to_error_message(),
             ParseError::Expected(tokens, location) => {
                 if tokens.len() == 1 {
                     let msg = format!("parser expected {} here", tokens[0]);
-                    write!(fmt, "{}", ErrorMessage::error(&msg[..], *location))
+                    ErrorMessage::error(&msg[..], *location)
                 } else {
                     let expected = join_with(tokens, ", ");
                     let msg = format!("parser expected one of {}", expected);
-                    write!(fmt, "{}", ErrorMessage::error(&msg[..], *location))
+                    ErrorMessage::error(&msg[..], *location)
                 }
             },
             ParseError::InRule(rule, location) => {
                 let msg = format!("failed trying to parse a {}", rule);
-                write!(fmt, "{}", ErrorMessage::error(&msg[..], *location))
-            },
-            ParseError::LexerError(error, location) => {
-                write!(fmt, "{}", ErrorMessage::error(&error.to_string()[..], *location))
+                ErrorMessage::error(&msg[..], *location)
             },
+            ParseError::LexerError(error, location) => ErrorMessage::error(&error.to_string()[..], *location),
         }
     }
 }
+
+impl<'a> Display for ParseError<'a> {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(fmt, "{}", self.to_error_message())
+    }
+}
@@ -25,18 +25,19 @@ pub mod pretty_printer;
 
 use crate::error::location::Location;
 use crate::lexer::token::Token;
-use ast::{Ast, Trait, Type, TypeDefinitionBody};
+use crate::types;
+use ast::{Ast, Given, Trait, Type, TypeDefinitionBody};
 use combinators::*;
 use error::{ParseError, ParseResult};
 
 type AstResult<'a, 'b> = ParseResult<'a, 'b, Ast<'b>>;
 
-/// The entry point to parsing. Parses an entire file, printing any
+/// The entry point to parsing. Parses an entire file, issuing any
 /// error found, or returns the Ast if there was no error.
 pub fn parse<'a, 'b>(input: Input<'a, 'b>) -> Result<Ast<'b>, ParseError<'b>> {
     let result = parse_file(input);
     if let Err(error) = &result {
-        eprintln!("{}", error);
+        crate::error::issue(error.to_error_message());
     }
     result
 }
@@ -71,13 +72,18 @@ parser!(statement_list loc =
 
 fn statement<'a, 'b>(input: Input<'a, 'b>) -> AstResult<'a, 'b> {
     match input[0].0 {
+        Token::Identifier(ref name) if name == "default" && matches!(input[1].0, Token::IntegerType(_)) => {
+            defaulted_definition(input)
+        },
         Token::ParenthesisLeft | Token::Identifier(_) => or(&[definition, assignment, expression], "statement")(input),
         Token::Type => or(&[type_definition, type_alias], "statement")(input),
+        Token::Derive => derived_type_definition(input),
         Token::Import => import(input),
         Token::Trait => trait_definition(input),
         Token::Impl => trait_impl(input),
         Token::Return => return_expr(input),
         Token::Extern => parse_extern(input),
+        Token::Implicit => implicit_definition(input),
         _ => expression(input),
     }
 }
@@ -92,14 +98,17 @@ fn raw_definition<'a, 'b>(input: Input<'a, 'b>) -> ParseResult<'a, 'b, ast::Defi
 
 parser!(function_definition location -> 'b ast::Definition<'b> =
     name <- pattern_argument;
+    implicit_parameters <- many0(implicit_parameter);
     args <- many1(pattern_argument);
     return_type <- maybe(function_return_type);
     _ <- expect(Token::Equal);
     body !<- block_or_statement;
     ast::Definition {
         pattern: Box::new(name),
-        expr: Box::new(Ast::lambda(args, return_type, body, location)),
+        expr: Box::new(Ast::lambda_with_implicits(implicit_parameters, args, return_type, body, location)),
         mutable: false,
+        implicit: false,
+        default_integer_kind: None,
         location,
         level: None,
         info: None,
@@ -107,10 +116,28 @@ parser!(function_definition location -> 'b ast::Definition<'b> =
     }
 );
 
-parser!(varargs location -> 'b () =
+// implicit_parameter = '(' 'implicit' identifier (':' type)? ')'
+parser!(implicit_parameter location -> 'b (String, Ast<'b>) =
+    _ <- expect(Token::ParenthesisLeft);
+    _ <- expect(Token::Implicit);
+    name !<- identifier;
+    typ <- maybe(function_return_type);
+    _ !<- expect(Token::ParenthesisRight);
+    {
+        let variable = Ast::variable(name.clone(), location);
+        match typ {
+            Some(typ) => (name, Ast::type_annotation(variable, typ, false, false, location)),
+            None => (name, variable),
+        }
+    }
+);
+
+// varargs = `...` basic_type?, the type being that of each argument passed in its place
+parser!(varargs location -> 'b Option<Box<Type<'b>>> =
     _ <- expect(Token::Range);
     _ <- expect(Token::MemberAccess);
-    ()
+    varargs_type <- maybe(basic_type);
+    varargs_type.map(Box::new)
 );
 
 parser!(function_return_type location -> 'b ast::Type<'b> =
@@ -128,6 +155,8 @@ parser!(variable_definition location -> 'b ast::Definition<'b> =
         pattern: Box::new(name),
         expr: Box::new(expr),
         mutable: mutable.is_some(),
+        implicit: false,
+        default_integer_kind: None,
         location,
         level: None,
         info: None,
@@ -135,6 +164,21 @@ parser!(variable_definition location -> 'b ast::Definition<'b> =
     }
 );
 
+// defaulted_definition = 'default' int_type '\n' definition
+parser!(defaulted_definition location =
+    _ <- expect(Token::Identifier("default".into()));
+    kind <- int_type_token;
+    _ !<- expect(Token::Newline);
+    definition !<- raw_definition;
+    Ast::Definition(ast::Definition { default_integer_kind: Some(kind), ..definition })
+);
+
+parser!(implicit_definition location =
+    _ <- expect(Token::Implicit);
+    definition !<- variable_definition;
+    Ast::Definition(ast::Definition { implicit: true, ..definition })
+);
+
 parser!(assignment location =
     lhs <- expression;
     _ <- expect(Token::Assignment);
@@ -159,10 +203,19 @@ parser!(type_annotation_pattern loc =
     lhs <- or(&[pattern_function_call, pattern_argument], "pattern");
     _ <- expect(Token::Colon);
     mutable <- maybe(expect(Token::Mut));
-    rhs !<- parse_type;
-    Ast::type_annotation(lhs, rhs, mutable.is_some(), loc)
+    rhs <- pattern_annotation_type;
+    Ast::type_annotation(lhs, rhs, mutable.is_some(), false, loc)
 );
 
+/// Gradual `: dyn type` annotations may only annotate expressions. A pattern seeing one
+/// backtracks so that a statement like `x : dyn i32` is parsed as an expression instead.
+fn pattern_annotation_type<'a, 'b>(input: Input<'a, 'b>) -> ParseResult<'a, 'b, Type<'b>> {
+    match input[0].0 {
+        Token::Dyn => Err(ParseError::InRule("type", input[0].1)),
+        _ => no_backtracking(parse_type)(input),
+    }
+}
+
 fn parenthesized_irrefutable_pattern<'a, 'b>(input: Input<'a, 'b>) -> AstResult<'a, 'b> {
     parenthesized(or(&[operator, pattern], "pattern"))(input)
 }
@@ -170,7 +223,7 @@ fn parenthesized_irrefutable_pattern<'a, 'b>(input: Input<'a, 'b>) -> AstResult<
 parser!(type_definition loc =
     _ <- expect(Token::Type);
     name <- typename;
-    args <- many0(identifier);
+    args <- many0(type_parameter);
     _ <- expect(Token::Equal);
     body !<- type_definition_body;
     Ast::type_definition(name, args, body, loc)
@@ -179,12 +232,72 @@ parser!(type_definition loc =
 parser!(type_alias loc =
     _ <- expect(Token::Type);
     name <- typename;
-    args <- many0(identifier);
+    args <- many0(type_parameter);
     _ <- expect(Token::Is);
     body !<- parse_type;
     Ast::type_definition(name, args, TypeDefinitionBody::Alias(body), loc)
 );
 
+parser!(derived_type_definition loc =
+    _ <- expect(Token::Derive);
+    traits !<- delimited(derivable_trait, expect(Token::Comma));
+    _ !<- expect(Token::Newline);
+    definition !<- type_definition;
+    desugar::desugar_derive(traits, definition, loc)
+);
+
+fn derivable_trait<'a, 'b>(input: Input<'a, 'b>) -> ParseResult<'a, 'b, (String, Location<'b>)> {
+    let (input, name, location) = typename(input)?;
+    match name.as_str() {
+        "Eq" | "Ord" | "Hash" => Ok((input, (name, location), location)),
+        _ => Err(ParseError::InRule("derivable trait (Eq, Ord, or Hash)", location)),
+    }
+}
+
+/// A type parameter is either a lone identifier whose kind is inferred
+/// or an identifier with an explicitly declared kind: `(f: * -> *)`
+fn type_parameter<'a, 'b>(input: Input<'a, 'b>) -> ParseResult<'a, 'b, (String, Option<types::Kind>)> {
+    match input[0].0 {
+        Token::ParenthesisLeft => parenthesized(kinded_type_parameter)(input),
+        _ => {
+            let (input, name, location) = identifier(input)?;
+            Ok((input, (name, None), location))
+        },
+    }
+}
+
+parser!(kinded_type_parameter _loc -> 'b (String, Option<types::Kind>) =
+    name <- identifier;
+    _ <- expect(Token::Colon);
+    kind !<- parse_kind;
+    (name, Some(kind))
+);
+
+/// kind = kind_argument ('->' kind)?
+fn parse_kind<'a, 'b>(input: Input<'a, 'b>) -> ParseResult<'a, 'b, types::Kind> {
+    let (input, parameter, location) = kind_argument(input)?;
+
+    if input[0].0 != Token::RightArrow {
+        return Ok((input, parameter, location));
+    }
+
+    let (input, result, result_location) = no_backtracking(parse_kind)(&input[1..])?;
+    let mut parameters = vec![parameter];
+    parameters.append(&mut result.parameters());
+    Ok((input, types::Kind::from_parameters(parameters), location.union(result_location)))
+}
+
+fn kind_argument<'a, 'b>(input: Input<'a, 'b>) -> ParseResult<'a, 'b, types::Kind> {
+    match input[0].0 {
+        Token::Multiply => {
+            let (input, _, location) = expect(Token::Multiply)(input)?;
+            Ok((input, types::Kind::Normal(0), location))
+        },
+        Token::ParenthesisLeft => parenthesized(parse_kind)(input),
+        _ => Err(ParseError::InRule("kind", input[0].1)),
+    }
+}
+
 fn type_definition_body<'a, 'b>(input: Input<'a, 'b>) -> ParseResult<'a, 'b, ast::TypeDefinitionBody<'b>> {
     match input[0].0 {
         Token::Indent => or(&[union_block_body, struct_block_body], "type_definition_body")(input),
@@ -243,33 +356,61 @@ parser!(trait_definition loc =
     args !<- many1(identifier);
     _ !<- maybe(expect(Token::RightArrow));
     fundeps !<- many0(identifier);
+    given !<- maybe(given);
     body <- maybe(trait_body);
-    Ast::trait_definition(name, args, fundeps, body.unwrap_or_default(), loc)
+    trait_definition_with_body(name, args, fundeps, given.unwrap_or_default(), body.unwrap_or_default(), loc)
 );
 
-parser!(trait_body loc -> 'b Vec<ast::TypeAnnotation<'b>> =
+parser!(trait_body loc -> 'b Vec<Ast<'b>> =
     _ <- expect(Token::With);
     body <- or(&[trait_body_block, trait_body_single], "trait body");
     body
 );
 
-parser!(trait_body_single loc -> 'b Vec<ast::TypeAnnotation<'b>> =
-    body <- declaration;
+parser!(trait_body_single loc -> 'b Vec<Ast<'b>> =
+    body <- trait_item;
     vec![body]
 );
 
-parser!(trait_body_block loc -> 'b Vec<ast::TypeAnnotation<'b>> =
+parser!(trait_body_block loc -> 'b Vec<Ast<'b>> =
     _ <- expect(Token::Indent);
-    body !<- delimited_trailing(declaration, expect(Token::Newline));
+    body !<- delimited_trailing(trait_item, expect(Token::Newline));
     _ !<- expect(Token::Unindent);
     body
 );
 
+/// Each item in a trait body is either a declaration or a default definition
+fn trait_item<'a, 'b>(input: Input<'a, 'b>) -> AstResult<'a, 'b> {
+    or(&[trait_declaration, definition], "trait declaration or default definition")(input)
+}
+
+fn trait_declaration<'a, 'b>(input: Input<'a, 'b>) -> AstResult<'a, 'b> {
+    declaration(input).map(|(input, declaration, location)| (input, Ast::TypeAnnotation(declaration), location))
+}
+
+fn trait_definition_with_body<'b>(
+    name: String, args: Vec<String>, fundeps: Vec<String>, given: Vec<Given<'b>>, body: Vec<Ast<'b>>,
+    location: Location<'b>,
+) -> Ast<'b> {
+    let mut declarations = vec![];
+    let mut defaults = vec![];
+
+    for item in body {
+        match item {
+            Ast::TypeAnnotation(declaration) => declarations.push(declaration),
+            Ast::Definition(default) => defaults.push(default),
+            _ => unreachable!("trait_item should only parse declarations and definitions"),
+        }
+    }
+
+    Ast::trait_definition(name, args, fundeps, given, declarations, defaults, location)
+}
+
 parser!(declaration loc -> 'b ast::TypeAnnotation<'b> =
     lhs <- pattern_argument;
     _ <- expect(Token::Colon);
     rhs !<- parse_type;
-    ast::TypeAnnotation { lhs: Box::new(lhs), rhs, mutable: false, location: loc, typ: None }
+    ast::TypeAnnotation { lhs: Box::new(lhs), rhs, mutable: false, gradual: false, location: loc, typ: None }
 );
 
 parser!(trait_impl loc =
@@ -299,10 +440,26 @@ parser!(impl_body_block loc -> 'b Vec<ast::Definition<'b>> =
     definitions
 );
 
-parser!(given loc -> 'b Vec<Trait<'b>> =
+parser!(given loc -> 'b Vec<Given<'b>> =
     _ <- expect(Token::Given);
-    traits <- delimited(required_trait, expect(Token::Comma));
-    traits
+    constraints <- delimited(given_constraint, expect(Token::Comma));
+    constraints
+);
+
+fn given_constraint<'a, 'b>(input: Input<'a, 'b>) -> ParseResult<'a, 'b, Given<'b>> {
+    or(&[type_equality, given_trait], "given constraint")(input)
+}
+
+parser!(given_trait _loc -> 'b Given<'b> =
+    trait_ <- required_trait;
+    Given::Trait(trait_)
+);
+
+parser!(type_equality _loc -> 'b Given<'b> =
+    lhs <- parse_type_no_pair;
+    _ <- expect(Token::Equal);
+    rhs !<- parse_type_no_pair;
+    Given::Equality(lhs, rhs)
 );
 
 parser!(required_trait location -> 'b Trait<'b> =
@@ -319,8 +476,9 @@ parser!(return_expr loc =
 
 parser!(parse_extern loc =
     _ <- expect(Token::Extern);
+    abi <- maybe(string_literal_token);
     declarations <- or(&[extern_block, extern_single], "extern");
-    Ast::extern_expr(declarations, loc)
+    Ast::extern_expr(declarations, abi, loc)
 );
 
 parser!(extern_block _loc -> 'b Vec<ast::TypeAnnotation<'b>>=
@@ -397,8 +555,39 @@ fn pop_operator<'c>(operator_stack: &mut Vec<&Token>, results: &mut Vec<(Ast<'c>
     results.push((call, location));
 }
 
-/// Parse an arbitrary expression using the shunting-yard algorithm
+/// expression = operator_expression ('where' definitions)?
 fn expression<'a, 'b>(input: Input<'a, 'b>) -> AstResult<'a, 'b> {
+    let (input, expression, location) = operator_expression(input, precedence)?;
+
+    if input[0].0 != Token::Where {
+        return Ok((input, expression, location));
+    }
+
+    let (input, definitions, definitions_location) = no_backtracking(where_definitions)(input)?;
+    let location = location.union(definitions_location);
+    Ok((input, Ast::where_expr(expression, definitions, location), location))
+}
+
+parser!(where_definitions _loc -> 'b Vec<ast::Definition<'b>> =
+    _ <- expect(Token::Where);
+    definitions <- or(&[impl_body_block, impl_body_single], "where definitions");
+    definitions
+);
+
+/// Parse an expression that does not contain an unparenthesized ',' or ';'.
+/// Used where ',' instead separates elements, e.g. the fields of a record spread.
+fn expression_no_pair<'a, 'b>(input: Input<'a, 'b>) -> AstResult<'a, 'b> {
+    operator_expression(input, |token| match token {
+        Token::Comma | Token::Semicolon => None,
+        other => precedence(other),
+    })
+}
+
+/// Parse an arbitrary expression using the shunting-yard algorithm, using the
+/// given function to determine which tokens are operators and their precedence.
+fn operator_expression<'a, 'b>(
+    input: Input<'a, 'b>, precedence: impl Fn(&Token) -> Option<(i8, bool)>,
+) -> AstResult<'a, 'b> {
     let (mut input, value, location) = term(input)?;
 
     let mut operator_stack = vec![];
@@ -435,14 +624,36 @@ fn term<'a, 'b>(input: Input<'a, 'b>) -> AstResult<'a, 'b> {
     match input[0].0 {
         Token::If => if_expr(input),
         Token::Match => match_expr(input),
-        _ => or(&[type_annotation, function_call, function_argument], "term")(input),
+        Token::Pipe => guard_expr(input),
+        _ => or(&[type_annotation, function_call, trailing_closure_call, function_argument], "term")(input),
     }
 }
 
 parser!(function_call loc =
     function <- member_access;
     args <- many1(function_argument);
-    desugar::desugar_explicit_currying(function, args, Ast::function_call, loc)
+    closure <- maybe(trailing_closure);
+    desugar::desugar_explicit_currying(function, args, |function, args, loc| {
+        Ast::function_call_with_trailing_closure(function, args, closure, loc)
+    }, loc)
+);
+
+parser!(trailing_closure_call loc =
+    function <- member_access;
+    closure <- trailing_closure;
+    Ast::function_call_with_trailing_closure(function, vec![], Some(closure), loc)
+);
+
+// trailing_closure = '{' pattern_argument+ (':' type)? '->' block_or_statement '}'
+parser!(trailing_closure loc =
+    _ <- expect(Token::BraceLeft);
+    args <- many1(pattern_argument);
+    return_type <- maybe(function_return_type);
+    _ <- expect(Token::RightArrow);
+    body !<- block_or_statement;
+    _ <- maybe(expect(Token::Newline));
+    _ !<- expect(Token::BraceRight);
+    Ast::lambda(args, return_type, body, loc)
 );
 
 parser!(pattern_function_call loc =
@@ -468,6 +679,12 @@ parser!(match_expr loc =
     Ast::match_expr(expression, branches, loc)
 );
 
+parser!(guard_expr loc =
+    branches <- many1(guard_branch);
+    otherwise !<- maybe(guard_else);
+    Ast::guard(branches, otherwise, loc)
+);
+
 parser!(not_expr loc =
     not <- expect(Token::Not);
     expr !<- term;
@@ -490,12 +707,13 @@ parser!(type_annotation loc =
     lhs <- or(&[function_call, function_argument], "term");
     _ <- expect(Token::Colon);
     mutable <- maybe(expect(Token::Mut));
+    gradual <- maybe(expect(Token::Dyn));
     rhs <- parse_type;
-    Ast::type_annotation(lhs, rhs, mutable.is_some(), loc)
+    Ast::type_annotation(lhs, rhs, mutable.is_some(), gradual.is_some(), loc)
 );
 
 fn parse_type<'a, 'b>(input: Input<'a, 'b>) -> ParseResult<'a, 'b, Type<'b>> {
-    or(&[function_type, type_application, pair_type, basic_type], "type")(input)
+    or(&[forall_type, function_type, type_application, pair_type, basic_type], "type")(input)
 }
 
 fn function_arg_type<'a, 'b>(input: Input<'a, 'b>) -> ParseResult<'a, 'b, Type<'b>> {
@@ -519,6 +737,7 @@ fn basic_type<'a, 'b>(input: Input<'a, 'b>) -> ParseResult<'a, 'b, Type<'b>> {
         Token::Identifier(_) => type_variable(input),
         Token::TypeName(_) => user_defined_type(input),
         Token::ParenthesisLeft => parenthesized_type(input),
+        Token::BraceLeft => record_type(input),
         _ => Err(ParseError::InRule("type", input[0].1)),
     }
 }
@@ -527,13 +746,38 @@ fn parenthesized_type<'a, 'b>(input: Input<'a, 'b>) -> ParseResult<'a, 'b, Type<
     parenthesized(parse_type)(input)
 }
 
-parser!(match_branch _loc -> 'b (Ast<'b>, Ast<'b>) =
+parser!(match_branch _loc -> 'b (Ast<'b>, Option<Ast<'b>>, Ast<'b>) =
     _ <- maybe_newline;
     _ <- expect(Token::Pipe);
     pattern !<- pattern;
+    guard !<- maybe(match_guard);
+    _ !<- expect(Token::RightArrow);
+    branch !<- block_or_statement;
+    (pattern, guard, branch)
+);
+
+parser!(match_guard _loc =
+    _ <- expect(Token::If);
+    condition !<- block_or_statement;
+    condition
+);
+
+parser!(guard_branch _loc -> 'b (Ast<'b>, Ast<'b>) =
+    _ <- maybe_newline;
+    _ <- expect(Token::Pipe);
+    condition <- block_or_statement;
     _ !<- expect(Token::RightArrow);
     branch !<- block_or_statement;
-    (pattern, branch)
+    (condition, branch)
+);
+
+parser!(guard_else _loc =
+    _ <- maybe_newline;
+    _ <- expect(Token::Pipe);
+    _ <- expect(Token::Else);
+    _ !<- expect(Token::RightArrow);
+    otherwise !<- block_or_statement;
+    otherwise
 );
 
 parser!(else_expr _loc =
@@ -563,23 +807,33 @@ fn pattern_function_argument<'a, 'b>(input: Input<'a, 'b>) -> AstResult<'a, 'b>
 }
 
 /// member_access = argument ('.' identifier)*
+///
+/// As a special case, `TypeName.identifier` is parsed as
+/// a trait method qualified by its trait name.
 fn member_access<'a, 'b>(input: Input<'a, 'b>) -> AstResult<'a, 'b> {
     let (mut input, mut arg, mut location) = argument(input)?;
 
     while input[0].0 == Token::MemberAccess {
         input = &input[1..];
 
-        let (new_input, field, field_location) = no_backtracking(identifier)(input)?;
+        let (new_input, field, field_location) = no_backtracking(field_name)(input)?;
         input = new_input;
         location = location.union(field_location);
-        arg = Ast::member_access(arg, field, location);
+
+        arg = match arg {
+            Ast::Variable(ast::Variable { kind: ast::VariableKind::TypeConstructor(trait_name), .. }) => {
+                Ast::trait_method(trait_name, field, location)
+            },
+            _ => Ast::member_access(arg, field, location),
+        };
     }
 
     Ok((input, arg, location))
 }
 
 fn argument<'a, 'b>(input: Input<'a, 'b>) -> AstResult<'a, 'b> {
-    match input[0].0 {
+    match &input[0].0 {
+        Token::Identifier(name) if name == "_" => hole(input),
         Token::Identifier(_) => variable(input),
         Token::StringLiteral(_) => string(input),
         Token::IntegerLiteral(_, _) => integer(input),
@@ -589,6 +843,7 @@ fn argument<'a, 'b>(input: Input<'a, 'b>) -> AstResult<'a, 'b> {
         Token::UnitLiteral => unit(input),
         Token::Fn => lambda(input),
         Token::ParenthesisLeft => parenthesized_expression(input),
+        Token::BraceLeft if starts_record_spread(input) => record_spread(input),
         Token::TypeName(_) => variant(input),
         _ => Err(ParseError::InRule("argument", input[0].1)),
     }
@@ -598,6 +853,7 @@ fn pattern_argument<'a, 'b>(input: Input<'a, 'b>) -> AstResult<'a, 'b> {
     match input[0].0 {
         Token::Identifier(_) => variable(input),
         Token::StringLiteral(_) => string(input),
+        Token::IntegerLiteral(_, _) | Token::CharLiteral(_) if input[1].0 == Token::Range => range_pattern(input),
         Token::IntegerLiteral(_, _) => integer(input),
         Token::FloatLiteral(_) => float(input),
         Token::CharLiteral(_) => parse_char(input),
@@ -609,6 +865,21 @@ fn pattern_argument<'a, 'b>(input: Input<'a, 'b>) -> AstResult<'a, 'b> {
     }
 }
 
+// range_pattern = range_bound '..' range_bound
+parser!(range_pattern loc =
+    start <- range_bound;
+    _ <- expect(Token::Range);
+    end !<- range_bound;
+    Ast::range(start, end, loc)
+);
+
+fn range_bound<'a, 'b>(input: Input<'a, 'b>) -> AstResult<'a, 'b> {
+    match input[0].0 {
+        Token::CharLiteral(_) => parse_char(input),
+        _ => integer(input),
+    }
+}
+
 parser!(lambda loc =
     _ <- expect(Token::Fn);
     args !<- many1(pattern_argument);
@@ -627,6 +898,44 @@ fn parenthesized_expression<'a, 'b>(input: Input<'a, 'b>) -> AstResult<'a, 'b> {
     parenthesized(or(&[expression, operator], "operator or expression"))(input)
 }
 
+// record_spread = '{' record_spread_field (',' record_spread_field)* '}'
+// where each field is either a `..base` or a `field = value` override
+parser!(record_spread loc =
+    _ <- expect(Token::BraceLeft);
+    fields !<- delimited(record_spread_field, expect(Token::Comma));
+    _ !<- expect(Token::BraceRight);
+    Ast::record_spread(fields, loc)
+);
+
+/// Braces after a function's arguments may also begin a trailing closure `{ x -> ... }`
+/// so only treat them as a record spread if they begin with `..base` or `field =`
+fn starts_record_spread(input: Input) -> bool {
+    matches!(
+        (input.get(1).map(|token| &token.0), input.get(2).map(|token| &token.0)),
+        (Some(Token::Range), _) | (Some(Token::Identifier(_)), Some(Token::Equal))
+    )
+}
+
+fn record_spread_field<'a, 'b>(input: Input<'a, 'b>) -> ParseResult<'a, 'b, (Option<String>, Ast<'b>)> {
+    match input[0].0 {
+        Token::Range => record_spread_base(input),
+        _ => record_spread_override(input),
+    }
+}
+
+parser!(record_spread_base _loc -> 'b (Option<String>, Ast<'b>) =
+    _ <- expect(Token::Range);
+    base !<- expression_no_pair;
+    (None, base)
+);
+
+parser!(record_spread_override _loc -> 'b (Option<String>, Ast<'b>) =
+    field <- identifier;
+    _ !<- expect(Token::Equal);
+    value !<- expression_no_pair;
+    (Some(field), value)
+);
+
 parser!(variant loc =
     name <- typename;
     Ast::type_constructor(name, loc)
@@ -662,6 +971,11 @@ parser!(parse_bool loc =
     Ast::bool_literal(value, loc)
 );
 
+parser!(hole loc =
+    _ <- expect(Token::Identifier("_".into()));
+    Ast::hole(loc)
+);
+
 parser!(unit loc =
     _ <- expect(Token::UnitLiteral);
     Ast::unit_literal(loc)
@@ -672,7 +986,15 @@ parser!(function_type loc -> 'b Type<'b> =
     varargs <- maybe(varargs);
     _ <- expect(Token::RightArrow);
     return_type <- parse_type;
-    Type::Function(args, Box::new(return_type), varargs.is_some(), loc)
+    Type::Function(args, Box::new(return_type), varargs.is_some(), varargs.flatten(), loc)
+);
+
+parser!(forall_type loc -> 'b Type<'b> =
+    _ <- expect(Token::Forall);
+    variables !<- many1(identifier);
+    _ !<- expect(Token::MemberAccess);
+    typ !<- parse_type;
+    Type::Forall(variables, Box::new(typ), loc)
 );
 
 parser!(type_application loc -> 'b Type<'b> =
@@ -725,7 +1047,31 @@ parser!(unit_type loc -> 'b Type<'b> =
 
 parser!(reference_type loc -> 'b Type<'b> =
     _ <- expect(Token::Ref);
-    Type::Reference(loc)
+    region <- maybe(region_name);
+    Type::Reference(region, loc)
+);
+
+// region_name = '{' identifier '}'
+parser!(region_name _loc -> 'b String =
+    _ <- expect(Token::BraceLeft);
+    name <- identifier;
+    _ <- expect(Token::BraceRight);
+    name
+);
+
+// record_type = '{' identifier ':' type (',' identifier ':' type)* '}'
+parser!(record_type loc -> 'b Type<'b> =
+    _ <- expect(Token::BraceLeft);
+    fields !<- delimited_trailing(record_type_field, expect(Token::Comma));
+    _ !<- expect(Token::BraceRight);
+    Type::Record(fields, loc)
+);
+
+parser!(record_type_field _loc -> 'b (String, Type<'b>) =
+    name <- identifier;
+    _ !<- expect(Token::Colon);
+    typ !<- parse_type_no_pair;
+    (name, typ)
 );
 
 parser!(type_variable loc -> 'b Type<'b> =
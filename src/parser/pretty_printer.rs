@@ -36,6 +36,7 @@ impl<'a> Display for ast::Variable<'a> {
             Identifier(name) => write!(f, "{}", name),
             Operator(token) => write!(f, "{}", token),
             TypeConstructor(name) => write!(f, "{}", name),
+            TraitMethod(trait_name, method) => write!(f, "{}.{}", trait_name, method),
         }
     }
 }
@@ -43,8 +44,12 @@ impl<'a> Display for ast::Variable<'a> {
 impl<'a> Display for ast::Lambda<'a> {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         write!(f, "(fn")?;
-        for arg in self.args.iter() {
-            write!(f, " {}", arg)?;
+        for (i, arg) in self.args.iter().enumerate() {
+            if i < self.implicit_parameters.len() {
+                write!(f, " (implicit {})", arg)?;
+            } else {
+                write!(f, " {}", arg)?;
+            }
         }
         if let Some(typ) = &self.return_type {
             write!(f, " : {}", typ)?;
@@ -61,7 +66,8 @@ impl<'a> Display for ast::FunctionCall<'a> {
 
 impl<'a> Display for ast::Definition<'a> {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "({} = {})", self.pattern, self.expr)
+        let implicit = if self.implicit { "implicit " } else { "" };
+        write!(f, "({}{} = {})", implicit, self.pattern, self.expr)
     }
 }
 
@@ -75,11 +81,27 @@ impl<'a> Display for ast::If<'a> {
     }
 }
 
+impl<'a> Display for ast::Guard<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "(guard")?;
+        for (condition, branch) in self.branches.iter() {
+            write!(f, " ({} {})", condition, branch)?;
+        }
+        if let Some(ref otherwise) = self.otherwise {
+            write!(f, " (else {})", otherwise)?;
+        }
+        write!(f, ")")
+    }
+}
+
 impl<'a> Display for ast::Match<'a> {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         write!(f, "(match {}", self.expression)?;
-        for (pattern, branch) in self.branches.iter() {
-            write!(f, " ({} {})", pattern, branch)?;
+        for (pattern, guard, branch) in self.branches.iter() {
+            match guard {
+                Some(guard) => write!(f, " ({} if {} {})", pattern, guard, branch)?,
+                None => write!(f, " ({} {})", pattern, branch)?,
+            }
         }
         write!(f, ")")
     }
@@ -96,11 +118,17 @@ impl<'a> Display for ast::Type<'a> {
             Pointer(_) => write!(f, "Ptr"),
             Boolean(_) => write!(f, "bool"),
             Unit(_) => write!(f, "unit"),
-            Reference(_) => write!(f, "ref"),
+            Reference(None, _) => write!(f, "ref"),
+            Reference(Some(region), _) => write!(f, "ref{{{}}}", region),
             TypeVariable(name, _) => write!(f, "{}", name),
             UserDefined(name, _) => write!(f, "{}", name),
-            Function(params, return_type, varargs, _) => {
-                write!(f, "({} {}-> {})", join_with(params, " "), if *varargs { "... " } else { "" }, return_type)
+            Function(params, return_type, varargs, varargs_type, _) => {
+                let varargs = match varargs_type {
+                    Some(typ) => format!("...{} ", typ),
+                    None if *varargs => "... ".to_string(),
+                    None => "".to_string(),
+                };
+                write!(f, "({} {}-> {})", join_with(params, " "), varargs, return_type)
             },
             TypeApplication(constructor, args, _) => {
                 write!(f, "({} {})", constructor, join_with(args, " "))
@@ -108,6 +136,13 @@ impl<'a> Display for ast::Type<'a> {
             Pair(first, rest, _) => {
                 write!(f, "({}, {})", first, rest)
             },
+            Forall(variables, typ, _) => {
+                write!(f, "(forall {}. {})", variables.join(" "), typ)
+            },
+            Record(fields, _) => {
+                let fields = fmap(fields, |(name, typ)| format!("{}: {}", name, typ));
+                write!(f, "{{{}}}", fields.join(", "))
+            },
         }
     }
 }
@@ -134,14 +169,22 @@ impl<'a> Display for ast::TypeDefinitionBody<'a> {
 
 impl<'a> Display for ast::TypeDefinition<'a> {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        let args = join_with(&self.args, "");
+        let args = fmap(&self.args, |(name, kind)| match kind {
+            Some(kind) => format!("({}: {})", name, kind),
+            None => name.clone(),
+        });
+        let args = join_with(&args, "");
         write!(f, "(type {} {} = {})", self.name, args, self.definition)
     }
 }
 
 impl<'a> Display for ast::TypeAnnotation<'a> {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "(: {} {})", self.lhs, self.rhs)
+        if self.gradual {
+            write!(f, "(: {} dyn {})", self.lhs, self.rhs)
+        } else {
+            write!(f, "(: {} {})", self.lhs, self.rhs)
+        }
     }
 }
 
@@ -157,7 +200,14 @@ impl<'a> Display for ast::TraitDefinition<'a> {
         if !self.fundeps.is_empty() {
             write!(f, "-> {} ", join_with(&self.fundeps, " "))?;
         }
-        write!(f, "with\n    {}\n)", join_with(&self.declarations, "\n    "))
+        if !self.given.is_empty() {
+            write!(f, "given {} ", join_with(&self.given, " "))?;
+        }
+        write!(f, "with\n    {}", join_with(&self.declarations, "\n    "))?;
+        for default in &self.defaults {
+            write!(f, "\n    {}", default)?;
+        }
+        write!(f, "\n)")
     }
 }
 
@@ -185,6 +235,15 @@ impl<'a> Display for ast::Trait<'a> {
     }
 }
 
+impl<'a> Display for ast::Given<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ast::Given::Trait(trait_) => write!(f, "{}", trait_),
+            ast::Given::Equality(lhs, rhs) => write!(f, "(= {} {})", lhs, rhs),
+        }
+    }
+}
+
 impl<'a> Display for ast::Return<'a> {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         write!(f, "(return {})", self.expression)
@@ -228,7 +287,10 @@ impl<'a> Display for ast::Sequence<'a> {
 
 impl<'a> Display for ast::Extern<'a> {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "(extern\n    {})", join_with(&self.declarations, "\n    "))
+        match &self.abi {
+            Some(abi) => write!(f, "(extern \"{}\"\n    {})", abi, join_with(&self.declarations, "\n    ")),
+            None => write!(f, "(extern\n    {})", join_with(&self.declarations, "\n    ")),
+        }
     }
 }
 
@@ -243,3 +305,35 @@ impl<'a> Display for ast::Assignment<'a> {
         write!(f, "({} := {})", self.lhs, self.rhs)
     }
 }
+
+impl<'a> Display for ast::RecordSpread<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut fields = fmap(&self.bases, |base| format!("..{}", base));
+        fields.extend(self.overrides.iter().map(|(field, value)| format!("{} = {}", field, value)));
+        write!(f, "{{{}}}", fields.join(", "))
+    }
+}
+
+impl<'a> Display for ast::TypeTest<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "({} is {})", self.expression, self.constructor)
+    }
+}
+
+impl<'a> Display for ast::Range<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "({} .. {})", self.start, self.end)
+    }
+}
+
+impl<'a> Display for ast::Hole<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "_")
+    }
+}
+
+impl<'a> Display for ast::Where<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "({} where {})", self.expression, join_with(&self.definitions, "; "))
+    }
+}
@@ -8,14 +8,17 @@ use crate::cache::{DefinitionInfoId, ModuleCache};
 use crate::error::location::{Locatable, Location};
 use crate::lexer::token::IntegerKind;
 use crate::lifetimes;
+use crate::util::{closest_name, fmap};
 
 use self::typeprinter::TypePrinter;
+use std::collections::HashMap;
 
 pub mod pattern;
 pub mod traitchecker;
 pub mod traits;
 pub mod typechecker;
 pub mod typed;
+pub mod typejson;
 pub mod typeprinter;
 
 /// The type to default any Inferred integer types to that were
@@ -54,6 +57,11 @@ pub struct FunctionType {
     pub return_type: Box<Type>,
     pub environment: Box<Type>,
     pub is_varargs: bool,
+
+    /// The type of each argument passed in the varargs portion of a varargs function,
+    /// e.g. `i32` in `sum: usz ...i32 -> i32`. If this is None any number of arguments
+    /// of any type may be passed instead.
+    pub varargs_type: Option<Box<Type>>,
 }
 
 /// Any type in ante. Note that a trait is not a type. Traits are
@@ -96,8 +104,27 @@ pub enum Type {
     /// Contains a region variable that is unified with other refs during type
     /// inference. All these refs will be allocated in the same region.
     Ref(lifetimes::LifetimeVariableId),
+
+    /// A polymorphic type nested within another type, e.g. the parameter type in
+    /// `(forall a. a -> a) -> i32`. These are only expected as the types of function
+    /// parameters, letting a function accept a polymorphic function as an argument.
+    /// The quantified type variables are never bound, they are replaced with fresh
+    /// type variables whenever the type is instantiated or skolemized instead.
+    Forall(Vec<TypeVariableId>, Box<Type>),
+
+    /// An anonymous record type `{ field1: type1, ..., fieldN: typeN }` accepting any
+    /// struct with at least the given fields. The type variable stands in for the struct
+    /// itself: once it is bound, the record is treated as equal to what it is bound to.
+    /// This makes records polymorphic in the struct they are used with until then.
+    Record(Vec<(String, Type)>, TypeVariableId),
 }
 
+/// A type returned by `Type::normalize`. Its type variables were renumbered and no longer
+/// refer to type variables in the cache, so it is kept opaque to ensure it is only ever
+/// compared or hashed rather than displayed or followed.
+#[derive(Clone, Eq, PartialEq, Hash)]
+pub struct NormalizedType(Type);
+
 #[derive(Debug, Clone)]
 pub enum GeneralizedType {
     /// A non-generic type
@@ -143,11 +170,77 @@ impl Type {
             Ref(_) => None,
             Function(function) => function.return_type.union_constructor_variants(cache),
             TypeApplication(typ, _) => typ.union_constructor_variants(cache),
+            Forall(_, typ) => typ.union_constructor_variants(cache),
+            Record(..) => None,
             UserDefined(id) => cache.type_infos[id.0].union_variants(),
             TypeVariable(_) => unreachable!("Constructors should always have concrete types"),
         }
     }
 
+    /// Follow all bindings in this type and renumber each remaining type variable in the order
+    /// it is first encountered. Types that are equal up to a consistent renaming of their type
+    /// variables normalize to the same type. For example, `a -> b` and `c -> d` are equal after
+    /// normalization while `a -> a` and `a -> b` are not.
+    ///
+    /// The renumbered type variables don't refer to any type variables in the cache so
+    /// the normalized type can only be compared or hashed, see `NormalizedType`.
+    pub fn normalize(&self, cache: &ModuleCache) -> NormalizedType {
+        NormalizedType(self.normalize_with(&mut HashMap::new(), cache))
+    }
+
+    /// Normalize each type, renumbering type variables consistently across all of them.
+    pub fn normalize_all(types: &[Type], cache: &ModuleCache) -> Vec<NormalizedType> {
+        let mut renumbering = HashMap::new();
+        fmap(types, |typ| NormalizedType(typ.normalize_with(&mut renumbering, cache)))
+    }
+
+    fn normalize_with(&self, renumbering: &mut HashMap<TypeVariableId, TypeVariableId>, cache: &ModuleCache) -> Type {
+        let mut renumber = |id: TypeVariableId| {
+            let next_id = TypeVariableId(renumbering.len());
+            *renumbering.entry(id).or_insert(next_id)
+        };
+
+        match self {
+            Type::Primitive(_) | Type::UserDefined(_) => self.clone(),
+            Type::TypeVariable(id) => match &cache.type_bindings[id.0] {
+                TypeBinding::Bound(binding) => binding.normalize_with(renumbering, cache),
+                TypeBinding::Unbound(..) => Type::TypeVariable(renumber(*id)),
+            },
+            Type::Function(function) => Type::Function(FunctionType {
+                parameters: fmap(&function.parameters, |parameter| parameter.normalize_with(renumbering, cache)),
+                return_type: Box::new(function.return_type.normalize_with(renumbering, cache)),
+                environment: Box::new(function.environment.normalize_with(renumbering, cache)),
+                is_varargs: function.is_varargs,
+                varargs_type: function
+                    .varargs_type
+                    .as_ref()
+                    .map(|typ| Box::new(typ.normalize_with(renumbering, cache))),
+            }),
+            Type::TypeApplication(constructor, args) => {
+                let constructor = Box::new(constructor.normalize_with(renumbering, cache));
+                Type::TypeApplication(constructor, fmap(args, |arg| arg.normalize_with(renumbering, cache)))
+            },
+            Type::Ref(id) => match &cache.type_bindings[id.0] {
+                TypeBinding::Bound(Type::TypeVariable(binding) | Type::Ref(binding)) => {
+                    Type::Ref(*binding).normalize_with(renumbering, cache)
+                },
+                _ => Type::Ref(renumber(*id)),
+            },
+            Type::Forall(variables, typ) => {
+                let variables = fmap(variables, |variable| renumber(*variable));
+                Type::Forall(variables, Box::new(typ.normalize_with(renumbering, cache)))
+            },
+            Type::Record(fields, row) => match &cache.type_bindings[row.0] {
+                TypeBinding::Bound(binding) => binding.normalize_with(renumbering, cache),
+                TypeBinding::Unbound(..) => {
+                    let row = renumber(*row);
+                    let fields = fmap(fields, |(name, typ)| (name.clone(), typ.normalize_with(renumbering, cache)));
+                    Type::Record(fields, row)
+                },
+            },
+        }
+    }
+
     /// Pretty-print each type with each typevar substituted for a, b, c, etc.
     pub fn display<'a, 'b>(&self, cache: &'a ModuleCache<'b>) -> typeprinter::TypePrinter<'a, 'b> {
         let typ = GeneralizedType::MonoType(self.clone());
@@ -317,6 +410,28 @@ impl<'a> TypeInfo<'a> {
             _ => None,
         }
     }
+
+    /// The name of each field of this type in declaration order. Empty if this is not a struct type.
+    pub fn field_names(&self) -> Vec<&str> {
+        match &self.body {
+            TypeInfoBody::Struct(fields) => fmap(fields, |field| field.name.as_str()),
+            _ => vec![],
+        }
+    }
+}
+
+/// Suffix for an error about a missing field `field_name`, listing the fields the type does
+/// have and suggesting the closest one in case it was misspelled.
+pub fn missing_field_hint(field_name: &str, field_names: &[&str]) -> String {
+    if field_names.is_empty() {
+        return String::new();
+    }
+
+    let mut hint = format!("; available fields: {}", field_names.join(", "));
+    if let Some(suggestion) = closest_name(field_name, field_names.iter().copied()) {
+        hint += &format!(". Did you mean {}?", suggestion);
+    }
+    hint
 }
 
 #[derive(Debug, Clone)]
@@ -328,6 +443,63 @@ pub enum Kind {
     /// A higher order kind where each element in the Vec is an argument. For example, the kind:
     /// HigherOrder(vec![ Normal(0), HigherOrder(vec![ Normal(0), Normal(1) ]), Normal(1) ])
     /// has kind: * -> (* -> (* -> *)) -> (* -> *)
-    #[allow(dead_code)]
     HigherOrder(Vec<Kind>),
 }
+
+impl Kind {
+    /// Returns the kinds of each type argument this kind takes before returning a type of kind *.
+    /// For example, the parameters of `* -> (* -> *) -> *` are `[*, * -> *]`.
+    pub fn parameters(&self) -> Vec<Kind> {
+        match self {
+            Kind::Normal(count) => vec![Kind::Normal(0); *count],
+            Kind::HigherOrder(kinds) => {
+                let (result, parameters) = kinds.split_last().unwrap();
+                let mut parameters = parameters.to_vec();
+                parameters.append(&mut result.parameters());
+                parameters
+            },
+        }
+    }
+
+    /// Create the kind taking each of the given parameters before returning a type of kind *.
+    pub fn from_parameters(mut parameters: Vec<Kind>) -> Kind {
+        if parameters.iter().all(|parameter| parameter.parameters().is_empty()) {
+            Kind::Normal(parameters.len())
+        } else {
+            parameters.push(Kind::Normal(0));
+            Kind::HigherOrder(parameters)
+        }
+    }
+
+    /// Returns the kind that results from applying this kind to the given number of type arguments,
+    /// or None if this kind does not take that many type arguments.
+    pub fn apply(&self, argument_count: usize) -> Option<Kind> {
+        let parameters = self.parameters();
+        if argument_count <= parameters.len() {
+            Some(Kind::from_parameters(parameters[argument_count..].to_vec()))
+        } else {
+            None
+        }
+    }
+}
+
+/// Kinds are equal if they take the same parameters, regardless of whether they are
+/// represented with `Normal` or `HigherOrder`.
+impl PartialEq for Kind {
+    fn eq(&self, other: &Kind) -> bool {
+        self.parameters() == other.parameters()
+    }
+}
+
+impl std::fmt::Display for Kind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for parameter in self.parameters() {
+            if parameter.parameters().is_empty() {
+                write!(f, "{} -> ", parameter)?;
+            } else {
+                write!(f, "({}) -> ", parameter)?;
+            }
+        }
+        write!(f, "*")
+    }
+}
@@ -19,6 +19,7 @@ use crate::types::{typechecker, PrimitiveType, Type, TypeInfoBody, TypeInfoId, S
 use crate::util::{fmap, join_with, unwrap_clone};
 
 use std::collections::{BTreeMap, BTreeSet};
+use std::rc::Rc;
 
 use super::GeneralizedType;
 
@@ -29,7 +30,7 @@ pub fn compile<'c>(match_expr: &ast::Match<'c>, cache: &mut ModuleCache<'c>) ->
     let result = matrix.compile(cache, match_expr.location);
 
     if result.context.reachable_branches.len() != match_expr.branches.len() {
-        for (i, (pattern, _branch)) in match_expr.branches.iter().enumerate() {
+        for (i, (pattern, _guard, _branch)) in match_expr.branches.iter().enumerate() {
             if !result.context.reachable_branches.contains(&i) {
                 warning!(pattern.locate(), "Unreachable pattern");
             }
@@ -59,6 +60,11 @@ pub enum VariantTag {
     /// for this constructor. Integers and floats are most notably translated to
     /// this rather than attempting to approximate the types' full ranges.
     Literal(ast::LiteralKind),
+
+    /// An inclusive range of integers or char codepoints, see `ast::Range::bounds`. A Switch on a column containing
+    /// a range pattern splits the column's ranges and literals into disjoint ranges of this tag,
+    /// see `split_ranges`. As with Literal, completeness checking is given up for these.
+    Range(i64, i64),
 }
 
 /// Every pattern in a match expression is represented as a Constructor which
@@ -81,9 +87,14 @@ impl Constructor {
     }
 
     fn matches(&self, candidate: &VariantTag) -> bool {
-        match self {
-            MatchAll(_) => true,
-            Variant(tag, _) => tag == candidate,
+        match (self, candidate) {
+            (MatchAll(_), _) => true,
+            // Ranges are only ever split into smaller ranges, so a candidate range is
+            // always either entirely within or entirely outside of this pattern's range.
+            (Variant(tag, _), VariantTag::Range(start, _)) => {
+                range_of_tag(tag).is_some_and(|(low, high)| low <= *start && *start <= high)
+            },
+            (Variant(tag, _), _) => tag == candidate,
         }
     }
 
@@ -206,6 +217,13 @@ impl PatternStack {
                 let variable = new_pattern_variable(".from_ast.Literal", location, cache);
                 PatternStack(vec![(Variant(tag, fields), variable)])
             },
+            Ast::Range(range) => {
+                let (start, end) =
+                    range.bounds().expect("Range pattern bounds should be checked during type inference");
+                let tag = VariantTag::Range(start, end);
+                let variable = new_pattern_variable(".from_ast.Range", location, cache);
+                PatternStack(vec![(Variant(tag, PatternStack(vec![])), variable)])
+            },
             Ast::FunctionCall(call) => match call.function.as_ref() {
                 Ast::Variable(variable) => {
                     let tag = VariantTag::UserDefined(variable.definition.unwrap());
@@ -312,10 +330,41 @@ fn get_missing_builtin_cases<T>(variants: &BTreeMap<&VariantTag, T>) -> Option<B
         (Some(Unit), _) => Some(missing_cases),
         // Literals always require a match-all, so a missing case is always inserted here.
         (Some(Literal(literal)), _) => insert_if(missing_cases, Literal(literal.clone()), true),
+        (Some(Range(start, end)), _) => insert_if(missing_cases, Range(*start, *end), true),
         _ => None,
     }
 }
 
+/// Returns the range of values the given tag matches if it is a range or an integer or char literal.
+fn range_of_tag(tag: &VariantTag) -> Option<(i64, i64)> {
+    match tag {
+        VariantTag::Range(start, end) => Some((*start, *end)),
+        VariantTag::Literal(LiteralKind::Integer(value, _)) => Some((*value as i64, *value as i64)),
+        VariantTag::Literal(LiteralKind::Char(value)) => Some((*value as i64, *value as i64)),
+        _ => None,
+    }
+}
+
+/// Splits the ranges of the given tags into disjoint ranges such that each range of a tag is
+/// the union of some of the resulting ranges. E.g. `'a'..'z'` and `'x'` are split into
+/// `'a'..'w'`, `'x'..'x'`, and `'y'..'z'`. Values not matched by any tag are not included.
+fn split_ranges<'a>(tags: impl Iterator<Item = &'a VariantTag>) -> Vec<VariantTag> {
+    let ranges: Vec<_> = tags.filter_map(range_of_tag).collect();
+
+    // Each range starts at one boundary and ends just before another. Boundaries
+    // are i128s since the end of a range may be i64::MAX.
+    let boundaries: BTreeSet<i128> =
+        ranges.iter().flat_map(|(start, end)| [*start as i128, *end as i128 + 1]).collect();
+    let boundaries: Vec<_> = boundaries.into_iter().collect();
+
+    boundaries
+        .windows(2)
+        .map(|window| (window[0] as i64, (window[1] - 1) as i64))
+        .filter(|(start, end)| ranges.iter().any(|(low, high)| low <= start && end <= high))
+        .map(|(start, end)| VariantTag::Range(start, end))
+        .collect()
+}
+
 fn get_covered_constructors<T>(variants: &BTreeMap<&VariantTag, T>) -> BTreeSet<VariantTag> {
     variants.iter().map(|(tag, _)| (*tag).clone()).collect()
 }
@@ -330,7 +379,7 @@ fn get_missing_cases<'c, T>(variants: &BTreeMap<&VariantTag, T>, cache: &ModuleC
     }
 
     match variants.iter().next().map(|(tag, _)| *tag).unwrap() {
-        True | False | Unit | Literal(_) => {
+        True | False | Unit | Literal(_) | Range(..) => {
             unreachable!("Found builtin constructor not covered by builtin_is_exhastive")
         },
 
@@ -380,11 +429,14 @@ fn collect_fields(rows: Vec<&PatternStack>) -> Vec<Vec<DefinitionInfoId>> {
     variables
 }
 
-#[derive(Default)]
 struct PatternMatrix {
     /// Each row holds the pattern stack of the pattern for a particular branch as
     /// well as the index of the branch that the pattern leads to in the source if matched.
     rows: Vec<(PatternStack, usize)>,
+
+    /// The indices of each branch with a guard. A row leading to one of these branches
+    /// may still fail to match if its guard is false, so it never makes a match exhaustive.
+    guarded_branches: Rc<BTreeSet<usize>>,
 }
 
 impl PatternMatrix {
@@ -393,10 +445,22 @@ impl PatternMatrix {
             .branches
             .iter()
             .enumerate()
-            .map(|(branch_index, (pattern, _))| (PatternStack::from_ast(pattern, cache, location), branch_index))
+            .map(|(branch_index, (pattern, _, _))| (PatternStack::from_ast(pattern, cache, location), branch_index))
+            .collect();
+
+        let guarded_branches = match_expr
+            .branches
+            .iter()
+            .enumerate()
+            .filter_map(|(branch_index, (_, guard, _))| guard.as_ref().map(|_| branch_index))
             .collect();
 
-        PatternMatrix { rows }
+        PatternMatrix { rows, guarded_branches: Rc::new(guarded_branches) }
+    }
+
+    /// Create a new matrix with the given rows, sharing this matrix's guarded branches.
+    fn with_rows(&self, rows: Vec<(PatternStack, usize)>) -> PatternMatrix {
+        PatternMatrix { rows, guarded_branches: self.guarded_branches.clone() }
     }
 
     /// This function corresponds to S(c, P -> A) in "Compiling
@@ -419,7 +483,7 @@ impl PatternMatrix {
         &self, tag: &VariantTag, arity: usize, fields: &mut Vec<Vec<DefinitionInfoId>>, cache: &mut ModuleCache<'c>,
         location: Location<'c>,
     ) -> Self {
-        let mut matrix = PatternMatrix::default();
+        let mut matrix = self.with_rows(vec![]);
 
         for (row, branch) in self.rows.iter() {
             if let Some(row) = row.specialize_row(tag, arity, fields, cache, location) {
@@ -452,7 +516,7 @@ impl PatternMatrix {
     fn default_specialize<'c>(
         &self, cache: &mut ModuleCache<'c>, location: Location<'c>,
     ) -> (DecisionTreeResult, Vec<DefinitionInfoId>) {
-        let mut matrix = PatternMatrix::default();
+        let mut matrix = self.with_rows(vec![]);
         let mut variables_to_bind = vec![];
 
         for (row, branch) in self.rows.iter() {
@@ -469,6 +533,7 @@ impl PatternMatrix {
     /// Handles exhaustiveness checking for the union internally.
     fn switch_on_pattern<'c>(&mut self, cache: &mut ModuleCache<'c>, location: Location<'c>) -> DecisionTreeResult {
         // Generate the set of constructors appearing in the column
+        let ranges;
         let mut matched_variants = BTreeMap::new();
         let mut switching_on = None;
 
@@ -480,6 +545,23 @@ impl PatternMatrix {
             }
         }
 
+        // Range patterns may overlap each other and any literals, so switch on disjoint ranges instead.
+        // Each of these ranges is matched by every row whose range or literal contains it.
+        if matched_variants.keys().any(|tag| matches!(tag, VariantTag::Range(..))) {
+            ranges = split_ranges(matched_variants.keys().copied());
+            matched_variants = ranges.iter().map(|range| (range, vec![])).collect();
+
+            for (row, _) in self.rows.iter() {
+                if let Some((constructor @ Variant(_, fields), _)) = row.head() {
+                    for (range, rows) in matched_variants.iter_mut() {
+                        if constructor.matches(range) {
+                            rows.push(fields);
+                        }
+                    }
+                }
+            }
+        }
+
         let missed_cases = get_missing_cases(&matched_variants, cache);
         let mut context = DecisionTreeContext::default();
 
@@ -550,8 +632,15 @@ impl PatternMatrix {
             // We have an in-exhaustive case expression
             DecisionTreeResult::fail()
         } else if self.first_row_is_all_wildcards() {
-            // If every pattern in the first row is a wildcard it must match.
-            DecisionTreeResult::leaf(self.rows[0].1)
+            let branch = self.rows[0].1;
+            if self.guarded_branches.contains(&branch) {
+                // The guard may fail, in which case matching continues with the remaining rows
+                let rest = self.with_rows(self.rows[1..].to_vec()).compile(cache, location);
+                DecisionTreeResult::guard(branch, rest)
+            } else {
+                // If every pattern in the first row is a wildcard it must match.
+                DecisionTreeResult::leaf(branch)
+            }
         } else {
             // There's at least one non-wild pattern in the matrix somewhere
             for (row, _) in self.rows.iter() {
@@ -618,6 +707,12 @@ impl DecisionTreeResult {
         DecisionTreeResult::new(DecisionTree::Leaf(branch), context)
     }
 
+    fn guard(branch: usize, rest: DecisionTreeResult) -> DecisionTreeResult {
+        let mut context = rest.context;
+        context.reachable_branches.insert(branch);
+        DecisionTreeResult::new(DecisionTree::Guard(branch, Box::new(rest.tree)), context)
+    }
+
     fn issue_inexhaustive_errors<'c>(&self, cache: &ModuleCache<'c>, location: Location<'c>) {
         let mut bindings = BTreeMap::new();
         DecisionTreeResult::issue_inexhaustive_errors_helper(&self.tree, None, &mut bindings, cache, location);
@@ -633,6 +728,13 @@ impl DecisionTreeResult {
         match tree {
             Leaf(_) => (),
             Fail => unreachable!("DecisionTree::Fail case should be matched on within DecisionTree::Switch"),
+            // If nothing else matches when the guard fails, whatever led to the guard is a missing case
+            Guard(_, rest) => match rest.as_ref() {
+                Fail => DecisionTreeResult::issue_inexhaustive_error(starting_id, bindings, location),
+                rest => {
+                    DecisionTreeResult::issue_inexhaustive_errors_helper(rest, starting_id, bindings, cache, location)
+                },
+            },
             Switch(id, cases) => {
                 for case in cases.iter() {
                     match &case.branch {
@@ -641,7 +743,8 @@ impl DecisionTreeResult {
                                 cases.iter().filter_map(|case| case.tag.as_ref()).map(|tag| (tag, ())).collect();
 
                             for tag in get_missing_cases(&covered_cases, cache) {
-                                bindings.insert(*id, DebugConstructor::new(&Some(tag), cache));
+                                bindings.insert(*id, DebugConstructor::missing(&tag, cache));
+                                let starting_id = starting_id.or(Some(*id));
                                 DecisionTreeResult::issue_inexhaustive_error(starting_id, bindings, location);
                             }
                         },
@@ -739,6 +842,10 @@ pub enum DecisionTree {
 
     /// Switch on the given pattern for each case of a tagged union or literal
     Switch(DefinitionInfoId, Vec<Case>),
+
+    /// Run the code at the given branch index if that branch's guard is true.
+    /// Otherwise, continue matching with the given tree.
+    Guard(usize, Box<DecisionTree>),
 }
 
 /// One Case of a DecisionTree::Switch, along with the branch of the DecisionTree to
@@ -785,6 +892,7 @@ impl DebugConstructor {
             // VariantTags would mean we should give up on completeness checking for them.
             Some(Literal(LiteralKind::Bool(_))) => unreachable!(),
             Some(Literal(LiteralKind::Unit)) => unreachable!(),
+            Some(Range(..)) => "_".to_string(),
             Some(True) => "true".to_string(),
             Some(False) => "false".to_string(),
             Some(VariantTag::Unit) => "()".to_string(),
@@ -794,6 +902,21 @@ impl DebugConstructor {
         DebugConstructor { tag, fields: vec![] }
     }
 
+    /// A constructor which no case matched. Each of its fields is shown as `_`.
+    fn missing<'c>(tag: &VariantTag, cache: &ModuleCache<'c>) -> DebugConstructor {
+        let mut constructor = DebugConstructor::new(&Some(tag.clone()), cache);
+
+        if let VariantTag::UserDefined(id) = tag {
+            let type_id = get_variant_type_from_constructor(*id, cache);
+            if let Some(variants) = cache.type_infos[type_id.0].union_variants() {
+                let variant = variants.iter().find(|variant| variant.id == *id).unwrap();
+                constructor.fields = vec![vec![]; variant.args.len()];
+            }
+        }
+
+        constructor
+    }
+
     fn from_case<'c>(case: &Case, cache: &ModuleCache<'c>) -> DebugConstructor {
         let mut constructor = DebugConstructor::new(&case.tag, cache);
         constructor.fields = case.fields.clone();
@@ -813,6 +936,7 @@ impl DecisionTree {
                 set_type(*id, typ, location, cache);
                 self.infer_impl(location, cache);
             },
+            DecisionTree::Guard(_, rest) => rest.infer(typ, location, cache),
         }
     }
 
@@ -854,6 +978,7 @@ impl DecisionTree {
                     case.branch.infer_impl(location, cache);
                 }
             },
+            DecisionTree::Guard(_, rest) => rest.infer_impl(location, cache),
         }
     }
 }
@@ -914,7 +1039,8 @@ impl Case {
             Some(True) => Type::Primitive(PrimitiveType::BooleanType),
             Some(False) => Type::Primitive(PrimitiveType::BooleanType),
             Some(VariantTag::Unit) => Type::Primitive(PrimitiveType::UnitType),
-            None => expected_type.clone(),
+            // Whether the range is of integers or chars was already decided when inferring the pattern
+            Some(Range(..)) | None => expected_type.clone(),
         }
     }
 }
@@ -934,6 +1060,10 @@ fn fmt_tree(tree: &DecisionTree, f: &mut std::fmt::Formatter, indent_level: usiz
     match tree {
         Leaf(branch) => write!(f, "Leaf({})", branch),
         Fail => write!(f, "Fail"),
+        Guard(branch, rest) => {
+            write!(f, "Guard({}) else ", branch)?;
+            fmt_tree(rest, f, indent_level)
+        },
         Switch(id, cases) => {
             write!(f, "match ${} with", id.0)?;
             let spaces = " ".repeat(indent_level);
@@ -17,12 +17,15 @@
 //! impl to the `ast::Variable` the TraitConstraint originated from, so that variable
 //! has the correct definition to compile during codegen. For any impl it fails to solve,
 //! a compile-time error will be issued.
-use crate::cache::{ImplInfoId, ModuleCache};
+use crate::cache::{ImplInfoId, ImplResolutionKey, ImplScopeId, ModuleCache, VariableId};
 use crate::error::location::Location;
 use crate::lexer::token::IntegerKind;
-use crate::types::traits::{RequiredTrait, TraitConstraint, TraitConstraints};
+use crate::types::traits::{
+    determined_typevars, Callsite, ConstraintSignature, GivenConstraint, RequiredTrait, TraitConstraint,
+    TraitConstraints,
+};
 use crate::types::typechecker::{self, TypeBindings, UnificationResult};
-use crate::types::{PrimitiveType, Type, TypeInfoId, TypeVariableId, DEFAULT_INTEGER_TYPE};
+use crate::types::{missing_field_hint, PrimitiveType, Type, TypeInfoId, TypeVariableId, DEFAULT_INTEGER_TYPE};
 use crate::util::{fmap, trustme};
 
 use colored::Colorize;
@@ -68,9 +71,27 @@ pub fn resolve_traits<'a>(
         );
     }
 
-    for constraint in other_constraints.iter() {
-        // Searching for an impl for normal constraints may require recursively searching for
-        // more impls (due to `impl A given B` constraints) before finding a matching one.
+    // Solving a constraint may bind the functional dependencies of another, e.g. solving
+    // `Conv i32 b` before `Print b`. So constraints whose type arguments are already known are
+    // solved first, repeating until no such constraints remain. The rest are solved in order.
+    let mut remaining = other_constraints;
+    loop {
+        let (known, unknown): (Vec<_>, Vec<_>) =
+            remaining.into_iter().partition(|constraint| constraint.typearg_typevars(cache).is_empty());
+
+        remaining = unknown;
+        if known.is_empty() {
+            break;
+        }
+
+        for constraint in known.iter() {
+            // Searching for an impl for normal constraints may require recursively searching for
+            // more impls (due to `impl A given B` constraints) before finding a matching one.
+            solve_normal_constraint(constraint, cache);
+        }
+    }
+
+    for constraint in remaining.iter() {
         solve_normal_constraint(constraint, cache);
     }
 
@@ -100,8 +121,18 @@ fn sort_traits<'c>(
     let mut member_access_constraints = vec![];
     let mut other_constraints = Vec::with_capacity(constraints.len());
 
+    // Constraints without type variables in their type arguments are solved in place
+    // and so cannot determine the functional dependencies of any propagated constraint.
+    let generic_constraints = constraints
+        .iter()
+        .filter(|constraint| !constraint.typearg_typevars(cache).is_empty())
+        .cloned()
+        .collect::<Vec<_>>();
+    let determined = determined_typevars(typevars_in_fn_signature.to_vec(), &generic_constraints, cache);
+    let determined = determined.into_iter().collect::<Vec<_>>();
+
     for constraint in constraints {
-        if should_propagate(&constraint, typevars_in_fn_signature, cache) {
+        if should_propagate(&constraint, &determined, cache) {
             propogated_traits.push(constraint.into_required_trait());
         } else if constraint.is_int_constraint(cache) {
             int_constraints.push(constraint);
@@ -120,9 +151,10 @@ fn sort_traits<'c>(
 /// should be resolved instead.
 /// For example, the trait constraint `Print i32` should never be propogated because it doesn't
 /// contain any typevariables. A constraint like `Print a` may be propogated if `a` is a
-/// typevariable used in the signature of the current function.
+/// typevariable used in the signature of the current function, or is determined by the
+/// functional dependency of another constraint such as `Iterator it a` for some `it` in the signature.
 fn should_propagate<'a>(
-    constraint: &TraitConstraint, typevars_in_fn_signature: &[TypeVariableId], cache: &ModuleCache<'a>,
+    constraint: &TraitConstraint, determined_typevars: &[TypeVariableId], cache: &ModuleCache<'a>,
 ) -> bool {
     // Don't check the fundeps since only the typeargs proper are used to find impls
     let arg_count = cache[constraint.trait_id()].typeargs.len();
@@ -131,7 +163,7 @@ fn should_propagate<'a>(
         .args()
         .iter()
         .take(arg_count)
-        .any(|arg| typechecker::contains_any_typevars_from_list(arg, typevars_in_fn_signature, cache));
+        .any(|arg| typechecker::contains_any_typevars_from_list(arg, determined_typevars, cache));
 
     if constraint.is_member_access(cache) {
         propagate = propagate && !is_datatype(&constraint.args()[0], cache);
@@ -148,6 +180,10 @@ fn is_datatype(typ: &Type, cache: &ModuleCache) -> bool {
         },
         Type::UserDefined(_) => true,
         Type::TypeApplication(constructor, _) => is_datatype(constructor, cache),
+        Type::Record(_, row) => match &cache.type_bindings[row.0] {
+            super::TypeBinding::Bound(binding) => is_datatype(binding, cache),
+            super::TypeBinding::Unbound(_, _) => true,
+        },
         _ => false,
     }
 }
@@ -157,6 +193,11 @@ fn is_datatype(typ: &Type, cache: &ModuleCache) -> bool {
 /// integer types. So instead of searching for an impl here, we simply check that the arg
 /// type `a` is a primitive integer type. If `a` is an unbound type variable, this will
 /// also bind `a` to `i32` by default.
+///
+/// As a special case, an integer literal used where a float is expected is also accepted
+/// and is later compiled as a float literal. This only applies to the literal itself, not
+/// to `Int a` constraints propagated from the signature of other definitions. Likewise, a
+/// literal used as any other type is reported as such rather than as a missing `Int` impl.
 fn find_int_constraint_impl<'c>(
     constraint: &TraitConstraint, bindings: &UnificationBindings, cache: &mut ModuleCache<'c>,
 ) -> UnificationResult<'c> {
@@ -174,12 +215,20 @@ fn find_int_constraint_impl<'c>(
                 _ => Ok(UnificationBindings::empty()),
             }
         },
-        Type::TypeVariable(_) => {
+        Type::Primitive(PrimitiveType::FloatType) if constraint.is_integer_literal_constraint(cache) => {
+            Ok(UnificationBindings::empty())
+        },
+        Type::TypeVariable(id) => {
             // The `Int a` constraint has special defaulting rules - since we know this typevar is
-            // unbound, bind it to the default integer type (i32) here.
+            // unbound, bind it to the default integer type here. This is i32 unless the literal
+            // this constraint came from is within a definition declaring its own `default`.
             // try_unify is used here to avoid performing the binding in case this impl isn't
             // selected to be used.
-            typechecker::try_unify(&typ, &DEFAULT_INTEGER_TYPE, constraint.locate(cache), cache)
+            let default = integer_default(&constraint.args()[0], *id, cache);
+            typechecker::try_unify(&typ, &default, constraint.locate(cache), cache)
+        },
+        _ if constraint.is_integer_literal_constraint(cache) => {
+            Err(make_error!(constraint.locate(cache), "This integer literal cannot have type {}", typ.display(cache)))
         },
         _ => Err(make_error!(
             constraint.locate(cache),
@@ -189,9 +238,24 @@ fn find_int_constraint_impl<'c>(
     }
 }
 
+/// Returns the default integer type recorded for the given constraint argument, or for the
+/// unbound type variable it is bound to, falling back to DEFAULT_INTEGER_TYPE.
+fn integer_default(arg: &Type, unbound: TypeVariableId, cache: &ModuleCache) -> Type {
+    let arg_default = match arg {
+        Type::TypeVariable(id) => cache.integer_literal_defaults.get(id),
+        _ => None,
+    };
+
+    match arg_default.or_else(|| cache.integer_literal_defaults.get(&unbound)) {
+        Some(kind) => Type::Primitive(PrimitiveType::IntegerType(*kind)),
+        None => DEFAULT_INTEGER_TYPE,
+    }
+}
+
 /// Check if the given `.` family trait constraint is satisfied.
 /// A constraint `a.field: b` is satisfied iff the type `a` has a
-/// field named `field` which unifies with type `b`.
+/// field named `field` which unifies with type `b`. For an anonymous
+/// record type this is checked against the fields of the record itself.
 /// If this is not the case, an appropriate error message is returned.
 fn find_member_access_impl<'c>(
     constraint: &TraitConstraint, bindings: &UnificationBindings, cache: &mut ModuleCache<'c>,
@@ -203,6 +267,19 @@ fn find_member_access_impl<'c>(
 
     match &collection {
         Type::UserDefined(id) => find_field(*id, &[], &field_name, expected_field_type, location, cache),
+        Type::Record(fields, _) => match fields.iter().find(|(name, _)| *name == field_name) {
+            Some((_, field_type)) => typechecker::try_unify(expected_field_type, field_type, location, cache),
+            None => {
+                let field_names = fmap(fields, |(name, _)| name.as_str());
+                Err(make_error!(
+                    location,
+                    "Record type {} has no field named {}{}",
+                    collection.display(cache),
+                    field_name,
+                    missing_field_hint(&field_name, &field_names)
+                ))
+            },
+        },
         Type::TypeApplication(typ, args) => match typ.as_ref() {
             Type::UserDefined(id) => find_field(*id, args, &field_name, expected_field_type, location, cache),
             _ => Err(make_error!(
@@ -247,7 +324,13 @@ fn find_field<'c>(
                 result_bindings.bindings.into_iter().filter(|(id, _)| !bindings.contains_key(id)).collect();
             Ok(result_bindings)
         },
-        None => Err(make_error!(location, "Type {} has no field named {}", type_info.name.blue(), field_name)),
+        None => Err(make_error!(
+            location,
+            "Type {} has no field named {}{}",
+            type_info.name.blue(),
+            field_name,
+            missing_field_hint(field_name, &type_info.field_names())
+        )),
     }
 }
 
@@ -255,7 +338,30 @@ fn find_field<'c>(
 /// or >1 matching impls are found.
 fn solve_normal_constraint<'c>(constraint: &TraitConstraint, cache: &mut ModuleCache<'c>) {
     let bindings = UnificationBindings::empty();
-    let mut matching_impls = find_matching_impls(constraint, &bindings, RECURSION_LIMIT, cache);
+    let key = impl_resolution_key(constraint, cache);
+
+    let mut matching_impls = match key.as_ref().and_then(|key| cache.impl_resolutions.get(key).copied()) {
+        // The impl is already known, but it must still be matched to bind its `given` constraints for this callsite
+        Some(Some(impl_id)) => try_match_impl(constraint, impl_id, &bindings, RECURSION_LIMIT, cache)
+            .and_then(Result::ok)
+            .into_iter()
+            .collect(),
+        Some(None) => vec![],
+        None => find_matching_impls(constraint, &bindings, RECURSION_LIMIT, cache),
+    };
+
+    // Ambiguous constraints are never cached so that each occurrence reports its candidates
+    if let Some(key) = key {
+        match matching_impls.as_slice() {
+            [(impls, _)] => {
+                cache.impl_resolutions.insert(key, impls.first().map(|(impl_id, _)| *impl_id));
+            },
+            [] => {
+                cache.impl_resolutions.insert(key, None);
+            },
+            _ => (),
+        }
+    }
 
     #[allow(clippy::comparison_chain)]
     if matching_impls.len() == 1 {
@@ -271,13 +377,33 @@ fn solve_normal_constraint<'c>(constraint: &TraitConstraint, cache: &mut ModuleC
             matching_impls.len(),
             constraint.display(cache)
         );
+        // Impls whose type arguments are equal up to renaming type variables are exact
+        // duplicates of each other, so point those out separately from merely overlapping impls.
+        let normalized = fmap(&matching_impls, |(impls, _)| Type::normalize_all(&cache[impls[0].0].typeargs, cache));
+
         for (i, (impls, _)) in matching_impls.iter().enumerate() {
             let impl_id = impls[0].0;
-            note!(cache[impl_id].location, "Candidate {}", i + 1);
+            match normalized[..i].iter().position(|typeargs| *typeargs == normalized[i]) {
+                Some(j) => note!(cache[impl_id].location, "Candidate {} (a duplicate of candidate {})", i + 1, j + 1),
+                None => note!(cache[impl_id].location, "Candidate {}", i + 1),
+            }
         }
     } else {
-        error!(constraint.locate(cache), "No impl found for {}", constraint.display(cache))
+        error!(constraint.locate(cache), "No impl found for {}", constraint.display(cache));
+        explain_unsatisfied_given_constraints(constraint, cache);
+    }
+}
+
+/// Returns the key to cache the resolution of this constraint under, if any.
+/// Only constraints whose arguments contain no type variables are cached since
+/// resolving any other constraint may bind its type variables.
+fn impl_resolution_key<'c>(constraint: &TraitConstraint, cache: &ModuleCache<'c>) -> Option<ImplResolutionKey> {
+    if constraint.args().iter().any(|arg| !typechecker::find_all_typevars(arg, false, cache).is_empty()) {
+        return None;
     }
+
+    let args = Type::normalize_all(constraint.args(), cache);
+    Some((constraint.trait_id(), args, constraint.scope))
 }
 
 /// Find and return (possibly multiple) matching impls for the given constraint.
@@ -326,71 +452,115 @@ fn find_matching_normal_impls<'c>(
 ) -> Vec<(Vec<(ImplInfoId, TraitConstraint)>, UnificationBindings)> {
     let scope = cache[constraint.scope].clone();
 
-    scope
-        .iter()
-        .filter_map(|&impl_id| {
-            // First, filter all the impls whose arguments typecheck against our constraint's arguments
-            if cache[impl_id].trait_id != constraint.trait_id() {
-                return None;
-            }
+    scope.iter().filter_map(|&impl_id| try_match_impl(constraint, impl_id, bindings, fuel, cache)?.ok()).collect()
+}
 
-            // Replace all the type variables in the `impl Foo a` so when we unify later we don't
-            // bind to the original `a`, just one instantiation of it.
-            let (impl_typeargs, impl_bindings) =
-                typechecker::replace_all_typevars(&cache[impl_id].typeargs.clone(), cache);
+/// An impl matching a constraint along with each impl required by its `given` clause,
+/// and the bindings needed for them to match.
+type MatchedImpls = (Vec<(ImplInfoId, TraitConstraint)>, UnificationBindings);
 
-            let location = constraint.locate(cache);
-            let type_bindings = typechecker::try_unify_all_with_bindings(
-                &impl_typeargs,
-                constraint.args(),
-                bindings.clone(),
-                location,
-                cache,
-            )
+/// Try to match a single impl against the given constraint. Returns None if the impl's trait or
+/// type arguments do not match the constraint at all. Otherwise returns the result of checking
+/// the impl's `given` clause - see `check_given_constraints`.
+fn try_match_impl<'c>(
+    constraint: &TraitConstraint, impl_id: ImplInfoId, bindings: &UnificationBindings, fuel: u32,
+    cache: &mut ModuleCache<'c>,
+) -> Option<Result<MatchedImpls, String>> {
+    // First, filter all the impls whose arguments typecheck against our constraint's arguments
+    if cache[impl_id].trait_id != constraint.trait_id() {
+        return None;
+    }
+
+    // Replace all the type variables in the `impl Foo a` so when we unify later we don't
+    // bind to the original `a`, just one instantiation of it.
+    let (impl_typeargs, impl_bindings) = typechecker::replace_all_typevars(&cache[impl_id].typeargs.clone(), cache);
+
+    let location = constraint.locate(cache);
+    let type_bindings =
+        typechecker::try_unify_all_with_bindings(&impl_typeargs, constraint.args(), bindings.clone(), location, cache)
             .ok()?;
 
-            // Then, check any `given Trait2 a ...` clauses for our impls to further narrow them down
-            check_given_constraints(constraint, impl_id, type_bindings, impl_bindings, fuel, cache)
-        })
-        .collect()
+    // Then, check any `given Trait2 a ...` clauses for our impls to further narrow them down
+    Some(check_given_constraints(constraint, impl_id, type_bindings, impl_bindings, fuel, cache))
 }
 
 /// Check whether the given constraint has any required `given` constraints for the impl to be
 /// valid. For example, the impl `impl Print a given Cast a string` has the given constraint
-/// `Cast a string` and is thus only valid if that impl can be found as well.
-/// If any of these given constraints cannot be solved then None is returned. Otherwise, the Vec
-/// of the original constraint and all its required given constraints are returned.
+/// `Cast a string` and is thus only valid if that impl can be found as well. Similarly,
+/// `impl Foo a b given a = b` is only valid if `a` and `b` can be unified.
+/// If any of these given constraints cannot be solved then the unsatisfied constraint is returned
+/// as a string for error reporting. Otherwise, the Vec of the original constraint and all its
+/// required given constraints are returned.
 fn check_given_constraints<'c>(
     constraint: &TraitConstraint, impl_id: ImplInfoId, mut unification_bindings: UnificationBindings,
     mut impl_bindings: TypeBindings, fuel: u32, cache: &mut ModuleCache<'c>,
-) -> Option<(Vec<(ImplInfoId, TraitConstraint)>, UnificationBindings)> {
+) -> Result<MatchedImpls, String> {
     let mut required_impls = vec![(impl_id, constraint.clone())];
 
     // TODO: Remove need for cloning here.
     // Needed because cache is borrowed mutably below.
-    for signature in cache[impl_id].given.clone() {
+    for given in cache[impl_id].given.clone() {
         // Must carry forward the impl_bindings we got from find_matching_normal_impls
         // manually since we don't want to insert them into the catch if this impl doesn't
         // get selected to be used for the TraitConstraint.
-        let args = fmap(&signature.args, |typ| {
+        let mut replace_typevars = |typ: &Type, cache: &mut ModuleCache<'c>| {
             typechecker::replace_all_typevars_with_bindings(typ, &mut impl_bindings, cache)
-        });
+        };
+
+        match given {
+            GivenConstraint::Trait(signature) => {
+                let args = fmap(&signature.args, |typ| replace_typevars(typ, cache));
+
+                let constraint =
+                    TraitConstraint::impl_given_constraint(signature.id, signature.trait_id, args, constraint, cache);
+
+                let mut matching_impls = find_matching_impls(&constraint, &unification_bindings, fuel, cache);
+
+                if matching_impls.len() == 1 {
+                    let (mut impls, bindings) = matching_impls.remove(0);
+                    unification_bindings.extend(bindings);
+                    required_impls.append(&mut impls);
+                } else {
+                    let args = fmap(constraint.args(), |arg| display_bound_type(arg, &unification_bindings, cache));
+                    return Err(format!("{} {}", cache[signature.trait_id].name.blue(), args.join(" ")));
+                }
+            },
+            GivenConstraint::Equality(lhs, rhs) => {
+                let lhs = replace_typevars(&lhs, cache);
+                let rhs = replace_typevars(&rhs, cache);
+                let location = constraint.locate(cache);
+
+                let result =
+                    typechecker::try_unify_with_bindings(&lhs, &rhs, &mut unification_bindings, location, cache);
+                if result.is_err() {
+                    let lhs = display_bound_type(&lhs, &unification_bindings, cache);
+                    let rhs = display_bound_type(&rhs, &unification_bindings, cache);
+                    return Err(format!("{} = {}", lhs, rhs));
+                }
+            },
+        }
+    }
 
-        let constraint =
-            TraitConstraint::impl_given_constraint(signature.id, signature.trait_id, args, constraint, cache);
+    Ok((required_impls, unification_bindings))
+}
 
-        let mut matching_impls = find_matching_impls(&constraint, &unification_bindings, fuel, cache);
+/// Display the given type after following any bindings it has in the cache or the given
+/// UnificationBindings, so that e.g. an impl's instantiated `a` is shown as `i32` instead.
+fn display_bound_type<'c>(typ: &Type, bindings: &UnificationBindings, cache: &ModuleCache<'c>) -> String {
+    typechecker::follow_bindings_in_cache_and_map(typ, bindings, cache).display(cache).to_string()
+}
 
-        if matching_impls.len() == 1 {
-            let (mut impls, bindings) = matching_impls.remove(0);
-            unification_bindings.extend(bindings);
-            required_impls.append(&mut impls);
-        } else {
-            return None;
+/// Issue a note for each impl of the constraint's trait whose type arguments match the constraint
+/// but which is not applicable because a member of its `given` clause is not satisfied.
+fn explain_unsatisfied_given_constraints<'c>(constraint: &TraitConstraint, cache: &mut ModuleCache<'c>) {
+    let scope = cache[constraint.scope].clone();
+    let bindings = UnificationBindings::empty();
+
+    for impl_id in scope {
+        if let Some(Err(unsatisfied)) = try_match_impl(constraint, impl_id, &bindings, RECURSION_LIMIT, cache) {
+            note!(cache[impl_id].location, "This impl requires {}, which is not satisfied", unsatisfied);
         }
     }
-
-    Some((required_impls, unification_bindings))
 }
 
 /// Binds a selected impl to its callsite. This attaches the relevant impl definition to the
@@ -403,6 +573,13 @@ fn bind_impl(impl_id: ImplInfoId, constraint: TraitConstraint, cache: &mut Modul
     let callsite = constraint.required.callsite.id();
     let required_impl = constraint.into_required_impl(impl_id);
 
+    // Using an impl declared in another module uses any import of that module
+    let callsite_location = cache[callsite].location;
+    if let Some(&user) = cache.modules.get(callsite_location.filename) {
+        let impl_location = cache[impl_id].location;
+        cache.mark_module_used_at(user, impl_location);
+    }
+
     let callsite_info = &mut cache[callsite];
     callsite_info.required_impls.push(required_impl);
 }
@@ -415,3 +592,35 @@ fn infer_trait_impl(id: ImplInfoId, cache: &mut ModuleCache) {
     let trait_impl = trustme::extend_lifetime(cache[id].trait_impl);
     typechecker::infer(trait_impl, cache);
 }
+
+/// Returns true if the given type is reference counted, that is, if an impl of the
+/// prelude's `Shared` marker trait for it is visible from the given impl scope. The
+/// variable is the callsite the `Shared` constraint is reported at. Like other constraints
+/// without type variables, the answer is cached in `cache.impl_resolutions`.
+pub fn is_reference_counted<'c>(
+    typ: &Type, callsite: VariableId, scope: ImplScopeId, cache: &mut ModuleCache<'c>,
+) -> bool {
+    let shared_trait = match cache.shared_trait {
+        Some(trait_id) => trait_id,
+        None => return false,
+    };
+
+    let signature =
+        ConstraintSignature { trait_id: shared_trait, args: vec![typ.clone()], id: cache.next_trait_constraint_id() };
+    let required = RequiredTrait { signature, callsite: Callsite::Direct(callsite) };
+    let constraint = TraitConstraint { required, scope };
+
+    let key = impl_resolution_key(&constraint, cache);
+    if let Some(resolution) = key.as_ref().and_then(|key| cache.impl_resolutions.get(key)) {
+        return resolution.is_some();
+    }
+
+    let matching_impls = find_matching_impls(&constraint, &UnificationBindings::empty(), RECURSION_LIMIT, cache);
+    let impl_id = matching_impls.first().and_then(|(impls, _)| impls.first().map(|(impl_id, _)| *impl_id));
+
+    // Ambiguous constraints are never cached, matching `solve_normal_constraint`
+    if let (Some(key), true) = (key, matching_impls.len() <= 1) {
+        cache.impl_resolutions.insert(key, impl_id);
+    }
+    !matching_impls.is_empty()
+}
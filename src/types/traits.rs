@@ -30,12 +30,16 @@
 //! methods on them for displaying them or converting between them.
 use colored::Colorize;
 
-use crate::cache::{ImplInfoId, ImplScopeId, ModuleCache, TraitInfoId, VariableId};
+use crate::cache::{DefinitionInfoId, ImplInfoId, ImplScopeId, ModuleCache, TraitInfoId, VariableId};
 use crate::error::location::Location;
-use crate::types::typechecker::find_all_typevars;
-use crate::types::{typeprinter::TypePrinter, Type, TypeVariableId};
-
-use std::collections::HashMap;
+use crate::types::typechecker::{find_all_typevars, follow_bindings_in_cache};
+use crate::types::{
+    typeprinter::{self, TypePrinter},
+    Type, TypeVariableId,
+};
+use crate::util::fmap;
+
+use std::collections::{HashMap, HashSet};
 use std::fmt::Display;
 
 use super::GeneralizedType;
@@ -57,6 +61,26 @@ pub struct ConstraintSignature {
     pub id: TraitConstraintId,
 }
 
+/// A single member of an impl's `given` clause. Each member must be
+/// satisfied for the impl to be selected for a TraitConstraint.
+#[derive(Debug, Clone)]
+pub enum GivenConstraint {
+    /// `given Print a` requires an impl of the trait to be found as well
+    Trait(ConstraintSignature),
+
+    /// `given a = b` requires the two types to unify
+    Equality(Type, Type),
+}
+
+impl GivenConstraint {
+    pub fn as_trait(&self) -> Option<&ConstraintSignature> {
+        match self {
+            GivenConstraint::Trait(signature) => Some(signature),
+            GivenConstraint::Equality(..) => None,
+        }
+    }
+}
+
 /// A trait required for a Definition to be compiled.
 /// The specific impl to use is unknown to the definition since
 /// different impls may be used at different callsites.
@@ -98,6 +122,16 @@ impl Callsite {
             Callsite::GivenIndirect(callsite, ..) => callsite,
         }
     }
+
+    /// The name of this kind of callsite, used when printing where a constraint originates
+    pub fn kind_name(self) -> &'static str {
+        match self {
+            Callsite::Direct(_) => "Direct",
+            Callsite::Indirect(..) => "Indirect",
+            Callsite::GivenDirect(..) => "GivenDirect",
+            Callsite::GivenIndirect(..) => "GivenIndirect",
+        }
+    }
 }
 
 /// An instantiated version of a RequiredTrait that is stored
@@ -109,6 +143,46 @@ pub struct RequiredImpl {
     pub callsite: Callsite,
 }
 
+/// The caller-facing contract of a definition: its type along with every trait
+/// a caller must provide an impl for when using it. Unlike RequiredTraits these hold
+/// no callsite information so a consuming module can check its uses of the definition
+/// without having the definition's body on hand.
+#[derive(Debug, Clone)]
+pub struct Obligations {
+    pub typ: GeneralizedType,
+    pub constraints: Vec<ConstraintSignature>,
+}
+
+impl Obligations {
+    /// Collect the obligations of the given definition. Returns None if the
+    /// definition has not been type checked yet.
+    pub fn of<'c>(definition: DefinitionInfoId, cache: &ModuleCache<'c>) -> Option<Obligations> {
+        let info = &cache[definition];
+        let typ = info.typ.clone()?;
+
+        let mut signatures = fmap(&info.required_traits, |required| required.signature.clone());
+
+        // A trait method also requires the trait it is declared in
+        if let Some((trait_id, args)) = &info.trait_info {
+            let id = TraitConstraintId(0); // Dummy value
+            signatures.push(ConstraintSignature { trait_id: *trait_id, args: args.clone(), id });
+        }
+
+        // Required traits may be duplicated across callsites, e.g. `given Add a, Add a`,
+        // but callers only need to satisfy each once.
+        let mut constraints: Vec<ConstraintSignature> = Vec::with_capacity(signatures.len());
+        for mut signature in signatures {
+            signature.args = fmap(&signature.args, |arg| follow_bindings_in_cache(arg, cache));
+
+            if !constraints.iter().any(|c| c.trait_id == signature.trait_id && c.args == signature.args) {
+                constraints.push(signature);
+            }
+        }
+
+        Some(Obligations { typ, constraints })
+    }
+}
+
 /// The trait/impl constrait passed around during type inference.
 /// - If at the end of a function an impl constraint contains a type
 ///   variable that escapes the current function (ie. is used in a
@@ -193,7 +267,12 @@ impl<'a, 'b> Display for ConstraintSignaturePrinter<'a, 'b> {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
         let trait_info = &self.cache[self.signature.trait_id];
 
-        write!(f, "{}", trait_info.name.blue())?;
+        if typeprinter::should_colorize() {
+            write!(f, "{}", trait_info.name.blue())?;
+        } else {
+            write!(f, "{}", trait_info.name)?;
+        }
+
         for arg in &self.signature.args {
             let typ = GeneralizedType::MonoType(arg.clone());
             let arg_printer = TypePrinter::new(typ, self.typevar_names.clone(), self.debug, self.cache);
@@ -280,6 +359,12 @@ impl TraitConstraint {
         self.required.signature.trait_id == cache.int_trait
     }
 
+    /// True if this is an `Int a` constraint arising directly from an integer literal
+    /// rather than one propagated from the signature of another definition.
+    pub fn is_integer_literal_constraint<'c>(&self, cache: &ModuleCache<'c>) -> bool {
+        self.is_int_constraint(cache) && matches!(self.required.callsite, Callsite::Direct(_))
+    }
+
     pub fn into_required_trait(self) -> RequiredTrait {
         self.required
     }
@@ -301,4 +386,61 @@ impl TraitConstraint {
     pub fn debug<'a, 'c>(&self, cache: &'a ModuleCache<'c>) -> ConstraintSignaturePrinter<'a, 'c> {
         self.clone().into_required_trait().debug(cache)
     }
+
+    /// Returns the type variables within this constraint's type arguments,
+    /// excluding those within the trait's functional dependencies.
+    pub fn typearg_typevars<'c>(&self, cache: &ModuleCache<'c>) -> Vec<TypeVariableId> {
+        let arg_count = cache[self.trait_id()].typeargs.len();
+        self.args().iter().take(arg_count).flat_map(|arg| find_all_typevars(arg, false, cache)).collect()
+    }
+
+    /// Returns the type variables within this constraint's functional dependencies
+    pub fn fundep_typevars<'c>(&self, cache: &ModuleCache<'c>) -> Vec<TypeVariableId> {
+        let arg_count = cache[self.trait_id()].typeargs.len();
+        self.args().iter().skip(arg_count).flat_map(|arg| find_all_typevars(arg, false, cache)).collect()
+    }
+}
+
+/// Returns each type variable in the arguments of the given constraints which is free, i.e. which
+/// is neither one of `typevars_in_signature` nor determined by the functional dependencies of the
+/// constraints. Nothing fixes what these type variables should be, so any constraint containing one is ambiguous.
+///
+/// For example, given the type variables of `a -> unit` with the constraints `Conv a b` (where `b` is
+/// a functional dependency of `Conv`), `Print b`, and `Parse string c`, only `c` is free.
+pub fn free_typevars<'c>(
+    typevars_in_signature: Vec<TypeVariableId>, constraints: &[TraitConstraint], cache: &ModuleCache<'c>,
+) -> Vec<TypeVariableId> {
+    let determined = determined_typevars(typevars_in_signature, constraints, cache);
+
+    let mut free = vec![];
+    for constraint in constraints {
+        for typevar in constraint.args().iter().flat_map(|arg| find_all_typevars(arg, false, cache)) {
+            if !determined.contains(&typevar) && !free.contains(&typevar) {
+                free.push(typevar);
+            }
+        }
+    }
+    free
+}
+
+/// Extends the given set of determined type variables with the functional dependencies of each
+/// constraint whose type arguments contain only determined type variables, until no more are found.
+pub fn determined_typevars<'c>(
+    determined: Vec<TypeVariableId>, constraints: &[TraitConstraint], cache: &ModuleCache<'c>,
+) -> HashSet<TypeVariableId> {
+    let mut determined: HashSet<_> = determined.into_iter().collect();
+    let mut changed = true;
+
+    while changed {
+        changed = false;
+        for constraint in constraints {
+            if constraint.typearg_typevars(cache).iter().all(|typevar| determined.contains(typevar)) {
+                for typevar in constraint.fundep_typevars(cache) {
+                    changed |= determined.insert(typevar);
+                }
+            }
+        }
+    }
+
+    determined
 }
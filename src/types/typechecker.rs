@@ -29,30 +29,53 @@
 use crate::cache::{DefinitionInfoId, DefinitionKind, ModuleCache, TraitInfoId};
 use crate::cache::{ImplScopeId, VariableId};
 use crate::error::location::{Locatable, Location};
-use crate::error::{get_error_count, ErrorMessage};
+use crate::error::{self, get_error_count, ErrorMessage};
 use crate::lexer::token::IntegerKind;
 use crate::parser::ast::{self, ClosureEnvironment};
-use crate::types::traits::{RequiredTrait, TraitConstraint, TraitConstraints};
+use crate::types::traits::{free_typevars, GivenConstraint, RequiredTrait, TraitConstraint, TraitConstraints};
 use crate::types::typed::Typed;
 use crate::types::{
-    pattern, traitchecker, FunctionType, LetBindingLevel, PrimitiveType, Type, Type::*, TypeBinding, TypeBinding::*,
-    TypeInfo, TypeVariableId, INITIAL_LEVEL, PAIR_TYPE, STRING_TYPE,
+    missing_field_hint, pattern, traitchecker, typeprinter, FunctionType, LetBindingLevel, PrimitiveType, Type,
+    Type::*, TypeBinding, TypeBinding::*, TypeInfo, TypeInfoBody, TypeVariableId, INITIAL_LEVEL, PAIR_TYPE,
+    STRING_TYPE,
 };
 use crate::util::*;
 
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::iter::once;
+use std::path::Path;
 use std::rc::Rc;
-use std::sync::atomic::{AtomicUsize, Ordering};
 
 use super::traits::{Callsite, ConstraintSignature, TraitConstraintId};
 use super::GeneralizedType;
 
-/// The current LetBindingLevel we are at.
-/// This increases by 1 whenever we enter the rhs of a `ast::Definition` and decreases
-/// by 1 whenever we exit this rhs. This helps keep track of which scope type variables
-/// arose from and whether they should be generalized or not. See
-/// http://okmij.org/ftp/ML/generalization.html for more information on let binding levels.
-pub static CURRENT_LEVEL: AtomicUsize = AtomicUsize::new(INITIAL_LEVEL);
+thread_local! {
+    /// The current LetBindingLevel we are at.
+    /// This increases by 1 whenever we enter the rhs of a `ast::Definition` and decreases
+    /// by 1 whenever we exit this rhs. This helps keep track of which scope type variables
+    /// arose from and whether they should be generalized or not. See
+    /// http://okmij.org/ftp/ML/generalization.html for more information on let binding levels.
+    ///
+    /// This is thread-local rather than global so that independent modules may be
+    /// inferred on separate threads, each with their own level.
+    static CURRENT_LEVEL: Cell<usize> = const { Cell::new(INITIAL_LEVEL) };
+
+    /// True once main has been inferred and CURRENT_LEVEL is set to INITIAL_LEVEL - 1
+    /// to resolve its traits. Before this, no type variable should be created below
+    /// INITIAL_LEVEL. This is only used to check the CURRENT_LEVEL in debug builds.
+    static FINISHED_MAIN: Cell<bool> = const { Cell::new(false) };
+
+    /// The default integer type of the definition currently being inferred, if it has one.
+    /// Unsuffixed integer literals record this in `cache.integer_literal_defaults`.
+    static DEFAULT_INTEGER_KIND: Cell<Option<IntegerKind>> = const { Cell::new(None) };
+}
+
+/// Sets the CURRENT_LEVEL of this thread, returning the previous level
+fn set_current_level(level: usize) -> usize {
+    CURRENT_LEVEL.with(|current| current.replace(level))
+}
 
 /// A sparse set of type bindings, used by try_unify
 pub type TypeBindings = HashMap<TypeVariableId, Type>;
@@ -147,7 +170,11 @@ pub fn replace_all_typevars_with_bindings<'c>(
             let return_type = Box::new(replace_all_typevars_with_bindings(&function.return_type, new_bindings, cache));
             let environment = Box::new(replace_all_typevars_with_bindings(&function.environment, new_bindings, cache));
             let is_varargs = function.is_varargs;
-            Function(FunctionType { parameters, return_type, environment, is_varargs })
+            let varargs_type = function
+                .varargs_type
+                .as_ref()
+                .map(|typ| Box::new(replace_all_typevars_with_bindings(typ, new_bindings, cache)));
+            Function(FunctionType { parameters, return_type, environment, is_varargs, varargs_type })
         },
         UserDefined(id) => UserDefined(*id),
 
@@ -163,6 +190,28 @@ pub fn replace_all_typevars_with_bindings<'c>(
             let args = fmap(args, |arg| replace_all_typevars_with_bindings(arg, new_bindings, cache));
             TypeApplication(Box::new(typ), args)
         },
+
+        // The quantified variables are renamed as well so the new type doesn't share them with the original
+        Forall(variables, typ) => {
+            let variables = fmap(variables, |variable| {
+                let new_variable = next_type_variable_id(cache);
+                new_bindings.insert(*variable, TypeVariable(new_variable));
+                new_variable
+            });
+            let typ = replace_all_typevars_with_bindings(typ, new_bindings, cache);
+            Forall(variables, Box::new(typ))
+        },
+
+        // Once the row variable is bound, the record is replaced with the type it is bound to
+        Record(fields, row) => match replace_typevar_with_binding(*row, new_bindings, TypeVariable, cache) {
+            TypeVariable(new_row) => {
+                let fields = fmap(fields, |(name, typ)| {
+                    (name.clone(), replace_all_typevars_with_bindings(typ, new_bindings, cache))
+                });
+                Record(fields, new_row)
+            },
+            other => other,
+        },
     }
 }
 
@@ -202,7 +251,9 @@ pub fn bind_typevars<'c>(typ: &Type, type_bindings: &TypeBindings, cache: &Modul
             let return_type = Box::new(bind_typevars(&function.return_type, type_bindings, cache));
             let environment = Box::new(bind_typevars(&function.environment, type_bindings, cache));
             let is_varargs = function.is_varargs;
-            Function(FunctionType { parameters, return_type, environment, is_varargs })
+            let varargs_type =
+                function.varargs_type.as_ref().map(|typ| Box::new(bind_typevars(typ, type_bindings, cache)));
+            Function(FunctionType { parameters, return_type, environment, is_varargs, varargs_type })
         },
         UserDefined(id) => UserDefined(*id),
 
@@ -217,6 +268,16 @@ pub fn bind_typevars<'c>(typ: &Type, type_bindings: &TypeBindings, cache: &Modul
             let args = fmap(args, |arg| bind_typevars(arg, type_bindings, cache));
             TypeApplication(Box::new(typ), args)
         },
+
+        Forall(variables, typ) => Forall(variables.clone(), Box::new(bind_typevars(typ, type_bindings, cache))),
+
+        Record(fields, row) => match bind_typevar(*row, type_bindings, TypeVariable, cache) {
+            TypeVariable(new_row) => {
+                let fields = fmap(fields, |(name, typ)| (name.clone(), bind_typevars(typ, type_bindings, cache)));
+                Record(fields, new_row)
+            },
+            other => other,
+        },
     }
 }
 
@@ -255,6 +316,7 @@ pub fn contains_any_typevars_from_list<'c>(typ: &Type, list: &[TypeVariableId],
             function.parameters.iter().any(|parameter| contains_any_typevars_from_list(parameter, list, cache))
                 || contains_any_typevars_from_list(&function.return_type, list, cache)
                 || contains_any_typevars_from_list(&function.environment, list, cache)
+                || function.varargs_type.iter().any(|typ| contains_any_typevars_from_list(typ, list, cache))
         },
 
         Ref(lifetime) => type_variable_contains_any_typevars_from_list(*lifetime, list, cache),
@@ -263,6 +325,15 @@ pub fn contains_any_typevars_from_list<'c>(typ: &Type, list: &[TypeVariableId],
             contains_any_typevars_from_list(typ, list, cache)
                 || args.iter().any(|arg| contains_any_typevars_from_list(arg, list, cache))
         },
+
+        Forall(_, typ) => contains_any_typevars_from_list(typ, list, cache),
+
+        Record(fields, row) => match &cache.type_bindings[row.0] {
+            Bound(typ) => contains_any_typevars_from_list(typ, list, cache),
+            Unbound(..) => {
+                list.contains(row) || fields.iter().any(|(_, typ)| contains_any_typevars_from_list(typ, list, cache))
+            },
+        },
     }
 }
 
@@ -278,15 +349,50 @@ fn type_variable_contains_any_typevars_from_list<'c>(
 
 /// Helper function for getting the next type variable at the current level
 fn next_type_variable_id(cache: &mut ModuleCache) -> TypeVariableId {
-    let level = LetBindingLevel(CURRENT_LEVEL.load(Ordering::SeqCst));
+    let level = current_level();
     cache.next_type_variable_id(level)
 }
 
 fn next_type_variable(cache: &mut ModuleCache) -> Type {
-    let level = LetBindingLevel(CURRENT_LEVEL.load(Ordering::SeqCst));
+    let level = current_level();
     cache.next_type_variable(level)
 }
 
+/// Like `next_type_variable_id`, for type variables which must be created at a level other than
+/// the current level. The given level is checked in debug builds as in `current_level`.
+fn next_type_variable_id_at(level: LetBindingLevel, cache: &mut ModuleCache) -> TypeVariableId {
+    debug_assert_level_valid(level.0);
+    cache.next_type_variable_id(level)
+}
+
+/// Create a skolem: a rigid type variable standing in for one of the quantified variables of
+/// a `forall` type, see `cache.skolems`. Skolems are created at INITIAL_LEVEL rather than the
+/// current level so that they are never generalized, any definition whose type they escape
+/// into is reported by `check_skolems_escape` instead.
+fn next_skolem_id(cache: &mut ModuleCache) -> TypeVariableId {
+    let skolem = next_type_variable_id_at(LetBindingLevel(INITIAL_LEVEL), cache);
+    cache.skolems.insert(skolem);
+    skolem
+}
+
+/// Returns the CURRENT_LEVEL that new type variables are created at.
+/// In debug builds this checks the level is not below INITIAL_LEVEL while
+/// inferring, which would indicate a mistake when restoring a previous level.
+fn current_level() -> LetBindingLevel {
+    let level = CURRENT_LEVEL.with(Cell::get);
+    debug_assert_level_valid(level);
+    LetBindingLevel(level)
+}
+
+/// Checks that a type variable may be created at the given level in debug builds
+fn debug_assert_level_valid(level: usize) {
+    debug_assert!(
+        level >= INITIAL_LEVEL || FINISHED_MAIN.with(Cell::get),
+        "Type variable created at level {}, below INITIAL_LEVEL",
+        level
+    );
+}
+
 fn to_trait_constraints(
     id: DefinitionInfoId, scope: ImplScopeId, callsite: VariableId, cache: &mut ModuleCache,
 ) -> TraitConstraints {
@@ -311,9 +417,57 @@ fn to_trait_constraints(
         });
     }
 
+    // Requiring a trait also requires each of its super-traits, e.g. `Ord a` requires `Eq a`
+    for signature in fmap(&traits, |trait_| trait_.required.signature.clone()) {
+        for signature in super_traits(&signature, cache) {
+            let callsite = Callsite::Indirect(callsite, signature.id);
+            traits.push(TraitConstraint { required: RequiredTrait { signature, callsite }, scope });
+        }
+    }
+
     traits
 }
 
+/// Returns each super-trait of the given constraint, including those of its super-traits,
+/// with the trait's own type variables replaced by the constraint's arguments.
+/// E.g. with `trait Ord a given Eq a`, the constraint `Ord i32` has the super-trait `Eq i32`.
+pub fn super_traits(signature: &ConstraintSignature, cache: &mut ModuleCache) -> Vec<ConstraintSignature> {
+    expand_super_traits(signature, &mut vec![signature.trait_id], cache)
+}
+
+/// Helper for `super_traits`. `expanding` is the chain of traits whose super-traits are
+/// currently being expanded. A trait which is (indirectly) its own super-trait is reported
+/// once and then removed from the `given` list of the trait that closes the cycle, so that
+/// later expansions terminate instead of looping forever.
+fn expand_super_traits(
+    signature: &ConstraintSignature, expanding: &mut Vec<TraitInfoId>, cache: &mut ModuleCache,
+) -> Vec<ConstraintSignature> {
+    let trait_info = &cache[signature.trait_id];
+    let trait_typevars = trait_info.typeargs.iter().chain(&trait_info.fundeps);
+    let bindings = trait_typevars.copied().zip(signature.args.iter().cloned()).collect();
+
+    let mut super_traits = vec![];
+    for given in trait_info.given.clone() {
+        if expanding.contains(&given.trait_id) {
+            let cycle = expanding.iter().chain(std::iter::once(&given.trait_id));
+            let cycle = cycle.map(|id| cache[*id].name.clone()).collect::<Vec<_>>().join(" -> ");
+            error!(cache[signature.trait_id].location, "Cyclic trait dependency: {}", cycle);
+            cache[signature.trait_id].given.retain(|super_trait| super_trait.trait_id != given.trait_id);
+            continue;
+        }
+
+        let args = fmap(&given.args, |arg| bind_typevars(arg, &bindings, cache));
+        let super_trait = ConstraintSignature { trait_id: given.trait_id, args, id: cache.next_trait_constraint_id() };
+
+        expanding.push(given.trait_id);
+        super_traits.append(&mut expand_super_traits(&super_trait, expanding, cache));
+        expanding.pop();
+
+        super_traits.push(super_trait);
+    }
+    super_traits
+}
+
 /// specializes the polytype s by copying the term and replacing the
 /// bound type variables consistently by new monotype variables.
 /// Returns the type bindings used to instantiate the type.
@@ -363,19 +517,66 @@ impl GeneralizedType {
 ///
 /// This version is also different in that it also replaces the type variables
 /// of monotypes.
+///
+/// Any variables quantified by the definition itself rather than by the trait
+/// (e.g. `b` in `trait Foo a with foo: a - b -> a`) are not shared with the rest
+/// of the impl. Each is instead replaced with a fresh type variable pushed onto
+/// `skolems` so that the caller can check the impl did not bind it to a more specific type.
 fn instantiate_impl_with_bindings<'b>(
-    typ: &GeneralizedType, bindings: &mut TypeBindings, cache: &mut ModuleCache<'b>,
+    typ: &GeneralizedType, bindings: &mut TypeBindings, skolems: &mut Vec<TypeVariableId>, cache: &mut ModuleCache<'b>,
 ) -> GeneralizedType {
     use GeneralizedType::*;
     match typ {
         MonoType(typ) => MonoType(replace_all_typevars_with_bindings(typ, bindings, cache)),
-        PolyType(_, typ) => {
-            // unreachable!("Impl already inferred to have polymorphic typ, {}", typ.debug(cache)),
-            MonoType(replace_all_typevars_with_bindings(typ, bindings, cache))
+        PolyType(variables, typ) => {
+            let mut definition_bindings = bindings.clone();
+            for variable in variables {
+                if !bindings.contains_key(variable) {
+                    let skolem = next_type_variable_id(cache);
+                    definition_bindings.insert(*variable, TypeVariable(skolem));
+                    skolems.push(skolem);
+                }
+            }
+
+            let typ = replace_all_typevars_with_bindings(typ, &mut definition_bindings, cache);
+
+            // Any remaining free variables are still shared with the other definitions in the impl
+            for (variable, binding) in definition_bindings {
+                if !variables.contains(&variable) {
+                    bindings.entry(variable).or_insert(binding);
+                }
+            }
+
+            MonoType(typ)
         },
     }
 }
 
+/// Checks each skolem created by instantiate_impl_with_bindings for the given impl definition
+/// is still an unbound type variable distinct from the others. If not, the impl's definition
+/// is less polymorphic than the trait's declaration requires.
+fn check_impl_skolems<'a>(
+    skolems: &[TypeVariableId], definition: &ast::Definition<'a>, trait_id: TraitInfoId, cache: &ModuleCache<'a>,
+) {
+    let mut seen = HashSet::new();
+    for skolem in skolems {
+        match follow_bindings_in_cache(&TypeVariable(*skolem), cache) {
+            TypeVariable(id) if seen.insert(id) => (),
+            typ => {
+                let trait_name = &cache.trait_infos[trait_id.0].name;
+                error!(
+                    definition.location,
+                    "{} is less polymorphic than its declaration in trait {}: a type variable it should be generic over is bound to {}",
+                    definition.pattern,
+                    trait_name,
+                    typ.display(cache)
+                );
+                return;
+            },
+        }
+    }
+}
+
 fn find_binding<'b>(id: TypeVariableId, map: &UnificationBindings, cache: &ModuleCache<'b>) -> TypeBinding {
     match &cache.type_bindings[id.0] {
         Bound(typ) => Bound(typ.clone()),
@@ -389,39 +590,10 @@ fn find_binding<'b>(id: TypeVariableId, map: &UnificationBindings, cache: &Modul
 struct OccursResult {
     occurs: bool,
     level_bindings: LevelBindings,
-}
-
-impl OccursResult {
-    fn does_not_occur() -> OccursResult {
-        OccursResult { occurs: false, level_bindings: vec![] }
-    }
-
-    fn new(occurs: bool, level_bindings: LevelBindings) -> OccursResult {
-        OccursResult { occurs, level_bindings }
-    }
-
-    fn then(mut self, mut f: impl FnMut() -> OccursResult) -> OccursResult {
-        if !self.occurs {
-            let mut other = f();
-            self.occurs = other.occurs;
-            self.level_bindings.append(&mut other.level_bindings);
-        }
-        self
-    }
 
-    fn then_all(mut self, types: &[Type], mut f: impl FnMut(&Type) -> OccursResult) -> OccursResult {
-        if !self.occurs {
-            for typ in types {
-                let mut other = f(typ);
-                self.occurs = other.occurs;
-                self.level_bindings.append(&mut other.level_bindings);
-                if self.occurs {
-                    return self;
-                }
-            }
-        }
-        self
-    }
+    /// If the type variable occurs, this is each type enclosing it from
+    /// the innermost to the outermost, used to explain the error to users.
+    path: Vec<Type>,
 }
 
 /// Can a monomorphic TypeVariable(id) be found inside this type?
@@ -429,62 +601,190 @@ impl OccursResult {
 /// Doing so increases the lifetime of the typevariable and lets us keep
 /// track of which type variables to generalize later on. It also means
 /// that occurs should only be called during unification however.
+///
+/// The type is searched with an explicit stack rather than recursively so that
+/// deeply nested types cannot overflow the call stack.
 fn occurs<'b>(
     id: TypeVariableId, level: LetBindingLevel, typ: &Type, bindings: &mut UnificationBindings,
     cache: &mut ModuleCache<'b>,
 ) -> OccursResult {
-    match typ {
-        Primitive(_) => OccursResult::does_not_occur(),
-        UserDefined(_) => OccursResult::does_not_occur(),
+    // Each type visited so far along with the index of the type enclosing it,
+    // so that the path to the type variable can be recovered once it is found.
+    let mut visited: Vec<(Cow<'_, Type>, Option<usize>)> = vec![];
+    let mut stack = vec![(Cow::Borrowed(typ), None)];
+    let mut level_bindings = vec![];
+
+    while let Some((typ, parent)) = stack.pop() {
+        let index = visited.len();
+
+        let found = match typ.as_ref() {
+            TypeVariable(var_id) | Ref(var_id) | Record(_, var_id) => {
+                match typevars_match(id, level, *var_id, bindings, cache) {
+                    TypevarMatch::Bound(binding) => {
+                        // A bound type variable is transparent, its binding is enclosed by the same types it is
+                        stack.push((Cow::Owned(binding), parent));
+                        continue;
+                    },
+                    TypevarMatch::Unbound(occurs, mut new_level_bindings) => {
+                        level_bindings.append(&mut new_level_bindings);
+                        occurs
+                    },
+                }
+            },
+            _ => false,
+        };
 
-        TypeVariable(var_id) => typevars_match(id, level, *var_id, bindings, cache),
-        Function(function) => occurs(id, level, &function.return_type, bindings, cache)
-            .then(|| occurs(id, level, &function.environment, bindings, cache))
-            .then_all(&function.parameters, |param| occurs(id, level, param, bindings, cache)),
-        TypeApplication(typ, args) => {
-            occurs(id, level, typ, bindings, cache).then_all(args, |arg| occurs(id, level, arg, bindings, cache))
+        visited.push((typ, parent));
+
+        if found {
+            return OccursResult { occurs: true, level_bindings, path: occurs_path(visited, parent) };
+        }
+
+        // Push the children in reverse so that they are searched in order
+        let children = match &visited[index].0 {
+            Cow::Borrowed(typ) => type_children(typ, Cow::Borrowed),
+            Cow::Owned(typ) => type_children(typ, |child| Cow::Owned(child.clone())),
+        };
+        stack.extend(children.into_iter().rev().map(|child| (child, Some(index))));
+    }
+
+    OccursResult { occurs: false, level_bindings, path: vec![] }
+}
+
+/// Returns each type enclosing the type variable found by `occurs`, from the innermost outward
+fn occurs_path(visited: Vec<(Cow<'_, Type>, Option<usize>)>, mut parent: Option<usize>) -> Vec<Type> {
+    let mut path = vec![];
+    while let Some(index) = parent {
+        path.push(visited[index].0.clone().into_owned());
+        parent = visited[index].1;
+    }
+    path
+}
+
+/// Returns the types directly contained within the given type, in the order they are
+/// unified and searched: the parameters, return type, environment, and varargs type (if any)
+/// of a function, the constructor and arguments of a type application, or the field types of a record.
+fn type_children<'t, T>(typ: &'t Type, mut f: impl FnMut(&'t Type) -> T) -> Vec<T> {
+    match typ {
+        Primitive(_) | UserDefined(_) | TypeVariable(_) | Ref(_) => vec![],
+        Function(function) => {
+            let mut children = fmap(&function.parameters, &mut f);
+            children.push(f(&function.return_type));
+            children.push(f(&function.environment));
+            children.extend(function.varargs_type.as_deref().map(f));
+            children
         },
-        Ref(lifetime) => typevars_match(id, level, *lifetime, bindings, cache),
+        TypeApplication(constructor, args) => std::iter::once(constructor.as_ref()).chain(args).map(f).collect(),
+        Forall(_, typ) => vec![f(typ)],
+        Record(fields, _) => fields.iter().map(|(_, typ)| f(typ)).collect(),
     }
 }
 
+enum TypevarMatch {
+    Bound(Type),
+    Unbound(/*occurs*/ bool, LevelBindings),
+}
+
 /// Helper function for the `occurs` check.
 ///
-/// Recurse within `haystack` to try to find an Unbound typevar and check if it
-/// has the same Id as the needle TypeVariableId.
+/// Check if the `haystack` typevar is the needle, returning its binding instead if it is bound
+/// so that the search can continue within it.
 fn typevars_match<'c>(
     needle: TypeVariableId, level: LetBindingLevel, haystack: TypeVariableId, bindings: &mut UnificationBindings,
     cache: &mut ModuleCache<'c>,
-) -> OccursResult {
+) -> TypevarMatch {
     match find_binding(haystack, bindings, cache) {
-        Bound(binding) => occurs(needle, level, &binding, bindings, cache),
+        Bound(binding) => TypevarMatch::Bound(binding),
         Unbound(original_level, _) => {
             let binding = if level < original_level { vec![(needle, level)] } else { vec![] };
-            OccursResult::new(needle == haystack, binding)
+            TypevarMatch::Unbound(needle == haystack, binding)
         },
     }
 }
 
 /// Returns what a given type is bound to, following all typevar links until it reaches an Unbound one.
+/// A record whose row variable is bound is likewise followed to the type it stands in for.
 pub fn follow_bindings_in_cache_and_map<'b>(
     typ: &Type, bindings: &UnificationBindings, cache: &ModuleCache<'b>,
 ) -> Type {
-    match typ {
-        TypeVariable(id) | Ref(id) => match find_binding(*id, bindings, cache) {
-            Bound(typ) => follow_bindings_in_cache_and_map(&typ, bindings, cache),
-            Unbound(..) => typ.clone(),
-        },
-        _ => typ.clone(),
+    let mut current = typ.clone();
+    let mut followed = vec![];
+
+    while let TypeVariable(id) | Ref(id) | Record(_, id) = current {
+        match find_binding(id, bindings, cache) {
+            Bound(typ) => {
+                mark_followed(&mut followed, id);
+                current = typ;
+            },
+            Unbound(..) => break,
+        }
     }
+    current
 }
 
 pub fn follow_bindings_in_cache<'b>(typ: &Type, cache: &ModuleCache<'b>) -> Type {
-    match typ {
-        TypeVariable(id) | Ref(id) => match &cache.type_bindings[id.0] {
-            Bound(typ) => follow_bindings_in_cache(typ, cache),
-            Unbound(..) => typ.clone(),
-        },
-        _ => typ.clone(),
+    let mut current = typ;
+    let mut followed = vec![];
+
+    while let TypeVariable(id) | Ref(id) | Record(_, id) = current {
+        match &cache.type_bindings[id.0] {
+            Bound(typ) => {
+                mark_followed(&mut followed, *id);
+                current = typ;
+            },
+            Unbound(..) => break,
+        }
+    }
+    current.clone()
+}
+
+/// Records that the binding of the given type variable was followed. The occurs check should
+/// prevent any type variable from being bound to itself, but if one is anyway this reports
+/// it rather than following the cycle forever.
+fn mark_followed(followed: &mut Vec<TypeVariableId>, id: TypeVariableId) {
+    if followed.contains(&id) {
+        panic!("Internal compiler error: type variable {} is bound to itself", id.0);
+    }
+    followed.push(id);
+}
+
+/// Determines how a mismatch found during unification is worded.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum UnifyDirection {
+    /// Neither type is the expected one: "Type mismatch between X and Y"
+    Symmetric,
+
+    /// The first type is the expected one, e.g. a type annotation: "Expected X but found Y"
+    ExpectedFirst,
+
+    /// The second type is the expected one: "Expected Y but found X"
+    ExpectedSecond,
+}
+
+impl UnifyDirection {
+    /// The direction to use when the two types being unified are swapped
+    fn flip(self) -> UnifyDirection {
+        match self {
+            UnifyDirection::Symmetric => UnifyDirection::Symmetric,
+            UnifyDirection::ExpectedFirst => UnifyDirection::ExpectedSecond,
+            UnifyDirection::ExpectedSecond => UnifyDirection::ExpectedFirst,
+        }
+    }
+
+    fn mismatch_error<'c>(
+        self, t1: &Type, t2: &Type, location: Location<'c>, cache: &ModuleCache<'c>,
+    ) -> ErrorMessage<'c> {
+        match self {
+            UnifyDirection::Symmetric => {
+                make_error!(location, "Type mismatch between {} and {}", t1.display(cache), t2.display(cache))
+            },
+            UnifyDirection::ExpectedFirst => {
+                make_error!(location, "Expected {} but found {}", t1.display(cache), t2.display(cache))
+            },
+            UnifyDirection::ExpectedSecond => {
+                make_error!(location, "Expected {} but found {}", t2.display(cache), t1.display(cache))
+            },
+        }
     }
 }
 
@@ -497,11 +797,52 @@ pub fn follow_bindings_in_cache<'b>(typ: &Type, cache: &ModuleCache<'b>) -> Type
 /// and the given bindings set may still be modified with prior type bindings.
 ///
 /// This function performs the bulk of the work for the various unification functions.
-#[allow(clippy::nonminimal_bool)]
 pub fn try_unify_with_bindings<'b>(
     t1: &Type, t2: &Type, bindings: &mut UnificationBindings, location: Location<'b>, cache: &mut ModuleCache<'b>,
 ) -> Result<(), ErrorMessage<'b>> {
-    match (t1, t2) {
+    try_unify_directed_with_bindings(t1, t2, bindings, UnifyDirection::Symmetric, location, cache)
+}
+
+/// A step of unification that is still to be performed. Unification works through a stack
+/// of these rather than recursing on the structure of each type so that unifying deeply
+/// nested types cannot overflow the call stack.
+enum UnificationStep<'t> {
+    Unify(Cow<'t, Type>, Cow<'t, Type>, UnifyDirection),
+
+    /// Marks the end of unifying the given types, at least one of which is a `forall` type
+    /// whose quantified variables were replaced with the given skolems. Popping this does
+    /// nothing, it is only used to report which types a skolem was bound while unifying.
+    /// See `skolem_binding_error`.
+    EndForall(Vec<TypeVariableId>, Cow<'t, Type>, Cow<'t, Type>),
+}
+
+/// Like try_unify_with_bindings, but the given direction determines how a mismatch is reported.
+fn try_unify_directed_with_bindings<'b>(
+    t1: &Type, t2: &Type, bindings: &mut UnificationBindings, direction: UnifyDirection, location: Location<'b>,
+    cache: &mut ModuleCache<'b>,
+) -> Result<(), ErrorMessage<'b>> {
+    let mut stack = vec![UnificationStep::Unify(Cow::Borrowed(t1), Cow::Borrowed(t2), direction)];
+
+    while let Some(step) = stack.pop() {
+        match step {
+            UnificationStep::Unify(t1, t2, direction) => {
+                unify_step(t1, t2, direction, &mut stack, bindings, location, cache)?;
+            },
+            UnificationStep::EndForall(..) => (),
+        }
+    }
+
+    Ok(())
+}
+
+/// Unify the outermost structure of the two given types, pushing
+/// the types within them that must be unified next onto the stack.
+#[allow(clippy::nonminimal_bool)]
+fn unify_step<'t, 'b>(
+    t1: Cow<'t, Type>, t2: Cow<'t, Type>, direction: UnifyDirection, stack: &mut Vec<UnificationStep<'t>>,
+    bindings: &mut UnificationBindings, location: Location<'b>, cache: &mut ModuleCache<'b>,
+) -> Result<(), ErrorMessage<'b>> {
+    match (t1.as_ref(), t2.as_ref()) {
         (Primitive(p1), Primitive(p2)) if p1 == p2 => Ok(()),
 
         (UserDefined(id1), UserDefined(id2)) if id1 == id2 => Ok(()),
@@ -512,9 +853,18 @@ pub fn try_unify_with_bindings<'b>(
         //   it to the minimum scope of type variables in b. This happens within the occurs check.
         //   The unification of the LetBindingLevel here is a form of lifetime inference for the
         //   typevar and is used during generalization to determine which variables to generalize.
-        (TypeVariable(id), _) => try_unify_type_variable_with_bindings(*id, t1, t2, bindings, location, cache),
+        (TypeVariable(id), _) => {
+            let id = *id;
+            try_unify_type_variable_with_bindings(id, t1, t2, direction, stack, bindings, location, cache)
+        },
+
+        (_, TypeVariable(id)) => {
+            let id = *id;
+            try_unify_type_variable_with_bindings(id, t2, t1, direction.flip(), stack, bindings, location, cache)
+        },
 
-        (_, TypeVariable(id)) => try_unify_type_variable_with_bindings(*id, t2, t1, bindings, location, cache),
+        (Record(..), _) => unify_record(t1, t2, direction, stack, bindings, location, cache),
+        (_, Record(..)) => unify_record(t2, t1, direction.flip(), stack, bindings, location, cache),
 
         (Function(function1), Function(function2)) => {
             if function1.parameters.len() != function2.parameters.len() {
@@ -534,16 +884,29 @@ pub fn try_unify_with_bindings<'b>(
                 }
             }
 
-            for (a_arg, b_arg) in function1.parameters.iter().zip(function2.parameters.iter()) {
-                try_unify_with_bindings(a_arg, b_arg, bindings, location, cache)?
-            }
-
-            try_unify_with_bindings(&function1.return_type, &function2.return_type, bindings, location, cache)?;
-            try_unify_with_bindings(&function1.environment, &function2.environment, bindings, location, cache)?;
+            // Unify each parameter both functions have, then each extra argument passed to a
+            // varargs function with its varargs type if it has one, then their return types,
+            // environments, and varargs types.
+            let mut a_parameters = children(&t1);
+            let mut b_parameters = children(&t2);
+            let a_rest = a_parameters.split_off(function1.parameters.len());
+            let b_rest = b_parameters.split_off(function2.parameters.len());
+
+            let common = a_parameters.len().min(b_parameters.len());
+            let a_extra = a_parameters.split_off(common);
+            let b_extra = b_parameters.split_off(common);
+
+            let a_varargs = a_rest.get(2).cloned();
+            let b_varargs = b_rest.get(2).cloned();
+            let a_extra = a_extra.into_iter().filter_map(|a| Some((a, b_varargs.clone()?)));
+            let b_extra = b_extra.into_iter().filter_map(|b| Some((a_varargs.clone()?, b)));
+
+            let pairs = a_parameters.into_iter().zip(b_parameters).chain(a_extra).chain(b_extra);
+            push_unify_steps(pairs.chain(a_rest.into_iter().zip(b_rest)), direction, stack);
             Ok(())
         },
 
-        (TypeApplication(a_constructor, a_args), TypeApplication(b_constructor, b_args)) => {
+        (TypeApplication(_, a_args), TypeApplication(_, b_args)) => {
             if a_args.len() != b_args.len() {
                 return Err(make_error!(
                     location,
@@ -553,47 +916,244 @@ pub fn try_unify_with_bindings<'b>(
                 ));
             }
 
-            try_unify_with_bindings(a_constructor, b_constructor, bindings, location, cache)?;
-
-            for (a_arg, b_arg) in a_args.iter().zip(b_args.iter()) {
-                try_unify_with_bindings(a_arg, b_arg, bindings, location, cache)?;
-            }
-
+            let pairs = children(&t1).into_iter().zip(children(&t2));
+            push_unify_steps(pairs, direction, stack);
             Ok(())
         },
 
         // Refs have a hidden lifetime variable we need to unify here
         (Ref(a_lifetime), Ref(_)) => {
-            try_unify_type_variable_with_bindings(*a_lifetime, t1, t2, bindings, location, cache)
+            let a_lifetime = *a_lifetime;
+            try_unify_type_variable_with_bindings(a_lifetime, t1, t2, direction, stack, bindings, location, cache)
+        },
+
+        (Forall(a_variables, a_type), Forall(b_variables, b_type)) if a_variables.len() == b_variables.len() => {
+            let skolems = fmap(a_variables, |_| next_skolem_id(cache));
+            let a_type = skolemize(a_variables, &skolems, a_type, cache);
+            let b_type = skolemize(b_variables, &skolems, b_type, cache);
+            stack.push(UnificationStep::EndForall(skolems, t1, t2));
+            stack.push(UnificationStep::Unify(Cow::Owned(a_type), Cow::Owned(b_type), direction));
+            Ok(())
+        },
+
+        // A type unifies with `forall a. t` only if it is at least as polymorphic, so rather than
+        // instantiating each quantified variable we replace it with a skolem: a rigid type variable
+        // standing in for an unknown type. The skolems are unified on the right so that the type
+        // variables of the other type are bound to them, rather than the other way around.
+        (Forall(variables, typ), _) | (_, Forall(variables, typ)) => {
+            let forall_first = matches!(t1.as_ref(), Forall(..));
+            let direction = if forall_first { direction.flip() } else { direction };
+            let skolems = fmap(variables, |_| next_skolem_id(cache));
+            let typ = skolemize(variables, &skolems, typ, cache);
+            let other = if forall_first { t2.clone() } else { t1.clone() };
+            stack.push(UnificationStep::EndForall(skolems, t1, t2));
+            stack.push(UnificationStep::Unify(other, Cow::Owned(typ), direction));
+            Ok(())
+        },
+
+        (a, b) => Err(direction.mismatch_error(a, b, location, cache)),
+    }
+}
+
+/// Unify a record type with another type. Records have width subtyping: a record unifies with
+/// any struct having at least its fields, after which its row variable is bound to that struct.
+/// Two records unify by binding both of their row variables to a new record with the fields of both.
+#[allow(clippy::too_many_arguments)]
+fn unify_record<'t, 'c>(
+    record: Cow<'t, Type>, other: Cow<'t, Type>, direction: UnifyDirection, stack: &mut Vec<UnificationStep<'t>>,
+    bindings: &mut UnificationBindings, location: Location<'c>, cache: &mut ModuleCache<'c>,
+) -> Result<(), ErrorMessage<'c>> {
+    let (fields, row) = match record.as_ref() {
+        Record(fields, row) => (fields, *row),
+        _ => unreachable!("unify_record called with non-record type {}", record.display(cache)),
+    };
+
+    let row_level = match find_binding(row, bindings, cache) {
+        Bound(binding) => {
+            stack.push(UnificationStep::Unify(Cow::Owned(binding), other, direction));
+            return Ok(());
         },
+        Unbound(level, _) => level,
+    };
+
+    let mut steps = vec![];
+    match other.as_ref() {
+        Record(other_fields, other_row) => {
+            let other_level = match find_binding(*other_row, bindings, cache) {
+                Bound(binding) => {
+                    stack.push(UnificationStep::Unify(record.clone(), Cow::Owned(binding), direction));
+                    return Ok(());
+                },
+                Unbound(level, _) => level,
+            };
+
+            let mut merged = fields.clone();
+            for (name, other_field) in other_fields {
+                match fields.iter().find(|(field_name, _)| field_name == name) {
+                    Some((_, field)) => steps.push((Cow::Owned(field.clone()), Cow::Owned(other_field.clone()))),
+                    None => merged.push((name.clone(), other_field.clone())),
+                }
+            }
+
+            if row != *other_row {
+                let merged_row = next_type_variable_id_at(std::cmp::min(row_level, other_level), cache);
+                let merged = Record(merged, merged_row);
+                steps.push((Cow::Owned(TypeVariable(row)), Cow::Owned(merged.clone())));
+                steps.push((Cow::Owned(TypeVariable(*other_row)), Cow::Owned(merged)));
+            }
+        },
+        UserDefined(_) | TypeApplication(..) => {
+            let (id, args) = match other.as_ref() {
+                TypeApplication(constructor, args) => {
+                    (follow_bindings_in_cache_and_map(constructor, bindings, cache), args.as_slice())
+                },
+                other => (other.clone(), &[][..]),
+            };
+
+            let type_info = match id {
+                UserDefined(id) => &cache[id],
+                _ => return Err(direction.mismatch_error(&record, &other, location, cache)),
+            };
+
+            let type_bindings = type_application_bindings(type_info, args);
+            for (name, field) in fields {
+                let struct_field = match type_info.find_field(name) {
+                    Some((_, struct_field)) => bind_typevars(&struct_field.field_type, &type_bindings, cache),
+                    None => {
+                        return Err(make_error!(
+                            location,
+                            "Type {} has no field named {}, which is required by {}{}",
+                            other.display(cache),
+                            name,
+                            record.display(cache),
+                            missing_field_hint(name, &type_info.field_names())
+                        ))
+                    },
+                };
+                steps.push((Cow::Owned(field.clone()), Cow::Owned(struct_field)));
+            }
+
+            steps.push((Cow::Owned(TypeVariable(row)), other.clone()));
+        },
+        _ => return Err(direction.mismatch_error(&record, &other, location, cache)),
+    }
+
+    push_unify_steps(steps.into_iter(), direction, stack);
+    Ok(())
+}
+
+/// Returns the types directly within the given type, see `type_children`.
+/// These borrow from the given type where possible rather than being cloned.
+fn children<'t>(typ: &Cow<'t, Type>) -> Vec<Cow<'t, Type>> {
+    match typ {
+        Cow::Borrowed(typ) => type_children(*typ, Cow::Borrowed),
+        Cow::Owned(typ) => type_children(typ, |child| Cow::Owned(child.clone())),
+    }
+}
+
+/// Push a step to unify each of the given pairs of types, such that they are unified in order
+fn push_unify_steps<'t>(
+    pairs: impl Iterator<Item = (Cow<'t, Type>, Cow<'t, Type>)>, direction: UnifyDirection,
+    stack: &mut Vec<UnificationStep<'t>>,
+) {
+    let steps = fmap(pairs, |(a, b)| UnificationStep::Unify(a, b, direction));
+    stack.extend(steps.into_iter().rev());
+}
+
+/// Replace each of the given quantified type variables in `typ` with the skolem at the same index
+fn skolemize(variables: &[TypeVariableId], skolems: &[TypeVariableId], typ: &Type, cache: &ModuleCache) -> Type {
+    let skolems = variables.iter().copied().zip(skolems.iter().copied()).collect();
+    replace_typevars(typ, &skolems, cache)
+}
+
+/// A skolem may not be bound to any type other than an unbound type variable, which is bound to
+/// the skolem instead. If the `forall` type the skolem was created for is still being unified,
+/// the type it was unified with is less polymorphic than it, e.g. when passing an `i32 -> i32`
+/// function where a `forall a. a -> a` function is expected. Otherwise the skolem was bound
+/// afterward, e.g. when defaulting an integer literal the skolem's type was unified with.
+fn skolem_binding_error<'t, 'c>(
+    skolem: TypeVariableId, binding: &Type, stack: &[UnificationStep<'t>], location: Location<'c>,
+    cache: &ModuleCache<'c>,
+) -> ErrorMessage<'c> {
+    let forall = stack.iter().rev().find_map(|step| match step {
+        UnificationStep::EndForall(skolems, t1, t2) if skolems.contains(&skolem) => Some((t1, t2)),
+        _ => None,
+    });
 
-        (a, b) => Err(make_error!(location, "Type mismatch between {} and {}", a.display(cache), b.display(cache))),
+    match forall {
+        Some((t1, t2)) => {
+            let (polymorphic, other) = if matches!(t1.as_ref(), Forall(..)) { (t1, t2) } else { (t2, t1) };
+            make_error!(
+                location,
+                "Type {} is not polymorphic enough to be used as {}",
+                other.display(cache),
+                polymorphic.display(cache)
+            )
+        },
+        None => make_error!(
+            location,
+            "Cannot bind the polymorphic type variable {} to {}",
+            TypeVariable(skolem).display(cache),
+            binding.display(cache)
+        ),
+    }
+}
+
+/// Skolems are never generalized, so one remaining in the type of a definition means it has
+/// escaped the `forall` type it was created for, e.g. by passing `fn (x: b) -> x` as a
+/// `forall a. a -> a` function where `b` is a type variable of the enclosing definition.
+fn check_skolems_escape<'c>(typ: &Type, location: Location<'c>, cache: &ModuleCache<'c>) {
+    if find_all_typevars(typ, false, cache).iter().any(|typevar| cache.skolems.contains(typevar)) {
+        error!(
+            location,
+            "A type variable quantified by a forall type escapes its scope in the type {} of this definition",
+            typ.display(cache)
+        );
     }
 }
 
 /// Unify a single type variable (id arising from the type a) with an expected type b.
 /// Follows the given TypeBindings in bindings and the cache if a is Bound.
-fn try_unify_type_variable_with_bindings<'c>(
-    id: TypeVariableId, a: &Type, b: &Type, bindings: &mut UnificationBindings, location: Location<'c>,
+#[allow(clippy::too_many_arguments)]
+fn try_unify_type_variable_with_bindings<'t, 'c>(
+    id: TypeVariableId, a: Cow<'t, Type>, b: Cow<'t, Type>, direction: UnifyDirection,
+    stack: &mut Vec<UnificationStep<'t>>, bindings: &mut UnificationBindings, location: Location<'c>,
     cache: &mut ModuleCache<'c>,
 ) -> Result<(), ErrorMessage<'c>> {
     match find_binding(id, bindings, cache) {
-        Bound(a) => try_unify_with_bindings(&a, b, bindings, location, cache),
+        Bound(a) => {
+            stack.push(UnificationStep::Unify(Cow::Owned(a), b, direction));
+            Ok(())
+        },
         Unbound(a_level, _a_kind) => {
             // Create binding for boundTy that is currently empty.
             // Ensure not to create recursive bindings to the same variable
-            let b = follow_bindings_in_cache_and_map(b, bindings, cache);
-            if *a != b {
-                let result = occurs(id, a_level, &b, bindings, cache);
+            let b = follow_bindings_in_cache_and_map(&b, bindings, cache);
+            if *a != b && cache.skolems.contains(&id) {
+                match &b {
+                    TypeVariable(other) if !cache.skolems.contains(other) => {
+                        stack.push(UnificationStep::Unify(Cow::Owned(b), a, direction.flip()));
+                        Ok(())
+                    },
+                    _ => Err(skolem_binding_error(id, &b, stack, location, cache)),
+                }
+            } else if *a != b {
+                let mut result = occurs(id, a_level, &b, bindings, cache);
                 if result.occurs {
+                    // Follow the pending bindings too so each type variable is shown as what it is bound to
+                    let show = |typ: &Type| bind_typevars(typ, &bindings.bindings, cache).debug(cache).to_string();
+                    let path = fmap(&result.path, show);
                     Err(make_error!(
                         location,
-                        "Cannot construct recursive type: {} = {}",
-                        a.debug(cache),
-                        b.debug(cache)
+                        "Cannot construct recursive type: {} = {}, since {} occurs in {}",
+                        show(&a),
+                        show(&b),
+                        show(&a),
+                        join_with(&path, ", which occurs in ")
                     ))
                 } else {
                     bindings.bindings.insert(id, b);
+                    bindings.level_bindings.append(&mut result.level_bindings);
                     Ok(())
                 }
             } else {
@@ -639,6 +1199,30 @@ pub fn try_unify_all_with_bindings<'c>(
     Ok(bindings)
 }
 
+/// Unify each pair of types from the two given slices, continuing past any failures
+/// so that every mismatch can be reported at once. Each pair is unified at the location
+/// of the same index in `locations`.
+///
+/// The bindings of each pair that unifies successfully are added to `bindings` so later
+/// pairs are checked against them. A pair that fails to unify has its partial bindings
+/// discarded so that it cannot cause further, cascading errors in the pairs after it.
+pub fn try_unify_all_collecting<'c>(
+    vec1: &[Type], vec2: &[Type], bindings: &mut UnificationBindings, locations: &[Location<'c>],
+    cache: &mut ModuleCache<'c>,
+) -> Vec<ErrorMessage<'c>> {
+    let mut errors = vec![];
+
+    for ((t1, t2), location) in vec1.iter().zip(vec2.iter()).zip(locations.iter()) {
+        let mut pair_bindings = bindings.clone();
+        match try_unify_with_bindings(t1, t2, &mut pair_bindings, *location, cache) {
+            Ok(()) => *bindings = pair_bindings,
+            Err(message) => errors.push(message),
+        }
+    }
+
+    errors
+}
+
 /// Concatenate all the types into a comma-separated string for error messages.
 fn concat_type_strings<'c>(types: &[Type], cache: &ModuleCache<'c>) -> String {
     let types = fmap(types, |typ| typ.display(cache).to_string());
@@ -651,13 +1235,37 @@ pub fn unify<'c>(t1: &Type, t2: &Type, location: Location<'c>, cache: &mut Modul
     perform_bindings_or_print_error(try_unify(t1, t2, location, cache), cache);
 }
 
+/// Like `unify`, but any error is returned rather than issued so that the caller may decide whether
+/// to report or discard it, e.g. when speculatively trying several types. As with `unify`, the
+/// type bindings are performed if unification succeeds, and nothing is bound otherwise.
+pub fn unify_quiet<'c>(
+    t1: &Type, t2: &Type, location: Location<'c>, cache: &mut ModuleCache<'c>,
+) -> Result<(), ErrorMessage<'c>> {
+    try_unify(t1, t2, location, cache).map(|bindings| bindings.perform(cache))
+}
+
+/// Like `try_unify`, where `expected` is the type the other was declared to have.
+/// A mismatch is reported as "Expected X but found Y" rather than symmetrically.
+pub fn try_unify_expected<'c>(
+    expected: &Type, actual: &Type, location: Location<'c>, cache: &mut ModuleCache<'c>,
+) -> UnificationResult<'c> {
+    let mut bindings = UnificationBindings::empty();
+    let direction = UnifyDirection::ExpectedFirst;
+    try_unify_directed_with_bindings(expected, actual, &mut bindings, direction, location, cache).map(|()| bindings)
+}
+
+/// Like `unify`, where `expected` is the type the other was declared to have, e.g. from a type annotation.
+pub fn unify_expected<'c>(expected: &Type, actual: &Type, location: Location<'c>, cache: &mut ModuleCache<'c>) {
+    perform_bindings_or_print_error(try_unify_expected(expected, actual, location, cache), cache);
+}
+
 /// Helper for committing to the results of try_unify.
 /// Places all the typevar bindings in the cache to be remembered,
 /// or otherwise prints out the given error message.
 pub fn perform_bindings_or_print_error<'c>(unification_result: UnificationResult<'c>, cache: &mut ModuleCache<'c>) {
     match unification_result {
         Ok(bindings) => bindings.perform(cache),
-        Err(message) => eprintln!("{}", message),
+        Err(message) => error::issue(message),
     }
 }
 
@@ -670,11 +1278,11 @@ fn perform_type_bindings(bindings: TypeBindings, cache: &mut ModuleCache) {
 }
 
 fn level_is_polymorphic(level: LetBindingLevel) -> bool {
-    level.0 > CURRENT_LEVEL.load(Ordering::SeqCst)
+    level.0 > CURRENT_LEVEL.with(Cell::get)
 }
 
-/// Collects all the type variables contained within typ into a Vec.
-/// If polymorphic_only is true, any polymorphic type variables will be filtered out.
+/// Collects all the type variables contained within typ into a Vec, in the order they
+/// appear when the type is read left to right. If polymorphic_only is true, any polymorphic type variables will be filtered out.
 ///
 /// Since this function uses CURRENT_LEVEL when polymorphic_only = true, the function
 /// should only be used with polymorphic_only = false outside of the typechecking pass.
@@ -689,8 +1297,11 @@ pub fn find_all_typevars<'a>(typ: &Type, polymorphic_only: bool, cache: &ModuleC
             for parameter in &function.parameters {
                 type_variables.append(&mut find_all_typevars(parameter, polymorphic_only, cache));
             }
-            type_variables.append(&mut find_all_typevars(&function.environment, polymorphic_only, cache));
+            if let Some(varargs_type) = &function.varargs_type {
+                type_variables.append(&mut find_all_typevars(varargs_type, polymorphic_only, cache));
+            }
             type_variables.append(&mut find_all_typevars(&function.return_type, polymorphic_only, cache));
+            type_variables.append(&mut find_all_typevars(&function.environment, polymorphic_only, cache));
             type_variables
         },
         TypeApplication(constructor, args) => {
@@ -701,6 +1312,29 @@ pub fn find_all_typevars<'a>(typ: &Type, polymorphic_only: bool, cache: &ModuleC
             type_variables
         },
         Ref(lifetime) => find_typevars_in_typevar_binding(*lifetime, polymorphic_only, cache),
+        Forall(variables, typ) => {
+            // The quantified variables are never generalized since a Forall is already polymorphic in them
+            let mut type_variables = find_all_typevars(typ, polymorphic_only, cache);
+            if polymorphic_only {
+                type_variables.retain(|variable| !variables.contains(variable));
+                type_variables
+            } else {
+                let mut all_variables = variables.clone();
+                all_variables.append(&mut type_variables);
+                all_variables
+            }
+        },
+        Record(fields, row) => match &cache.type_bindings[row.0] {
+            Bound(typ) => find_all_typevars(typ, polymorphic_only, cache),
+            Unbound(..) => {
+                let mut type_variables = vec![];
+                for (_, field_type) in fields {
+                    type_variables.append(&mut find_all_typevars(field_type, polymorphic_only, cache));
+                }
+                type_variables.append(&mut find_typevars_in_typevar_binding(*row, polymorphic_only, cache));
+                type_variables
+            },
+        },
     }
 }
 
@@ -721,6 +1355,67 @@ fn find_typevars_in_typevar_binding(
     }
 }
 
+/// Like find_all_typevars but each type variable is only returned once, in the order it is first found.
+/// This is faster than sorting and deduplicating the result of find_all_typevars for large types
+/// which often contain the same type variables many times.
+pub fn find_unique_typevars<'a>(typ: &Type, polymorphic_only: bool, cache: &ModuleCache<'a>) -> Vec<TypeVariableId> {
+    let mut typevars = vec![];
+    collect_unique_typevars(typ, polymorphic_only, &mut HashSet::new(), &mut typevars, cache);
+    typevars
+}
+
+fn collect_unique_typevars<'a>(
+    typ: &Type, polymorphic_only: bool, seen: &mut HashSet<TypeVariableId>, typevars: &mut Vec<TypeVariableId>,
+    cache: &ModuleCache<'a>,
+) {
+    match typ {
+        Primitive(_) | UserDefined(_) => (),
+        TypeVariable(id) | Ref(id) => match &cache.type_bindings[id.0] {
+            Bound(t) => collect_unique_typevars(t, polymorphic_only, seen, typevars, cache),
+            Unbound(level, _) => {
+                if (level_is_polymorphic(*level) || !polymorphic_only) && seen.insert(*id) {
+                    typevars.push(*id);
+                }
+            },
+        },
+        Function(function) => {
+            for parameter in &function.parameters {
+                collect_unique_typevars(parameter, polymorphic_only, seen, typevars, cache);
+            }
+            if let Some(varargs_type) = &function.varargs_type {
+                collect_unique_typevars(varargs_type, polymorphic_only, seen, typevars, cache);
+            }
+            collect_unique_typevars(&function.return_type, polymorphic_only, seen, typevars, cache);
+            collect_unique_typevars(&function.environment, polymorphic_only, seen, typevars, cache);
+        },
+        TypeApplication(constructor, args) => {
+            collect_unique_typevars(constructor, polymorphic_only, seen, typevars, cache);
+            for arg in args {
+                collect_unique_typevars(arg, polymorphic_only, seen, typevars, cache);
+            }
+        },
+        Record(fields, row) => match &cache.type_bindings[row.0] {
+            Bound(typ) => collect_unique_typevars(typ, polymorphic_only, seen, typevars, cache),
+            Unbound(level, _) => {
+                for (_, field_type) in fields {
+                    collect_unique_typevars(field_type, polymorphic_only, seen, typevars, cache);
+                }
+                if (level_is_polymorphic(*level) || !polymorphic_only) && seen.insert(*row) {
+                    typevars.push(*row);
+                }
+            },
+        },
+        Forall(..) => {
+            // Foralls are rare enough that we defer to find_all_typevars for their handling of quantified variables
+            for id in find_all_typevars(typ, polymorphic_only, cache) {
+                if seen.insert(id) {
+                    typevars.push(id);
+                }
+            }
+        },
+    }
+}
+
 fn find_all_typevars_in_traits<'a>(traits: &TraitConstraints, cache: &ModuleCache<'a>) -> Vec<TypeVariableId> {
     let mut typevars = vec![];
     for constraint in traits.iter() {
@@ -731,16 +1426,25 @@ fn find_all_typevars_in_traits<'a>(traits: &TraitConstraints, cache: &ModuleCach
     typevars
 }
 
-/// Find all typevars declared inside the current LetBindingLevel and wrap the type in a PolyType
-/// e.g.  generalize (a -> b -> b) = forall a b. a -> b -> b
+/// Find all typevars declared inside the current LetBindingLevel and wrap the type in a PolyType.
+/// The quantified variables are ordered by their first appearance in the type rather than by
+/// when they were created, e.g.  generalize (b -> a -> b) = forall b a. b -> a -> b
 fn generalize<'a>(typ: &Type, cache: &ModuleCache<'a>) -> GeneralizedType {
-    let mut typevars = find_all_typevars(typ, true, cache);
+    // A type annotated with an explicit `forall` is quantified over its own variables
+    // along with any others in it that may be generalized.
+    if let Forall(mut variables, typ) = follow_bindings_in_cache(typ, cache) {
+        for typevar in find_unique_typevars(&typ, true, cache) {
+            if !variables.contains(&typevar) {
+                variables.push(typevar);
+            }
+        }
+        return GeneralizedType::PolyType(variables, *typ);
+    }
+
+    let typevars = find_unique_typevars(typ, true, cache);
     if typevars.is_empty() {
         GeneralizedType::MonoType(typ.clone())
     } else {
-        // TODO: This can be sped up, e.g. we wouldn't need to dedup at all if we didn't use a Vec
-        typevars.sort();
-        typevars.dedup();
         GeneralizedType::PolyType(typevars, typ.clone())
     }
 }
@@ -748,8 +1452,7 @@ fn generalize<'a>(typ: &Type, cache: &ModuleCache<'a>) -> GeneralizedType {
 fn infer_nested_definition(
     definition_id: DefinitionInfoId, impl_scope: ImplScopeId, callsite: VariableId, cache: &mut ModuleCache,
 ) -> (GeneralizedType, TraitConstraints) {
-    let level = LetBindingLevel(CURRENT_LEVEL.load(Ordering::SeqCst));
-    let typevar = cache.next_type_variable(level);
+    let typevar = next_type_variable(cache);
     let info = &mut cache.definition_infos[definition_id.0];
     let definition = info.definition.as_mut().unwrap();
 
@@ -765,7 +1468,7 @@ fn infer_nested_definition(
             let definition = trustme::extend_lifetime(*definition);
             infer(definition, cache);
         },
-        DefinitionKind::Extern(declaration) => {
+        DefinitionKind::Extern(declaration, _) => {
             let definition = trustme::extend_lifetime(*declaration);
             infer(definition, cache);
         },
@@ -829,9 +1532,33 @@ fn make_tuple_type(mut types: Vec<Type>) -> Type {
     ret
 }
 
-/// Binds a given type to an irrefutable pattern, recursing on the pattern and verifying
-/// that it is indeed irrefutable. If should_generalize is true, this generalizes the type given
-/// to any variable encountered. Appends the given required_traits list in the DefinitionInfo's
+/// Check that the inferred type of an expression is at least as general as its annotated
+/// type `forall variables. annotated`. The quantified variables are treated as skolems while
+/// unifying: binding any of them would mean the expression only works at some of the types
+/// the annotation claims it works at, e.g. annotating `fn x -> x + 1` with `forall a. a -> a`.
+fn check_polymorphic_annotation<'c>(
+    variables: &[TypeVariableId], annotated: &Type, inferred: &Type, location: Location<'c>,
+    cache: &mut ModuleCache<'c>,
+) {
+    match try_unify(inferred, annotated, location, cache) {
+        Ok(bindings) if variables.iter().any(|variable| bindings.bindings.contains_key(variable)) => {
+            let inferred = bind_typevars(inferred, &bindings.bindings, cache);
+            let polymorphic = Forall(variables.to_vec(), Box::new(annotated.clone()));
+            error!(
+                location,
+                "Type {} is not polymorphic enough to be used as {}",
+                inferred.display(cache),
+                polymorphic.display(cache)
+            );
+        },
+        Ok(bindings) => bindings.perform(cache),
+        Err(message) => error::issue(message),
+    }
+}
+
+/// Binds a given type to an irrefutable pattern, recursing on the pattern and verifying
+/// that it is indeed irrefutable. If should_generalize is true, this generalizes the type given
+/// to any variable encountered. Appends the given required_traits list in the DefinitionInfo's
 /// required_traits field.
 fn bind_irrefutable_pattern<'c>(
     ast: &mut ast::Ast<'c>, typ: &Type, required_traits: &[RequiredTrait], should_generalize: bool,
@@ -848,6 +1575,7 @@ fn bind_irrefutable_pattern<'c>(
             },
             _ => error!(ast.locate(), "Pattern is not irrefutable"),
         },
+        Range(_) => error!(ast.locate(), "Pattern is not irrefutable"),
         Variable(variable) => {
             let definition_id = variable.definition.unwrap();
             let info = &cache.definition_infos[definition_id.0];
@@ -859,13 +1587,24 @@ fn bind_irrefutable_pattern<'c>(
                     GeneralizedType::MonoType(existing_type) => {
                         unify(&existing_type.clone(), typ, variable.location, cache);
                     },
+                    // A definition annotated with a `forall` type is given it on the first
+                    // of the two passes over its pattern in `Definition::infer_impl`
+                    GeneralizedType::PolyType(_, _) if matches!(typ, Forall(..)) => (),
                     GeneralizedType::PolyType(_, _) => {
                         unreachable!("Cannot unify a polytype: {}", existing_type.debug(cache))
                     },
                 }
             }
 
-            let typ = if should_generalize { generalize(typ, cache) } else { GeneralizedType::MonoType(typ.clone()) };
+            let typ = if should_generalize {
+                generalize(typ, cache)
+            } else {
+                // A parameter with a `forall` type may be used at a different type each time it is referenced
+                match follow_bindings_in_cache(typ, cache) {
+                    Forall(variables, typ) => GeneralizedType::PolyType(variables, *typ),
+                    _ => GeneralizedType::MonoType(typ.clone()),
+                }
+            };
 
             let info = &mut cache.definition_infos[definition_id.0];
             info.required_traits.extend_from_slice(required_traits);
@@ -874,10 +1613,30 @@ fn bind_irrefutable_pattern<'c>(
             info.typ = Some(typ);
         },
         TypeAnnotation(annotation) => {
-            unify(typ, annotation.typ.as_ref().unwrap(), annotation.location, cache);
+            unify_expected(annotation.typ.as_ref().unwrap(), typ, annotation.location, cache);
             bind_irrefutable_pattern(annotation.lhs.as_mut(), typ, required_traits, should_generalize, cache);
         },
         FunctionCall(call) if call.is_pair_constructor() => {
+            // Report a tuple pattern with too many elements at the pattern itself rather than
+            // as a mismatch on whichever element happens to be matched against a non-pair type.
+            let pattern_length = tuple_pattern_length(call);
+            let fresh_type;
+            let typ = match known_tuple_length(typ, cache) {
+                Some(type_length) if type_length > 1 && type_length < pattern_length => {
+                    let typ = follow_bindings_in_cache(typ, cache);
+                    error!(
+                        call.location,
+                        "This pattern has {} elements but is matched against the {}-tuple {}",
+                        pattern_length,
+                        type_length,
+                        typ.display(cache)
+                    );
+                    fresh_type = next_type_variable(cache);
+                    &fresh_type
+                },
+                _ => typ,
+            };
+
             let args = fmap(&call.args, |_| next_type_variable(cache));
             let pair_type = Box::new(Type::UserDefined(PAIR_TYPE));
 
@@ -889,6 +1648,7 @@ fn bind_irrefutable_pattern<'c>(
                 return_type: Box::new(pair_type.clone()),
                 environment: Box::new(Type::Primitive(PrimitiveType::UnitType)),
                 is_varargs: false,
+                varargs_type: None,
             });
 
             call.function.set_type(function_type);
@@ -983,8 +1743,72 @@ fn infer_trait_definition_traits(name: &str, trait_id: TraitInfoId, cache: &mut
 ///        the location of the ast in this function, which would just be the entire Definition.
 ///        Additionally, it would give the entire function type instead of just the return
 ///        type or parameter type that was incorrect.
+/// Returns the number of elements in the given tuple pattern. Since tuples are nested
+/// pairs, `(a, b, c)` has 3 elements while `((a, b), c)` only has 2.
+fn tuple_pattern_length(pair: &ast::FunctionCall) -> usize {
+    match pair.args.last() {
+        Some(ast::Ast::FunctionCall(rest)) if rest.is_pair_constructor() => 1 + tuple_pattern_length(rest),
+        _ => pair.args.len(),
+    }
+}
+
+/// Returns the number of elements in the given tuple type if it is known. A tuple
+/// ending in an unbound type variable may be extended by binding that variable to
+/// another pair so its length is not yet known.
+fn known_tuple_length(typ: &Type, cache: &ModuleCache) -> Option<usize> {
+    match follow_bindings_in_cache(typ, cache) {
+        TypeApplication(constructor, args) if *constructor == Type::UserDefined(PAIR_TYPE) && args.len() == 2 => {
+            known_tuple_length(&args[1], cache).map(|length| length + 1)
+        },
+        TypeVariable(_) => None,
+        _ => Some(1),
+    }
+}
+
+/// Returns the type of the element at the given index of a tuple, e.g. `foo.1`. Each element but the
+/// last is the first half of a pair whose second half holds the remaining elements. If the remaining
+/// elements are not yet known, the tuple is assumed to have just enough elements for the index.
+fn infer_tuple_index<'a>(tuple_type: &Type, index: usize, location: Location<'a>, cache: &mut ModuleCache<'a>) -> Type {
+    let mut rest = follow_bindings_in_cache(tuple_type, cache);
+
+    for i in 0..=index {
+        match rest {
+            TypeApplication(constructor, mut args) if constructor.is_pair_type() && args.len() == 2 => {
+                if i == index {
+                    return args.swap_remove(0);
+                }
+                rest = follow_bindings_in_cache(&args[1], cache);
+            },
+            TypeVariable(_) => {
+                // Indexing into a value requires it to be a tuple of at least 2 elements
+                let remaining = if i == 0 { (index + 1).max(2) } else { index - i + 1 };
+                if remaining == 1 {
+                    return rest;
+                }
+
+                let elements = fmap(0..remaining, |_| next_type_variable(cache));
+                let element = elements[index - i].clone();
+                unify(&make_tuple_type(elements), &rest, location, cache);
+                return element;
+            },
+            _ if i == 0 => {
+                error!(location, "Cannot index into {} since it is not a tuple", tuple_type.display(cache));
+                return next_type_variable(cache);
+            },
+            _ if i == index => return rest,
+            _ => {
+                error!(location, "Index {} is out of range for a tuple of {} elements", index, i + 1);
+                return next_type_variable(cache);
+            },
+        }
+    }
+
+    unreachable!()
+}
+
 fn bind_irrefutable_pattern_in_impl<'a>(
-    ast: &ast::Ast<'a>, trait_id: TraitInfoId, bindings: &mut TypeBindings, cache: &mut ModuleCache<'a>,
+    ast: &ast::Ast<'a>, trait_id: TraitInfoId, bindings: &mut TypeBindings, skolems: &mut Vec<TypeVariableId>,
+    cache: &mut ModuleCache<'a>,
 ) {
     use ast::Ast::*;
     match ast {
@@ -992,18 +1816,18 @@ fn bind_irrefutable_pattern_in_impl<'a>(
             let name = variable.to_string();
             let trait_type = lookup_definition_type_in_trait(&name, trait_id, cache);
 
-            let trait_type = instantiate_impl_with_bindings(&trait_type, bindings, cache);
+            let trait_type = instantiate_impl_with_bindings(&trait_type, bindings, skolems, cache);
 
             let definition_id = variable.definition.unwrap();
             let info = &mut cache[definition_id];
             info.typ = Some(trait_type);
         },
         TypeAnnotation(annotation) => {
-            bind_irrefutable_pattern_in_impl(annotation.lhs.as_ref(), trait_id, bindings, cache);
+            bind_irrefutable_pattern_in_impl(annotation.lhs.as_ref(), trait_id, bindings, skolems, cache);
         },
         FunctionCall(call) => {
             for arg in &call.args {
-                bind_irrefutable_pattern_in_impl(arg, trait_id, bindings, cache);
+                bind_irrefutable_pattern_in_impl(arg, trait_id, bindings, skolems, cache);
             }
         },
         _ => {
@@ -1014,8 +1838,8 @@ fn bind_irrefutable_pattern_in_impl<'a>(
 
 /// Checks that the traits used in `pattern` are a subset of traits used in the `given` list of
 /// an impl or in the `given` list of the corresponding function in the trait declaration.
-fn check_impl_propagated_traits(
-    pattern: &ast::Ast, trait_id: TraitInfoId, given: &[ConstraintSignature], cache: &mut ModuleCache,
+fn check_impl_propagated_traits<'a>(
+    pattern: &ast::Ast<'a>, trait_id: TraitInfoId, given: &[ConstraintSignature], cache: &mut ModuleCache<'a>,
 ) {
     use ast::Ast::*;
     match pattern {
@@ -1036,7 +1860,7 @@ fn check_impl_propagated_traits(
             let mut new_ids = Vec::with_capacity(used_traits.len());
 
             for used in used_traits {
-                if let Some(id) = find_matching_trait(&used, &useable_traits, given, cache) {
+                if let Some(id) = find_matching_trait(&used, &useable_traits, given, variable.location, cache) {
                     new_ids.push(id);
                 } else {
                     // TODO: Should issue this error earlier to give a better callsite for the error
@@ -1065,40 +1889,48 @@ fn check_impl_propagated_traits(
 // TODO: `useable_traits` here is always going to be empty. We'll likely need a
 // `Vec<ConstraintSignature>` field on each definition to account for trait definitions
 // with no body.
-fn find_matching_trait(
-    used: &RequiredTrait, useable_traits: &[RequiredTrait], given: &[ConstraintSignature], cache: &mut ModuleCache,
+fn find_matching_trait<'a>(
+    used: &RequiredTrait, useable_traits: &[RequiredTrait], given: &[ConstraintSignature], location: Location<'a>,
+    cache: &mut ModuleCache<'a>,
 ) -> Option<TraitConstraintId> {
-    for useable in useable_traits {
-        if useable.signature.trait_id == used.signature.trait_id {
-            if let Ok(bindings) = try_unify_all_with_bindings(
-                &used.signature.args,
-                &useable.signature.args,
-                UnificationBindings::empty(),
-                Location::builtin(),
-                cache,
-            ) {
+    let candidates = useable_traits.iter().map(|useable| &useable.signature).chain(given);
+    let mut matches: Vec<(&ConstraintSignature, UnificationBindings)> = vec![];
+
+    for candidate in candidates.filter(|candidate| candidate.trait_id == used.signature.trait_id) {
+        let bindings = UnificationBindings::empty();
+        if let Ok(bindings) =
+            try_unify_all_with_bindings(&used.signature.args, &candidate.args, bindings, Location::builtin(), cache)
+        {
+            // A candidate matching without binding any type variables is exactly the constraint used
+            if bindings.bindings.is_empty() {
                 bindings.perform(cache);
-                return Some(useable.signature.id);
+                return Some(candidate.id);
             }
-        }
-    }
 
-    for useable in given {
-        if useable.trait_id == used.signature.trait_id {
-            if let Ok(bindings) = try_unify_all_with_bindings(
-                &used.signature.args,
-                &useable.args,
-                UnificationBindings::empty(),
-                Location::builtin(),
-                cache,
-            ) {
-                bindings.perform(cache);
-                return Some(useable.id);
+            // Identical constraints like `given Add a, Add a` are harmless and are not reported
+            if !matches.iter().any(|(other, _)| other.args == candidate.args) {
+                matches.push((candidate, bindings));
             }
         }
     }
 
-    None
+    if matches.len() > 1 {
+        let display = |signature: &ConstraintSignature| {
+            let candidate = RequiredTrait { signature: signature.clone(), callsite: used.callsite };
+            candidate.display(cache).to_string()
+        };
+        error!(
+            location,
+            "Ambiguous impls for {}: both {} and {} match",
+            used.display(cache),
+            display(matches[0].0),
+            display(matches[1].0)
+        );
+    }
+
+    let (matched, bindings) = matches.into_iter().next()?;
+    bindings.perform(cache);
+    Some(matched.id)
 }
 
 pub trait Inferable<'a> {
@@ -1108,24 +1940,165 @@ pub trait Inferable<'a> {
 /// Compile an entire program, starting from main then lazily compiling
 /// each used function as it is called.
 pub fn infer_ast<'a>(ast: &mut ast::Ast<'a>, cache: &mut ModuleCache<'a>) {
-    CURRENT_LEVEL.store(INITIAL_LEVEL, Ordering::SeqCst);
+    set_current_level(INITIAL_LEVEL);
+    FINISHED_MAIN.with(|finished| finished.set(false));
     let (_, traits) = infer(ast, cache);
-    CURRENT_LEVEL.store(INITIAL_LEVEL - 1, Ordering::SeqCst);
+    set_current_level(INITIAL_LEVEL - 1);
+    FINISHED_MAIN.with(|finished| finished.set(true));
 
     let exposed_traits = traitchecker::resolve_traits(traits, &[], cache);
     // No traits should be propogated above the top-level main function
     assert!(exposed_traits.is_empty());
+
+    issue_unused_import_warnings(ast.locate().filename, cache);
+    issue_typed_hole_warnings(cache);
+}
+
+/// Infer a single standalone expression at the top level, e.g. one line entered into a REPL.
+/// Unlike `infer_ast`, traits still required by the expression's type are returned rather than
+/// asserted to be empty, along with the diagnostics issued while checking it. Definitions made
+/// by the expression remain in the cache so later calls can refer to them.
+#[allow(dead_code)]
+pub fn infer_expression<'a>(
+    ast: &mut ast::Ast<'a>, cache: &mut ModuleCache<'a>,
+) -> (GeneralizedType, Vec<RequiredTrait>, Vec<String>) {
+    set_current_level(INITIAL_LEVEL);
+    FINISHED_MAIN.with(|finished| finished.set(false));
+    let (typ, traits) = infer(ast, cache);
+    set_current_level(INITIAL_LEVEL - 1);
+    FINISHED_MAIN.with(|finished| finished.set(true));
+
+    let typ = follow_bindings_in_cache(&typ, cache);
+    let typevars_in_type = find_all_typevars(&typ, true, cache);
+    let remaining_traits = traitchecker::resolve_traits(traits, &typevars_in_type, cache);
+
+    (generalize(&typ, cache), remaining_traits, error::take_diagnostics())
+}
+
+/// Returns the type of the innermost node of the given ast whose location contains the given
+/// location, generalized over any type variables it still contains. This is meant for tools
+/// like editors to show the type of the expression under the cursor once `infer_ast` has run.
+#[allow(dead_code)]
+pub fn type_at<'a>(location: Location<'a>, ast: &ast::Ast<'a>, cache: &ModuleCache<'a>) -> Option<GeneralizedType> {
+    let node = innermost_node_at(location, ast)?;
+    let typ = follow_bindings_in_cache(node.get_type()?, cache);
+    Some(generalize(&typ, cache))
+}
+
+/// Finds the innermost node containing the given location. When several children contain
+/// it, e.g. because they were desugared from the same source, the first of them is searched.
+fn innermost_node_at<'a, 'b>(location: Location<'a>, ast: &'b ast::Ast<'a>) -> Option<&'b ast::Ast<'a>> {
+    if !ast.locate().contains(location) {
+        return None;
+    }
+
+    let child = ast_children(ast).into_iter().find_map(|child| innermost_node_at(location, child));
+    Some(child.unwrap_or(ast))
+}
+
+/// Returns each expression or pattern directly within the given ast node
+fn ast_children<'a, 'b>(ast: &'b ast::Ast<'a>) -> Vec<&'b ast::Ast<'a>> {
+    use ast::Ast::*;
+    let definition_children = |definition: &'b ast::Definition<'a>| [definition.pattern.as_ref(), &definition.expr];
+
+    match ast {
+        Literal(_) | Variable(_) | TypeDefinition(_) | Import(_) | TraitDefinition(_) | Extern(_) => vec![],
+        Lambda(lambda) => lambda.args.iter().chain(std::iter::once(lambda.body.as_ref())).collect(),
+        FunctionCall(call) => std::iter::once(call.function.as_ref()).chain(&call.args).collect(),
+        Definition(definition) => definition_children(definition).to_vec(),
+        If(if_) => {
+            let mut children = vec![if_.condition.as_ref(), &if_.then];
+            children.extend(if_.otherwise.as_deref());
+            children
+        },
+        Guard(guard) => {
+            let mut children: Vec<_> = guard.branches.iter().flat_map(|(condition, body)| [condition, body]).collect();
+            children.extend(guard.otherwise.as_deref());
+            children
+        },
+        Match(match_) => {
+            let mut children = vec![match_.expression.as_ref()];
+            for (pattern, guard, body) in &match_.branches {
+                children.push(pattern);
+                children.extend(guard);
+                children.push(body);
+            }
+            children
+        },
+        TypeAnnotation(annotation) => vec![&annotation.lhs],
+        TraitImpl(impl_) => impl_.definitions.iter().flat_map(definition_children).collect(),
+        Return(return_) => vec![&return_.expression],
+        Sequence(sequence) => sequence.statements.iter().collect(),
+        MemberAccess(member_access) => vec![&member_access.lhs],
+        Assignment(assignment) => vec![&assignment.lhs, &assignment.rhs],
+        RecordSpread(spread) => spread.bases.iter().chain(spread.overrides.iter().map(|(_, value)| value)).collect(),
+        TypeTest(test) => vec![&test.expression, &test.constructor],
+        Where(where_) => {
+            let definitions = where_.definitions.iter().flat_map(definition_children);
+            std::iter::once(where_.expression.as_ref()).chain(definitions).collect()
+        },
+        Range(range) => vec![&range.start, &range.end],
+        Hole(_) => vec![],
+    }
+}
+
+/// Warn for each import in the given file that had none of its definitions
+/// referenced during type inference. Only imports within the main module are
+/// checked since other modules are only partially inferred as their definitions
+/// are used.
+fn issue_unused_import_warnings(filename: &Path, cache: &ModuleCache) {
+    for import in &cache.import_infos {
+        if import.location.filename == filename && !cache.is_import_used(import) {
+            warning!(import.location, "Unused import {}", import.name);
+        }
+    }
+}
+
+/// Report the type of each typed hole now that inference has finished and their types
+/// are as specific as they will get. A program with holes can still be compiled, though
+/// it will abort if any hole is reached while running.
+fn issue_typed_hole_warnings(cache: &ModuleCache) {
+    for (location, typ, bindings_in_scope) in &cache.holes {
+        if bindings_in_scope.is_empty() {
+            warning!(*location, "Hole has type {}", typ.display(cache));
+        } else {
+            let bindings = bindings_in_scope.join(", ");
+            warning!(*location, "Hole has type {}, with local variables {} in scope", typ.display(cache), bindings);
+        }
+    }
 }
 
 pub fn infer<'a, T>(ast: &mut T, cache: &mut ModuleCache<'a>) -> (Type, TraitConstraints)
 where
     T: Inferable<'a> + Typed + std::fmt::Display,
 {
+    let entry_level = CURRENT_LEVEL.with(Cell::get);
     let (typ, traits) = ast.infer_impl(cache);
+
+    // Any infer_impl that changes the CURRENT_LEVEL must restore it afterward, otherwise
+    // later definitions would be silently generalized at the wrong level.
+    debug_assert_eq!(
+        CURRENT_LEVEL.with(Cell::get),
+        entry_level,
+        "CURRENT_LEVEL was not restored after inferring {}",
+        ast
+    );
+
     ast.set_type(typ.clone());
     (typ, traits)
 }
 
+/// Infer the type of each node in order, returning each type along
+/// with the trait constraints of every node combined.
+pub fn infer_all<'a, 'n, T>(
+    nodes: impl IntoIterator<Item = &'n mut T>, cache: &mut ModuleCache<'a>,
+) -> (Vec<Type>, TraitConstraints)
+where
+    T: 'n + Inferable<'a> + Typed + std::fmt::Display,
+{
+    fmap_mut_pair_flatten_second(nodes, |node| infer(node, cache))
+}
+
 /// Note: each Ast's inference rule is given above the impl if available.
 impl<'a> Inferable<'a> for ast::Ast<'a> {
     fn infer_impl(&mut self, cache: &mut ModuleCache<'a>) -> (Type, TraitConstraints) {
@@ -1146,8 +2119,16 @@ impl<'a> Inferable<'a> for ast::Literal<'a> {
                     let callsite = cache.push_variable(x.to_string(), self.location);
                     let trait_impl = TraitConstraint::int_constraint(int_type, callsite, cache);
                     self.kind = Integer(x, IntegerKind::Inferred(int_type));
+
+                    if let Some(default) = DEFAULT_INTEGER_KIND.with(|kind| kind.get()) {
+                        cache.integer_literal_defaults.insert(int_type, default);
+                    }
                     (Type::TypeVariable(int_type), vec![trait_impl])
                 } else {
+                    // Pointer-sized literals are checked once the target is known during monomorphisation
+                    if kind.can_represent(x) == Some(false) {
+                        error!(self.location, "This integer literal does not fit in type {}", kind);
+                    }
                     (Type::Primitive(PrimitiveType::IntegerType(kind)), vec![])
                 }
             },
@@ -1198,11 +2179,40 @@ impl<'a> Inferable<'a> for ast::Variable<'a> {
         };
 
         let (t, traits, mapping) = s.instantiate(traits, cache);
+        inherit_integer_literal_defaults(&mapping, cache);
         self.instantiation_mapping = Rc::new(mapping);
         (t, traits)
     }
 }
 
+/// Each fresh type variable of an instantiated definition keeps the default integer type of
+/// the literal type variable it replaces so that `default` still applies to literals whose
+/// type was generalized, e.g. the return type of `foo () = 3`.
+fn inherit_integer_literal_defaults(mapping: &TypeBindings, cache: &mut ModuleCache) {
+    if mapping.is_empty() || cache.integer_literal_defaults.is_empty() {
+        return;
+    }
+
+    // A literal's type variable may have been bound to another type variable which was
+    // generalized in its place, so defaults are looked up by the variable each is bound to.
+    // These are sorted so the first literal wins if several with different defaults were unified.
+    let mut literals: Vec<_> = cache.integer_literal_defaults.iter().map(|(id, default)| (*id, *default)).collect();
+    literals.sort();
+
+    let mut defaults = HashMap::new();
+    for (id, default) in literals {
+        if let TypeVariable(root) = follow_bindings_in_cache(&TypeVariable(id), cache) {
+            defaults.entry(root).or_insert(default);
+        }
+    }
+
+    for (original, instantiated) in mapping {
+        if let (Some(default), TypeVariable(instantiated)) = (defaults.get(original), instantiated) {
+            cache.integer_literal_defaults.entry(*instantiated).or_insert(*default);
+        }
+    }
+}
+
 /* Abs
  *   arg_type1 = newvar ()
  *   arg_type2 = newvar ()
@@ -1214,6 +2224,9 @@ impl<'a> Inferable<'a> for ast::Variable<'a> {
  */
 impl<'a> Inferable<'a> for ast::Lambda<'a> {
     fn infer_impl(&mut self, cache: &mut ModuleCache<'a>) -> (Type, TraitConstraints) {
+        // Any type variable created before this point comes from outside of this lambda
+        let first_local_typevar = cache.type_bindings.len();
+
         // The newvars for the parameters are filled out during name resolution
         let parameter_types = fmap(&self.args, |_| next_type_variable(cache));
 
@@ -1227,7 +2240,7 @@ impl<'a> Inferable<'a> for ast::Lambda<'a> {
             // Check if user specified a return type
             let typ = typ.clone();
             let (return_type, traits) = self.body.infer_impl(cache);
-            unify(&typ, &return_type, self.location, cache);
+            unify_expected(&typ, &return_type, self.location, cache);
             (typ, traits)
         } else {
             infer(self.body.as_mut(), cache)
@@ -1238,13 +2251,25 @@ impl<'a> Inferable<'a> for ast::Lambda<'a> {
             return_type: Box::new(return_type),
             environment: Box::new(infer_closure_environment(&self.closure_environment, cache)),
             is_varargs: false,
+            varargs_type: None,
         });
 
-        // let typevars_in_fn = find_all_typevars(&typ, false, cache);
-        // let exposed_traits = traitchecker::resolve_traits(traits.clone(), &typevars_in_fn, cache);
-        // self.required_traits = exposed_traits;
+        // Constraints only involving type variables local to this lambda can be solved now. The rest
+        // are kept on the lambda and are still returned since the type variables they refer to
+        // may be generalized or bound by an enclosing definition, e.g. `print (f x)` within `f`.
+        let mut typevars_in_fn = find_all_typevars(&typ, false, cache);
+        for constraint in &traits {
+            for arg in constraint.args() {
+                let outer = find_all_typevars(arg, false, cache).into_iter().filter(|id| id.0 < first_local_typevar);
+                typevars_in_fn.extend(outer);
+            }
+        }
+
+        let exposed_traits = traitchecker::resolve_traits(traits.clone(), &typevars_in_fn, cache);
 
-        // TODO: should we return exposed traits instead?
+        let traits = traits.into_iter().filter(|constraint| exposed_traits.contains(&constraint.required)).collect();
+
+        self.required_traits = exposed_traits;
         (typ, traits)
     }
 }
@@ -1263,16 +2288,25 @@ impl<'a> Inferable<'a> for ast::Lambda<'a> {
 impl<'a> Inferable<'a> for ast::FunctionCall<'a> {
     fn infer_impl(&mut self, cache: &mut ModuleCache<'a>) -> (Type, TraitConstraints) {
         let (f, mut traits) = infer(self.function.as_mut(), cache);
-        let (parameters, mut arg_traits) = fmap_mut_pair_flatten_second(&mut self.args, |arg| infer(arg, cache));
+        let (parameters, mut arg_traits) = infer_all(&mut self.args, cache);
 
         let return_type = next_type_variable(cache);
         traits.append(&mut arg_traits);
 
+        if self.trailing_closure && !check_trailing_closure_parameter(self, &f, cache) {
+            return (return_type, traits);
+        }
+
+        if !check_call_arguments(self, &f, &parameters, &return_type, cache) {
+            return (return_type, traits);
+        }
+
         let new_function = Function(FunctionType {
             parameters,
             return_type: Box::new(return_type.clone()),
             environment: Box::new(next_type_variable(cache)),
             is_varargs: false,
+            varargs_type: None,
         });
 
         unify(&f, &new_function, self.location, cache);
@@ -1280,6 +2314,79 @@ impl<'a> Inferable<'a> for ast::FunctionCall<'a> {
     }
 }
 
+/// Check each argument of a call against the corresponding parameter of the function
+/// being called, issuing an error for every argument that does not match rather than
+/// only the first. Returns false if any errors were issued.
+///
+/// Calls to functions whose type is not yet known, or whose parameter count differs
+/// from the number of arguments, are left to be checked by unifying the whole function type.
+/// Extra arguments passed to a varargs function are checked against its varargs type, if any.
+fn check_call_arguments<'a>(
+    call: &ast::FunctionCall<'a>, f: &Type, arguments: &[Type], return_type: &Type, cache: &mut ModuleCache<'a>,
+) -> bool {
+    let function = match follow_bindings_in_cache(f, cache) {
+        Function(function) => function,
+        _ => return true,
+    };
+
+    let mut parameters = function.parameters;
+    if let Some(varargs_type) = &function.varargs_type {
+        if arguments.len() > parameters.len() {
+            parameters.resize(arguments.len(), varargs_type.as_ref().clone());
+        }
+    }
+
+    if parameters.len() != arguments.len() {
+        return true;
+    }
+
+    let locations = fmap(&call.args, |arg| arg.locate());
+    let mut bindings = UnificationBindings::empty();
+    let errors = try_unify_all_collecting(&parameters, arguments, &mut bindings, &locations, cache);
+
+    if errors.is_empty() {
+        return true;
+    }
+
+    bindings.perform(cache);
+    for error in errors {
+        error::issue(error);
+    }
+
+    // Still give the call its return type so that uses of its result can be checked
+    let _ = unify_quiet(&function.return_type, return_type, call.location, cache);
+    false
+}
+
+/// A trailing closure is always passed as the last argument of a call. Check that the
+/// parameter it is passed as is a function, issuing an error and returning false if it
+/// is known not to be. Other mismatches are left to be reported by unification.
+fn check_trailing_closure_parameter<'a>(call: &ast::FunctionCall<'a>, f: &Type, cache: &ModuleCache<'a>) -> bool {
+    let index = call.args.len() - 1;
+
+    let parameter = match follow_bindings_in_cache(f, cache) {
+        Function(function) => match function.parameters.get(index) {
+            Some(parameter) => follow_bindings_in_cache(parameter, cache),
+            None => return true,
+        },
+        _ => return true,
+    };
+
+    match parameter {
+        Function(_) | TypeVariable(_) | Forall(..) => true,
+        _ => {
+            error!(
+                call.args[index].locate(),
+                "This trailing closure is passed as parameter {} of {}, which has type {} rather than a function type",
+                index + 1,
+                call.function,
+                parameter.display(cache)
+            );
+            false
+        },
+    }
+}
+
 /// True if the expression can be generalized. Generalizing expressions
 /// will cause them to be re-evaluated whenever they're used with new types,
 /// so generalization should be limited to when this would be expected by
@@ -1288,6 +2395,7 @@ fn should_generalize(ast: &ast::Ast) -> bool {
     match ast {
         ast::Ast::Variable(_) => true,
         ast::Ast::Lambda(lambda) => lambda.closure_environment.is_empty(),
+        ast::Ast::TypeAnnotation(annotation) => should_generalize(&annotation.lhs),
         _ => false,
     }
 }
@@ -1313,13 +2421,31 @@ impl<'a> Inferable<'a> for ast::Definition<'a> {
         }
 
         let level = self.level.unwrap();
-        let previous_level = CURRENT_LEVEL.swap(level.0, Ordering::SeqCst);
+        let previous_level = set_current_level(level.0);
+        let previous_default = DEFAULT_INTEGER_KIND.with(|kind| kind.replace(self.default_integer_kind));
 
         // The rhs of a Definition must be inferred at a greater LetBindingLevel than
         // the lhs below. Here we use level for the rhs and level - 1 for the lhs
         let (t, traits) = infer(self.expr.as_mut(), cache);
+        DEFAULT_INTEGER_KIND.with(|kind| kind.set(previous_default));
+
+        // A definition annotated with a `forall` type is given exactly that type rather than
+        // the type inferred for it, provided the inferred type is at least as general.
+        let t = match self.pattern.as_ref() {
+            ast::Ast::TypeAnnotation(annotation) => {
+                let annotated = annotation.typ.as_ref().unwrap();
+                match follow_bindings_in_cache(annotated, cache) {
+                    Forall(variables, polymorphic) => {
+                        check_polymorphic_annotation(&variables, &polymorphic, &t, annotation.location, cache);
+                        annotated.clone()
+                    },
+                    _ => t,
+                }
+            },
+            _ => t,
+        };
 
-        CURRENT_LEVEL.store(level.0 - 1, Ordering::SeqCst);
+        set_current_level(level.0 - 1);
 
         // TODO: the inferred type t needs to be unified with the patterns type before
         // resolve_traits is called. For now it is sufficient to call bind_irrefutable_pattern
@@ -1337,8 +2463,10 @@ impl<'a> Inferable<'a> for ast::Definition<'a> {
         // which entails wrapping type variables in a forall, and finding which traits
         // usages of this definitio require.
         let traits = if should_generalize(self.expr.as_ref()) {
-            let typevars_in_fn = find_all_typevars(self.pattern.get_type().unwrap(), false, cache);
-            let exposed_traits = traitchecker::resolve_traits(traits, &typevars_in_fn, cache);
+            let typ = self.pattern.get_type().unwrap().clone();
+            let typevars_in_fn = find_all_typevars(&typ, false, cache);
+            let exposed_traits = traitchecker::resolve_traits(traits.clone(), &typevars_in_fn, cache);
+            let exposed_traits = remove_ambiguous_traits(traits, exposed_traits, &typ, self, cache);
 
             bind_irrefutable_pattern(self.pattern.as_mut(), &t, &exposed_traits, true, cache);
             vec![]
@@ -1346,29 +2474,126 @@ impl<'a> Inferable<'a> for ast::Definition<'a> {
             traits
         };
 
+        check_skolems_escape(self.pattern.get_type().unwrap(), self.location, cache);
+
         // TODO: Can these operations on the LetBindingLevel be simplified?
-        CURRENT_LEVEL.store(previous_level, Ordering::SeqCst);
+        set_current_level(previous_level);
         (unit, traits)
     }
 }
 
+/// Reports the exposed constraints containing a type variable that neither `typ` nor the functional
+/// dependencies of the other exposed constraints determine, e.g. `Parse a b` in `f x = ignore (parse x)`.
+/// Nothing decides which impl such a constraint should use so they are removed from the result.
+/// Type variables in `Int` constraints are exempt since these are defaulted to `i32` instead.
+fn remove_ambiguous_traits<'a>(
+    traits: TraitConstraints, exposed_traits: Vec<RequiredTrait>, typ: &Type, definition: &ast::Definition<'a>,
+    cache: &ModuleCache<'a>,
+) -> Vec<RequiredTrait> {
+    // The given constraints of an impl may still determine these, see check_impl_propagated_traits
+    if is_impl_definition(definition.pattern.as_ref(), cache) {
+        return exposed_traits;
+    }
+
+    let exposed = fmap(exposed_traits.iter(), |required| {
+        traits.iter().find(|constraint| constraint.required == *required).unwrap().clone()
+    });
+
+    // Type variables from an outer definition are not generalized here and may still be determined later
+    let mut typevars_in_fn = find_all_typevars(typ, false, cache);
+    for arg in exposed.iter().flat_map(|constraint| constraint.args()) {
+        let outer = find_all_typevars(arg, false, cache)
+            .into_iter()
+            .filter(|typevar| find_all_typevars(&TypeVariable(*typevar), true, cache).is_empty());
+        typevars_in_fn.extend(outer);
+    }
+
+    let defaultable = exposed
+        .iter()
+        .filter(|constraint| constraint.is_int_constraint(cache))
+        .flat_map(|constraint| constraint.typearg_typevars(cache))
+        .collect::<Vec<_>>();
+
+    let mut ambiguous_typevars = free_typevars(typevars_in_fn, &exposed, cache);
+    ambiguous_typevars.retain(|typevar| !defaultable.contains(typevar));
+
+    if ambiguous_typevars.is_empty() {
+        return exposed_traits;
+    }
+
+    let (ambiguous, exposed_traits): (Vec<_>, Vec<_>) = exposed_traits.into_iter().partition(|required| {
+        let args = &required.signature.args;
+        args.iter().any(|arg| contains_any_typevars_from_list(arg, &ambiguous_typevars, cache))
+    });
+
+    error!(
+        definition.location,
+        "Ambiguous type: the type of {} does not determine which impls to use for {}",
+        definition.pattern,
+        typeprinter::display_traits(typ, &ambiguous, cache)
+    );
+    exposed_traits
+}
+
+fn is_impl_definition(pattern: &ast::Ast, cache: &ModuleCache) -> bool {
+    match pattern {
+        ast::Ast::Variable(variable) => cache[variable.definition.unwrap()].trait_impl.is_some(),
+        ast::Ast::TypeAnnotation(annotation) => is_impl_definition(&annotation.lhs, cache),
+        _ => false,
+    }
+}
+
 impl<'a> Inferable<'a> for ast::If<'a> {
     fn infer_impl(&mut self, cache: &mut ModuleCache<'a>) -> (Type, TraitConstraints) {
-        let (condition, mut traits) = infer(self.condition.as_mut(), cache);
+        let branches = once(self.then.as_mut()).chain(self.otherwise.as_deref_mut());
+        let (types, traits) = infer_all(once(self.condition.as_mut()).chain(branches), cache);
+
         let bool_type = Type::Primitive(PrimitiveType::BooleanType);
-        unify(&condition, &bool_type, self.condition.locate(), cache);
+        unify(&types[0], &bool_type, self.condition.locate(), cache);
 
-        let (then, mut then_traits) = infer(self.then.as_mut(), cache);
-        traits.append(&mut then_traits);
+        match types.get(2) {
+            Some(otherwise) => {
+                unify(&types[1], otherwise, self.location, cache);
+                (types[1].clone(), traits)
+            },
+            None => (Type::Primitive(PrimitiveType::UnitType), traits),
+        }
+    }
+}
 
-        if let Some(otherwise) = &mut self.otherwise {
-            let (otherwise, mut otherwise_traits) = infer(otherwise.as_mut(), cache);
-            traits.append(&mut otherwise_traits);
+impl<'a> Inferable<'a> for ast::Guard<'a> {
+    fn infer_impl(&mut self, cache: &mut ModuleCache<'a>) -> (Type, TraitConstraints) {
+        let bool_type = Type::Primitive(PrimitiveType::BooleanType);
+        let mut return_type = None;
+        let mut traits = vec![];
 
-            unify(&then, &otherwise, self.location, cache);
-            (then, traits)
-        } else {
-            (Type::Primitive(PrimitiveType::UnitType), traits)
+        for (condition, branch) in self.branches.iter_mut() {
+            let (condition_type, mut condition_traits) = infer(condition, cache);
+            unify(&condition_type, &bool_type, condition.locate(), cache);
+            traits.append(&mut condition_traits);
+
+            let (branch_type, mut branch_traits) = infer(branch, cache);
+            traits.append(&mut branch_traits);
+
+            // Each branch is unified against the first so mismatches are reported on the offending branch
+            match &return_type {
+                Some(return_type) => unify(return_type, &branch_type, branch.locate(), cache),
+                None => return_type = Some(branch_type),
+            }
+        }
+
+        // Like `If`, a guard without an else branch may not evaluate any
+        // of its branches so its result is unit.
+        match &mut self.otherwise {
+            Some(otherwise) => {
+                let (otherwise_type, mut otherwise_traits) = infer(otherwise.as_mut(), cache);
+                traits.append(&mut otherwise_traits);
+
+                let return_type = return_type.unwrap();
+                unify(&return_type, &otherwise_type, otherwise.locate(), cache);
+                (return_type, traits)
+            },
+            None => (Type::Primitive(PrimitiveType::UnitType), traits),
         }
     }
 }
@@ -1378,28 +2603,28 @@ impl<'a> Inferable<'a> for ast::Match<'a> {
         let error_count = get_error_count();
 
         let (expression, mut traits) = infer(self.expression.as_mut(), cache);
-        let mut return_type = Type::Primitive(PrimitiveType::UnitType);
 
-        if !self.branches.is_empty() {
-            // Unroll the first iteration of inferring (pattern, branch) types so each
-            // subsequent (pattern, branch) types can be unified against the first.
-            let (pattern_type, mut pattern_traits) = infer(&mut self.branches[0].0, cache);
+        // Each pattern is unified with the matched expression before inferring the guards and
+        // branches so that the variables they bind have their types known within them.
+        let patterns = self.branches.iter_mut().map(|(pattern, _, _)| pattern);
+        let (pattern_types, mut pattern_traits) = infer_all(patterns, cache);
+        traits.append(&mut pattern_traits);
 
-            traits.append(&mut pattern_traits);
-            unify(&expression, &pattern_type, self.branches[0].0.locate(), cache);
+        for ((pattern, guard, _), pattern_type) in self.branches.iter_mut().zip(&pattern_types) {
+            unify(&expression, pattern_type, pattern.locate(), cache);
+            if let Some(guard) = guard {
+                infer_match_guard(guard, &mut traits, cache);
+            }
+        }
 
-            let (branch, mut branch_traits) = infer(&mut self.branches[0].1, cache);
-            return_type = branch;
-            traits.append(&mut branch_traits);
+        let branches = self.branches.iter_mut().map(|(_, _, branch)| branch);
+        let (branch_types, mut branch_traits) = infer_all(branches, cache);
+        traits.append(&mut branch_traits);
 
-            for (pattern, branch) in self.branches.iter_mut().skip(1) {
-                let (pattern_type, mut pattern_traits) = infer(pattern, cache);
-                let (branch_type, mut branch_traits) = infer(branch, cache);
-                unify(&expression, &pattern_type, pattern.locate(), cache);
-                unify(&return_type, &branch_type, branch.locate(), cache);
-                traits.append(&mut pattern_traits);
-                traits.append(&mut branch_traits);
-            }
+        // Each branch is unified against the first so mismatches are reported on the offending branch
+        let return_type = branch_types.first().cloned().unwrap_or(Type::Primitive(PrimitiveType::UnitType));
+        for ((_, _, branch), branch_type) in self.branches.iter().zip(&branch_types).skip(1) {
+            unify(&return_type, branch_type, branch.locate(), cache);
         }
 
         // Compiling the decision tree for this pattern requires each pattern is well-typed.
@@ -1416,6 +2641,14 @@ impl<'a> Inferable<'a> for ast::Match<'a> {
     }
 }
 
+/// Infer the guard of a match branch, which must be a bool. This is done after
+/// inferring the branch's pattern so that the guard may use the variables it binds.
+fn infer_match_guard<'a>(guard: &mut ast::Ast<'a>, traits: &mut TraitConstraints, cache: &mut ModuleCache<'a>) {
+    let (guard_type, mut guard_traits) = infer(guard, cache);
+    unify(&guard_type, &Type::Primitive(PrimitiveType::BooleanType), guard.locate(), cache);
+    traits.append(&mut guard_traits);
+}
+
 impl<'a> Inferable<'a> for ast::TypeDefinition<'a> {
     fn infer_impl(&mut self, _cache: &mut ModuleCache<'a>) -> (Type, TraitConstraints) {
         (Type::Primitive(PrimitiveType::UnitType), vec![])
@@ -1425,8 +2658,32 @@ impl<'a> Inferable<'a> for ast::TypeDefinition<'a> {
 impl<'a> Inferable<'a> for ast::TypeAnnotation<'a> {
     fn infer_impl(&mut self, cache: &mut ModuleCache<'a>) -> (Type, TraitConstraints) {
         let (typ, traits) = infer(self.lhs.as_mut(), cache);
-        unify(&typ, self.typ.as_mut().unwrap(), self.location, cache);
-        (typ, traits)
+
+        if self.gradual {
+            // A mismatch at a gradual boundary is deferred until runtime where
+            // the cast is checked instead. See `monomorphise_type_annotation`.
+            let annotated = self.typ.clone().unwrap();
+            match try_unify_expected(&annotated, &typ, self.location, cache) {
+                Ok(bindings) => bindings.perform(cache),
+                Err(message) => error::issue(message.into_warning()),
+            }
+            (annotated, traits)
+        } else if let Forall(variables, annotated) = follow_bindings_in_cache(self.typ.as_ref().unwrap(), cache) {
+            check_polymorphic_annotation(&variables, &annotated, &typ, self.location, cache);
+
+            // Each use of the annotated expression may be at a different type, as with a variable
+            let fresh_variables = variables.iter().map(|variable| (*variable, next_type_variable_id(cache))).collect();
+            let mut traits = traits;
+            for constraint in traits.iter_mut() {
+                for typ in constraint.args_mut() {
+                    *typ = replace_typevars(typ, &fresh_variables, cache);
+                }
+            }
+            (replace_typevars(&annotated, &fresh_variables, cache), traits)
+        } else {
+            unify_expected(self.typ.as_ref().unwrap(), &typ, self.location, cache);
+            (typ, traits)
+        }
     }
 }
 
@@ -1440,7 +2697,18 @@ impl<'a> Inferable<'a> for ast::Import<'a> {
 
 impl<'a> Inferable<'a> for ast::TraitDefinition<'a> {
     fn infer_impl(&mut self, cache: &mut ModuleCache<'a>) -> (Type, TraitConstraints) {
-        let previous_level = CURRENT_LEVEL.swap(self.level.unwrap().0, Ordering::SeqCst);
+        let unit = Type::Primitive(PrimitiveType::UnitType);
+
+        // Trait definitions are inferred on demand when one of their methods is used, possibly
+        // from within one of the trait's own defaults. Marking the trait as inferred up front
+        // prevents these from recursively inferring the trait again.
+        if self.typ.is_some() {
+            return (unit, vec![]);
+        } else {
+            self.typ = Some(unit.clone());
+        }
+
+        let previous_level = set_current_level(self.level.unwrap().0);
 
         for declaration in self.declarations.iter_mut() {
             let rhs = declaration.typ.as_ref().unwrap();
@@ -1448,8 +2716,61 @@ impl<'a> Inferable<'a> for ast::TraitDefinition<'a> {
             bind_irrefutable_pattern(declaration.lhs.as_mut(), rhs, &[], true, cache);
         }
 
-        CURRENT_LEVEL.store(previous_level, Ordering::SeqCst);
-        (Type::Primitive(PrimitiveType::UnitType), vec![])
+        set_current_level(previous_level);
+
+        for default in self.defaults.iter_mut() {
+            infer_trait_default(default, self.trait_info.unwrap(), cache);
+        }
+
+        (unit, vec![])
+    }
+}
+
+/// Default definitions in a trait are checked once, generically, against their declaration.
+/// Since an impl using a default never sees its body, the only trait the default may require
+/// is the trait it is defined in, which is always satisfied by the impl using it.
+fn infer_trait_default<'a>(default: &mut ast::Definition<'a>, trait_id: TraitInfoId, cache: &mut ModuleCache<'a>) {
+    let definition_id = match default.pattern.as_ref() {
+        ast::Ast::Variable(variable) if default.typ.is_none() => variable.definition.unwrap(),
+        _ => return,
+    };
+
+    if cache[definition_id].trait_default.is_none() {
+        return;
+    }
+
+    let name = cache[definition_id].name.clone();
+    let declared_type = lookup_definition_type_in_trait(&name, trait_id, cache);
+
+    // Instantiate the declaration at the default's level so that the default is generalized
+    let previous_level = set_current_level(default.level.unwrap().0);
+    let trait_info = &cache.trait_infos[trait_id.0];
+    let trait_typevars = trait_info.typeargs.iter().chain(&trait_info.fundeps).copied().collect::<Vec<_>>();
+
+    let mut skolems = fmap(&trait_typevars, |_| next_type_variable_id(cache));
+    let trait_args = fmap(&skolems, |skolem| TypeVariable(*skolem));
+    let mut bindings = trait_typevars.into_iter().zip(trait_args.iter().cloned()).collect();
+    let typ = instantiate_impl_with_bindings(&declared_type, &mut bindings, &mut skolems, cache);
+    set_current_level(previous_level);
+
+    cache[definition_id].typ = Some(typ);
+    infer(default, cache);
+    check_impl_skolems(&skolems, default, trait_id, cache);
+
+    let args = fmap(&trait_args, |arg| follow_bindings_in_cache(arg, cache));
+    let own_trait = ConstraintSignature { trait_id, args, id: TraitConstraintId(0) };
+    for required in cache[definition_id].required_traits.clone() {
+        let args = fmap(&required.signature.args, |arg| follow_bindings_in_cache(arg, cache));
+        if required.signature.trait_id != trait_id || args != own_trait.args {
+            let own_trait = RequiredTrait { signature: own_trait.clone(), callsite: required.callsite };
+            error!(
+                default.location,
+                "Default definition of {} requires {}, but only {} may be used in a default definition",
+                name,
+                required.display(cache),
+                own_trait.display(cache)
+            );
+        }
     }
 }
 
@@ -1466,7 +2787,7 @@ impl<'a> Inferable<'a> for ast::TraitImpl<'a> {
 
         // Need to replace all typevars here so we do not rebind over them.
         // E.g. an impl for `Cmp a given Int a` could be accidentally bound to `Cmp usz`
-        let (trait_arg_types, _) = replace_all_typevars(&self.trait_arg_types, cache);
+        let (trait_arg_types, mut impl_typevars) = replace_all_typevars(&self.trait_arg_types, cache);
 
         // Instantiate the typevars in the parent trait to bind their definition
         // types against the types in this trait impl. This needs to be done once
@@ -1478,26 +2799,43 @@ impl<'a> Inferable<'a> for ast::TraitImpl<'a> {
         let mut impl_bindings: HashMap<_, _> = typevars_to_replace.into_iter().zip(trait_arg_types).collect();
 
         for definition in self.definitions.iter_mut() {
+            let mut skolems = vec![];
             bind_irrefutable_pattern_in_impl(
                 definition.pattern.as_ref(),
                 self.trait_info.unwrap(),
                 &mut impl_bindings,
+                &mut skolems,
                 cache,
             );
 
             let (_, traits) = infer(definition, cache);
+            check_impl_skolems(&skolems, definition, self.trait_info.unwrap(), cache);
 
             // Need to check we only use traits that are `given` by the definition
             // in question or by the overall impl.
-            check_impl_propagated_traits(
-                definition.pattern.as_ref(),
-                self.trait_info.unwrap(),
-                &cache[self.impl_id.unwrap()].given.clone(),
-                cache,
-            );
+            // These are in terms of the impl's original type variables so they must be replaced
+            // the same way as the trait arguments to compare them against the traits used.
+            let given = cache[self.impl_id.unwrap()].given.iter().filter_map(GivenConstraint::as_trait).cloned();
+            let given = fmap(given.collect::<Vec<_>>(), |mut signature| {
+                signature.args =
+                    fmap(&signature.args, |arg| replace_all_typevars_with_bindings(arg, &mut impl_typevars, cache));
+                signature
+            });
+            check_impl_propagated_traits(definition.pattern.as_ref(), self.trait_info.unwrap(), &given, cache);
+
+            // Refer back to the impl's own type variables now that the given constraints are matched
+            for (original, replacement) in &impl_typevars {
+                let _ = unify_quiet(replacement, &TypeVariable(*original), Location::builtin(), cache);
+            }
 
             // No traits should be propagated outside of the impl. The only way this can happen
-            // is if the definition is not generalized and traits are used.
+            // is if the definition is not generalized and traits are used. Those without type
+            // variables, e.g. `Int i32` from the literal in `zero = 0`, can be solved in place instead.
+            let (traits, known_traits): (Vec<_>, Vec<_>) =
+                traits.into_iter().partition(|trait_| !trait_.typearg_typevars(cache).is_empty());
+
+            traitchecker::resolve_traits(known_traits, &[], cache);
+
             for trait_ in traits {
                 error!(
                     definition.location,
@@ -1507,6 +2845,16 @@ impl<'a> Inferable<'a> for ast::TraitImpl<'a> {
             }
         }
 
+        // Any member this impl doesn't define falls back to the trait's default definition.
+        // Looking up the member's type ensures the trait, and thus each default, is inferred.
+        let trait_id = self.trait_info.unwrap();
+        for default in cache[trait_id].defaults.clone() {
+            if cache[default].typ.is_none() {
+                let name = cache[default].name.clone();
+                lookup_definition_type_in_trait(&name, trait_id, cache);
+            }
+        }
+
         (Type::Primitive(PrimitiveType::UnitType), vec![])
     }
 }
@@ -1520,27 +2868,18 @@ impl<'a> Inferable<'a> for ast::Return<'a> {
 
 impl<'a> Inferable<'a> for ast::Sequence<'a> {
     fn infer_impl(&mut self, cache: &mut ModuleCache<'a>) -> (Type, TraitConstraints) {
-        let ignore_len = self.statements.len() - 1;
-        let mut traits = vec![];
-
-        for statement in self.statements.iter_mut().take(ignore_len) {
-            let (_, mut statement_traits) = infer(statement, cache);
-            traits.append(&mut statement_traits);
-        }
-
-        let (last_statement_type, mut statement_traits) = infer(self.statements.last_mut().unwrap(), cache);
-        traits.append(&mut statement_traits);
-        (last_statement_type, traits)
+        let (mut statement_types, traits) = infer_all(&mut self.statements, cache);
+        (statement_types.pop().unwrap(), traits)
     }
 }
 
 impl<'a> Inferable<'a> for ast::Extern<'a> {
     fn infer_impl(&mut self, cache: &mut ModuleCache<'a>) -> (Type, TraitConstraints) {
-        let previous_level = CURRENT_LEVEL.swap(self.level.unwrap().0, Ordering::SeqCst);
+        let previous_level = set_current_level(self.level.unwrap().0);
         for declaration in self.declarations.iter_mut() {
             bind_irrefutable_pattern(declaration.lhs.as_mut(), declaration.typ.as_ref().unwrap(), &[], true, cache);
         }
-        CURRENT_LEVEL.store(previous_level, Ordering::SeqCst);
+        set_current_level(previous_level);
         (Type::Primitive(PrimitiveType::UnitType), vec![])
     }
 }
@@ -1563,10 +2902,14 @@ impl<'a> Inferable<'a> for ast::MemberAccess<'a> {
     fn infer_impl(&mut self, cache: &mut ModuleCache<'a>) -> (Type, TraitConstraints) {
         let (collection_type, mut traits) = infer(self.lhs.as_mut(), cache);
 
-        let level = LetBindingLevel(CURRENT_LEVEL.load(Ordering::SeqCst));
+        if let Ok(index) = self.field.parse() {
+            return (infer_tuple_index(&collection_type, index, self.location, cache), traits);
+        }
+
+        let level = current_level();
         let trait_id = cache.get_member_access_trait(&self.field, level);
 
-        let field_type = cache.next_type_variable(level);
+        let field_type = next_type_variable(cache);
 
         let typeargs = vec![collection_type, field_type.clone()];
         let callsite = cache.push_variable(format!(".{}", self.field), self.location);
@@ -1577,10 +2920,279 @@ impl<'a> Inferable<'a> for ast::MemberAccess<'a> {
     }
 }
 
+impl<'a> Inferable<'a> for ast::Where<'a> {
+    /// Each definition is inferred before the expression using it. Since each
+    /// Definition is at its own LetBindingLevel they are generalized as usual.
+    fn infer_impl(&mut self, cache: &mut ModuleCache<'a>) -> (Type, TraitConstraints) {
+        let (_, mut traits) = infer_all(&mut self.definitions, cache);
+        let (typ, mut expression_traits) = infer(self.expression.as_mut(), cache);
+        traits.append(&mut expression_traits);
+        (typ, traits)
+    }
+}
+
+impl<'a> Inferable<'a> for ast::TypeTest<'a> {
+    /// `x is Variant` is true if `x` was constructed with the given variant of a union type.
+    /// The type of `x` must be the same union type that the variant constructs.
+    fn infer_impl(&mut self, cache: &mut ModuleCache<'a>) -> (Type, TraitConstraints) {
+        let (expression_type, mut traits) = infer(self.expression.as_mut(), cache);
+        let bool_type = Type::Primitive(PrimitiveType::BooleanType);
+
+        if union_variant_tag(&self.constructor, cache).is_none() {
+            error!(self.constructor.locate(), "Expected a union variant after 'is' but found {}", self.constructor);
+            return (bool_type, traits);
+        }
+
+        let (constructor_type, mut constructor_traits) = infer(self.constructor.as_mut(), cache);
+        traits.append(&mut constructor_traits);
+
+        let variant_type = match constructor_type {
+            Function(function) => *function.return_type,
+            other => other,
+        };
+
+        if unify_quiet(&expression_type, &variant_type, self.location, cache).is_err() {
+            error!(
+                self.constructor.locate(),
+                "{} is a variant of {}, not {}",
+                self.constructor,
+                variant_type.display(cache),
+                expression_type.display(cache)
+            );
+        }
+
+        (bool_type, traits)
+    }
+}
+
+impl<'a> Inferable<'a> for ast::Range<'a> {
+    /// A range pattern has the type of its bounds, which must be
+    /// either both integers or both chars.
+    fn infer_impl(&mut self, cache: &mut ModuleCache<'a>) -> (Type, TraitConstraints) {
+        let (start_type, mut traits) = infer(self.start.as_mut(), cache);
+        let (end_type, mut end_traits) = infer(self.end.as_mut(), cache);
+        traits.append(&mut end_traits);
+
+        match self.bounds() {
+            Some((start, end)) => {
+                unify(&start_type, &end_type, self.end.locate(), cache);
+                if start > end {
+                    error!(
+                        self.location,
+                        "This range pattern is empty since {} is greater than {}", self.start, self.end
+                    );
+                }
+            },
+            None => error!(self.location, "The bounds of a range pattern must be either both integers or both chars"),
+        }
+
+        (start_type, traits)
+    }
+}
+
+impl<'a> Inferable<'a> for ast::Hole<'a> {
+    /// A hole may have any type, so it is given a fresh type variable which
+    /// the surrounding context then narrows down.
+    fn infer_impl(&mut self, cache: &mut ModuleCache<'a>) -> (Type, TraitConstraints) {
+        let typ = next_type_variable(cache);
+        cache.holes.push((self.location, typ.clone(), self.bindings_in_scope.clone()));
+        (typ, vec![])
+    }
+}
+
+/// Returns the tag of the union variant the given constructor refers to,
+/// or None if it does not refer to a variant of a union type.
+pub fn union_variant_tag(constructor: &ast::Ast, cache: &ModuleCache) -> Option<u8> {
+    match constructor {
+        ast::Ast::Variable(ast::Variable { definition: Some(id), .. }) => match &cache[*id].definition {
+            Some(DefinitionKind::TypeConstructor { tag, .. }) => *tag,
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+impl<'a> Inferable<'a> for ast::RecordSpread<'a> {
+    /// A record spread `{ ..base, field = value }` has the same type as each of its bases.
+    /// Each overridden field implies the same member access constraint as `base.field` would,
+    /// requiring the base type to have a field of that name with the type of the new value.
+    fn infer_impl(&mut self, cache: &mut ModuleCache<'a>) -> (Type, TraitConstraints) {
+        let record_type = next_type_variable(cache);
+
+        if self.bases.is_empty() {
+            error!(self.location, "A record spread needs at least one base, e.g. `..base`, to copy fields from");
+        }
+
+        let (base_types, mut traits) = infer_all(&mut self.bases, cache);
+        for (base, base_type) in self.bases.iter().zip(base_types) {
+            unify(&record_type, &base_type, base.locate(), cache);
+        }
+
+        let mut overridden = HashSet::new();
+        for (field, value) in self.overrides.iter_mut() {
+            if !overridden.insert(field.clone()) {
+                error!(value.locate(), "Field {} is overridden more than once in this record spread", field);
+            }
+
+            let (field_type, mut value_traits) = infer(value, cache);
+            traits.append(&mut value_traits);
+
+            let trait_id = cache.get_member_access_trait(field, current_level());
+            let typeargs = vec![record_type.clone(), field_type];
+            let callsite = cache.push_variable(format!(".{}", field), value.locate());
+            traits.push(TraitConstraint::member_access_constraint(trait_id, typeargs, callsite, cache));
+        }
+
+        // With multiple bases, each field must be overridden to decide which base it is taken from
+        if self.bases.len() > 1 {
+            match struct_field_names(&record_type, cache) {
+                Some(fields) => {
+                    let conflicts = fields.into_iter().filter(|field| !overridden.contains(field)).collect::<Vec<_>>();
+                    if !conflicts.is_empty() {
+                        error!(
+                            self.location,
+                            "Multiple bases conflict on {}, override these fields to choose which value to use",
+                            conflicts.join(", ")
+                        );
+                    }
+                },
+                None => error!(
+                    self.location,
+                    "Cannot spread multiple bases of type {} since it is not known to be a struct type",
+                    record_type.display(cache)
+                ),
+            }
+        }
+
+        (record_type, traits)
+    }
+}
+
+/// Returns the name of each field of the given type if it is known to be a struct type
+fn struct_field_names<'c>(typ: &Type, cache: &ModuleCache<'c>) -> Option<Vec<String>> {
+    match follow_bindings_in_cache(typ, cache) {
+        Type::UserDefined(id) => match &cache[id].body {
+            TypeInfoBody::Struct(fields) => Some(fmap(fields, |field| field.name.clone())),
+            _ => None,
+        },
+        Type::TypeApplication(typ, _) => struct_field_names(&typ, cache),
+        _ => None,
+    }
+}
+
 impl<'a> Inferable<'a> for ast::Assignment<'a> {
     fn infer_impl(&mut self, cache: &mut ModuleCache<'a>) -> (Type, TraitConstraints) {
-        let mut traits = infer(self.lhs.as_mut(), cache).1;
+        let (lhs_type, mut traits) = infer(self.lhs.as_mut(), cache);
         traits.append(&mut infer(self.rhs.as_mut(), cache).1);
+
+        if !is_mutable_location(self.lhs.as_ref(), &lhs_type, cache) {
+            error!(self.lhs.locate(), "Cannot assign to immutable location");
+        }
+
         (Type::Primitive(PrimitiveType::UnitType), traits)
     }
 }
+
+/// True if the given expression can be assigned to. This is either a mutable
+/// variable, a field of another mutable location, or a reference or pointer to
+/// the location to store to.
+fn is_mutable_location<'a>(lhs: &ast::Ast<'a>, typ: &Type, cache: &ModuleCache<'a>) -> bool {
+    match follow_bindings_in_cache(typ, cache) {
+        TypeApplication(constructor, _) if matches!(*constructor, Ref(_) | Primitive(PrimitiveType::Ptr)) => {
+            return true;
+        },
+        _ => (),
+    }
+
+    match lhs {
+        ast::Ast::Variable(variable) => match variable.definition {
+            Some(id) => cache[id].mutable,
+            None => true,
+        },
+        ast::Ast::MemberAccess(access) => match access.lhs.get_type() {
+            Some(typ) => is_mutable_location(&access.lhs, typ, cache),
+            None => true,
+        },
+        ast::Ast::TypeAnnotation(annotation) => match annotation.lhs.get_type() {
+            Some(typ) => is_mutable_location(&annotation.lhs, typ, cache),
+            None => true,
+        },
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nameresolution::NameResolver;
+
+    /// Inferring an ordinary program, including generalized definitions and ones used before they are defined,
+    /// restores the CURRENT_LEVEL after each node and never creates a type variable
+    /// below INITIAL_LEVEL, so none of the debug checks on the level fail.
+    #[test]
+    fn normal_inference_passes_level_checks() {
+        let contents = "add m n = fn f -> fn x -> m f (n f x)\none f x = f x\ntwo = add one one\n\
+                        id 3\nid \"four\"\nid x = x\n";
+        let mut cache = NameResolver::start_from_source(contents);
+
+        let ast = cache.parse_trees.get_mut(0).unwrap();
+        infer_ast(ast, &mut cache);
+        assert_eq!(CURRENT_LEVEL.with(Cell::get), INITIAL_LEVEL - 1);
+    }
+
+    /// Inferring each statement with `infer_all` gives the same types and trait constraints,
+    /// in the same order, as inferring them one at a time and appending their constraints.
+    #[test]
+    fn infer_all_matches_inferring_each_node() {
+        let contents = "a = 1\nb = a + 2\nif b > a then \"b\" else \"a\"\nmatch b | 3 -> 'c' | _ -> 'a'\n";
+
+        let infer_statements = |manually: bool| {
+            let mut cache = NameResolver::start_from_source(contents);
+
+            let statements = match cache.parse_trees.get_mut(0).unwrap() {
+                ast::Ast::Sequence(sequence) => &mut sequence.statements,
+                _ => unreachable!("Expected a sequence of statements"),
+            };
+
+            set_current_level(INITIAL_LEVEL);
+            FINISHED_MAIN.with(|finished| finished.set(false));
+            let (types, traits) = if manually {
+                let mut types = vec![];
+                let mut traits = vec![];
+                for statement in statements.iter_mut() {
+                    let (typ, mut statement_traits) = infer(statement, &mut cache);
+                    types.push(typ);
+                    traits.append(&mut statement_traits);
+                }
+                (types, traits)
+            } else {
+                infer_all(statements, &mut cache)
+            };
+            set_current_level(INITIAL_LEVEL - 1);
+            FINISHED_MAIN.with(|finished| finished.set(true));
+
+            let types = fmap(types, |typ| typ.display(&cache).to_string());
+            let traits = fmap(traits, |constraint| constraint.display(&cache).to_string());
+            (types, traits)
+        };
+
+        let (types, traits) = infer_statements(false);
+        assert_eq!(types, ["unit", "unit", "string", "char"]);
+        assert!(!traits.is_empty());
+        assert_eq!((types, traits), infer_statements(true));
+    }
+
+    /// Forgetting to restore the level after inferring main's definitions
+    /// leaves it below INITIAL_LEVEL, which the next type variable trips.
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "below INITIAL_LEVEL")]
+    fn broken_level_path_fails_level_check() {
+        let mut cache = ModuleCache::new(Path::new("."));
+        set_current_level(INITIAL_LEVEL);
+        FINISHED_MAIN.with(|finished| finished.set(false));
+
+        set_current_level(INITIAL_LEVEL - 1);
+        next_type_variable_id(&mut cache);
+    }
+}
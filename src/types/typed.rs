@@ -39,6 +39,7 @@ impl_typed_for!(Lambda);
 impl_typed_for!(FunctionCall);
 impl_typed_for!(Definition);
 impl_typed_for!(If);
+impl_typed_for!(Guard);
 impl_typed_for!(Match);
 impl_typed_for!(TypeDefinition);
 impl_typed_for!(TypeAnnotation);
@@ -50,3 +51,8 @@ impl_typed_for!(Sequence);
 impl_typed_for!(Extern);
 impl_typed_for!(MemberAccess);
 impl_typed_for!(Assignment);
+impl_typed_for!(RecordSpread);
+impl_typed_for!(TypeTest);
+impl_typed_for!(Where);
+impl_typed_for!(Range);
+impl_typed_for!(Hole);
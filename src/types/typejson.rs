@@ -0,0 +1,201 @@
+//! typejson.rs - Exports the inferred type of each definition as JSON.
+//! This lets external tools such as editors build a symbol index from a single
+//! run of the compiler rather than parsing the colored output of `--show-types`.
+//!
+//! Types are exported as nested objects tagged with a "kind" field mirroring the
+//! variants of `Type`. Type variables are named the same way as in `typeprinter.rs`
+//! so that `a` in a definition's type refers to the same `a` in its traits.
+use crate::cache::{DefinitionInfoId, ModuleCache};
+use crate::types::traits::{ConstraintSignature, TraitConstraintId};
+use crate::types::typechecker::find_all_typevars;
+use crate::types::typeprinter::fill_typevar_map;
+use crate::types::{FunctionType, GeneralizedType, PrimitiveType, Type, TypeBinding, TypeVariableId};
+use crate::util::{fmap, join_with};
+
+use std::collections::HashMap;
+
+/// Returns a JSON array with the name, location, type, and traits of
+/// each definition exported from the first compiled module.
+pub fn export_definitions(cache: &ModuleCache) -> String {
+    let resolver = cache.name_resolvers.get_mut(0).unwrap();
+    let mut definitions = resolver.exports.definitions.iter().collect::<Vec<_>>();
+
+    // Make sure the output has a deterministic order for testing
+    definitions.sort();
+
+    let definitions = fmap(definitions, |(name, id)| format!("  {}", export_definition(name, *id, cache)));
+    if definitions.is_empty() {
+        "[]\n".to_string()
+    } else {
+        format!("[\n{}\n]\n", join_with(&definitions, ",\n"))
+    }
+}
+
+fn export_definition(name: &str, id: DefinitionInfoId, cache: &ModuleCache) -> String {
+    let info = &cache[id];
+    let mut exporter = TypeExporter { typevar_names: HashMap::new(), next_name: 0, cache };
+
+    let typ = match &info.typ {
+        Some(typ) => {
+            exporter.name_typevars(typ.find_all_typevars(false, cache));
+            exporter.export_generalized_type(typ)
+        },
+        None => "null".to_string(),
+    };
+
+    let mut traits = fmap(&info.required_traits, |required| required.signature.clone());
+
+    // Trait functions are additionally constrained by the trait they originate from
+    if let Some((trait_id, args)) = &info.trait_info {
+        let id = TraitConstraintId(0); // Dummy value
+        traits.push(ConstraintSignature { trait_id: *trait_id, args: args.clone(), id });
+    }
+
+    let mut traits = fmap(traits, |signature| exporter.export_trait(&signature));
+    traits.sort();
+    traits.dedup();
+
+    let location = &info.location;
+    format!(
+        "{{\"name\": {}, \"location\": {{\"file\": {}, \"line\": {}, \"column\": {}}}, \"type\": {}, \"traits\": [{}]}}",
+        quote(name),
+        quote(&location.filename.to_string_lossy()),
+        location.start.line,
+        location.start.column,
+        typ,
+        join_with(&traits, ", ")
+    )
+}
+
+/// Wrap the given string in quotes, escaping any characters not allowed in a JSON string
+fn quote(string: &str) -> String {
+    let mut result = String::with_capacity(string.len() + 2);
+    result.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+struct TypeExporter<'a, 'b> {
+    /// Maps unique type variable IDs to human readable names like a, b, c, etc.
+    typevar_names: HashMap<TypeVariableId, String>,
+    next_name: usize,
+    cache: &'a ModuleCache<'b>,
+}
+
+impl<'a, 'b> TypeExporter<'a, 'b> {
+    fn name_typevars(&mut self, typevars: Vec<TypeVariableId>) {
+        fill_typevar_map(&mut self.typevar_names, typevars, &mut self.next_name);
+    }
+
+    fn typevar_name(&self, id: TypeVariableId) -> String {
+        self.typevar_names.get(&id).cloned().unwrap_or_else(|| "?".to_string())
+    }
+
+    fn export_trait(&mut self, signature: &ConstraintSignature) -> String {
+        for arg in &signature.args {
+            self.name_typevars(find_all_typevars(arg, false, self.cache));
+        }
+
+        let name = &self.cache[signature.trait_id].name;
+        let args = fmap(&signature.args, |arg| self.export_type(arg));
+        format!("{{\"trait\": {}, \"args\": [{}]}}", quote(name), join_with(&args, ", "))
+    }
+
+    fn export_generalized_type(&self, typ: &GeneralizedType) -> String {
+        match typ {
+            GeneralizedType::MonoType(typ) => self.export_type(typ),
+            GeneralizedType::PolyType(typevars, typ) => self.export_forall(typevars, typ),
+        }
+    }
+
+    fn export_type(&self, typ: &Type) -> String {
+        match typ {
+            Type::Primitive(primitive) => self.export_primitive(primitive),
+            Type::Function(function) => self.export_function(function),
+            Type::TypeVariable(id) => self.export_type_variable(*id),
+            Type::UserDefined(id) => {
+                format!("{{\"kind\": \"user-defined\", \"name\": {}}}", quote(&self.cache.type_infos[id.0].name))
+            },
+            Type::TypeApplication(constructor, args) => {
+                let constructor = self.export_type(constructor);
+                let args = fmap(args, |arg| self.export_type(arg));
+                format!(
+                    "{{\"kind\": \"application\", \"constructor\": {}, \"args\": [{}]}}",
+                    constructor,
+                    join_with(&args, ", ")
+                )
+            },
+            Type::Ref(lifetime) => match &self.cache.type_bindings[lifetime.0] {
+                TypeBinding::Bound(typ) => self.export_type(typ),
+                TypeBinding::Unbound(..) => "{\"kind\": \"ref\"}".to_string(),
+            },
+            Type::Forall(typevars, typ) => self.export_forall(typevars, typ),
+            Type::Record(fields, row) => match &self.cache.type_bindings[row.0] {
+                TypeBinding::Bound(typ) => self.export_type(typ),
+                TypeBinding::Unbound(..) => {
+                    let fields = fmap(fields, |(name, typ)| {
+                        format!("{{\"name\": {}, \"type\": {}}}", quote(name), self.export_type(typ))
+                    });
+                    format!(
+                        "{{\"kind\": \"record\", \"fields\": [{}], \"row\": {}}}",
+                        join_with(&fields, ", "),
+                        self.export_type_variable(*row)
+                    )
+                },
+            },
+        }
+    }
+
+    fn export_primitive(&self, primitive: &PrimitiveType) -> String {
+        let name = match primitive {
+            PrimitiveType::IntegerType(kind) => kind.to_string(),
+            PrimitiveType::FloatType => "float".to_string(),
+            PrimitiveType::CharType => "char".to_string(),
+            PrimitiveType::BooleanType => "bool".to_string(),
+            PrimitiveType::UnitType => "unit".to_string(),
+            PrimitiveType::Ptr => "Ptr".to_string(),
+        };
+        format!("{{\"kind\": \"primitive\", \"name\": {}}}", quote(&name))
+    }
+
+    fn export_function(&self, function: &FunctionType) -> String {
+        let parameters = fmap(&function.parameters, |parameter| self.export_type(parameter));
+        format!(
+            "{{\"kind\": \"function\", \"parameters\": [{}], \"return\": {}, \"environment\": {}, \"varargs\": {}}}",
+            join_with(&parameters, ", "),
+            self.export_type(&function.return_type),
+            self.export_type(&function.environment),
+            function.is_varargs
+        )
+    }
+
+    fn export_type_variable(&self, id: TypeVariableId) -> String {
+        match &self.cache.type_bindings[id.0] {
+            TypeBinding::Bound(typ) => self.export_type(typ),
+            TypeBinding::Unbound(..) => {
+                format!("{{\"kind\": \"variable\", \"name\": {}}}", quote(&self.typevar_name(id)))
+            },
+        }
+    }
+
+    fn export_forall(&self, typevars: &[TypeVariableId], typ: &Type) -> String {
+        let typ = self.export_type(typ);
+        if typevars.is_empty() {
+            return typ;
+        }
+
+        let variables = fmap(typevars, |typevar| quote(&self.typevar_name(*typevar)));
+        format!("{{\"kind\": \"forall\", \"variables\": [{}], \"type\": {}}}", join_with(&variables, ", "), typ)
+    }
+}
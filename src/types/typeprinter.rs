@@ -3,12 +3,16 @@
 //! printing out a bound type requires using the cache as well. Resultingly,
 //! types/traits are displayed via `type.display(cache)` rather than directly having
 //! a Display impl.
-use crate::cache::{ModuleCache, TraitInfoId};
-use crate::types::traits::{ConstraintSignature, ConstraintSignaturePrinter, RequiredTrait, TraitConstraintId};
+use crate::cache::{ImplInfoId, ModuleCache, TraitInfoId};
+use crate::error;
+use crate::types::traits::{
+    ConstraintSignature, ConstraintSignaturePrinter, GivenConstraint, Obligations, RequiredTrait, TraitConstraintId,
+};
 use crate::types::typechecker::find_all_typevars;
 use crate::types::{FunctionType, PrimitiveType, Type, TypeBinding, TypeInfoId, TypeVariableId};
-use crate::util::join_with;
+use crate::util::{fmap, join_with};
 
+use std::cell::Cell;
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::fmt::{Debug, Display, Formatter};
@@ -27,9 +31,37 @@ pub struct TypePrinter<'a, 'b> {
     /// Controls whether to show or hide some hidden data, like ref lifetimes
     debug: bool,
 
+    /// When set, the type is printed as plain text without any color escape codes.
+    no_color: bool,
+
+    /// How the parameters of function types are printed
+    function_style: FunctionStyle,
+
+    /// Types nested deeper than this are printed as `...`. This guards against
+    /// hanging on cyclic type bindings, which should never occur but may when debugging.
+    max_depth: usize,
+
+    /// The depth of the type currently being printed
+    depth: Cell<usize>,
+
     cache: &'a ModuleCache<'b>,
 }
 
+/// The default maximum depth of a type to print before eliding the rest with `...`
+const MAX_TYPE_DEPTH: usize = 100;
+
+/// The syntax used to print function types
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum FunctionStyle {
+    /// Ante's own syntax: `(a - b -> c)`
+    #[default]
+    Curried,
+
+    /// The tupled syntax of most other languages: `(a, b) -> c`
+    #[allow(dead_code)]
+    Tupled,
+}
+
 impl<'a, 'b> Display for TypePrinter<'a, 'b> {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
         self.fmt_generalized_type(&self.typ, f)
@@ -42,19 +74,45 @@ impl<'a, 'b> Debug for TypePrinter<'a, 'b> {
     }
 }
 
+/// Returns false if colored output has been disabled, either through `error::color_output`
+/// or because the `colored` crate has detected that output is not going to a terminal.
+/// Types are printed as plain text in this case.
+pub fn should_colorize() -> bool {
+    error::colored_output() && colored::control::SHOULD_COLORIZE.should_colorize()
+}
+
 /// Fill a HashMap with human readable names for each typevar in the given Vec.
 /// For example, given [TypeVariableId(53), TypeVariableId(92)] this may yield `a` and `b`
 /// respectively.
-fn fill_typevar_map(map: &mut HashMap<TypeVariableId, String>, typevars: Vec<TypeVariableId>, current: &mut char) {
+pub(super) fn fill_typevar_map(
+    map: &mut HashMap<TypeVariableId, String>, typevars: Vec<TypeVariableId>, current: &mut usize,
+) {
     for typevar in typevars {
         if let Entry::Vacant(entry) = map.entry(typevar) {
-            entry.insert(current.to_string());
-            *current = (*current as u8 + 1) as char;
-            assert!(*current != 'z'); // TODO: wrap to aa, ab, ac...
+            entry.insert(next_typevar_name(current));
         }
     }
 }
 
+/// Returns the human readable name for the typevar numbered `current` and increments it.
+/// Names go from `a` to `z`, then wrap to `aa`, `ab`, ... `az`, `ba`, and so on.
+fn next_typevar_name(current: &mut usize) -> String {
+    let mut index = *current;
+    *current += 1;
+
+    let mut name = vec![];
+    loop {
+        name.push(b'a' + (index % 26) as u8);
+        if index < 26 {
+            break;
+        }
+        index = index / 26 - 1;
+    }
+
+    name.reverse();
+    String::from_utf8(name).unwrap()
+}
+
 /// Prints out the given type and traits on screen. The type and traits are all taken in together
 /// so that any repeated typevariables e.g. `TypeVariableId(55)` that may be used in both the type
 /// and any traits are given the same name in both. Printing out the type separately from the
@@ -62,29 +120,34 @@ fn fill_typevar_map(map: &mut HashMap<TypeVariableId, String>, typevars: Vec<Typ
 /// different names.
 pub fn show_type_and_traits<'b>(
     typ: &GeneralizedType, traits: &[RequiredTrait], trait_info: &Option<(TraitInfoId, Vec<Type>)>,
-    cache: &ModuleCache<'b>,
+    verbose_callsites: bool, cache: &ModuleCache<'b>,
 ) {
     let mut map = HashMap::new();
-    let mut current = 'a';
+    let mut current = 0;
 
     let typevars = typ.find_all_typevars(false, cache);
     fill_typevar_map(&mut map, typevars, &mut current);
 
     let debug = true;
     let typ = typ.clone();
-    print!("{}", TypePrinter { typ, cache, debug, typevar_names: map.clone() });
+    print!("{}", TypePrinter::new(typ, map.clone(), debug, cache));
 
     let mut traits = traits
         .iter()
         .map(|required_trait| {
             fill_typevar_map(&mut map, required_trait.find_all_typevars(cache), &mut current);
-            ConstraintSignaturePrinter {
+            let constraint = ConstraintSignaturePrinter {
                 signature: required_trait.signature.clone(),
                 cache,
                 debug,
                 typevar_names: map.clone(),
+            };
+
+            if verbose_callsites {
+                format!("{} ({})", constraint, required_trait.callsite.kind_name())
+            } else {
+                constraint.to_string()
             }
-            .to_string()
         })
         .collect::<Vec<String>>();
 
@@ -99,7 +162,11 @@ pub fn show_type_and_traits<'b>(
             id: TraitConstraintId(0), // Dummy value
         };
         let p = ConstraintSignaturePrinter { signature, cache, debug, typevar_names: map.clone() };
-        traits.push(p.to_string());
+        if verbose_callsites {
+            traits.push(format!("{} (Trait)", p));
+        } else {
+            traits.push(p.to_string());
+        }
     }
 
     // Remove "duplicate" traits so users don't see `given Add a, Add a`.
@@ -115,11 +182,123 @@ pub fn show_type_and_traits<'b>(
     println!();
 }
 
+/// Prints out the type of a definition followed by the obligations a caller must
+/// satisfy to use it. As with `show_type_and_traits`, type variables are named
+/// consistently between the type and each obligation.
+pub fn show_obligations<'b>(obligations: &Obligations, cache: &ModuleCache<'b>) {
+    let mut map = HashMap::new();
+    let mut current = 0;
+
+    let typevars = obligations.typ.find_all_typevars(false, cache);
+    fill_typevar_map(&mut map, typevars, &mut current);
+
+    let debug = true;
+    let typ = obligations.typ.clone();
+    print!("{}", TypePrinter::new(typ, map.clone(), debug, cache));
+
+    for signature in &obligations.constraints {
+        for arg in &signature.args {
+            fill_typevar_map(&mut map, find_all_typevars(arg, false, cache), &mut current);
+        }
+        let signature = signature.clone();
+        let p = ConstraintSignaturePrinter { signature, cache, debug, typevar_names: map.clone() };
+        print!("\n  requires {}", p);
+    }
+
+    println!();
+}
+
+/// Displays the given traits separated by commas. Type variables are named consistently
+/// between each trait, continuing from the names they would have when displaying `typ`.
+pub fn display_traits<'b>(typ: &Type, traits: &[RequiredTrait], cache: &ModuleCache<'b>) -> String {
+    let mut map = HashMap::new();
+    let mut current = 0;
+    fill_typevar_map(&mut map, find_all_typevars(typ, false, cache), &mut current);
+
+    let traits = fmap(traits, |required_trait| {
+        fill_typevar_map(&mut map, required_trait.find_all_typevars(cache), &mut current);
+        let signature = required_trait.signature.clone();
+        ConstraintSignaturePrinter { signature, cache, debug: false, typevar_names: map.clone() }.to_string()
+    });
+    traits.join(", ")
+}
+
+/// Prints out the header of an impl, `impl Trait args given constraints`, followed by
+/// the type of each of its methods. Type variables are named consistently between the
+/// header and every method so that a typevar shared between them has the same name in each.
+pub fn show_impl<'b>(impl_id: ImplInfoId, cache: &ModuleCache<'b>) {
+    let mut map = HashMap::new();
+    let mut current = 0;
+    let debug = true;
+    let info = &cache[impl_id];
+
+    for arg in &info.typeargs {
+        fill_typevar_map(&mut map, find_all_typevars(arg, false, cache), &mut current);
+    }
+
+    let signature = ConstraintSignature {
+        trait_id: info.trait_id,
+        args: info.typeargs.clone(),
+        id: TraitConstraintId(0), // Dummy value
+    };
+    print!("impl {}", ConstraintSignaturePrinter { signature, cache, debug, typevar_names: map.clone() });
+
+    let given = info
+        .given
+        .iter()
+        .map(|constraint| match constraint {
+            GivenConstraint::Trait(signature) => {
+                for arg in &signature.args {
+                    fill_typevar_map(&mut map, find_all_typevars(arg, false, cache), &mut current);
+                }
+                let signature = signature.clone();
+                ConstraintSignaturePrinter { signature, cache, debug, typevar_names: map.clone() }.to_string()
+            },
+            GivenConstraint::Equality(lhs, rhs) => {
+                fill_typevar_map(&mut map, find_all_typevars(lhs, false, cache), &mut current);
+                fill_typevar_map(&mut map, find_all_typevars(rhs, false, cache), &mut current);
+                let lhs = TypePrinter::new(GeneralizedType::MonoType(lhs.clone()), map.clone(), debug, cache);
+                let rhs = TypePrinter::new(GeneralizedType::MonoType(rhs.clone()), map.clone(), debug, cache);
+                format!("{} = {}", lhs, rhs)
+            },
+        })
+        .collect::<Vec<String>>();
+
+    if !given.is_empty() {
+        print!(" given {}", join_with(&given, ", "));
+    }
+
+    for definition_id in &info.definitions {
+        let definition = &cache[*definition_id];
+        print!("\n  {} : ", definition.name);
+
+        match &definition.typ {
+            Some(typ) => {
+                fill_typevar_map(&mut map, typ.find_all_typevars(false, cache), &mut current);
+                print!("{}", TypePrinter::new(typ.clone(), map.clone(), debug, cache));
+            },
+            None => print!("(none)"),
+        }
+    }
+
+    println!();
+}
+
 impl<'a, 'b> TypePrinter<'a, 'b> {
     pub fn new(
         typ: GeneralizedType, typevar_names: HashMap<TypeVariableId, String>, debug: bool, cache: &'a ModuleCache<'b>,
     ) -> Self {
-        TypePrinter { typ, typevar_names, debug, cache }
+        let function_style = FunctionStyle::default();
+        TypePrinter {
+            typ,
+            typevar_names,
+            debug,
+            no_color: !should_colorize(),
+            function_style,
+            max_depth: MAX_TYPE_DEPTH,
+            depth: Cell::new(0),
+            cache,
+        }
     }
 
     pub fn debug_type(typ: GeneralizedType, cache: &'a ModuleCache<'b>) -> Self {
@@ -138,19 +317,38 @@ impl<'a, 'b> TypePrinter<'a, 'b> {
     pub fn display_type(typ: GeneralizedType, cache: &'a ModuleCache<'b>) -> Self {
         let typevars = typ.find_all_typevars(false, cache);
         let mut typevar_names = HashMap::new();
-        let mut current = 'a';
-
-        for typevar in typevars {
-            if typevar_names.get(&typevar).is_none() {
-                typevar_names.insert(typevar, current.to_string());
-                current = (current as u8 + 1) as char;
-                assert!(current != 'z'); // TODO: wrap to aa, ab, ac...
-            }
-        }
+        fill_typevar_map(&mut typevar_names, typevars, &mut 0);
 
         Self::new(typ, typevar_names, true, cache)
     }
 
+    /// Like `display_type` but without any colors, for output that may
+    /// not be displayed on a terminal such as documentation or test files.
+    pub fn plain(typ: GeneralizedType, cache: &'a ModuleCache<'b>) -> Self {
+        TypePrinter { no_color: true, ..Self::display_type(typ, cache) }
+    }
+
+    /// Print function types in the given style rather than the default curried style
+    #[allow(dead_code)]
+    pub fn with_function_style(self, function_style: FunctionStyle) -> Self {
+        TypePrinter { function_style, ..self }
+    }
+
+    /// Elide any part of the type nested deeper than the given depth rather than the default MAX_TYPE_DEPTH
+    #[allow(dead_code)]
+    pub fn with_max_depth(self, max_depth: usize) -> Self {
+        TypePrinter { max_depth, ..self }
+    }
+
+    /// Write the given text in blue unless this printer is set to print plain text
+    fn write_blue(&self, text: &str, f: &mut Formatter) -> std::fmt::Result {
+        if self.no_color {
+            write!(f, "{}", text)
+        } else {
+            write!(f, "{}", text.blue())
+        }
+    }
+
     fn fmt_generalized_type(&self, typ: &GeneralizedType, f: &mut Formatter) -> std::fmt::Result {
         match typ {
             GeneralizedType::MonoType(typ) => self.fmt_type(typ, f),
@@ -159,6 +357,18 @@ impl<'a, 'b> TypePrinter<'a, 'b> {
     }
 
     fn fmt_type(&self, typ: &Type, f: &mut Formatter) -> std::fmt::Result {
+        let depth = self.depth.get();
+        if depth >= self.max_depth {
+            return self.write_blue("...", f);
+        }
+
+        self.depth.set(depth + 1);
+        let result = self.fmt_type_inner(typ, f);
+        self.depth.set(depth);
+        result
+    }
+
+    fn fmt_type_inner(&self, typ: &Type, f: &mut Formatter) -> std::fmt::Result {
         match typ {
             Type::Primitive(primitive) => self.fmt_primitive(primitive, f),
             Type::Function(function) => self.fmt_function(function, f),
@@ -166,43 +376,82 @@ impl<'a, 'b> TypePrinter<'a, 'b> {
             Type::UserDefined(id) => self.fmt_user_defined_type(*id, f),
             Type::TypeApplication(constructor, args) => self.fmt_type_application(constructor, args, f),
             Type::Ref(lifetime) => self.fmt_ref(*lifetime, f),
+            Type::Forall(typevars, typ) => self.fmt_forall(typevars, typ, f),
+            Type::Record(fields, row) => self.fmt_record(fields, *row, f),
         }
     }
 
     fn fmt_primitive(&self, primitive: &PrimitiveType, f: &mut Formatter) -> std::fmt::Result {
         match primitive {
-            PrimitiveType::IntegerType(kind) => write!(f, "{}", kind.to_string().blue()),
-            PrimitiveType::FloatType => write!(f, "{}", "float".blue()),
-            PrimitiveType::CharType => write!(f, "{}", "char".blue()),
-            PrimitiveType::BooleanType => write!(f, "{}", "bool".blue()),
-            PrimitiveType::UnitType => write!(f, "{}", "unit".blue()),
-            PrimitiveType::Ptr => write!(f, "{}", "Ptr".blue()),
+            PrimitiveType::IntegerType(kind) => self.write_blue(&kind.to_string(), f),
+            PrimitiveType::FloatType => self.write_blue("float", f),
+            PrimitiveType::CharType => self.write_blue("char", f),
+            PrimitiveType::BooleanType => self.write_blue("bool", f),
+            PrimitiveType::UnitType => self.write_blue("unit", f),
+            PrimitiveType::Ptr => self.write_blue("Ptr", f),
         }
     }
 
     fn fmt_function(&self, function: &FunctionType, f: &mut Formatter) -> std::fmt::Result {
-        write!(f, "{}", "(".blue())?;
+        match self.function_style {
+            FunctionStyle::Curried => self.fmt_curried_function(function, f),
+            FunctionStyle::Tupled => self.fmt_tupled_function(function, f),
+        }
+    }
+
+    fn fmt_curried_function(&self, function: &FunctionType, f: &mut Formatter) -> std::fmt::Result {
+        self.write_blue("(", f)?;
         for (i, param) in function.parameters.iter().enumerate() {
             self.fmt_type(param, f)?;
             write!(f, " ")?;
 
             if i != function.parameters.len() - 1 {
-                write!(f, "{}", "- ".blue())?;
+                self.write_blue("- ", f)?;
             }
         }
 
         if function.is_varargs {
-            write!(f, "{}", "... ".blue())?;
+            self.write_blue("...", f)?;
+            if let Some(varargs_type) = &function.varargs_type {
+                self.fmt_type(varargs_type, f)?;
+            }
+            write!(f, " ")?;
         }
 
         if function.environment.is_unit(self.cache) {
-            write!(f, "{}", "-> ".blue())?;
+            self.write_blue("-> ", f)?;
         } else {
-            write!(f, "{}", "=> ".blue())?;
+            self.write_blue("=> ", f)?;
         }
 
         self.fmt_type(function.return_type.as_ref(), f)?;
-        write!(f, "{}", ")".blue())
+        self.write_blue(")", f)
+    }
+
+    fn fmt_tupled_function(&self, function: &FunctionType, f: &mut Formatter) -> std::fmt::Result {
+        self.write_blue("(", f)?;
+        for (i, param) in function.parameters.iter().enumerate() {
+            if i != 0 {
+                self.write_blue(", ", f)?;
+            }
+            self.fmt_type(param, f)?;
+        }
+
+        if function.is_varargs {
+            let separator = if function.parameters.is_empty() { "..." } else { ", ..." };
+            self.write_blue(separator, f)?;
+            if let Some(varargs_type) = &function.varargs_type {
+                self.fmt_type(varargs_type, f)?;
+            }
+        }
+
+        if function.environment.is_unit(self.cache) {
+            self.write_blue(") -> ", f)?;
+        } else {
+            self.write_blue(") => ", f)?;
+        }
+
+        self.fmt_type(function.return_type.as_ref(), f)
     }
 
     fn fmt_type_variable(&self, id: TypeVariableId, f: &mut Formatter) -> std::fmt::Result {
@@ -210,19 +459,18 @@ impl<'a, 'b> TypePrinter<'a, 'b> {
             TypeBinding::Bound(typ) => self.fmt_type(typ, f),
             TypeBinding::Unbound(..) => {
                 let default = "?".to_string();
-                let name = self.typevar_names.get(&id).unwrap_or(&default).blue();
-                write!(f, "{}", name)
+                let name = self.typevar_names.get(&id).unwrap_or(&default);
+                self.write_blue(name, f)
             },
         }
     }
 
     fn fmt_user_defined_type(&self, id: TypeInfoId, f: &mut Formatter) -> std::fmt::Result {
-        let name = self.cache.type_infos[id.0].name.blue();
-        write!(f, "{}", name)
+        self.write_blue(&self.cache.type_infos[id.0].name, f)
     }
 
     fn fmt_type_application(&self, constructor: &Type, args: &[Type], f: &mut Formatter) -> std::fmt::Result {
-        write!(f, "{}", "(".blue())?;
+        self.write_blue("(", f)?;
 
         if constructor.is_pair_type() {
             self.fmt_pair(args, f)?;
@@ -234,7 +482,7 @@ impl<'a, 'b> TypePrinter<'a, 'b> {
             }
         }
 
-        write!(f, "{}", ")".blue())
+        self.write_blue(")", f)
     }
 
     fn fmt_pair(&self, args: &[Type], f: &mut Formatter) -> std::fmt::Result {
@@ -242,7 +490,7 @@ impl<'a, 'b> TypePrinter<'a, 'b> {
 
         self.fmt_type(&args[0], f)?;
 
-        write!(f, "{}", ", ".blue())?;
+        self.write_blue(", ", f)?;
 
         match &args[1] {
             Type::TypeApplication(constructor, args) if constructor.is_pair_type() => self.fmt_pair(args, f),
@@ -254,7 +502,7 @@ impl<'a, 'b> TypePrinter<'a, 'b> {
         match &self.cache.type_bindings[lifetime.0] {
             TypeBinding::Bound(typ) => self.fmt_type(typ, f),
             TypeBinding::Unbound(..) => {
-                write!(f, "{}", "ref".blue())?;
+                self.write_blue("ref", f)?;
 
                 if self.debug {
                     match self.typevar_names.get(&lifetime) {
@@ -267,14 +515,69 @@ impl<'a, 'b> TypePrinter<'a, 'b> {
         }
     }
 
+    /// Records are shown as `{field1: type1, ..., fieldN: typeN | row}` while their row
+    /// is unbound, or as the type they stand in for otherwise.
+    fn fmt_record(&self, fields: &[(String, Type)], row: TypeVariableId, f: &mut Formatter) -> std::fmt::Result {
+        if let TypeBinding::Bound(typ) = &self.cache.type_bindings[row.0] {
+            return self.fmt_type(typ, f);
+        }
+
+        self.write_blue("{", f)?;
+        for (i, (name, typ)) in fields.iter().enumerate() {
+            if i != 0 {
+                self.write_blue(", ", f)?;
+            }
+            write!(f, "{}", name)?;
+            self.write_blue(": ", f)?;
+            self.fmt_type(typ, f)?;
+        }
+        self.write_blue(" | ", f)?;
+        self.fmt_type_variable(row, f)?;
+        self.write_blue("}", f)
+    }
+
     fn fmt_forall(&self, typevars: &[TypeVariableId], typ: &Type, f: &mut Formatter) -> std::fmt::Result {
-        write!(f, "{}", "(forall".blue())?;
+        self.write_blue("(forall", f)?;
         for typevar in typevars.iter() {
             write!(f, " ")?;
             self.fmt_type_variable(*typevar, f)?;
         }
-        write!(f, "{}", ". ".blue())?;
+        self.write_blue(". ", f)?;
         self.fmt_type(typ, f)?;
-        write!(f, "{}", ")".blue())
+        self.write_blue(")", f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::token::IntegerKind;
+    use std::path::Path;
+
+    /// Even when color is forced on, disabling colored output prints types as plain text
+    /// without any of the escape sequences `colored` would otherwise emit.
+    #[test]
+    fn disabled_color_output_prints_without_escape_sequences() {
+        let cache = ModuleCache::new(Path::new("."));
+        let i32_type = Type::Primitive(PrimitiveType::IntegerType(IntegerKind::I32));
+        let typ = Type::Function(FunctionType {
+            parameters: vec![i32_type, Type::Primitive(PrimitiveType::CharType)],
+            return_type: Box::new(Type::Primitive(PrimitiveType::BooleanType)),
+            environment: Box::new(Type::Primitive(PrimitiveType::UnitType)),
+            is_varargs: false,
+            varargs_type: None,
+        });
+
+        let was_colored = error::colored_output();
+        colored::control::set_override(true);
+        error::color_output(false);
+
+        let printed = typ.display(&cache).to_string();
+
+        error::color_output(was_colored);
+        colored::control::unset_override();
+
+        assert_eq!(printed, "(i32 - char -> bool)");
+        assert!(!printed.contains('\x1b'));
     }
 }
@@ -1,6 +1,11 @@
 //! util/mod.rs - Various utility functions used throughout the compiler.
 //! Mostly consists of convenience functions for iterators such as `fmap`.
-use std::{fmt::Display, path::PathBuf, process::Command};
+use std::{
+    fmt::Display,
+    ops::{Add, Rem, Sub},
+    path::PathBuf,
+    process::Command,
+};
 
 #[macro_use]
 pub mod logging;
@@ -16,16 +21,18 @@ where
     iterable.into_iter().map(f).collect()
 }
 
-/// What a name! Iterate the array, mapping each element with a function that returns a pair
+/// What a name! Iterate the elements, mapping each with a function that returns a pair
 /// of a value and a vector. Accumulate the results in two separate vectors, the second of
 /// which is flattened from all the second-element vectors found so far.
-pub fn fmap_mut_pair_flatten_second<T, Ret1, Ret2, F>(array: &mut [T], mut f: F) -> (Vec<Ret1>, Vec<Ret2>)
+pub fn fmap_mut_pair_flatten_second<'a, T: 'a, Ret1, Ret2, F>(
+    elements: impl IntoIterator<Item = &'a mut T>, mut f: F,
+) -> (Vec<Ret1>, Vec<Ret2>)
 where
     F: FnMut(&mut T) -> (Ret1, Vec<Ret2>),
 {
-    let mut ret1 = Vec::with_capacity(array.len());
-    let mut ret2 = Vec::with_capacity(array.len());
-    for elem in array.iter_mut() {
+    let mut ret1 = vec![];
+    let mut ret2 = vec![];
+    for elem in elements {
         let (elem1, mut vec) = f(elem);
         ret1.push(elem1);
         ret2.append(&mut vec);
@@ -43,6 +50,52 @@ pub fn join_with<T: Display>(vec: &[T], delimiter: &str) -> String {
     fmap(vec, |t| format!("{}", t)).join(delimiter)
 }
 
+/// Returns the candidate closest to the given name, if any is close enough to plausibly be a typo of it
+pub fn closest_name<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    // A name can always be turned into any other of its length by replacing every character,
+    // so require at least one character in common.
+    let length = name.chars().count();
+    let max_distance = std::cmp::max(1, length / 3).min(length.saturating_sub(1));
+
+    candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// The Levenshtein distance between two strings: the minimum number of single
+/// character insertions, deletions, or substitutions to turn one into the other.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + if a_char == *b_char { 0 } else { 1 };
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+/// Round the given offset up to the nearest multiple of the given alignment
+pub fn align_up<T>(offset: T, alignment: T) -> T
+where
+    T: Copy + Default + PartialEq + Add<Output = T> + Sub<Output = T> + Rem<Output = T>,
+{
+    let remainder = offset % alignment;
+    if remainder == T::default() {
+        offset
+    } else {
+        offset + (alignment - remainder)
+    }
+}
+
 pub fn link(object_filename: &str, binary_filename: &str) {
     // call gcc to compile the bitcode to a binary
     let output = format!("-o{}", binary_filename);